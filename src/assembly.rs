@@ -1,11 +1,122 @@
-use crate::ast::ASTNode;
+use crate::ast::statement::{Function, Statement};
+use crate::ast::{ASTNode, Storage, Symbol};
+use std::rc::Rc;
 use crate::scan::Token;
+use crate::typecheck::{evaluate_constant, Constant};
+use crate::types::{Record, RecordKind, Type};
+use std::collections::HashMap;
 use std::io::{BufWriter, Result as IoResult, Write};
 
 pub mod assembly_writer_arm64;
 
+/// The targets the compiler writes assembly for
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
 pub enum SupportedArchitectures {
+    /// ARM64 macOS: Mach-O objects and Apple's variant of the procedure call standard
+    #[default]
     ARM64,
+    /// ARM64 Linux: ELF objects and the procedure call standard as AAPCS64 has it
+    ARM64Linux,
+}
+
+thread_local! {
+    /// The `va_list` of AAPCS64, made once so that every use of it is the same type
+    static AAPCS64_VA_LIST: Type = {
+        let record = Record::new(RecordKind::Struct, Some("__va_list".to_string()));
+        let pointer = Type::Void.pointer_to();
+        record
+            .define(vec![
+                (Some("__stack".to_string()), pointer.clone()),
+                (Some("__gr_top".to_string()), pointer.clone()),
+                (Some("__vr_top".to_string()), pointer),
+                (Some("__gr_offs".to_string()), Type::Int),
+                (Some("__vr_offs".to_string()), Type::Int),
+            ])
+            .expect("The members of va_list have different names");
+        Type::Record(record)
+    };
+}
+
+impl SupportedArchitectures {
+    /// The target a triple like `aarch64-linux-gnu` or `arm64-apple-darwin` names, `None` for
+    /// the ones the compiler cannot write assembly for
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::assembly::SupportedArchitectures;
+    /// assert_eq!(SupportedArchitectures::from_triple("aarch64-unknown-linux-gnu"), Some(SupportedArchitectures::ARM64Linux));
+    /// assert_eq!(SupportedArchitectures::from_triple("arm64-apple-macosx14.0.0"), Some(SupportedArchitectures::ARM64));
+    /// assert_eq!(SupportedArchitectures::from_triple("x86_64-linux-gnu"), None);
+    /// ```
+    pub fn from_triple(triple: &str) -> Option<Self> {
+        let mut parts = triple.split('-');
+        if !matches!(parts.next(), Some("aarch64" | "arm64")) {
+            return None;
+        }
+        let parts: Vec<&str> = parts.collect();
+        let names = |names: &[&str]| parts.iter().any(|part| names.iter().any(|name| part.starts_with(name)));
+        match (names(&["linux"]), names(&["apple", "darwin", "macos"])) {
+            (true, false) => Some(SupportedArchitectures::ARM64Linux),
+            (false, true) => Some(SupportedArchitectures::ARM64),
+            _ => None,
+        }
+    }
+
+    /// The macros a C compiler for this target predefines, as `NAME=VALUE`
+    pub fn predefined_macros(&self) -> &'static [&'static str] {
+        match self {
+            SupportedArchitectures::ARM64 => &[
+                "__aarch64__=1",
+                "__arm64__=1",
+                "__APPLE__=1",
+                "__MACH__=1",
+                "__LP64__=1",
+                "_LP64=1",
+                "__CHAR_BIT__=8",
+            ],
+            SupportedArchitectures::ARM64Linux => &[
+                "__aarch64__=1",
+                "__linux__=1",
+                "__linux=1",
+                "__gnu_linux__=1",
+                "__unix__=1",
+                "__unix=1",
+                "__ELF__=1",
+                "__LP64__=1",
+                "_LP64=1",
+                "__CHAR_BIT__=8",
+            ],
+        }
+    }
+
+    /// The type of `va_list`. ARM64 Darwin passes every variadic argument on the stack, so it
+    /// only has to point to the next one. AAPCS64 passes them in registers like the others, the
+    /// called function saves those and the `va_list` says where to find the next one in the
+    /// saved registers and on the stack.
+    pub fn va_list(&self) -> Type {
+        match self {
+            SupportedArchitectures::ARM64 => Type::Char.pointer_to(),
+            SupportedArchitectures::ARM64Linux => AAPCS64_VA_LIST.with(Type::clone),
+        }
+    }
+
+    /// What the assembly name of a C function or variable starts with, Darwin puts an underscore
+    /// in front of every C name
+    pub fn symbol_prefix(&self) -> &'static str {
+        match self {
+            SupportedArchitectures::ARM64 => "_",
+            SupportedArchitectures::ARM64Linux => "",
+        }
+    }
+
+    /// What labels the assembler keeps out of the symbol table start with
+    pub fn local_label_prefix(&self) -> &'static str {
+        match self {
+            SupportedArchitectures::ARM64 => "L",
+            SupportedArchitectures::ARM64Linux => ".L",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -15,6 +126,63 @@ pub enum RegisterList {
     R2,
     R3,
     R4,
+    /// Floating point registers, for values of type `float` and `double`. These are the ones
+    /// a called function may change, the others it has to keep for its caller.
+    F0,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    F25,
+    F26,
+    F27,
+    F28,
+    F29,
+    F30,
+    F31,
+}
+
+impl RegisterList {
+    pub fn is_floating(&self) -> bool {
+        !matches!(self, RegisterList::R0 | RegisterList::R1 | RegisterList::R2 | RegisterList::R3 | RegisterList::R4)
+    }
+}
+
+
+/// Escape bytes for use inside a quoted `.ascii`/`.asciz` directive.
+/// Printable ASCII is kept as is, everything else becomes a three digit octal escape.
+///
+/// # Examples
+///
+/// ```
+/// # use compiler::assembly::escape_assembly_string;
+/// assert_eq!(escape_assembly_string(b"say \"hi\"\n"), "say \\\"hi\\\"\\012");
+/// ```
+pub fn escape_assembly_string(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            }
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
 }
 
 
@@ -24,61 +192,196 @@ pub struct AssemblyWriter<W: Write> {
 }
 
 
+/// A switch with fewer cases than this always compares them one at a time
+const JUMP_TABLE_MIN_CASES: usize = 4;
+
+/// A jump table is only used when it has at most this many entries for each case,
+/// the entries between the cases go to `default`
+const JUMP_TABLE_ENTRIES_PER_CASE: u64 = 3;
+
+/// Where the statements in the body of a `switch` jump to
+struct SwitchLabels {
+    /// The label of each `case`, by value
+    cases: HashMap<i64, String>,
+    default: Option<String>,
+    /// Right after the switch, where `break` goes
+    end: String,
+}
+
+
+/// The type the type checker gave an expression
+fn expression_type(node: &ASTNode) -> IoResult<&Type> {
+    node.ty.as_ref().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "Expression was not type checked")
+    })
+}
+
 trait WriteAssembly {
     fn format_register(&self, register: &RegisterList) -> String;
-    fn allocate_register(&mut self) -> RegisterList;
+    /// A free general register, an error when every one is in use
+    fn allocate_register(&mut self) -> IoResult<RegisterList>;
+    /// A free floating point register, an error when every one is in use
+    fn allocate_float_register(&mut self) -> IoResult<RegisterList>;
+    /// A register for a value of type `ty`, a floating point one for floating types
+    fn allocate_register_for(&mut self, ty: &Type) -> IoResult<RegisterList> {
+        if ty.is_floating() {
+            self.allocate_float_register()
+        } else {
+            self.allocate_register()
+        }
+    }
     fn free_register(&mut self, register: RegisterList);
     fn free_all_registers(&mut self);
-    fn load_register(&mut self, value: i32) -> IoResult<RegisterList>;
+    /// How many floating point registers are free, or general ones if `floating` is false
+    fn free_register_count(&self, floating: bool) -> usize;
+    /// Push the value in `register` on the stack and free the register
+    fn spill_register(&mut self, register: RegisterList) -> IoResult<()>;
+    /// Pop the value [`Self::spill_register`] pushed last into a new register, a floating
+    /// point one if `floating`
+    fn reload_register(&mut self, floating: bool) -> IoResult<RegisterList>;
+    fn load_register(&mut self, value: i64, ty: &Type) -> IoResult<RegisterList>;
+    /// Load a floating constant of type `ty` into a floating point register
+    fn load_float(&mut self, value: f64, ty: &Type) -> IoResult<RegisterList>;
+    /// Place a string literal in read-only data and load its address into a register
+    fn load_string_address(&mut self, bytes: &[u8]) -> IoResult<RegisterList>;
+    /// Load a variable, extending it to a full register as its type requires.
+    /// The value of a struct or union is its address, in this and every other method.
+    fn load_variable(&mut self, symbol: &Symbol) -> IoResult<RegisterList>;
+    /// Store the low bytes of `register` into a variable, the register keeps the value.
+    /// A struct or union is copied from the address in `register`.
+    fn store_variable(&mut self, register: RegisterList, symbol: &Symbol) -> IoResult<RegisterList>;
+    fn load_variable_address(&mut self, symbol: &Symbol) -> IoResult<RegisterList>;
+    /// What `va_start` sets a `va_list` to in the function being compiled: the address of its
+    /// first variadic argument, or of a `va_list` describing them when that is a struct
+    fn variadic_arguments(&mut self) -> IoResult<RegisterList>;
+    /// `va_arg` on a target whose `va_list` is a struct, which the type checker leaves to the
+    /// code generator: load the next variadic argument and move the `va_list` past it.
+    /// A struct or union is copied into the temporary of the node.
+    fn variadic_argument(&mut self, node: &ASTNode) -> IoResult<RegisterList>;
+    /// Give a variable with static storage its memory, holding the value of a constant
+    /// `initializer` or zeros before the program starts
+    fn define_static_variable(&mut self, symbol: &Rc<Symbol>, initializer: Option<&ASTNode>) -> IoResult<()>;
+    /// Load a value of type `ty` from the address in `address`, freeing the address register
+    fn load_through(&mut self, address: RegisterList, ty: &Type) -> IoResult<RegisterList>;
+    /// Store a value of type `ty` to the address in `address`, freeing the address register
+    /// but keeping the value
+    fn store_through(&mut self, value: RegisterList, address: RegisterList, ty: &Type) -> IoResult<RegisterList>;
+    /// Call a function as the platform's calling convention requires. Registers in use
+    /// keep their values, the result is in a new register.
+    fn generate_call(&mut self, node: &ASTNode) -> IoResult<RegisterList>;
+    /// Convert the value in `register` from one type to another
+    fn cast_register(&mut self, register: RegisterList, from: &Type, to: &Type) -> IoResult<RegisterList>;
     fn print_register(&mut self, register: RegisterList) -> IoResult<RegisterList>;
-    fn add_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList) -> IoResult<RegisterList>;
-    fn subtract_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList) -> IoResult<RegisterList>;
-    fn multiply_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList) -> IoResult<RegisterList>;
-    fn divide_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList) -> IoResult<RegisterList>;
+    fn add_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList>;
+    fn subtract_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList>;
+    fn multiply_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList>;
+    fn divide_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList>;
+    /// The remainder of dividing `reg_1` by `reg_2`, both integers of type `ty`
+    fn modulo_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList>;
+    /// `&`, `|`, `^`, `<<` or `>>` on two integers of type `ty`
+    fn bitwise_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, operation: &Token, ty: &Type) -> IoResult<RegisterList>;
+    /// `-`, `~` or `!` on a value of type `ty`. The result of `!` is an int, 1 when the value is 0.
+    fn unary_register(&mut self, register: RegisterList, operation: &Token, ty: &Type) -> IoResult<RegisterList>;
+    /// Compare two values of type `ty`, the result is 1 when `comparison` holds and 0 otherwise
+    fn compare_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, comparison: &Token, ty: &Type) -> IoResult<RegisterList>;
+    /// A label no other part of the program uses
+    fn new_label(&mut self) -> String;
+    /// The label for the C label `name` in `function`, which cannot clash with the ones [`Self::new_label`] makes
+    fn named_label(&self, function: &Function, name: &str) -> String;
+    fn write_label(&mut self, label: &str) -> IoResult<()>;
+    fn jump(&mut self, label: &str) -> IoResult<()>;
+    /// Jump to `label` when the value of type `ty` in `register` is nonzero, or when it is zero
+    /// if `nonzero` is false. The register is freed.
+    fn jump_if(&mut self, register: RegisterList, ty: &Type, nonzero: bool, label: &str) -> IoResult<()>;
+    /// Jump to `label` when the integer of type `ty` in `register` is `value`, the register keeps it
+    fn jump_if_equal(&mut self, register: &RegisterList, ty: &Type, value: i64, label: &str) -> IoResult<()>;
+    /// Jump to one of the labels in `table` by the integer of type `ty` in `register`: the first
+    /// label for the value `first`, the next one for the value after that, and so on. Values
+    /// outside the table go to `otherwise`. The register is freed.
+    fn jump_table(&mut self, register: RegisterList, ty: &Type, first: i64, table: &[&str], otherwise: &str) -> IoResult<()>;
+    /// Move a value of type `ty` into `destination`, freeing `source`
+    fn move_register(&mut self, destination: &RegisterList, source: RegisterList, ty: &Type) -> IoResult<()>;
+    /// Copy a value of type `ty` into a new register, `register` keeps it too
+    fn copy_register(&mut self, register: &RegisterList, ty: &Type) -> IoResult<RegisterList>;
     fn generate_assembly_from_ast(&mut self, node: &ASTNode) -> IoResult<RegisterList> {
         match node.operation {
-            Token::INT(n) => {
-                Ok(self.load_register(n)?)
+            Token::INT(_)
+            | Token::UINT(_)
+            | Token::LONG(_)
+            | Token::ULONG(_)
+            | Token::LONGLONG(_)
+            | Token::ULONGLONG(_)
+            | Token::CHAR(_) => {
+                let value = node.operation.integer_value().expect("Integer literal without a value");
+                Ok(self.load_register(value, expression_type(node)?)?)
             }
-            Token::PLUS => {
-                // Recursively generate assembly for left and right subtrees
-                let left_reg = self.generate_assembly_from_ast(
-                    node.left.as_ref().expect("Missing left operand")
-                )?;
-                let right_reg = self.generate_assembly_from_ast(
-                    node.right.as_ref().expect("Missing right operand")
-                )?;
-
-                // Perform addition
-                Ok(self.add_registers(left_reg, right_reg)?)
+            Token::FLOAT(bits) => self.load_float(f32::from_bits(bits) as f64, expression_type(node)?),
+            Token::DOUBLE(bits) => self.load_float(f64::from_bits(bits), expression_type(node)?),
+            Token::STRING(ref bytes) => self.load_string_address(bytes),
+            Token::IDENTIFIER(_) => {
+                let symbol = node.symbol.as_ref().ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, "Identifier without a symbol")
+                })?;
+                self.load_variable(symbol)
             }
-            Token::MINUS => {
-                let left_reg = self.generate_assembly_from_ast(
-                    node.left.as_ref().expect("Missing left operand")
-                )?;
-                let right_reg = self.generate_assembly_from_ast(
-                    node.right.as_ref().expect("Missing right operand")
-                )?;
-                self.subtract_registers(left_reg, right_reg)
+            Token::AddressOf => self.generate_address(node.left.as_ref().expect("Missing operand")),
+            Token::VariadicArguments => self.variadic_arguments(),
+            Token::VaArg => self.variadic_argument(node),
+            Token::Call => self.generate_call(node),
+            Token::LOGAND | Token::LOGOR => self.generate_logical(node),
+            Token::QUESTION => self.generate_conditional(node),
+            Token::Dereference => {
+                let address = self.generate_assembly_from_ast(node.left.as_ref().expect("Missing operand"))?;
+                self.load_through(address, expression_type(node)?)
             }
-            Token::ASTERISK => {
-                let left_reg = self.generate_assembly_from_ast(
-                    node.left.as_ref().expect("Missing left operand")
-                )?;
-                let right_reg = self.generate_assembly_from_ast(
-                    node.right.as_ref().expect("Missing right operand")
-                )?;
-                self.multiply_registers(left_reg, right_reg)
+            Token::ASSIGN => {
+                let target = node.left.as_ref().expect("Missing left operand");
+                let value = self.generate_assembly_from_ast(node.right.as_ref().expect("Missing right operand"))?;
+                match &target.symbol {
+                    Some(symbol) => self.store_variable(value, symbol),
+                    None => {
+                        let (value, address) = self.generate_keeping(value, |writer| writer.generate_address(target))?;
+                        self.store_through(value, address, expression_type(node)?)
+                    }
+                }
+            }
+            Token::EQ | Token::NE | Token::LT | Token::GT | Token::LE | Token::GE => {
+                let left = node.left.as_ref().expect("Missing left operand");
+                let right = node.right.as_ref().expect("Missing right operand");
+                let left_reg = self.generate_assembly_from_ast(left)?;
+                let (left_reg, right_reg) = self.generate_keeping(left_reg, |writer| writer.generate_assembly_from_ast(right))?;
+                // Both operands have the same type after type checking
+                self.compare_registers(left_reg, right_reg, &node.operation, expression_type(left)?)
+            }
+            Token::Negate | Token::INVERT | Token::LOGNOT => {
+                let operand = node.left.as_ref().expect("Missing operand");
+                let register = self.generate_assembly_from_ast(operand)?;
+                self.unary_register(register, &node.operation, expression_type(operand)?)
             }
-            Token::SLASH => {
+            Token::Cast => {
+                let operand = node.left.as_ref().expect("Missing cast operand");
+                let register = self.generate_assembly_from_ast(operand)?;
+                self.cast_register(register, expression_type(operand)?, expression_type(node)?)
+            }
+            Token::PLUS
+            | Token::MINUS
+            | Token::ASTERISK
+            | Token::SLASH
+            | Token::PERCENT
+            | Token::AMPER
+            | Token::OR
+            | Token::XOR
+            | Token::LSHIFT
+            | Token::RSHIFT => {
+                // Recursively generate assembly for left and right subtrees
                 let left_reg = self.generate_assembly_from_ast(
                     node.left.as_ref().expect("Missing left operand")
                 )?;
-                let right_reg = self.generate_assembly_from_ast(
-                    node.right.as_ref().expect("Missing right operand")
-                )?;
-                self.divide_registers(left_reg, right_reg)
+                let right = node.right.as_ref().expect("Missing right operand");
+                let (left_reg, right_reg) = self.generate_keeping(left_reg, |writer| writer.generate_assembly_from_ast(right))?;
+                self.generate_operation(left_reg, right_reg, &node.operation, expression_type(node)?)
             }
+            ref operation if operation.compound_operator().is_some() => self.generate_compound_assignment(node),
             _ => Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "Unsupported or invalid operation",
@@ -86,9 +389,274 @@ trait WriteAssembly {
         }
     }
 
+    /// Generate a value while the one in `kept` waits for it, like the right operand of a
+    /// binary operator while the left one waits. When fewer than two registers like `kept`
+    /// are free, `kept` waits on the stack instead, so however deep the expression goes
+    /// there is a register for each operand.
+    ///
+    /// returns: the register `kept` is in now, which may be another one, and the new value
+    fn generate_keeping(
+        &mut self,
+        kept: RegisterList,
+        generate: impl FnOnce(&mut Self) -> IoResult<RegisterList>,
+    ) -> IoResult<(RegisterList, RegisterList)> {
+        let floating = kept.is_floating();
+        if self.free_register_count(floating) >= 2 {
+            let value = generate(self)?;
+            return Ok((kept, value));
+        }
+        self.spill_register(kept)?;
+        let value = generate(self)?;
+        let kept = self.reload_register(floating)?;
+        Ok((kept, value))
+    }
+
+    /// Apply an arithmetic or bitwise operator to two values of type `ty`
+    fn generate_operation(&mut self, reg_1: RegisterList, reg_2: RegisterList, operation: &Token, ty: &Type) -> IoResult<RegisterList> {
+        match operation {
+            Token::PLUS => self.add_registers(reg_1, reg_2, ty),
+            Token::MINUS => self.subtract_registers(reg_1, reg_2, ty),
+            Token::ASTERISK => self.multiply_registers(reg_1, reg_2, ty),
+            Token::SLASH => self.divide_registers(reg_1, reg_2, ty),
+            Token::PERCENT => self.modulo_registers(reg_1, reg_2, ty),
+            _ => self.bitwise_registers(reg_1, reg_2, operation, ty),
+        }
+    }
+
+    /// `a op= b`, `a++` and `a--`. The address of `a` is only worked out once, so `a[f()] += 1`
+    /// calls `f` once. The value of `a++` and `a--` is the one `a` had before.
+    fn generate_compound_assignment(&mut self, node: &ASTNode) -> IoResult<RegisterList> {
+        let ty = expression_type(node)?;
+        let target = node.left.as_ref().expect("Missing left operand");
+        let operand = node.right.as_ref().expect("Missing right operand");
+        // The type checker gave the right operand the type the operation is done in
+        let operation_type = expression_type(operand)?;
+        let operator = node.operation.compound_operator().expect("Not a compound assignment");
+
+        let address = match &target.symbol {
+            Some(_) => None,
+            None => Some(self.generate_address(target)?),
+        };
+        let current = match &target.symbol {
+            Some(symbol) => self.load_variable(symbol)?,
+            None => {
+                let address = self.copy_register(address.as_ref().expect("Missing address"), &Type::Long)?;
+                self.load_through(address, ty)?
+            }
+        };
+        let previous = match node.operation {
+            Token::PostIncrement | Token::PostDecrement => Some(self.copy_register(&current, ty)?),
+            _ => None,
+        };
+
+        let current = self.cast_register(current, ty, operation_type)?;
+        let value = self.generate_assembly_from_ast(operand)?;
+        let result = self.generate_operation(current, value, &operator, operation_type)?;
+        let result = self.cast_register(result, operation_type, ty)?;
+        let result = match &target.symbol {
+            Some(symbol) => self.store_variable(result, symbol)?,
+            None => self.store_through(result, address.expect("Missing address"), ty)?,
+        };
+        match previous {
+            Some(previous) => {
+                self.free_register(result);
+                Ok(previous)
+            }
+            None => Ok(result),
+        }
+    }
+
+    /// `&&` and `||`: the right operand is only evaluated when the left one does not decide the result
+    fn generate_logical(&mut self, node: &ASTNode) -> IoResult<RegisterList> {
+        // An operand that is true decides `||`, one that is false decides `&&`
+        let decided = node.operation == Token::LOGOR;
+        let (decided_label, end_label) = (self.new_label(), self.new_label());
+        let result = self.allocate_register()?;
+        for operand in [&node.left, &node.right] {
+            let operand = operand.as_ref().expect("Missing operand");
+            let register = self.generate_assembly_from_ast(operand)?;
+            self.jump_if(register, expression_type(operand)?, decided, &decided_label)?;
+        }
+
+        let value = self.load_register(!decided as i64, &Type::Int)?;
+        self.move_register(&result, value, &Type::Int)?;
+        self.jump(&end_label)?;
+        self.write_label(&decided_label)?;
+        let value = self.load_register(decided as i64, &Type::Int)?;
+        self.move_register(&result, value, &Type::Int)?;
+        self.write_label(&end_label)?;
+        Ok(result)
+    }
+
+    /// `c ? a : b`, only the result that is chosen is evaluated
+    fn generate_conditional(&mut self, node: &ASTNode) -> IoResult<RegisterList> {
+        let ty = expression_type(node)?;
+        let condition = node.left.as_ref().expect("Missing condition");
+        let results = node.right.as_ref().expect("Missing results");
+        let (false_label, end_label) = (self.new_label(), self.new_label());
+        let result = self.allocate_register_for(ty)?;
+
+        let register = self.generate_assembly_from_ast(condition)?;
+        self.jump_if(register, expression_type(condition)?, false, &false_label)?;
+        let value = self.generate_assembly_from_ast(results.left.as_ref().expect("Missing result"))?;
+        self.move_register(&result, value, ty)?;
+        self.jump(&end_label)?;
+        self.write_label(&false_label)?;
+        let value = self.generate_assembly_from_ast(results.right.as_ref().expect("Missing result"))?;
+        self.move_register(&result, value, ty)?;
+        self.write_label(&end_label)?;
+        Ok(result)
+    }
+
+    /// Load the address of an lvalue into a register
+    fn generate_address(&mut self, node: &ASTNode) -> IoResult<RegisterList> {
+        match (&node.operation, &node.symbol) {
+            (Token::IDENTIFIER(_), Some(symbol)) => self.load_variable_address(symbol),
+            (Token::STRING(bytes), _) => self.load_string_address(bytes),
+            // The address of `*p` is the value of `p`
+            (Token::Dereference, _) => self.generate_assembly_from_ast(node.left.as_ref().expect("Missing operand")),
+            // A struct or union that is not an lvalue, like one returned by a call, is in memory
+            // already, its value is its address
+            _ if node.ty.as_ref().is_some_and(Type::is_record) => self.generate_assembly_from_ast(node),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Not an lvalue",
+            )),
+        }
+    }
+
+    /// Generate a statement, every register is free again afterwards. `switch` has the labels
+    /// of the innermost switch the statement is in, if any.
+    fn generate_statement(&mut self, statement: &Statement, function: &Function, switch: Option<&SwitchLabels>) -> IoResult<()> {
+        let outside_switch = || std::io::Error::new(std::io::ErrorKind::InvalidInput, "Not inside a switch");
+        match statement {
+            // A global declared inside the function, which is defined outside it
+            Statement::Declaration(symbol, _) if matches!(symbol.storage, Storage::Static { external: true, .. }) => {}
+            Statement::Declaration(symbol, initializer) if symbol.is_static() => {
+                self.define_static_variable(symbol, initializer.as_ref())?;
+            }
+            Statement::Expression(None) | Statement::Declaration(_, None) => {}
+            Statement::Expression(Some(expression)) => {
+                let register = self.generate_assembly_from_ast(expression)?;
+                self.free_register(register);
+            }
+            Statement::Declaration(symbol, Some(initializer)) => {
+                let register = self.generate_assembly_from_ast(initializer)?;
+                let register = self.store_variable(register, symbol)?;
+                self.free_register(register);
+            }
+            Statement::Return(value, _) => {
+                let register = match value {
+                    Some(value) => Some(self.generate_assembly_from_ast(value)?),
+                    None => None,
+                };
+                self.write_return(register, function)?;
+            }
+            Statement::Block(statements) => {
+                for statement in statements {
+                    self.generate_statement(statement, function, switch)?;
+                }
+            }
+            Statement::Switch { value, body, cases, default } => {
+                self.generate_switch(value, body, cases, *default, function)?;
+            }
+            Statement::Case(value, statement, _) => {
+                let switch = switch.ok_or_else(outside_switch)?;
+                let Some(Constant::Integer(value)) = evaluate_constant(value) else {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Case value is not a constant"));
+                };
+                let label = switch.cases.get(&value).ok_or_else(outside_switch)?;
+                self.write_label(label)?;
+                self.generate_statement(statement, function, Some(switch))?;
+            }
+            Statement::Default(statement, _) => {
+                let switch = switch.ok_or_else(outside_switch)?;
+                self.write_label(switch.default.as_ref().ok_or_else(outside_switch)?)?;
+                self.generate_statement(statement, function, Some(switch))?;
+            }
+            Statement::Break(_) => self.jump(&switch.ok_or_else(outside_switch)?.end)?,
+            Statement::Label(name, statement, _) => {
+                let label = self.named_label(function, name);
+                self.write_label(&label)?;
+                self.generate_statement(statement, function, switch)?;
+            }
+            Statement::Goto(name, _) => {
+                let label = self.named_label(function, name);
+                self.jump(&label)?;
+            }
+        }
+        self.free_all_registers();
+        Ok(())
+    }
+
+    /// Jump to the `case` that matches the value, then generate the body. Dense cases jump
+    /// through a table indexed by the value, others are compared one at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `value`: what is switched on, already promoted
+    /// * `body`: the statement with the `case` labels
+    /// * `cases`: the value of every `case` label, converted to the type of `value`
+    /// * `default`: whether there is a `default` label, where any other value goes. Without one
+    ///   other values leave the switch.
+    /// * `function`: the function the switch is in
+    ///
+    /// returns: IoResult<()>
+    fn generate_switch(&mut self, value: &ASTNode, body: &Statement, cases: &[i64], default: bool, function: &Function) -> IoResult<()> {
+        let ty = expression_type(value)?;
+        let labels = SwitchLabels {
+            cases: cases.iter().map(|&case| (case, self.new_label())).collect(),
+            default: default.then(|| self.new_label()),
+            end: self.new_label(),
+        };
+        let otherwise = labels.default.as_ref().unwrap_or(&labels.end);
+
+        let mut sorted = cases.to_vec();
+        if ty.is_signed() {
+            sorted.sort();
+        } else {
+            sorted.sort_by_key(|&case| case as u64);
+        }
+        // A table needs an entry for every value from the smallest case to the largest
+        let entries = match (sorted.first(), sorted.last()) {
+            (Some(first), Some(last)) => (last.wrapping_sub(*first) as u64).saturating_add(1),
+            _ => 0,
+        };
+
+        let register = self.generate_assembly_from_ast(value)?;
+        if sorted.len() >= JUMP_TABLE_MIN_CASES && entries <= JUMP_TABLE_ENTRIES_PER_CASE * sorted.len() as u64 {
+            let first = sorted[0];
+            let table: Vec<&str> = (0..entries)
+                .map(|index| match labels.cases.get(&first.wrapping_add(index as i64)) {
+                    Some(label) => label.as_str(),
+                    None => otherwise.as_str(),
+                })
+                .collect();
+            self.jump_table(register, ty, first, &table, otherwise)?;
+        } else {
+            for case in &sorted {
+                self.jump_if_equal(&register, ty, *case, &labels.cases[case])?;
+            }
+            self.free_register(register);
+            self.jump(otherwise)?;
+        }
+
+        self.generate_statement(body, function, Some(&labels))?;
+        self.write_label(&labels.end)
+    }
+
     /// Method to write standard ARM64 macOS assembly headers
     fn write_assembly_headers(&mut self) -> IoResult<()>;
 
     /// Method to write exit syscall at the end of the program
     fn write_exit_syscall(&mut self) -> IoResult<()>;
+
+    /// Label the function and set up its stack frame with room for every local
+    fn write_function_prologue(&mut self, function: &Function) -> IoResult<()>;
+
+    /// Return from the function, `return` statements jump here
+    fn write_function_epilogue(&mut self, function: &Function) -> IoResult<()>;
+
+    /// Move the returned value into the return register and jump to the epilogue
+    fn write_return(&mut self, register: Option<RegisterList>, function: &Function) -> IoResult<()>;
 }
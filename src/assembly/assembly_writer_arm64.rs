@@ -1,11 +1,260 @@
-use crate::assembly::{AssemblyWriter, RegisterList, SupportedArchitectures, WriteAssembly};
-use crate::ast::ASTNode;
-use std::io::{BufWriter, Result as IoResult, Write};
+use crate::assembly::{escape_assembly_string, expression_type, AssemblyWriter, RegisterList, SupportedArchitectures, WriteAssembly};
+use crate::ast::statement::{ExternalDeclaration, Function, TranslationUnit};
+use crate::ast::{ASTNode, Storage, Symbol};
+use crate::scan::Token;
+use crate::typecheck::{check_expression, evaluate_constant, Constant};
+use crate::types::Type;
+use std::collections::HashSet;
+use std::io::{BufWriter, Error, ErrorKind, Result as IoResult, Write};
+use std::rc::Rc;
+
+/// The frame record (saved x29 and x30) sits at the bottom of every stack frame, locals go above it
+const FRAME_RECORD_SIZE: usize = 16;
+
+/// x0 to x7 pass arguments, and v0 to v7 the floating point ones
+const ARGUMENT_REGISTERS: usize = 8;
+
+/// A variadic function on Linux saves the argument registers x0 to x7 and then q0 to q7, where
+/// `va_arg` finds the variadic arguments passed in registers
+const GENERAL_SAVE_AREA_SIZE: usize = ARGUMENT_REGISTERS * 8;
+const FLOAT_SAVE_AREA_SIZE: usize = ARGUMENT_REGISTERS * 16;
+
+/// The members of the AAPCS64 `va_list`: where the next variadic argument on the stack is, the
+/// ends of the saved general and floating point registers, and how far before those ends the
+/// next unused ones are. The offsets are negative until the registers are used up.
+const VA_STACK: usize = 0;
+const VA_GR_TOP: usize = 8;
+const VA_VR_TOP: usize = 16;
+const VA_GR_OFFS: usize = 24;
+const VA_VR_OFFS: usize = 28;
+const VA_LIST_SIZE: usize = 32;
+
+/// The general registers values are worked out in, handed out in this order
+const REGISTERS: [RegisterList; 5] = [RegisterList::R0, RegisterList::R1, RegisterList::R2, RegisterList::R3, RegisterList::R4];
+
+/// The floating point registers values are worked out in, handed out in this order. d8 to d15
+/// are left alone, a function has to keep their low halves for its caller.
+const FLOAT_REGISTERS: [RegisterList; 24] = [
+    RegisterList::F0,
+    RegisterList::F1,
+    RegisterList::F2,
+    RegisterList::F3,
+    RegisterList::F4,
+    RegisterList::F5,
+    RegisterList::F6,
+    RegisterList::F7,
+    RegisterList::F16,
+    RegisterList::F17,
+    RegisterList::F18,
+    RegisterList::F19,
+    RegisterList::F20,
+    RegisterList::F21,
+    RegisterList::F22,
+    RegisterList::F23,
+    RegisterList::F24,
+    RegisterList::F25,
+    RegisterList::F26,
+    RegisterList::F27,
+    RegisterList::F28,
+    RegisterList::F29,
+    RegisterList::F30,
+    RegisterList::F31,
+];
+
+/// Part of the initial value of a variable with static storage
+enum StaticData {
+    /// A scalar of the type
+    Value(Type, Constant),
+    /// The characters of a string literal initialising a `char` array, as many as fit
+    Bytes(Vec<u8>),
+}
+
+/// The parts of the initial value of a variable with static storage, each with its offset in the object
+type StaticValue = Vec<(usize, StaticData)>;
+
+/// Add the values a checked static initialiser gives to `data`, `offset` is where the
+/// initialiser starts. The bytes between them, like padding or the elements a list leaves out, are zero.
+fn static_data(value: &ASTNode, offset: usize, data: &mut StaticValue) -> IoResult<()> {
+    let ty = expression_type(value)?;
+    match (&value.operation, &ty) {
+        (Token::InitializerList, Type::Array(element, _)) => {
+            for (index, element_value) in value.arguments().into_iter().enumerate() {
+                static_data(element_value, offset + index * element.size(), data)?;
+            }
+        }
+        (Token::InitializerList, Type::Record(record)) => {
+            for (member, member_value) in record.initialized_members().iter().zip(value.arguments()) {
+                static_data(member_value, offset + member.offset, data)?;
+            }
+        }
+        (Token::STRING(bytes), Type::Array(..)) => {
+            let mut bytes = bytes.clone();
+            bytes.push(0);
+            bytes.truncate(ty.size());
+            data.push((offset, StaticData::Bytes(bytes)));
+        }
+        _ => {
+            let constant = evaluate_constant(value)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Static initialiser is not a constant"))?;
+            data.push((offset, StaticData::Value(ty.clone(), constant)));
+        }
+    }
+    Ok(())
+}
+
+/// The homogeneous floating-point aggregate a type is, at most four members of one
+/// floating type. A `float` or `double` counts as one on its own.
+///
+/// returns: the member type and how many members there are
+fn floating_members(ty: &Type) -> Option<(Type, usize)> {
+    let members = match ty {
+        Type::Float | Type::Double => (ty.clone(), 1),
+        Type::Array(element, length) => {
+            let (base, count) = floating_members(element)?;
+            (base, count * length)
+        }
+        // Every member has to be made of the same floating type, then there is no padding
+        Type::Record(record) => {
+            let mut base: Option<Type> = None;
+            for member in record.members() {
+                let (member_base, _) = floating_members(&member.ty)?;
+                if *base.get_or_insert_with(|| member_base.clone()) != member_base {
+                    return None;
+                }
+            }
+            let base = base?;
+            if !ty.size().is_multiple_of(base.size()) {
+                return None;
+            }
+            let count = ty.size() / base.size();
+            (base, count)
+        }
+        _ => return None,
+    };
+    Some(members).filter(|(_, count)| (1..=4).contains(count))
+}
+
+/// Where the procedure call standard puts an argument, which is also where the called
+/// function finds its parameter
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ArgumentLocation {
+    /// In consecutive registers, the first one and how many, a struct or union can take two
+    Registers(usize, usize),
+    /// In consecutive floating point registers, the first one and how many, one for each
+    /// member of a homogeneous floating-point aggregate
+    FloatRegisters(usize, usize),
+    /// At an offset into the arguments at the bottom of the caller's frame
+    Stack(usize),
+}
+
+/// Structs and unions larger than 16 bytes are passed and returned through memory, by address,
+/// unless they are homogeneous floating-point aggregates
+fn is_indirect(ty: &Type) -> bool {
+    ty.is_record() && ty.size() > 16 && floating_members(ty).is_none()
+}
+
+/// Darwin does not look for homogeneous floating-point aggregates among variadic arguments,
+/// so every struct or union larger than 16 bytes goes by address
+fn is_indirect_variadic(ty: &Type) -> bool {
+    ty.is_record() && ty.size() > 16
+}
+
+/// Hands out the argument registers and the stack to arguments one after another, following
+/// AAPCS64 and, for Darwin, Apple's changes to it
+struct ArgumentClassifier {
+    architecture: SupportedArchitectures,
+    next_register: usize,
+    next_float_register: usize,
+    /// The size of the stack arguments so far
+    stack_size: usize,
+}
+
+impl ArgumentClassifier {
+    fn new(architecture: SupportedArchitectures) -> Self {
+        Self {
+            architecture,
+            next_register: 0,
+            next_float_register: 0,
+            stack_size: 0,
+        }
+    }
+
+    /// Assign the next argument its location. Floating values and homogeneous floating-point
+    /// aggregates go in floating point registers, one for each member. Other structs and
+    /// unions of up to 16 bytes go in as many general registers as they have 8 byte words.
+    /// Either goes on the stack when its registers do not fit. On the stack Darwin only gives
+    /// scalars their natural size and alignment, AAPCS64 gives every argument whole 8 byte
+    /// words. Darwin puts the arguments of a variadic function that match no parameter on the
+    /// stack, each in 8 byte words of its own, AAPCS64 treats them like the others.
+    ///
+    /// # Arguments
+    ///
+    /// * `ty`: the type of the argument
+    /// * `variadic`: whether it matches no parameter
+    ///
+    /// returns: ArgumentLocation
+    fn classify(&mut self, ty: &Type, variadic: bool) -> ArgumentLocation {
+        let darwin = self.architecture == SupportedArchitectures::ARM64;
+        if variadic && darwin {
+            let size = if is_indirect_variadic(ty) { 8 } else { ty.size().next_multiple_of(8) };
+            let offset = self.stack_size.next_multiple_of(8);
+            self.stack_size = offset + size;
+            return ArgumentLocation::Stack(offset);
+        }
+        let (size, alignment, registers) = match ty {
+            _ if is_indirect(ty) => (8, 8, 1),
+            Type::Record(_) => (ty.size().next_multiple_of(8), 8, ty.size().div_ceil(8)),
+            _ if darwin => (ty.size(), ty.alignment(), 1),
+            _ => (ty.size().next_multiple_of(8), 8, 1),
+        };
+        // Once something goes on the stack, so does everything after it that would use the same registers
+        if let Some((_, count)) = floating_members(ty) {
+            if self.next_float_register + count <= ARGUMENT_REGISTERS {
+                self.next_float_register += count;
+                return ArgumentLocation::FloatRegisters(self.next_float_register - count, count);
+            }
+            self.next_float_register = ARGUMENT_REGISTERS;
+        } else if self.next_register + registers <= ARGUMENT_REGISTERS {
+            self.next_register += registers;
+            return ArgumentLocation::Registers(self.next_register - registers, registers);
+        } else {
+            self.next_register = ARGUMENT_REGISTERS;
+        }
+
+        let offset = self.stack_size.next_multiple_of(alignment);
+        self.stack_size = offset + size;
+        ArgumentLocation::Stack(offset)
+    }
+}
+
+/// Assign each argument its location, see [`ArgumentClassifier::classify`]
+///
+/// # Arguments
+///
+/// * `types`: the type of each argument, in order
+/// * `fixed`: how many of them match a parameter, the rest are variadic
+/// * `architecture`: the target, whose procedure call standard places them
+///
+/// returns: the location of each argument and the size of the stack area, a multiple of 16
+fn classify_arguments(types: &[Type], fixed: usize, architecture: SupportedArchitectures) -> (Vec<ArgumentLocation>, usize) {
+    let mut classifier = ArgumentClassifier::new(architecture);
+    let locations = types.iter().enumerate().map(|(index, ty)| classifier.classify(ty, index >= fixed)).collect();
+    (locations, classifier.stack_size.next_multiple_of(16))
+}
 
 // ARM64-specific implementation
 pub struct ARM64Writer<W: Write> {
     writer: AssemblyWriter<W>,
     available_registers: Vec<RegisterList>, // Track available registers
+    available_float_registers: Vec<RegisterList>, // Floating point registers, tracked apart from the others
+    string_literals: Vec<Vec<u8>>, // Written to the read-only data section once the code is done
+    static_variables: Vec<(Rc<Symbol>, Option<StaticValue>)>, // Written to the data sections once the code is done
+    local_offsets: Vec<usize>, // Offset of each local of the current function from x29, by slot
+    frame_size: usize, // Bytes the current function reserves on the stack, a multiple of 16
+    result_address_offset: Option<usize>, // Where x8 is kept when the current function returns a struct through memory
+    variadic_offset: Option<usize>, // What va_start sets a va_list to in the current function, from x29
+    label_count: usize, // Labels made so far, each one gets a new number
+    defined_labels: HashSet<String>, // Functions and globals defined in the translation unit, the others are in other files
 }
 
 impl<W: Write> ARM64Writer<W> {
@@ -15,15 +264,381 @@ impl<W: Write> ARM64Writer<W> {
                 file: BufWriter::new(writer),
                 architecture: SupportedArchitectures::ARM64,
             },
-            available_registers: vec![
-                RegisterList::R4,
-                RegisterList::R3,
-                RegisterList::R2,
-                RegisterList::R1,
-                RegisterList::R0,
-            ],
+            available_registers: REGISTERS.into_iter().rev().collect(),
+            available_float_registers: FLOAT_REGISTERS.into_iter().rev().collect(),
+            string_literals: Vec::new(),
+            static_variables: Vec::new(),
+            local_offsets: Vec::new(),
+            frame_size: FRAME_RECORD_SIZE,
+            result_address_offset: None,
+            variadic_offset: None,
+            label_count: 0,
+            defined_labels: HashSet::new(),
+        }
+    }
+
+    fn is_linux(&self) -> bool {
+        self.writer.architecture == SupportedArchitectures::ARM64Linux
+    }
+
+    fn string_label(&self, index: usize) -> String {
+        format!("{}_.str.{}", self.writer.architecture.local_label_prefix(), index)
+    }
+
+    fn return_label(&self, function: &Function) -> String {
+        format!("{}_return_{}", self.writer.architecture.local_label_prefix(), function.name)
+    }
+
+    /// The assembly name of a C function or variable, with an underscore in front on Darwin
+    fn symbol_name(&self, name: &str) -> String {
+        format!("{}{}", self.writer.architecture.symbol_prefix(), name)
+    }
+
+    /// The assembly label of a symbol with static storage
+    fn static_label(&self, symbol: &Symbol) -> IoResult<String> {
+        match &symbol.storage {
+            Storage::Static { label, .. } => Ok(self.symbol_name(label)),
+            Storage::Automatic => Err(Error::new(ErrorKind::InvalidInput, "Local variable has no label")),
+        }
+    }
+
+    /// The operands that reach `label`: the one `adrp` takes for its 4KB page, and the one the
+    /// next instruction takes for the offset into the page. With `got` they reach its entry
+    /// in the global offset table instead.
+    fn page_operands(&self, label: &str, got: bool) -> (String, String) {
+        match (self.is_linux(), got) {
+            (false, false) => (format!("{}@PAGE", label), format!("{}@PAGEOFF", label)),
+            (false, true) => (format!("{}@GOTPAGE", label), format!("{}@GOTPAGEOFF", label)),
+            (true, false) => (label.to_string(), format!(":lo12:{}", label)),
+            (true, true) => (format!(":got:{}", label), format!(":got_lo12:{}", label)),
         }
     }
+
+    /// The register as wide as `ty`: `w` registers for 32 bits and less, `x` registers otherwise.
+    /// Values narrower than 32 bits are kept sign or zero extended to 32 bits.
+    /// Floating point registers are `s` registers for a `float` and `d` registers for a `double`.
+    fn sized_register(&self, register: &RegisterList, ty: &Type) -> String {
+        let name = self.format_register(register);
+        match (register.is_floating(), ty.size() <= 4) {
+            (false, true) => name.replacen('x', "w", 1),
+            (true, true) => name.replacen('d', "s", 1),
+            (_, false) => name,
+        }
+    }
+
+    /// Compare the register `name` with `value`, which goes in the register `scratch` first
+    /// when it does not fit in an immediate. `scratch` is as wide as `name`.
+    fn write_compare_immediate(&mut self, name: &str, value: i64, scratch: &str) -> IoResult<()> {
+        match value {
+            0..=4095 => writeln!(self.writer.file, "\tcmp {}, #{}", name, value),
+            -4095..=-1 => writeln!(self.writer.file, "\tcmn {}, #{}", name, -value),
+            _ => {
+                self.write_move_immediate(scratch, value, if scratch.starts_with('w') { 2 } else { 4 })?;
+                writeln!(self.writer.file, "\tcmp {}, {}", name, scratch)
+            }
+        }
+    }
+
+    /// Build `value` in the register `name` with `mov` and `movk`, taking `chunks` 16-bit chunks of it
+    fn write_move_immediate(&mut self, name: &str, value: i64, chunks: usize) -> IoResult<()> {
+        // A single mov covers anything movz/movn can build from one 16-bit chunk
+        if (-0x10000..=0xffff).contains(&value) {
+            return writeln!(self.writer.file, "\tmov {0}, #{1}\t// {0}={1}", name, value);
+        }
+
+        // Otherwise build the value 16 bits at a time
+        let bits = value as u64;
+        writeln!(self.writer.file, "\tmov {0}, #{1}\t// {0}={2}", name, bits & 0xffff, value)?;
+        for shift in [16, 32, 48].into_iter().take(chunks - 1) {
+            let chunk = (bits >> shift) & 0xffff;
+            if chunk != 0 {
+                writeln!(self.writer.file, "\tmovk {}, #{}, lsl #{}", name, chunk, shift)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a symbol is defined in another file, the linker fills in its address in the
+    /// global offset table
+    fn is_in_other_file(&self, symbol: &Symbol) -> bool {
+        matches!(&symbol.storage, Storage::Static { label, external: true } if !self.defined_labels.contains(label))
+    }
+
+    /// The memory operand for a variable. A local is in the frame, one with static storage
+    /// is reached through its page, whose address goes in x16, or through the global offset
+    /// table if it is in another file.
+    fn variable_operand(&mut self, symbol: &Symbol) -> IoResult<String> {
+        if self.is_in_other_file(symbol) {
+            let (page, offset) = self.page_operands(&self.static_label(symbol)?, true);
+            writeln!(self.writer.file, "\tadrp x16, {}", page)?;
+            writeln!(self.writer.file, "\tldr x16, [x16, {}]", offset)?;
+            return Ok("[x16]".to_string());
+        }
+        if symbol.is_static() {
+            let (page, offset) = self.page_operands(&self.static_label(symbol)?, false);
+            writeln!(self.writer.file, "\tadrp x16, {}", page)?;
+            return Ok(format!("[x16, {}]", offset));
+        }
+        self.memory_operand("x29", self.local_offsets[symbol.slot], symbol.ty.size())
+    }
+
+    /// The memory operand for `size` bytes at `offset` from `base`. Offsets too large for
+    /// a scaled immediate have their address computed into x16 first.
+    fn memory_operand(&mut self, base: &str, offset: usize, size: usize) -> IoResult<String> {
+        let size = size.max(1);
+        if offset.is_multiple_of(size) && offset / size <= 4095 {
+            return Ok(format!("[{}, #{}]", base, offset));
+        }
+
+        self.write_immediate_operation("add", "x16", base, offset)?;
+        Ok("[x16]".to_string())
+    }
+
+    /// Copy `size` bytes from the address in `source` to the address in `destination`,
+    /// 8 bytes at a time and then what is left. Uses x10 to x13, which cannot be either address.
+    fn write_copy(&mut self, destination: &str, source: &str, size: usize) -> IoResult<()> {
+        writeln!(self.writer.file, "\tmov x10, {}", source)?;
+        writeln!(self.writer.file, "\tmov x11, {}", destination)?;
+        let words = size / 8;
+        if words > 4 {
+            // Long copies loop rather than growing the code
+            writeln!(self.writer.file, "\tmov x12, #{}", words & 0xffff)?;
+            if words > 0xffff {
+                writeln!(self.writer.file, "\tmovk x12, #{}, lsl #16", (words >> 16) & 0xffff)?;
+            }
+            writeln!(self.writer.file, "1:\tldr x13, [x10], #8")?;
+            writeln!(self.writer.file, "\tstr x13, [x11], #8")?;
+            writeln!(self.writer.file, "\tsubs x12, x12, #1")?;
+            writeln!(self.writer.file, "\tb.ne 1b")?;
+        } else {
+            for _ in 0..words {
+                writeln!(self.writer.file, "\tldr x13, [x10], #8")?;
+                writeln!(self.writer.file, "\tstr x13, [x11], #8")?;
+            }
+        }
+        let mut left = size % 8;
+        for (chunk, load, store) in [(4, "ldr", "str"), (2, "ldrh", "strh"), (1, "ldrb", "strb")] {
+            if left >= chunk {
+                writeln!(self.writer.file, "\t{} w13, [x10], #{}", load, chunk)?;
+                writeln!(self.writer.file, "\t{} w13, [x11], #{}", store, chunk)?;
+                left -= chunk;
+            }
+        }
+        Ok(())
+    }
+
+    /// `add`/`sub` with an immediate of up to 24 bits, split into a shifted and an unshifted part
+    fn write_immediate_operation(&mut self, operation: &str, destination: &str, source: &str, value: usize) -> IoResult<()> {
+        let (high, low) = (value >> 12, value & 0xfff);
+        if high > 0 {
+            writeln!(self.writer.file, "\t{} {}, {}, #{}, lsl #12", operation, destination, source, high)?;
+        }
+        if low > 0 || high == 0 {
+            let source = if high > 0 { destination } else { source };
+            writeln!(self.writer.file, "\t{} {}, {}, #{}", operation, destination, source, low)?;
+        }
+        Ok(())
+    }
+
+    /// The load that extends a value of type `ty` the way [`Self::sized_register`] expects
+    fn load_instruction(ty: &Type) -> &'static str {
+        match ty {
+            Type::Char | Type::SignedChar => "ldrsb",
+            Type::UnsignedChar => "ldrb",
+            Type::Short => "ldrsh",
+            Type::UnsignedShort => "ldrh",
+            _ => "ldr",
+        }
+    }
+
+    fn store_instruction(ty: &Type) -> &'static str {
+        match ty.size() {
+            1 => "strb",
+            2 => "strh",
+            _ => "str",
+        }
+    }
+
+    fn write_binary_operation(&mut self, instruction: &str, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList> {
+        let result_reg = self.result_register(&reg_1)?;
+        writeln!(
+            self.writer.file,
+            "    {} {}, {}, {}",
+            instruction,
+            self.sized_register(&result_reg, ty),
+            self.sized_register(&reg_1, ty),
+            self.sized_register(&reg_2, ty)
+        )?;
+        if result_reg != reg_1 {
+            self.free_register(reg_1);
+        }
+        self.free_register(reg_2);
+        Ok(result_reg)
+    }
+
+    /// Where an operation on `operand` puts its result: a new register like it, or `operand`
+    /// itself when every such register is in use
+    fn result_register(&mut self, operand: &RegisterList) -> IoResult<RegisterList> {
+        if self.free_register_count(operand.is_floating()) == 0 {
+            return Ok(operand.clone());
+        }
+        if operand.is_floating() { self.allocate_float_register() } else { self.allocate_register() }
+    }
+
+    /// Convert between a floating type and another floating or integer type. The value moves
+    /// to a register of the other kind when only one of the types is floating.
+    fn write_float_conversion(&mut self, register: RegisterList, from: &Type, to: &Type) -> IoResult<RegisterList> {
+        let converted = match (from.is_floating(), to.is_floating()) {
+            (true, true) if from == to => return Ok(register),
+            (true, true) => {
+                let converted = self.allocate_float_register()?;
+                writeln!(
+                    self.writer.file,
+                    "\tfcvt {}, {}",
+                    self.sized_register(&converted, to),
+                    self.sized_register(&register, from)
+                )?;
+                converted
+            }
+            (false, _) => {
+                // Integers narrower than 32 bits are extended to 32 bits already
+                let converted = self.allocate_float_register()?;
+                writeln!(
+                    self.writer.file,
+                    "\t{} {}, {}",
+                    if from.is_signed() { "scvtf" } else { "ucvtf" },
+                    self.sized_register(&converted, to),
+                    self.sized_register(&register, from)
+                )?;
+                converted
+            }
+            (true, false) => {
+                // The fraction is dropped, then a narrower integer is extended from its low bits
+                let converted = self.allocate_register()?;
+                let wide = if to.size() < 4 { &Type::Int } else { to };
+                writeln!(
+                    self.writer.file,
+                    "\t{} {}, {}",
+                    if to.is_signed() { "fcvtzs" } else { "fcvtzu" },
+                    self.sized_register(&converted, wide),
+                    self.sized_register(&register, from)
+                )?;
+                self.free_register(register);
+                return self.cast_register(converted, wide, to);
+            }
+        };
+        self.free_register(register);
+        Ok(converted)
+    }
+
+    /// Compare two floating values of type `ty`, the result is an int in a general register.
+    /// The conditions are the ones that fail when either value is a NaN, except for `!=`.
+    fn write_float_comparison(&mut self, reg_1: RegisterList, reg_2: RegisterList, comparison: &Token, ty: &Type) -> IoResult<RegisterList> {
+        let condition = match comparison {
+            Token::EQ => "eq",
+            Token::NE => "ne",
+            Token::LT => "mi",
+            Token::GT => "gt",
+            Token::LE => "ls",
+            Token::GE => "ge",
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Not a comparison")),
+        };
+        writeln!(
+            self.writer.file,
+            "    fcmp {}, {}",
+            self.sized_register(&reg_1, ty),
+            self.sized_register(&reg_2, ty)
+        )?;
+        self.free_register(reg_1);
+        self.free_register(reg_2);
+        let result = self.allocate_register()?;
+        writeln!(self.writer.file, "    cset {}, {}", self.sized_register(&result, &Type::Int), condition)?;
+        Ok(result)
+    }
+
+    /// Store every parameter in its local, from the register or the caller's stack it arrives in.
+    /// A struct or union passed by address is copied, so the function has its own.
+    fn write_parameters(&mut self, function: &Function) -> IoResult<()> {
+        let types: Vec<Type> = function.parameters.iter().map(|parameter| parameter.ty.clone()).collect();
+        let (locations, _) = classify_arguments(&types, types.len(), self.writer.architecture);
+        for (parameter, location) in function.parameters.iter().zip(locations) {
+            let ty = &parameter.ty;
+            let offset = self.local_offsets[parameter.slot];
+            // Arguments on the stack are right above this function's frame
+            let source = match location {
+                ArgumentLocation::Registers(first, _) if is_indirect(ty) => format!("x{}", first),
+                ArgumentLocation::Registers(first, count) if ty.is_record() => {
+                    for word in 0..count {
+                        let operand = self.memory_operand("x29", offset + word * 8, 8)?;
+                        writeln!(self.writer.file, "\tstr x{}, {}\t// {}", first + word, operand, parameter.name)?;
+                    }
+                    continue;
+                }
+                ArgumentLocation::Registers(first, _) => {
+                    let register = if ty.size() <= 4 { format!("w{}", first) } else { format!("x{}", first) };
+                    let operand = self.variable_operand(parameter)?;
+                    writeln!(self.writer.file, "\t{} {}, {}\t// {}", Self::store_instruction(ty), register, operand, parameter.name)?;
+                    continue;
+                }
+                ArgumentLocation::FloatRegisters(first, count) => {
+                    let (base, _) = floating_members(ty).expect("Not a floating type");
+                    let prefix = if base.size() == 4 { "s" } else { "d" };
+                    for member in 0..count {
+                        let operand = self.memory_operand("x29", offset + member * base.size(), base.size())?;
+                        writeln!(self.writer.file, "\tstr {}{}, {}\t// {}", prefix, first + member, operand, parameter.name)?;
+                    }
+                    continue;
+                }
+                ArgumentLocation::Stack(stack_offset) if is_indirect(ty) => {
+                    let operand = self.memory_operand("x29", self.frame_size + stack_offset, 8)?;
+                    writeln!(self.writer.file, "\tldr x9, {}", operand)?;
+                    "x9".to_string()
+                }
+                ArgumentLocation::Stack(stack_offset) if ty.is_record() => {
+                    self.write_immediate_operation("add", "x9", "x29", self.frame_size + stack_offset)?;
+                    "x9".to_string()
+                }
+                ArgumentLocation::Stack(stack_offset) => {
+                    let register = if ty.size() <= 4 { "w9" } else { "x9" };
+                    let operand = self.memory_operand("x29", self.frame_size + stack_offset, ty.size())?;
+                    writeln!(self.writer.file, "\t{} {}, {}", Self::load_instruction(ty), register, operand)?;
+                    let operand = self.variable_operand(parameter)?;
+                    writeln!(self.writer.file, "\t{} {}, {}\t// {}", Self::store_instruction(ty), register, operand, parameter.name)?;
+                    continue;
+                }
+            };
+            self.write_immediate_operation("add", "x14", "x29", offset)?;
+            self.write_copy("x14", &source, ty.size())?;
+        }
+        Ok(())
+    }
+
+    /// Save the argument registers of a variadic function on Linux at `save_area`, x0 to x7 and
+    /// then q0 to q7, and set up the `va_list` after them that `va_start` copies. The variadic
+    /// arguments start after the registers and the stack `classifier` gave the parameters.
+    fn write_register_save_area(&mut self, save_area: usize, classifier: &ArgumentClassifier) -> IoResult<()> {
+        self.write_immediate_operation("add", "x9", "x29", save_area)?;
+        for pair in 0..ARGUMENT_REGISTERS / 2 {
+            writeln!(self.writer.file, "\tstp x{}, x{}, [x9, #{}]", 2 * pair, 2 * pair + 1, 16 * pair)?;
+        }
+        for pair in 0..ARGUMENT_REGISTERS / 2 {
+            writeln!(self.writer.file, "\tstp q{}, q{}, [x9, #{}]", 2 * pair, 2 * pair + 1, GENERAL_SAVE_AREA_SIZE + 32 * pair)?;
+        }
+
+        let va_list = GENERAL_SAVE_AREA_SIZE + FLOAT_SAVE_AREA_SIZE;
+        self.write_immediate_operation("add", "x10", "x29", self.frame_size + classifier.stack_size.next_multiple_of(8))?;
+        writeln!(self.writer.file, "\tstr x10, [x9, #{}]\t// __stack", va_list + VA_STACK)?;
+        writeln!(self.writer.file, "\tadd x10, x9, #{}", GENERAL_SAVE_AREA_SIZE)?;
+        writeln!(self.writer.file, "\tstr x10, [x9, #{}]\t// __gr_top", va_list + VA_GR_TOP)?;
+        writeln!(self.writer.file, "\tadd x10, x9, #{}", va_list)?;
+        writeln!(self.writer.file, "\tstr x10, [x9, #{}]\t// __vr_top", va_list + VA_VR_TOP)?;
+        let unused = (ARGUMENT_REGISTERS - classifier.next_register) * 8;
+        self.write_move_immediate("w10", -(unused as i64), 2)?;
+        writeln!(self.writer.file, "\tstr w10, [x9, #{}]\t// __gr_offs", va_list + VA_GR_OFFS)?;
+        let unused = (ARGUMENT_REGISTERS - classifier.next_float_register) * 16;
+        self.write_move_immediate("w10", -(unused as i64), 2)?;
+        writeln!(self.writer.file, "\tstr w10, [x9, #{}]\t// __vr_offs", va_list + VA_VR_OFFS)
+    }
 }
 
 
@@ -36,110 +651,716 @@ impl<W: std::io::Write> WriteAssembly for ARM64Writer<W> {
             RegisterList::R2 => "x2",
             RegisterList::R3 => "x3",
             RegisterList::R4 => "x4",
+            RegisterList::F0 => "d0",
+            RegisterList::F1 => "d1",
+            RegisterList::F2 => "d2",
+            RegisterList::F3 => "d3",
+            RegisterList::F4 => "d4",
+            RegisterList::F5 => "d5",
+            RegisterList::F6 => "d6",
+            RegisterList::F7 => "d7",
+            RegisterList::F16 => "d16",
+            RegisterList::F17 => "d17",
+            RegisterList::F18 => "d18",
+            RegisterList::F19 => "d19",
+            RegisterList::F20 => "d20",
+            RegisterList::F21 => "d21",
+            RegisterList::F22 => "d22",
+            RegisterList::F23 => "d23",
+            RegisterList::F24 => "d24",
+            RegisterList::F25 => "d25",
+            RegisterList::F26 => "d26",
+            RegisterList::F27 => "d27",
+            RegisterList::F28 => "d28",
+            RegisterList::F29 => "d29",
+            RegisterList::F30 => "d30",
+            RegisterList::F31 => "d31",
         }.to_string()
     }
-    fn allocate_register(&mut self) -> RegisterList {
+    fn allocate_register(&mut self) -> IoResult<RegisterList> {
         self.available_registers
             .pop()
-            .expect("No available registers")
+            .ok_or_else(|| Error::new(ErrorKind::OutOfMemory, "No available registers"))
+    }
+
+    fn allocate_float_register(&mut self) -> IoResult<RegisterList> {
+        self.available_float_registers
+            .pop()
+            .ok_or_else(|| Error::new(ErrorKind::OutOfMemory, "No available floating point registers"))
     }
 
     fn free_register(&mut self, register: RegisterList) {
-        self.available_registers.push(register);
+        if register.is_floating() {
+            self.available_float_registers.push(register);
+        } else {
+            self.available_registers.push(register);
+        }
     }
 
     fn free_all_registers(&mut self) {
-        self.available_registers = vec![
-            RegisterList::R0,
-            RegisterList::R1,
-            RegisterList::R2,
-            RegisterList::R3,
-            RegisterList::R4,
-        ];
+        // Same order as in new(), so x0 is handed out first again
+        self.available_registers = REGISTERS.into_iter().rev().collect();
+        self.available_float_registers = FLOAT_REGISTERS.into_iter().rev().collect();
     }
 
-    fn load_register(&mut self, value: i32) -> IoResult<RegisterList> {
-        let register = self.allocate_register();
-        writeln!(
-            self.writer.file,
-            "\tmov {0}, #{1}\t// {0}={1}",
-            self.format_register(&register),
-            value
-        )?;
+    fn free_register_count(&self, floating: bool) -> usize {
+        if floating {
+            self.available_float_registers.len()
+        } else {
+            self.available_registers.len()
+        }
+    }
+
+    // The whole register goes on the stack in 16 bytes of its own, like around calls
+    fn spill_register(&mut self, register: RegisterList) -> IoResult<()> {
+        writeln!(self.writer.file, "\tstr {}, [sp, #-16]!", self.format_register(&register))?;
+        self.free_register(register);
+        Ok(())
+    }
+
+    fn reload_register(&mut self, floating: bool) -> IoResult<RegisterList> {
+        let register = if floating { self.allocate_float_register()? } else { self.allocate_register()? };
+        writeln!(self.writer.file, "\tldr {}, [sp], #16", self.format_register(&register))?;
         Ok(register)
     }
 
-    fn print_register(&mut self, register: RegisterList) -> IoResult<RegisterList> {
-        // ARM64-specific print implementation
-        writeln!(self.writer.file, "    // Print register value")?;
-        writeln!(self.writer.file, "    mov x0, #1           // stdout")?;
-        writeln!(self.writer.file, "    mov x16, #4          // write syscall")?;
-        writeln!(self.writer.file, "    svc #0x80")?;
+    fn load_register(&mut self, value: i64, ty: &Type) -> IoResult<RegisterList> {
+        let register = self.allocate_register()?;
+        let name = self.sized_register(&register, ty);
+        // A 32-bit register only holds the low half, which is what C's conversion to the type keeps
+        let (value, chunks) = if ty.size() <= 4 { (value as i32 as i64, 2) } else { (value, 4) };
+        self.write_move_immediate(&name, value, chunks)?;
+        Ok(register)
+    }
+
+    fn load_float(&mut self, value: f64, ty: &Type) -> IoResult<RegisterList> {
+        let register = self.allocate_float_register()?;
+        let name = self.sized_register(&register, ty);
+        // The bits are built in a general register and moved across
+        let (bits, general) = match ty {
+            Type::Float => ((value as f32).to_bits() as i32 as i64, "w9"),
+            _ => (value.to_bits() as i64, "x9"),
+        };
+        if bits == 0 {
+            writeln!(self.writer.file, "\tfmov {}, {}zr\t// {}={}", name, &general[..1], name, value)?;
+            return Ok(register);
+        }
+        self.write_move_immediate(general, bits, if *ty == Type::Float { 2 } else { 4 })?;
+        writeln!(self.writer.file, "\tfmov {}, {}\t// {}={}", name, general, name, value)?;
         Ok(register)
     }
 
-    fn add_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList) -> IoResult<RegisterList> {
-        let result_reg = self.allocate_register();
+    fn load_string_address(&mut self, bytes: &[u8]) -> IoResult<RegisterList> {
+        let register = self.allocate_register()?;
+        let name = self.format_register(&register);
+        let (page, offset) = self.page_operands(&self.string_label(self.string_literals.len()), false);
+        self.string_literals.push(bytes.to_vec());
+
+        writeln!(self.writer.file, "\tadrp {}, {}", name, page)?;
+        writeln!(self.writer.file, "\tadd {0}, {0}, {1}", name, offset)?;
+        Ok(register)
+    }
+
+    fn load_variable(&mut self, symbol: &Symbol) -> IoResult<RegisterList> {
+        if symbol.ty.is_record() {
+            return self.load_variable_address(symbol);
+        }
+        let address = self.variable_operand(symbol)?;
+        let register = self.allocate_register_for(&symbol.ty)?;
         writeln!(
             self.writer.file,
-            "    add {}, {}, {}",
-            self.format_register(&result_reg),
-            self.format_register(&reg_1),
-            self.format_register(&reg_2)
+            "\t{} {}, {}\t// {}",
+            Self::load_instruction(&symbol.ty),
+            self.sized_register(&register, &symbol.ty),
+            address,
+            symbol.name
         )?;
-        self.free_register(reg_1);
-        self.free_register(reg_2);
-        Ok(result_reg)
+        Ok(register)
     }
 
-    fn subtract_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList) -> IoResult<RegisterList> {
-        let result_reg = self.allocate_register();
+    fn store_variable(&mut self, register: RegisterList, symbol: &Symbol) -> IoResult<RegisterList> {
+        if symbol.ty.is_record() {
+            let address = self.load_variable_address(symbol)?;
+            return self.store_through(register, address, &symbol.ty);
+        }
+        let address = self.variable_operand(symbol)?;
         writeln!(
             self.writer.file,
-            "    sub {}, {}, {}",
-            self.format_register(&result_reg),
-            self.format_register(&reg_1),
-            self.format_register(&reg_2)
+            "\t{} {}, {}\t// {}",
+            Self::store_instruction(&symbol.ty),
+            self.sized_register(&register, &symbol.ty),
+            address,
+            symbol.name
         )?;
-        self.free_register(reg_1);
-        self.free_register(reg_2);
-        Ok(result_reg)
+        Ok(register)
     }
 
-    fn multiply_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList) -> IoResult<RegisterList> {
-        let result_reg = self.allocate_register();
+    fn load_variable_address(&mut self, symbol: &Symbol) -> IoResult<RegisterList> {
+        let register = self.allocate_register()?;
+        let name = self.format_register(&register);
+        if self.is_in_other_file(symbol) {
+            let (page, offset) = self.page_operands(&self.static_label(symbol)?, true);
+            writeln!(self.writer.file, "\tadrp {}, {}", name, page)?;
+            writeln!(self.writer.file, "\tldr {0}, [{0}, {1}]", name, offset)?;
+            return Ok(register);
+        }
+        if symbol.is_static() {
+            let (page, offset) = self.page_operands(&self.static_label(symbol)?, false);
+            writeln!(self.writer.file, "\tadrp {}, {}", name, page)?;
+            writeln!(self.writer.file, "\tadd {0}, {0}, {1}", name, offset)?;
+            return Ok(register);
+        }
+        let offset = self.local_offsets[symbol.slot];
+        self.write_immediate_operation("add", &name, "x29", offset)?;
+        Ok(register)
+    }
+
+    fn variadic_arguments(&mut self) -> IoResult<RegisterList> {
+        let offset = self
+            .variadic_offset
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Variadic arguments outside of a variadic function"))?;
+        let register = self.allocate_register()?;
+        self.write_immediate_operation("add", &self.format_register(&register), "x29", offset)?;
+        Ok(register)
+    }
+
+    // The argument is in the register save area while the offset for its kind of register is
+    // still negative after moving past it, otherwise it is at __stack. x9 ends up with its address.
+    fn variadic_argument(&mut self, node: &ASTNode) -> IoResult<RegisterList> {
+        if !self.is_linux() {
+            return Err(Error::new(ErrorKind::InvalidInput, "va_arg of a va_list that is not a struct"));
+        }
+        let ty = expression_type(node)?.clone();
+        let list = self.generate_address(node.left.as_deref().expect("Missing operand"))?;
+        let result = match &node.symbol {
+            Some(temporary) => self.load_variable_address(temporary)?,
+            None => self.allocate_register_for(&ty)?,
+        };
+        let (list_name, result_name) = (self.format_register(&list), self.format_register(&result));
+
+        let indirect = is_indirect(&ty);
+        let floating = floating_members(&ty).filter(|_| !indirect);
+        let (offsets, top, used) = match &floating {
+            Some((_, count)) => (VA_VR_OFFS, VA_VR_TOP, count * 16),
+            None if indirect => (VA_GR_OFFS, VA_GR_TOP, 8),
+            None => (VA_GR_OFFS, VA_GR_TOP, ty.size().div_ceil(8) * 8),
+        };
+        // A homogeneous floating-point aggregate has each member in a 16 byte register of its own,
+        // they are gathered into the temporary
+        let gathered = floating.as_ref().filter(|_| ty.is_record());
+        let (stack_label, join_label) = (self.new_label(), self.new_label());
+        writeln!(self.writer.file, "\tldrsw x9, [{}, #{}]", list_name, offsets)?;
+        writeln!(self.writer.file, "\ttbz w9, #31, {}", stack_label)?;
+        writeln!(self.writer.file, "\tadd w10, w9, #{}", used)?;
+        writeln!(self.writer.file, "\tstr w10, [{}, #{}]", list_name, offsets)?;
+        writeln!(self.writer.file, "\tcmp w10, #0")?;
+        writeln!(self.writer.file, "\tb.gt {}", stack_label)?;
+        writeln!(self.writer.file, "\tldr x10, [{}, #{}]", list_name, top)?;
+        writeln!(self.writer.file, "\tadd x9, x10, x9")?;
+        if let Some((base, count)) = gathered {
+            let register = if base.size() == 4 { "w10" } else { "x10" };
+            for member in 0..*count {
+                writeln!(self.writer.file, "\tldr {}, [x9, #{}]", register, member * 16)?;
+                writeln!(self.writer.file, "\tstr {}, [{}, #{}]", register, result_name, member * base.size())?;
+            }
+        }
+        writeln!(self.writer.file, "\tb {}", join_label)?;
+
+        self.write_label(&stack_label)?;
+        writeln!(self.writer.file, "\tldr x9, [{}, #{}]", list_name, VA_STACK)?;
+        let size = if indirect { 8 } else { ty.size().next_multiple_of(8) };
+        writeln!(self.writer.file, "\tadd x10, x9, #{}", size)?;
+        writeln!(self.writer.file, "\tstr x10, [{}, #{}]", list_name, VA_STACK)?;
+
+        if gathered.is_some() {
+            // On the stack the members are together already
+            self.write_copy(&result_name, "x9", ty.size())?;
+            self.write_label(&join_label)?;
+        } else {
+            self.write_label(&join_label)?;
+            if indirect {
+                writeln!(self.writer.file, "\tldr x9, [x9]")?;
+            }
+            if ty.is_record() {
+                self.write_copy(&result_name, "x9", ty.size())?;
+            } else {
+                writeln!(self.writer.file, "\t{} {}, [x9]", Self::load_instruction(&ty), self.sized_register(&result, &ty))?;
+            }
+        }
+        self.free_register(list);
+        Ok(result)
+    }
+
+    fn define_static_variable(&mut self, symbol: &Rc<Symbol>, initializer: Option<&ASTNode>) -> IoResult<()> {
+        let data = match initializer {
+            Some(initializer) => {
+                let mut data = Vec::new();
+                static_data(initializer, 0, &mut data)?;
+                Some(data)
+            }
+            None => None,
+        };
+        self.static_variables.push((symbol.clone(), data));
+        Ok(())
+    }
+
+    fn load_through(&mut self, address: RegisterList, ty: &Type) -> IoResult<RegisterList> {
+        if ty.is_record() {
+            return Ok(address);
+        }
+        let register = self.allocate_register_for(ty)?;
         writeln!(
             self.writer.file,
-            "    mul {}, {}, {}",
-            self.format_register(&result_reg),
-            self.format_register(&reg_1),
-            self.format_register(&reg_2)
+            "\t{} {}, [{}]",
+            Self::load_instruction(ty),
+            self.sized_register(&register, ty),
+            self.format_register(&address)
         )?;
-        self.free_register(reg_1);
+        self.free_register(address);
+        Ok(register)
+    }
+
+    fn store_through(&mut self, value: RegisterList, address: RegisterList, ty: &Type) -> IoResult<RegisterList> {
+        if ty.is_record() {
+            let (destination, source) = (self.format_register(&address), self.format_register(&value));
+            self.write_copy(&destination, &source, ty.size())?;
+            self.free_register(address);
+            return Ok(value);
+        }
+        writeln!(
+            self.writer.file,
+            "\t{} {}, [{}]",
+            Self::store_instruction(ty),
+            self.sized_register(&value, ty),
+            self.format_register(&address)
+        )?;
+        self.free_register(address);
+        Ok(value)
+    }
+
+    fn generate_call(&mut self, node: &ASTNode) -> IoResult<RegisterList> {
+        let function = node.left.as_deref().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Call without a function"))?;
+        // A function is called by name, anything else is a pointer to the function
+        let (name, function_type) = match (&function.operation, expression_type(function)?) {
+            (Token::IDENTIFIER(name), ty @ Type::Function { .. }) => (Some(name), ty),
+            (_, Type::Pointer(pointee)) if matches!(**pointee, Type::Function { .. }) => (None, &**pointee),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Only functions can be called")),
+        };
+        let return_type = expression_type(node)?.clone();
+        let arguments = node.arguments();
+        // Arguments past the parameters of a variadic function are passed differently
+        let fixed = match function_type {
+            Type::Function { parameters, variadic: true, .. } => parameters.len(),
+            _ => arguments.len(),
+        };
+
+        // The called function can change every scratch register, the ones in use are saved
+        let available = self.available_registers.clone();
+        let available_float = self.available_float_registers.clone();
+        let live: Vec<RegisterList> = REGISTERS
+            .into_iter()
+            .filter(|register| !available.contains(register))
+            .chain(FLOAT_REGISTERS.into_iter().filter(|register| !available_float.contains(register)))
+            .collect();
+        for register in &live {
+            writeln!(self.writer.file, "\tstr {}, [sp, #-16]!", self.format_register(register))?;
+        }
+        self.free_all_registers();
+
+        // A pointer to the function is evaluated first and kept on the stack like the arguments
+        let mut kept_size = 0;
+        if name.is_none() {
+            let register = self.generate_assembly_from_ast(function)?;
+            writeln!(self.writer.file, "\tstr {}, [sp, #-16]!", self.format_register(&register))?;
+            self.free_register(register);
+            kept_size += 16;
+        }
+
+        // Each argument is kept on the stack as soon as it is evaluated, so evaluating the
+        // next one cannot change it. A struct or union is copied there.
+        let mut types = Vec::new();
+        let mut ends = Vec::new();
+        for argument in &arguments {
+            let ty = expression_type(argument)?.clone();
+            let register = self.generate_assembly_from_ast(argument)?;
+            let name = self.format_register(&register);
+            let size = if ty.is_record() { ty.size().next_multiple_of(16).max(16) } else { 16 };
+            self.write_immediate_operation("sub", "sp", "sp", size)?;
+            if ty.is_record() {
+                self.write_copy("sp", &name, ty.size())?;
+            } else {
+                writeln!(self.writer.file, "\tstr {}, [sp]", name)?;
+            }
+            self.free_register(register);
+            kept_size += size;
+            ends.push(kept_size);
+            types.push(ty);
+        }
+
+        // Then they move to where the called function expects them, below the ones kept
+        let (locations, stack_size) = classify_arguments(&types, fixed, self.writer.architecture);
+        if stack_size > 0 {
+            self.write_immediate_operation("sub", "sp", "sp", stack_size)?;
+        }
+        for (index, (ty, location)) in types.iter().zip(&locations).enumerate() {
+            let kept = stack_size + kept_size - ends[index];
+            let indirect = if index < fixed || self.is_linux() { is_indirect(ty) } else { is_indirect_variadic(ty) };
+            match *location {
+                ArgumentLocation::Stack(offset) if ty.is_record() && !indirect => {
+                    self.write_immediate_operation("add", "x9", "sp", kept)?;
+                    self.write_immediate_operation("add", "x14", "sp", offset)?;
+                    self.write_copy("x14", "x9", ty.size())?;
+                }
+                ArgumentLocation::Stack(offset) if indirect => {
+                    self.write_immediate_operation("add", "x9", "sp", kept)?;
+                    let operand = self.memory_operand("sp", offset, 8)?;
+                    writeln!(self.writer.file, "\tstr x9, {}", operand)?;
+                }
+                ArgumentLocation::Stack(offset) => {
+                    let operand = self.memory_operand("sp", kept, 8)?;
+                    writeln!(self.writer.file, "\tldr x9, {}", operand)?;
+                    let operand = self.memory_operand("sp", offset, ty.size())?;
+                    let register = if ty.size() <= 4 { "w9" } else { "x9" };
+                    writeln!(self.writer.file, "\t{} {}, {}", Self::store_instruction(ty), register, operand)?;
+                }
+                ArgumentLocation::Registers(first, _) if is_indirect(ty) => {
+                    self.write_immediate_operation("add", &format!("x{}", first), "sp", kept)?;
+                }
+                ArgumentLocation::Registers(first, count) => {
+                    for word in 0..count {
+                        let operand = self.memory_operand("sp", kept + word * 8, 8)?;
+                        writeln!(self.writer.file, "\tldr x{}, {}", first + word, operand)?;
+                    }
+                }
+                // A floating value was kept as a whole d register, its low bytes are the value
+                ArgumentLocation::FloatRegisters(first, count) => {
+                    let (base, _) = floating_members(ty).expect("Not a floating type");
+                    let prefix = if base.size() == 4 { "s" } else { "d" };
+                    for member in 0..count {
+                        let operand = self.memory_operand("sp", kept + member * base.size(), base.size())?;
+                        writeln!(self.writer.file, "\tldr {}{}, {}", prefix, first + member, operand)?;
+                    }
+                }
+            }
+        }
+
+        // A large struct or union is returned into memory whose address goes in x8
+        let result = node.symbol.as_ref();
+        if let (Some(result), true) = (result, is_indirect(&return_type)) {
+            self.write_immediate_operation("add", "x8", "x29", self.local_offsets[result.slot])?;
+        }
+        match name {
+            Some(name) => writeln!(self.writer.file, "\tbl {}", self.symbol_name(name))?,
+            None => {
+                let operand = self.memory_operand("sp", stack_size + kept_size - 16, 8)?;
+                writeln!(self.writer.file, "\tldr x16, {}", operand)?;
+                writeln!(self.writer.file, "\tblr x16")?;
+            }
+        }
+        match result {
+            // A homogeneous floating-point aggregate comes back in v0 to v3, one member in each
+            Some(result) if floating_members(&return_type).is_some() => {
+                let (base, count) = floating_members(&return_type).expect("Not a floating type");
+                let prefix = if base.size() == 4 { "s" } else { "d" };
+                let offset = self.local_offsets[result.slot];
+                for member in 0..count {
+                    let operand = self.memory_operand("x29", offset + member * base.size(), base.size())?;
+                    writeln!(self.writer.file, "\tstr {}{}, {}", prefix, member, operand)?;
+                }
+            }
+            // A small one comes back in x0 and x1
+            Some(result) if !is_indirect(&return_type) => {
+                let offset = self.local_offsets[result.slot];
+                for word in 0..return_type.size().div_ceil(8) {
+                    let operand = self.memory_operand("x29", offset + word * 8, 8)?;
+                    writeln!(self.writer.file, "\tstr x{}, {}", word, operand)?;
+                }
+            }
+            Some(_) => {}
+            // Out of the way of the saved registers, a floating result keeps its bits
+            None if return_type.is_floating() => writeln!(self.writer.file, "\tfmov x9, d0")?,
+            None => writeln!(self.writer.file, "\tmov x9, x0")?,
+        }
+
+        if stack_size + kept_size > 0 {
+            self.write_immediate_operation("add", "sp", "sp", stack_size + kept_size)?;
+        }
+        for register in live.iter().rev() {
+            writeln!(self.writer.file, "\tldr {}, [sp], #16", self.format_register(register))?;
+        }
+        self.available_registers = available;
+        self.available_float_registers = available_float;
+        match result {
+            Some(result) => self.load_variable_address(result),
+            None if return_type.is_floating() => {
+                let register = self.allocate_float_register()?;
+                writeln!(self.writer.file, "\tfmov {}, x9", self.format_register(&register))?;
+                Ok(register)
+            }
+            None => {
+                let register = self.allocate_register()?;
+                writeln!(self.writer.file, "\tmov {}, x9", self.format_register(&register))?;
+                // Darwin has the called function extend a result narrower than 32 bits, AAPCS64
+                // leaves the bits above it undefined
+                match return_type.size() < 4 && return_type.is_integer() && self.is_linux() {
+                    true => self.cast_register(register, &Type::Int, &return_type),
+                    false => Ok(register),
+                }
+            }
+        }
+    }
+
+    fn cast_register(&mut self, register: RegisterList, from: &Type, to: &Type) -> IoResult<RegisterList> {
+        if from.is_floating() || to.is_floating() {
+            return self.write_float_conversion(register, from, to);
+        }
+        let (w, x) = (self.sized_register(&register, &Type::Int), self.format_register(&register));
+        let signed = to.is_signed();
+        // Narrower values are kept extended to 32 bits. They need extending again when they get
+        // narrower or change signedness, anything 32 bits or wider just keeps the bits it has.
+        let needs_extension = from.size() > to.size() || from.is_signed() != signed;
+        match to.size() {
+            8 if from.size() <= 4 => {
+                if from.is_signed() {
+                    writeln!(self.writer.file, "\tsxtw {}, {}", x, w)?;
+                } else {
+                    // Writing a w register clears the upper half
+                    writeln!(self.writer.file, "\tmov {0}, {0}", w)?;
+                }
+            }
+            2 if needs_extension => writeln!(self.writer.file, "\t{} {1}, {1}", if signed { "sxth" } else { "uxth" }, w)?,
+            1 if needs_extension => writeln!(self.writer.file, "\t{} {1}, {1}", if signed { "sxtb" } else { "uxtb" }, w)?,
+            _ => {}
+        }
+        Ok(register)
+    }
+
+    fn print_register(&mut self, register: RegisterList) -> IoResult<RegisterList> {
+        // ARM64-specific print implementation
+        writeln!(self.writer.file, "    // Print register value")?;
+        writeln!(self.writer.file, "    mov x0, #1           // stdout")?;
+        writeln!(self.writer.file, "    mov x16, #4          // write syscall")?;
+        writeln!(self.writer.file, "    svc #0x80")?;
+        Ok(register)
+    }
+
+    fn add_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList> {
+        let instruction = if ty.is_floating() { "fadd" } else { "add" };
+        self.write_binary_operation(instruction, reg_1, reg_2, ty)
+    }
+
+    fn subtract_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList> {
+        let instruction = if ty.is_floating() { "fsub" } else { "sub" };
+        self.write_binary_operation(instruction, reg_1, reg_2, ty)
+    }
+
+    fn multiply_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList> {
+        let instruction = if ty.is_floating() { "fmul" } else { "mul" };
+        self.write_binary_operation(instruction, reg_1, reg_2, ty)
+    }
+
+    fn divide_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList> {
+        let instruction = match ty {
+            _ if ty.is_floating() => "fdiv",
+            _ if ty.is_signed() => "sdiv",
+            _ => "udiv",
+        };
+        self.write_binary_operation(instruction, reg_1, reg_2, ty)
+    }
+
+    fn modulo_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, ty: &Type) -> IoResult<RegisterList> {
+        // There is no remainder instruction, it is what the quotient times the divisor leaves
+        let result_reg = self.result_register(&reg_1)?;
+        let (result, dividend, divisor) = (
+            self.sized_register(&result_reg, ty),
+            self.sized_register(&reg_1, ty),
+            self.sized_register(&reg_2, ty),
+        );
+        // Without a register of its own the quotient goes in x9, as the dividend is still needed
+        let quotient = match (result_reg == reg_1, ty.size() <= 4) {
+            (false, _) => result.clone(),
+            (true, true) => "w9".to_string(),
+            (true, false) => "x9".to_string(),
+        };
+        writeln!(self.writer.file, "    {} {}, {}, {}", if ty.is_signed() { "sdiv" } else { "udiv" }, quotient, dividend, divisor)?;
+        writeln!(self.writer.file, "    msub {}, {}, {}, {}", result, quotient, divisor, dividend)?;
+        if result_reg != reg_1 {
+            self.free_register(reg_1);
+        }
         self.free_register(reg_2);
         Ok(result_reg)
     }
 
-    fn divide_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList) -> IoResult<RegisterList> {
-        let result_reg = self.allocate_register();
+    fn bitwise_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, operation: &Token, ty: &Type) -> IoResult<RegisterList> {
+        let instruction = match operation {
+            Token::AMPER => "and",
+            Token::OR => "orr",
+            Token::XOR => "eor",
+            Token::LSHIFT => "lsl",
+            // Signed values shift in copies of the sign bit, unsigned ones zeros
+            Token::RSHIFT if ty.is_signed() => "asr",
+            Token::RSHIFT => "lsr",
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Not a bitwise operation")),
+        };
+        self.write_binary_operation(instruction, reg_1, reg_2, ty)
+    }
+
+    fn unary_register(&mut self, register: RegisterList, operation: &Token, ty: &Type) -> IoResult<RegisterList> {
+        let name = self.sized_register(&register, ty);
+        match operation {
+            Token::Negate => {
+                let instruction = if ty.is_floating() { "fneg" } else { "neg" };
+                writeln!(self.writer.file, "    {} {}, {}", instruction, name, name)?;
+                Ok(register)
+            }
+            Token::INVERT => {
+                writeln!(self.writer.file, "    mvn {}, {}", name, name)?;
+                Ok(register)
+            }
+            // The int result of a floating value goes in a general register
+            Token::LOGNOT if ty.is_floating() => {
+                writeln!(self.writer.file, "    fcmp {}, #0.0", name)?;
+                self.free_register(register);
+                let result = self.allocate_register()?;
+                writeln!(self.writer.file, "    cset {}, eq", self.sized_register(&result, &Type::Int))?;
+                Ok(result)
+            }
+            Token::LOGNOT => {
+                writeln!(self.writer.file, "    cmp {}, #0", name)?;
+                writeln!(self.writer.file, "    cset {}, eq", self.sized_register(&register, &Type::Int))?;
+                Ok(register)
+            }
+            _ => Err(Error::new(ErrorKind::InvalidInput, "Not a unary operation")),
+        }
+    }
+
+    fn compare_registers(&mut self, reg_1: RegisterList, reg_2: RegisterList, comparison: &Token, ty: &Type) -> IoResult<RegisterList> {
+        if ty.is_floating() {
+            return self.write_float_comparison(reg_1, reg_2, comparison, ty);
+        }
+        let signed = ty.is_signed();
+        let condition = match comparison {
+            Token::EQ => "eq",
+            Token::NE => "ne",
+            Token::LT if signed => "lt",
+            Token::LT => "lo",
+            Token::GT if signed => "gt",
+            Token::GT => "hi",
+            Token::LE if signed => "le",
+            Token::LE => "ls",
+            Token::GE if signed => "ge",
+            Token::GE => "hs",
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "Not a comparison")),
+        };
         writeln!(
             self.writer.file,
-            "    udiv {}, {}, {}",
-            self.format_register(&result_reg),
-            self.format_register(&reg_1),
-            self.format_register(&reg_2)
+            "    cmp {}, {}",
+            self.sized_register(&reg_1, ty),
+            self.sized_register(&reg_2, ty)
         )?;
-        self.free_register(reg_1);
         self.free_register(reg_2);
-        Ok(result_reg)
+        // The result is an int
+        writeln!(self.writer.file, "    cset {}, {}", self.sized_register(&reg_1, &Type::Int), condition)?;
+        Ok(reg_1)
+    }
+
+    fn new_label(&mut self) -> String {
+        self.label_count += 1;
+        format!("{}{}", self.writer.architecture.local_label_prefix(), self.label_count)
+    }
+
+    fn named_label(&self, function: &Function, name: &str) -> String {
+        // A `.` cannot be part of a C name, so no other function's label or return label looks like this
+        format!("{}_{}.{}", self.writer.architecture.local_label_prefix(), function.name, name)
+    }
+
+    fn write_label(&mut self, label: &str) -> IoResult<()> {
+        writeln!(self.writer.file, "{}:", label)
+    }
+
+    fn jump(&mut self, label: &str) -> IoResult<()> {
+        writeln!(self.writer.file, "\tb {}", label)
+    }
+
+    fn jump_if(&mut self, register: RegisterList, ty: &Type, nonzero: bool, label: &str) -> IoResult<()> {
+        let name = self.sized_register(&register, ty);
+        if ty.is_floating() {
+            writeln!(self.writer.file, "\tfcmp {}, #0.0", name)?;
+            writeln!(self.writer.file, "\tb.{} {}", if nonzero { "ne" } else { "eq" }, label)?;
+        } else {
+            writeln!(self.writer.file, "\t{} {}, {}", if nonzero { "cbnz" } else { "cbz" }, name, label)?;
+        }
+        self.free_register(register);
+        Ok(())
+    }
+
+    fn jump_if_equal(&mut self, register: &RegisterList, ty: &Type, value: i64, label: &str) -> IoResult<()> {
+        let name = self.sized_register(register, ty);
+        let scratch = if ty.size() <= 4 { "w9" } else { "x9" };
+        self.write_compare_immediate(&name, value, scratch)?;
+        writeln!(self.writer.file, "\tb.eq {}", label)
+    }
+
+    fn jump_table(&mut self, register: RegisterList, ty: &Type, first: i64, table: &[&str], otherwise: &str) -> IoResult<()> {
+        let name = self.sized_register(&register, ty);
+        let (index, scratch, scaled_index) = match ty.size() <= 4 {
+            true => ("w9", "w10", "w9, uxtw #2"),
+            false => ("x9", "x10", "x9, lsl #2"),
+        };
+        // Values below the first case wrap around to large unsigned indexes, so one comparison
+        // finds every value outside the table
+        match first {
+            0..=4095 => writeln!(self.writer.file, "\tsub {}, {}, #{}", index, name, first)?,
+            _ => {
+                self.write_move_immediate(scratch, first, if ty.size() <= 4 { 2 } else { 4 })?;
+                writeln!(self.writer.file, "\tsub {}, {}, {}", index, name, scratch)?;
+            }
+        }
+        self.free_register(register);
+        self.write_compare_immediate(index, table.len() as i64 - 1, scratch)?;
+        writeln!(self.writer.file, "\tb.hi {}", otherwise)?;
+
+        // Each entry is the distance from the table to the code of its case
+        let table_label = self.new_label();
+        writeln!(self.writer.file, "\tadr x10, {}", table_label)?;
+        writeln!(self.writer.file, "\tldrsw x11, [x10, {}]", scaled_index)?;
+        writeln!(self.writer.file, "\tadd x10, x10, x11")?;
+        writeln!(self.writer.file, "\tbr x10")?;
+        writeln!(self.writer.file, "{}:", table_label)?;
+        for label in table {
+            writeln!(self.writer.file, "\t.long {} - {}", label, table_label)?;
+        }
+        Ok(())
+    }
+
+    fn move_register(&mut self, destination: &RegisterList, source: RegisterList, ty: &Type) -> IoResult<()> {
+        // A struct or union is its address, and a void value is never used
+        let (destination_name, source_name) = match ty {
+            Type::Record(_) => (self.format_register(destination), self.format_register(&source)),
+            _ => (self.sized_register(destination, ty), self.sized_register(&source, ty)),
+        };
+        if *ty != Type::Void {
+            let instruction = if ty.is_floating() { "fmov" } else { "mov" };
+            writeln!(self.writer.file, "\t{} {}, {}", instruction, destination_name, source_name)?;
+        }
+        self.free_register(source);
+        Ok(())
+    }
+
+    fn copy_register(&mut self, register: &RegisterList, ty: &Type) -> IoResult<RegisterList> {
+        let copy = self.allocate_register_for(ty)?;
+        let instruction = if ty.is_floating() { "fmov" } else { "mov" };
+        writeln!(self.writer.file, "\t{} {}, {}", instruction, self.format_register(&copy), self.format_register(register))?;
+        Ok(copy)
     }
 
     fn write_assembly_headers(&mut self) -> IoResult<()> {
         writeln!(self.writer.file, "// Auto-generated ARM64 assembly")?;
+        writeln!(self.writer.file, ".arch armv8-a")?;
         writeln!(self.writer.file, ".text")?;
         writeln!(self.writer.file, ".global _main")?;
         writeln!(self.writer.file, ".align 2")?;
-        writeln!(self.writer.file, "")?;
+        writeln!(self.writer.file)?;
 
         // Start main function
         writeln!(self.writer.file, "_main:")?;
@@ -149,7 +1370,7 @@ impl<W: std::io::Write> WriteAssembly for ARM64Writer<W> {
 
     fn write_exit_syscall(&mut self) -> IoResult<()> {
         // Standard macOS ARM64 exit syscall
-        writeln!(self.writer.file, "")?;
+        writeln!(self.writer.file)?;
         writeln!(self.writer.file, "    // Exit program")?;
         writeln!(self.writer.file, "    mov x0, #0           // Exit status 0")?;
         writeln!(self.writer.file, "    mov x16, #1          // Exit syscall")?;
@@ -157,35 +1378,343 @@ impl<W: std::io::Write> WriteAssembly for ARM64Writer<W> {
 
         Ok(())
     }
+
+    fn write_function_prologue(&mut self, function: &Function) -> IoResult<()> {
+        // Give every local a naturally aligned slot above the frame record. Structs and unions
+        // take whole 8 byte words, as they are moved to and from registers that way.
+        self.local_offsets.clear();
+        let mut offset = FRAME_RECORD_SIZE;
+        for symbol in &function.locals {
+            offset = offset.next_multiple_of(symbol.ty.alignment());
+            self.local_offsets.push(offset);
+            offset += if symbol.ty.is_record() { symbol.ty.size().next_multiple_of(8) } else { symbol.ty.size() };
+        }
+        self.result_address_offset = is_indirect(&function.return_type).then(|| {
+            offset = offset.next_multiple_of(8) + 8;
+            offset - 8
+        });
+        // A variadic function on Linux saves the argument registers, followed by the `va_list`
+        // that `va_start` copies
+        let save_area_size = GENERAL_SAVE_AREA_SIZE + FLOAT_SAVE_AREA_SIZE + VA_LIST_SIZE;
+        let save_area = (function.variadic && self.is_linux()).then(|| {
+            offset = offset.next_multiple_of(16) + save_area_size;
+            offset - save_area_size
+        });
+        // The stack pointer has to stay 16 byte aligned
+        self.frame_size = offset.next_multiple_of(16);
+
+        let mut classifier = ArgumentClassifier::new(self.writer.architecture);
+        for parameter in &function.parameters {
+            classifier.classify(&parameter.ty, false);
+        }
+        self.variadic_offset = match save_area {
+            Some(save_area) => Some(save_area + GENERAL_SAVE_AREA_SIZE + FLOAT_SAVE_AREA_SIZE),
+            // On Darwin the variadic arguments are where one more argument would go, which is
+            // always on the stack
+            None if function.variadic => match classifier.classify(&Type::Long, true) {
+                ArgumentLocation::Stack(offset) => Some(self.frame_size + offset),
+                _ => None,
+            },
+            None => None,
+        };
+
+        let name = self.symbol_name(&function.name);
+        writeln!(self.writer.file)?;
+        if function.external {
+            writeln!(self.writer.file, ".global {}", name)?;
+        }
+        writeln!(self.writer.file, ".align 2")?;
+        if self.is_linux() {
+            writeln!(self.writer.file, ".type {}, %function", name)?;
+        }
+        writeln!(self.writer.file, "{}:", name)?;
+        self.write_immediate_operation("sub", "sp", "sp", self.frame_size)?;
+        writeln!(self.writer.file, "\tstp x29, x30, [sp]")?;
+        writeln!(self.writer.file, "\tmov x29, sp")?;
+
+        if let Some(offset) = self.result_address_offset {
+            let operand = self.memory_operand("x29", offset, 8)?;
+            writeln!(self.writer.file, "\tstr x8, {}", operand)?;
+        }
+        if let Some(save_area) = save_area {
+            self.write_register_save_area(save_area, &classifier)?;
+        }
+        self.write_parameters(function)
+    }
+
+    fn write_function_epilogue(&mut self, function: &Function) -> IoResult<()> {
+        // Reaching the end of main returns 0
+        if function.name == "main" {
+            writeln!(self.writer.file, "\tmov w0, #0")?;
+        }
+        writeln!(self.writer.file, "{}:", self.return_label(function))?;
+        writeln!(self.writer.file, "\tldp x29, x30, [sp]")?;
+        self.write_immediate_operation("add", "sp", "sp", self.frame_size)?;
+        writeln!(self.writer.file, "\tret")?;
+        if self.is_linux() {
+            writeln!(self.writer.file, ".size {0}, .-{0}", self.symbol_name(&function.name))?;
+        }
+        Ok(())
+    }
+
+    fn write_return(&mut self, register: Option<RegisterList>, function: &Function) -> IoResult<()> {
+        let return_type = &function.return_type;
+        match &register {
+            Some(register) if return_type.is_record() => {
+                let source = self.format_register(register);
+                match (floating_members(return_type), self.result_address_offset) {
+                    // A homogeneous floating-point aggregate goes in v0 to v3, one member in each
+                    (Some((base, count)), _) => {
+                        let prefix = if base.size() == 4 { "s" } else { "d" };
+                        for member in 0..count {
+                            let operand = self.memory_operand(&source, member * base.size(), base.size())?;
+                            writeln!(self.writer.file, "\tldr {}{}, {}", prefix, member, operand)?;
+                        }
+                    }
+                    // Into the memory the caller provided
+                    (None, Some(offset)) => {
+                        let operand = self.memory_operand("x29", offset, 8)?;
+                        writeln!(self.writer.file, "\tldr x9, {}", operand)?;
+                        self.write_copy("x9", &source, return_type.size())?;
+                    }
+                    // In x0 and x1, copied through the stack so nothing past its end is read
+                    (None, None) => {
+                        writeln!(self.writer.file, "\tsub sp, sp, #16")?;
+                        self.write_copy("sp", &source, return_type.size())?;
+                        writeln!(self.writer.file, "\tldp x0, x1, [sp], #16")?;
+                    }
+                }
+            }
+            Some(register) if return_type.is_floating() => writeln!(
+                self.writer.file,
+                "\tfmov {}, {}",
+                self.sized_register(&RegisterList::F0, return_type),
+                self.sized_register(register, return_type)
+            )?,
+            Some(register) => writeln!(
+                self.writer.file,
+                "\tmov {}, {}",
+                self.sized_register(&RegisterList::R0, return_type),
+                self.sized_register(register, return_type)
+            )?,
+            None => {}
+        }
+        if let Some(register) = register {
+            self.free_register(register);
+        }
+        writeln!(self.writer.file, "\tb {}", self.return_label(function))?;
+        Ok(())
+    }
 }
 
 
 // Example usage
 impl<W: std::io::Write> ARM64Writer<W> {
+    /// Compile a program that has been through [`crate::typecheck::check_translation_unit`]
+    pub fn compile_translation_unit(&mut self, unit: &TranslationUnit) -> IoResult<()> {
+        self.writer.architecture = unit.architecture;
+        writeln!(self.writer.file, "// Auto-generated ARM64 assembly")?;
+        writeln!(self.writer.file, ".arch armv8-a")?;
+        writeln!(self.writer.file, ".text")?;
+
+        self.defined_labels = unit
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                ExternalDeclaration::Function(function) => Some(function.name.clone()),
+                ExternalDeclaration::Variable(symbol, _) => Some(symbol.name.clone()),
+                ExternalDeclaration::FunctionDeclaration(_) | ExternalDeclaration::VariableDeclaration(_) => None,
+            })
+            .collect();
+        for declaration in &unit.declarations {
+            match declaration {
+                ExternalDeclaration::Function(function) => {
+                    self.write_function_prologue(function)?;
+                    for statement in &function.body {
+                        self.generate_statement(statement, function, None)?;
+                    }
+                    self.write_function_epilogue(function)?;
+                }
+                // Nothing to write, the assembler leaves a label that is not defined to the linker
+                ExternalDeclaration::FunctionDeclaration(_) | ExternalDeclaration::VariableDeclaration(_) => {}
+                ExternalDeclaration::Variable(symbol, initializer) => {
+                    self.define_static_variable(symbol, initializer.as_ref())?;
+                }
+            }
+        }
+
+        // Initialisers can add string literals
+        self.write_static_variables()?;
+        self.write_string_literals()?;
+        // Without the note an ELF linker makes the stack executable
+        if self.is_linux() {
+            writeln!(self.writer.file)?;
+            writeln!(self.writer.file, ".section .note.GNU-stack,\"\",@progbits")?;
+        }
+
+        self.writer.file.flush()?;
+
+        Ok(())
+    }
+
+    /// Compile a single expression into a program that evaluates it and exits
     pub fn compile_ast(&mut self, ast: &ASTNode) -> IoResult<()> {
+        let mut ast = ast.clone();
+        check_expression(&mut ast).map_err(|err| Error::new(ErrorKind::InvalidInput, format!("{:?}", err)))?;
+
         self.write_assembly_headers()?;
         
         // Generate assembly from the root of the AST
-        let result_reg = self.generate_assembly_from_ast(ast)?;
+        let result_reg = self.generate_assembly_from_ast(&ast)?;
 
         // Optional: print final result
         self.print_register(result_reg.clone())?;
 
-        // Free the final result register
-        self.free_register(result_reg);
+        // The expression is done, every register is free again
+        self.free_all_registers();
 
         self.write_exit_syscall()?;
 
+        self.write_string_literals()?;
+
         self.writer.file.flush()?;
 
         Ok(())
     }
+
+    /// Write every variable with static storage: the initialised ones to the data section, the
+    /// others to the zero filled section, which takes no room in the object file
+    fn write_static_variables(&mut self) -> IoResult<()> {
+        let (initialised, zeroed): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.static_variables).into_iter().partition(|(_, value)| value.is_some());
+
+        if !initialised.is_empty() {
+            writeln!(self.writer.file)?;
+            writeln!(self.writer.file, "{}", if self.is_linux() { ".data" } else { ".section __DATA,__data" })?;
+        }
+        for (symbol, data) in initialised {
+            let label = self.write_static_label(&symbol)?;
+            writeln!(self.writer.file, ".p2align {}", symbol.ty.alignment().trailing_zeros())?;
+            writeln!(self.writer.file, "{}:", label)?;
+            let mut position = 0;
+            for (offset, value) in data.into_iter().flatten() {
+                self.write_zeros(offset - position)?;
+                position = offset + match value {
+                    StaticData::Value(ty, value) => {
+                        self.write_constant(value, &ty)?;
+                        ty.size()
+                    }
+                    StaticData::Bytes(bytes) => {
+                        writeln!(self.writer.file, "\t.ascii \"{}\"", escape_assembly_string(&bytes))?;
+                        bytes.len()
+                    }
+                };
+            }
+            self.write_zeros(symbol.ty.size() - position)?;
+        }
+
+        if self.is_linux() && !zeroed.is_empty() {
+            writeln!(self.writer.file)?;
+            writeln!(self.writer.file, ".bss")?;
+        }
+        for (symbol, _) in zeroed {
+            let label = self.write_static_label(&symbol)?;
+            if self.is_linux() {
+                writeln!(self.writer.file, ".p2align {}", symbol.ty.alignment().trailing_zeros())?;
+                writeln!(self.writer.file, "{}:", label)?;
+                self.write_zeros(symbol.ty.size())?;
+                continue;
+            }
+            writeln!(
+                self.writer.file,
+                ".zerofill __DATA,__bss,{},{},{}",
+                label,
+                symbol.ty.size(),
+                symbol.ty.alignment().trailing_zeros()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write `count` zero bytes of static data, if there are any
+    fn write_zeros(&mut self, count: usize) -> IoResult<()> {
+        if count > 0 {
+            writeln!(self.writer.file, "\t.zero {}", count)?;
+        }
+        Ok(())
+    }
+
+    /// Make a symbol with static storage visible to other files if it is external, returns its label.
+    /// ELF symbols also get their type and size.
+    fn write_static_label(&mut self, symbol: &Symbol) -> IoResult<String> {
+        let label = self.static_label(symbol)?;
+        if matches!(symbol.storage, Storage::Static { external: true, .. }) {
+            writeln!(self.writer.file, ".globl {}", label)?;
+        }
+        if self.is_linux() {
+            writeln!(self.writer.file, ".type {}, %object", label)?;
+            writeln!(self.writer.file, ".size {}, {}", label, symbol.ty.size())?;
+        }
+        Ok(label)
+    }
+
+    /// Write the data directive for a constant of type `ty`
+    fn write_constant(&mut self, value: Constant, ty: &Type) -> IoResult<()> {
+        let directive = match ty.size() {
+            1 => ".byte",
+            2 => ".short",
+            4 => ".long",
+            _ => ".quad",
+        };
+        let (label, offset) = match value {
+            Constant::Integer(value) => return writeln!(self.writer.file, "\t{} {}", directive, value),
+            // The bits of the value, assemblers do not all take floating literals
+            Constant::Float(value) if *ty == Type::Float => {
+                return writeln!(self.writer.file, "\t{} {:#x}\t// {}", directive, (value as f32).to_bits(), value)
+            }
+            Constant::Float(value) => return writeln!(self.writer.file, "\t{} {:#x}\t// {}", directive, value.to_bits(), value),
+            Constant::SymbolAddress(symbol, offset) => (self.static_label(&symbol)?, offset),
+            Constant::StringAddress(bytes, offset) => {
+                self.string_literals.push(bytes);
+                (self.string_label(self.string_literals.len() - 1), offset)
+            }
+        };
+        match offset {
+            0 => writeln!(self.writer.file, "\t{} {}", directive, label),
+            offset if offset > 0 => writeln!(self.writer.file, "\t{} {}+{}", directive, label, offset),
+            offset => writeln!(self.writer.file, "\t{} {}-{}", directive, label, offset.unsigned_abs()),
+        }
+    }
+
+    /// Write every string literal used by the program to the C string section. The linker
+    /// splits that section at each NUL and merges the same strings, so a literal with a NUL
+    /// inside goes to the constant section instead, where it stays in one piece.
+    fn write_string_literals(&mut self) -> IoResult<()> {
+        let (embedded, plain): (Vec<_>, Vec<_>) =
+            self.string_literals.iter().enumerate().partition(|(_, bytes)| bytes.contains(&0));
+
+        let sections = match self.is_linux() {
+            true => [".rodata.str1.1,\"aMS\",@progbits,1", ".rodata"],
+            false => ["__TEXT,__cstring,cstring_literals", "__TEXT,__const"],
+        };
+        for (section, literals) in sections.into_iter().zip([plain, embedded]) {
+            if literals.is_empty() {
+                continue;
+            }
+            writeln!(self.writer.file)?;
+            writeln!(self.writer.file, ".section {}", section)?;
+            for (index, bytes) in literals {
+                writeln!(self.writer.file, "{}:", self.string_label(index))?;
+                writeln!(self.writer.file, "\t.asciz \"{}\"", escape_assembly_string(bytes))?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::scan::Token;
     use std::fs;
     use std::fs::File;
     use std::io::{BufWriter, Cursor};
@@ -193,20 +1722,12 @@ mod tests {
 
     // Helper function to create a simple AST node
     fn create_int_node(value: i32) -> ASTNode {
-        ASTNode {
-            operation: Token::INT(value),
-            left: None,
-            right: None,
-        }
+        ASTNode::make_leaf(Token::INT(value)).unwrap()
     }
 
     // Helper function to create an operation node
     fn create_op_node(op: Token, left: ASTNode, right: ASTNode) -> ASTNode {
-        ASTNode {
-            operation: op,
-            left: Some(Box::new(left)),
-            right: Some(Box::new(right)),
-        }
+        ASTNode::new(Ok(op), Box::new(left), Box::new(right)).unwrap()
     }
 
     #[test]
@@ -216,10 +1737,14 @@ mod tests {
 
         assert_eq!(writer.format_register(&RegisterList::R4), "x4");
         assert_eq!(writer.format_register(&RegisterList::R3), "x3");
+        assert_eq!(writer.format_register(&RegisterList::F1), "d1");
+        assert_eq!(writer.sized_register(&RegisterList::F1, &Type::Float), "s1");
     }
 
 
-    fn setup_writer() -> (ARM64Writer<BufWriter<Cursor<Vec<u8>>>>, Cursor<Vec<u8>>) {
+    type TestWriter = ARM64Writer<BufWriter<Cursor<Vec<u8>>>>;
+
+    fn setup_writer() -> (TestWriter, Cursor<Vec<u8>>) {
         let output = Cursor::new(Vec::new());
         let writer = ARM64Writer::new(BufWriter::new(output.clone()));
         (writer, output)
@@ -230,9 +1755,9 @@ mod tests {
         let (mut writer, _) = setup_writer();
 
         // Allocate all registers
-        let r0 = writer.allocate_register();
-        let r1 = writer.allocate_register();
-        let r2 = writer.allocate_register();
+        let r0 = writer.allocate_register().unwrap();
+        let r1 = writer.allocate_register().unwrap();
+        let r2 = writer.allocate_register().unwrap();
 
         assert_eq!(r0, RegisterList::R0);
         assert_eq!(r1, RegisterList::R1);
@@ -240,17 +1765,19 @@ mod tests {
 
         // Free a register and reallocate
         writer.free_register(r1.clone());
-        let r1_reallocated = writer.allocate_register();
+        let r1_reallocated = writer.allocate_register().unwrap();
         assert_eq!(r1, r1_reallocated);
 
         // Ensure no registers are left
-        writer.allocate_register(); // R3
-        writer.allocate_register(); // R4
+        writer.allocate_register().unwrap(); // R3
+        writer.allocate_register().unwrap(); // R4
         assert!(writer.available_registers.is_empty());
+        assert!(writer.allocate_register().is_err());
 
         // Free all registers
         writer.free_all_registers();
         assert_eq!(writer.available_registers.len(), 5);
+        assert_eq!(writer.allocate_register().unwrap(), RegisterList::R0);
     }
     
 
@@ -267,7 +1794,29 @@ mod tests {
         // Read generated assembly
         let file_content = fs::read_to_string(filename).unwrap();
 
-        assert!(file_content.contains("mov x0, #42"));
+        assert!(file_content.contains("mov w0, #42"));
+
+        if fs::metadata(filename).is_ok() {
+            fs::remove_file(filename).unwrap();
+        }
+    }
+
+    // Test for a literal that does not fit in a single mov
+    #[test]
+    fn test_wide_integer_loading() {
+        let filename = "test_wide_int_load.s";
+        let file = BufWriter::new(File::create(filename).unwrap());
+        let ast = ASTNode::make_leaf(Token::ULONG(0x1234_0000_5678_9abc)).unwrap();
+
+        let mut writer = ARM64Writer::new(file);
+        writer.compile_ast(&ast).unwrap();
+
+        let file_content = fs::read_to_string(filename).unwrap();
+
+        assert!(file_content.contains("mov x0, #39612"));
+        assert!(file_content.contains("movk x0, #22136, lsl #16"));
+        assert!(!file_content.contains("lsl #32"));
+        assert!(file_content.contains("movk x0, #4660, lsl #48"));
 
         if fs::metadata(filename).is_ok() {
             fs::remove_file(filename).unwrap();
@@ -291,8 +1840,8 @@ mod tests {
         // Read generated assembly
         let file_content = fs::read_to_string(filename).unwrap();
 
-        assert!(file_content.contains("mov x0, #10"));
-        assert!(file_content.contains("mov x1, #20"));
+        assert!(file_content.contains("mov w0, #10"));
+        assert!(file_content.contains("mov w1, #20"));
         assert!(file_content.contains("add"));
 
         if fs::metadata(filename).is_ok() {
@@ -317,8 +1866,8 @@ mod tests {
         // Read generated assembly
         let file_content = fs::read_to_string(filename).unwrap();
 
-        assert!(file_content.contains("mov x0, #30"));
-        assert!(file_content.contains("mov x1, #15"));
+        assert!(file_content.contains("mov w0, #30"));
+        assert!(file_content.contains("mov w1, #15"));
         assert!(file_content.contains("sub"));
 
         if fs::metadata(filename).is_ok() {
@@ -343,8 +1892,8 @@ mod tests {
         // Read generated assembly
         let file_content = fs::read_to_string(filename).unwrap();
 
-        assert!(file_content.contains("mov x0, #5"));
-        assert!(file_content.contains("mov x1, #7"));
+        assert!(file_content.contains("mov w0, #5"));
+        assert!(file_content.contains("mov w1, #7"));
         assert!(file_content.contains("mul"));
 
         if fs::metadata(filename).is_ok() {
@@ -369,9 +1918,10 @@ mod tests {
         // Read generated assembly
         let file_content = fs::read_to_string(filename).unwrap();
 
-        assert!(file_content.contains("mov x0, #20"));
-        assert!(file_content.contains("mov x1, #4"));
-        assert!(file_content.contains("udiv"));
+        assert!(file_content.contains("mov w0, #20"));
+        assert!(file_content.contains("mov w1, #4"));
+        // int division is signed
+        assert!(file_content.contains("sdiv w2, w0, w1"));
 
         if fs::metadata(filename).is_ok() {
             fs::remove_file(filename).unwrap();
@@ -399,11 +1949,11 @@ mod tests {
         // Read generated assembly
         let file_content = fs::read_to_string(filename).unwrap();
 
-        assert!(file_content.contains("mov x0, #5"));
-        assert!(file_content.contains("mov x1, #3"));
-        assert!(file_content.contains("add x2, x0, x1"));
-        assert!(file_content.contains("mov x1, #2"));
-        assert!(file_content.contains("mul x0, x2, x1"));
+        assert!(file_content.contains("mov w0, #5"));
+        assert!(file_content.contains("mov w1, #3"));
+        assert!(file_content.contains("add w2, w0, w1"));
+        assert!(file_content.contains("mov w1, #2"));
+        assert!(file_content.contains("mul w0, w2, w1"));
 
         if fs::metadata(filename).is_ok() {
             fs::remove_file(filename).unwrap();
@@ -411,6 +1961,27 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_string_literal() {
+        let filename = "test_string_literal.s";
+        let file = BufWriter::new(File::create(filename).unwrap());
+        let ast = ASTNode::make_leaf(Token::STRING(b"Hi \"you\"\n".to_vec())).unwrap();
+
+        let mut writer = ARM64Writer::new(file);
+        writer.compile_ast(&ast).unwrap();
+
+        let file_content = fs::read_to_string(filename).unwrap();
+
+        assert!(file_content.contains("adrp x0, L_.str.0@PAGE"));
+        assert!(file_content.contains("add x0, x0, L_.str.0@PAGEOFF"));
+        assert!(file_content.contains(".section __TEXT,__cstring,cstring_literals\nL_.str.0:\n"));
+        assert!(file_content.contains(".asciz \"Hi \\\"you\\\"\\012\""));
+
+        if fs::metadata(filename).is_ok() {
+            fs::remove_file(filename).unwrap();
+        }
+    }
+
     #[test]
     fn test_write_assembly_headers() {
         let filename = "test_headers.s";
@@ -424,7 +1995,7 @@ mod tests {
         let file_content = fs::read_to_string(filename).unwrap();
 
         // Check for standard headers
-        assert!(file_content.contains(".arch arm64"), "Missing architecture directive");
+        assert!(file_content.contains(".arch armv8-a"), "Missing architecture directive");
         assert!(file_content.contains(".text"), "Missing text section directive");
         assert!(file_content.contains(".global _main"), "Missing global main directive");
         assert!(file_content.contains("_main:"), "Missing main label");
@@ -457,4 +2028,631 @@ mod tests {
             fs::remove_file(filename).unwrap();
         }
     }
-}
\ No newline at end of file
+
+    fn compile_program(source: &str) -> String {
+        compile_program_for(source, SupportedArchitectures::ARM64)
+    }
+
+    fn compile_program_for(source: &str, architecture: SupportedArchitectures) -> String {
+        let mut unit = TranslationUnit::parse_for_target(&mut crate::scan::Lexer::new(source.as_bytes()), architecture).unwrap();
+        crate::typecheck::check_translation_unit(&mut unit).unwrap();
+
+        let mut output = Vec::new();
+        ARM64Writer::new(&mut output).compile_translation_unit(&unit).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_function_frame_and_return() {
+        let assembly = compile_program("long f() { char c = 1; long l = c; return l; }");
+
+        // Locals: c at 16, l aligned up to 24, frame rounded up to 32
+        assert!(assembly.contains(".global _f\n.align 2\n_f:\n\tsub sp, sp, #32\n\tstp x29, x30, [sp]\n\tmov x29, sp\n"));
+        assert!(assembly.contains("\tstrb w0, [x29, #16]\t// c"));
+        assert!(assembly.contains("\tldrsb w0, [x29, #16]\t// c\n\tsxtw x0, w0\n\tstr x0, [x29, #24]\t// l"));
+        assert!(assembly.contains("\tmov x0, x0\n\tb L_return_f\n"));
+        assert!(assembly.contains("L_return_f:\n\tldp x29, x30, [sp]\n\tadd sp, sp, #32\n\tret\n"));
+        // Only main returns 0 when it reaches its end
+        assert!(!assembly.contains("mov w0, #0"));
+    }
+
+    #[test]
+    fn test_typed_loads_and_arithmetic() {
+        let assembly = compile_program(
+            "int main() { unsigned short s = 65535; unsigned u = 7; short t = 65535; return s / u + (unsigned char) t; }",
+        );
+
+        assert!(assembly.contains("\tldrh w0, [x29, #16]\t// s"));
+        assert!(assembly.contains("\tstrh w0, [x29, #24]\t// t"));
+        assert!(assembly.contains("\tldrsh w"));
+        // unsigned short promotes to int, which converts to unsigned int for the division
+        assert!(assembly.contains("udiv w2, w0, w1"));
+        assert!(assembly.contains("\tuxtb w1, w1"));
+        assert!(assembly.contains("\tmov w0, #0\nL_return_main:"));
+    }
+
+    #[test]
+    fn test_narrowing_and_widening_casts() {
+        let assembly = compile_program("unsigned long f() { unsigned u = 4294967295u; signed char c = 300; return u + (unsigned long) c; }");
+
+        // 300 does not fit in a signed char, the store keeps the low byte
+        assert!(assembly.contains("\tmov w0, #300\t// w0=300\n\tsxtb w0, w0\n\tstrb w0"));
+        assert!(assembly.contains("\tmov w0, #-1\t// w0=-1"));
+        assert!(assembly.contains("\tldr w0, [x29, #16]\t// u\n\tmov w0, w0\n"));
+        assert!(assembly.contains("\tldrsb w1, [x29, #20]\t// c\n\tsxtw x1, w1\n"));
+        assert!(assembly.contains("add x2, x0, x1"));
+    }
+
+    #[test]
+    fn test_large_frame() {
+        let mut source = String::from("char f() {");
+        for index in 0..600 {
+            source.push_str(&format!(" long l{};", index));
+        }
+        source.push_str(" char last = 1; return last; }");
+        let assembly = compile_program(&source);
+
+        // 16 + 600 * 8 + 1 rounds up to 4832 bytes, which does not fit in one immediate
+        assert!(assembly.contains("\tsub sp, sp, #1, lsl #12\n\tsub sp, sp, #736\n"));
+        assert!(assembly.contains("\tadd sp, sp, #1, lsl #12\n\tadd sp, sp, #736\n"));
+        // Byte offsets above 4095 cannot be encoded in strb either
+        assert!(assembly.contains("\tadd x16, x29, #1, lsl #12\n\tadd x16, x16, #720\n\tstrb w0, [x16]\t// last"));
+    }
+
+    #[test]
+    fn test_pointers() {
+        let assembly = compile_program("int main() { short x; short *p = &x; *p = 3; p = p + 1; return *(p - 1) < x; }");
+
+        assert!(assembly.contains("\tadd x0, x29, #16\n\tstr x0, [x29, #24]\t// p"));
+        // *p = 3 stores through the pointer
+        assert!(assembly.contains("\tldr x1, [x29, #24]\t// p\n\tstrh w0, [x1]\n"));
+        // p + 1 moves by sizeof(short)
+        assert!(assembly.contains("\tmov x2, #2\t// x2=2\n    mul x3, x1, x2\n    add x2, x0, x3\n"));
+        assert!(assembly.contains("\tldrsh w3, [x2]\n\tldrsh w2, [x29, #16]\t// x\n    cmp w3, w2\n    cset w3, lt\n"));
+    }
+
+    #[test]
+    fn test_unsigned_and_pointer_comparisons() {
+        let assembly = compile_program("int main() { unsigned u; char *p; return (u >= 1u) + (p == 0); }");
+
+        assert!(assembly.contains("cset w0, hs"));
+        assert!(assembly.contains("    cmp x1, x2\n    cset w1, eq\n"));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let assembly = compile_program("long main() { char c; short a[3]; long m[2][2]; m[1][0] = a[2]; return sizeof m; }");
+
+        // c at 16, a aligned to 18, m aligned to 24
+        assert!(assembly.contains("\tsub sp, sp, #64\n"));
+        assert!(assembly.contains("\tadd x0, x29, #18\n"));
+        assert!(assembly.contains("\tldrsh w3, [x2]\n\tsxtw x3, w3\n"));
+        assert!(assembly.contains("\tadd x2, x29, #24\n"));
+        // A row of m is 16 bytes
+        assert!(assembly.contains("\tmov x1, #16\t// x1=16\n"));
+        assert!(assembly.contains("\tmov x0, #32\t// x0=32\n"));
+    }
+
+    #[test]
+    fn test_static_variables() {
+        let assembly = compile_program(
+            "int n; static long m = 2; char *s = \"ab\" + 1; int *p = &n;\n\
+             int main() { static short calls; calls = n; return *p + m; }",
+        );
+
+        // Page-relative addressing, through x16 for loads and stores
+        assert!(assembly.contains("\tadrp x16, _n@PAGE\n\tldr w0, [x16, _n@PAGEOFF]\t// n\n"));
+        assert!(assembly.contains("\tadrp x16, _calls.1@PAGE\n\tstrh w0, [x16, _calls.1@PAGEOFF]\t// calls\n"));
+
+        // Initialised variables go in the data section, `static` ones are not global
+        assert!(assembly.contains(
+            ".section __DATA,__data\n.p2align 3\n_m:\n\t.quad 2\n\
+             .globl _s\n.p2align 3\n_s:\n\t.quad L_.str.0+1\n\
+             .globl _p\n.p2align 3\n_p:\n\t.quad _n\n"
+        ));
+        assert!(assembly.contains(".globl _n\n.zerofill __DATA,__bss,_n,4,2\n.zerofill __DATA,__bss,_calls.1,2,1\n"));
+        assert!(assembly.contains("L_.str.0:\n\t.asciz \"ab\"\n"));
+
+        let assembly = compile_program("struct s { int a[3]; } v; int *f() { return v.a; }");
+        assert!(assembly.contains("\tadrp x0, _v@PAGE\n\tadd x0, x0, _v@PAGEOFF\n"));
+    }
+
+    #[test]
+    fn test_string_literals_with_nul() {
+        let assembly = compile_program("char *a = \"a\\0b\"; char *b = \"b\"; int main() { return *a; }");
+        assert!(assembly.contains(".section __TEXT,__cstring,cstring_literals\nL_.str.1:\n\t.asciz \"b\"\n"));
+        assert!(assembly.contains(".section __TEXT,__const\nL_.str.0:\n\t.asciz \"a\\000b\"\n"));
+    }
+
+    #[test]
+    fn test_initializer_lists() {
+        let assembly = compile_program(
+            "int table[4] = {1, 2, 3, 4}; struct p { char c; long l; } g = {1}; int partial[5] = {7, 8};\n\
+             char s[] = \"abc\", t[3] = \"abc\"; char *names[] = {\"x\"};\n\
+             int main() { static short m[][2] = {1, 2, 3}; return table[0] + m[1][0]; }",
+        );
+        assert!(assembly.contains("_table:\n\t.long 1\n\t.long 2\n\t.long 3\n\t.long 4\n"));
+        // Padding and the members or elements left out are zero
+        assert!(assembly.contains("_g:\n\t.byte 1\n\t.zero 15\n"));
+        assert!(assembly.contains("_partial:\n\t.long 7\n\t.long 8\n\t.zero 12\n"));
+        assert!(assembly.contains("_m.1:\n\t.short 1\n\t.short 2\n\t.short 3\n\t.zero 2\n"));
+        // The NUL is only there when the array has room for it
+        assert!(assembly.contains("_s:\n\t.ascii \"abc\\000\"\n"));
+        assert!(assembly.contains("_t:\n\t.ascii \"abc\"\n"));
+        assert!(assembly.contains("_names:\n\t.quad L_.str.0\n"));
+    }
+
+    #[test]
+    fn test_classify_arguments() {
+        let small = Type::Record(crate::types::Record::new(crate::types::RecordKind::Struct, None));
+        if let Type::Record(record) = &small {
+            record.define(vec![(Some("a".to_string()), Type::Long), (Some("b".to_string()), Type::Char)]).unwrap();
+        }
+        let big = Type::Record(crate::types::Record::new(crate::types::RecordKind::Union, None));
+        if let Type::Record(record) = &big {
+            record.define(vec![(Some("a".to_string()), Type::Char.array_of(17))]).unwrap();
+        }
+
+        let mut types = vec![Type::Int; 6];
+        types.extend([small.clone(), Type::Char, Type::Short, big, small.clone(), Type::Long]);
+        let (locations, stack_size) = classify_arguments(&types, types.len(), SupportedArchitectures::ARM64);
+        assert_eq!(&locations[5..], &[
+            ArgumentLocation::Registers(5, 1),
+            // Needs two registers, only two are left
+            ArgumentLocation::Registers(6, 2),
+            // Darwin packs scalars on the stack
+            ArgumentLocation::Stack(0),
+            ArgumentLocation::Stack(2),
+            // Passed by address
+            ArgumentLocation::Stack(8),
+            ArgumentLocation::Stack(16),
+            ArgumentLocation::Stack(32),
+        ]);
+        assert_eq!(stack_size, 48);
+
+        // AAPCS64 gives every argument on the stack whole words
+        let (locations, stack_size) = classify_arguments(&types, types.len(), SupportedArchitectures::ARM64Linux);
+        assert_eq!(&locations[7..], &[
+            ArgumentLocation::Stack(0),
+            ArgumentLocation::Stack(8),
+            ArgumentLocation::Stack(16),
+            ArgumentLocation::Stack(24),
+            ArgumentLocation::Stack(40),
+        ]);
+        assert_eq!(stack_size, 48);
+
+        // Variadic arguments go on the stack in words of their own, even when registers are free
+        let types = vec![Type::Char.pointer_to(), Type::Int, Type::Double, small, Type::Long];
+        let (locations, stack_size) = classify_arguments(&types, 1, SupportedArchitectures::ARM64);
+        assert_eq!(locations, vec![
+            ArgumentLocation::Registers(0, 1),
+            ArgumentLocation::Stack(0),
+            ArgumentLocation::Stack(8),
+            ArgumentLocation::Stack(16),
+            ArgumentLocation::Stack(32),
+        ]);
+        assert_eq!(stack_size, 48);
+
+        // Unless the target follows AAPCS64, which passes them like the others
+        let (locations, stack_size) = classify_arguments(&types, 1, SupportedArchitectures::ARM64Linux);
+        assert_eq!(locations, vec![
+            ArgumentLocation::Registers(0, 1),
+            ArgumentLocation::Registers(1, 1),
+            ArgumentLocation::FloatRegisters(0, 1),
+            ArgumentLocation::Registers(2, 2),
+            ArgumentLocation::Registers(4, 1),
+        ]);
+        assert_eq!(stack_size, 0);
+    }
+
+    #[test]
+    fn test_classify_floating_arguments() {
+        let record = |kind, members: Vec<Type>| {
+            let record = crate::types::Record::new(kind, None);
+            record.define(members.into_iter().enumerate().map(|(index, ty)| (Some(format!("m{}", index)), ty)).collect()).unwrap();
+            Type::Record(record)
+        };
+        let quad = record(crate::types::RecordKind::Struct, vec![Type::Double.array_of(2), Type::Double.array_of(2)]);
+        let pair = record(crate::types::RecordKind::Union, vec![Type::Float.array_of(2), Type::Float]);
+        let mixed = record(crate::types::RecordKind::Struct, vec![Type::Float, Type::Int]);
+        assert_eq!(floating_members(&quad), Some((Type::Double, 4)));
+        assert_eq!(floating_members(&pair), Some((Type::Float, 2)));
+        assert_eq!(floating_members(&mixed), None);
+        assert_eq!(floating_members(&Type::Float.array_of(5)), None);
+        // Four doubles are 32 bytes, but go in registers all the same
+        assert!(!is_indirect(&quad));
+
+        let types = vec![Type::Double, Type::Int, quad.clone(), pair.clone(), mixed, Type::Float, Type::Float, quad, Type::Long];
+        let (locations, stack_size) = classify_arguments(&types, types.len(), SupportedArchitectures::ARM64);
+        assert_eq!(locations, vec![
+            ArgumentLocation::FloatRegisters(0, 1),
+            ArgumentLocation::Registers(0, 1),
+            ArgumentLocation::FloatRegisters(1, 4),
+            ArgumentLocation::FloatRegisters(5, 2),
+            // Not homogeneous, in a general register
+            ArgumentLocation::Registers(1, 1),
+            ArgumentLocation::FloatRegisters(7, 1),
+            // The floating point registers are used up, general ones are not
+            ArgumentLocation::Stack(0),
+            ArgumentLocation::Stack(8),
+            ArgumentLocation::Registers(2, 1),
+        ]);
+        assert_eq!(stack_size, 48);
+    }
+
+    #[test]
+    fn test_floating_point() {
+        let assembly = compile_program(
+            "double half(float f) { return f / 2; }\n\
+             int main() { long l = 3; double d = half(1.5f) + l; unsigned char c = d; return d < 1.0; }",
+        );
+
+        // The parameter arrives in s0, the result goes back in d0
+        assert!(assembly.contains("\tmov x29, sp\n\tstr s0, [x29, #16]\t// f\n"));
+        assert!(assembly.contains("\tldr s0, [x29, #16]\t// f\n\tmov w0, #2\t// w0=2\n\tscvtf s1, w0\n    fdiv s2, s0, s1\n\tfcvt d1, s2\n\tfmov d0, d1\n"));
+        // A constant is built in a general register and moved across
+        assert!(assembly.contains("\tmov w9, #0\t// w9=1069547520\n\tmovk w9, #16320, lsl #16\n\tfmov s0, w9\t// s0=1.5\n"));
+        assert!(assembly.contains("\tldr s0, [sp, #0]\n\tbl _half\n\tfmov x9, d0\n\tadd sp, sp, #16\n\tfmov d0, x9\n"));
+        assert!(assembly.contains("\tscvtf d1, x0\n    fadd d2, d0, d1\n"));
+        // Conversions to integers drop the fraction, then narrow as usual
+        assert!(assembly.contains("\tfcvtzu w0, d0\n\tuxtb w0, w0\n\tstrb w0"));
+        assert!(assembly.contains("    fcmp d0, d1\n    cset w0, mi\n"));
+    }
+
+    #[test]
+    fn test_floating_point_registers_and_data() {
+        let assembly = compile_program(
+            "struct v { float x, y; }; float scale = 0.5f; double zero;\n\
+             struct v twice(struct v a) { a.x = a.x * scale; return a; }\n\
+             double f(double d) { struct v a; return d + twice(a).y; }",
+        );
+
+        // A homogeneous floating-point aggregate comes in and goes out one member per register
+        assert!(assembly.contains("\tstr s0, [x29, #16]\t// a\n\tstr s1, [x29, #20]\t// a\n"));
+        assert!(assembly.contains("\tldr s0, [x0, #0]\n\tldr s1, [x0, #4]\n\tb L_return_twice\n"));
+        // d is live across the call
+        assert!(assembly.contains("\tldr d0, [x29, #16]\t// d\n\tstr d0, [sp, #-16]!\n"));
+        assert!(assembly.contains("\tbl _twice\n\tstr s0, [x29, #32]\n\tstr s1, [x29, #36]\n\tadd sp, sp, #16\n\tldr d0, [sp], #16\n"));
+        assert!(assembly.contains("_scale:\n\t.long 0x3f000000\t// 0.5\n"));
+        assert!(assembly.contains(".zerofill __DATA,__bss,_zero,8,3\n"));
+    }
+
+    #[test]
+    fn test_floating_point_register_class() {
+        // After d7 come d16 to d31, d8 to d15 are kept for the caller
+        let assembly = compile_program(
+            "double f(double a, double b) { return a + (b + (a + (b + (a + (b + (a + (b + (a + b)))))))); }",
+        );
+        assert!(assembly.contains("\tldr d7, [x29, #24]\t// b\n\tldr d16, [x29, #16]\t// a\n\tldr d17, [x29, #24]\t// b\n"));
+        assert!(assembly.contains("    fadd d18, d16, d17\n    fadd d17, d7, d18\n"));
+        assert!(!assembly.contains("d8") && !assembly.contains("[sp, #-16]!"));
+
+        // Registers past d7 are saved around calls like the others
+        let assembly = compile_program(
+            "double g(void);\n\
+             double f(double a) { return a + (a + (a + (a + (a + (a + (a + (a + (a + g())))))))); }",
+        );
+        assert!(assembly.contains("\tstr d16, [sp, #-16]!\n\tbl _g\n"));
+        assert!(assembly.contains("\tldr d16, [sp], #16\n"));
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let assembly = compile_program(
+            "int f(int a, unsigned u, long l) { return (a >> 1) + (u >> 2) + (a % 3) + (u % 4u) + (l << a & 7 | 8 ^ l); }",
+        );
+
+        // Right shifts follow the signedness of their left operand
+        assert!(assembly.contains("    asr w2, w0, w1\n"));
+        assert!(assembly.contains("    lsr w3, w1, w0\n"));
+        // The remainder is what the quotient times the divisor leaves
+        assert!(assembly.contains("    sdiv w1, w3, w2\n    msub w1, w1, w2, w3\n"));
+        assert!(assembly.contains("    udiv w3, w1, w0\n    msub w3, w3, w0, w1\n"));
+        // The int count is widened for a long shift
+        assert!(assembly.contains("\tsxtw x2, w2\n    lsl x1, x3, x2\n"));
+        assert!(assembly.contains("    and x3, x1, x2\n"));
+        assert!(assembly.contains("    eor x4, x2, x1\n    orr x1, x3, x4\n"));
+    }
+
+    #[test]
+    fn test_unary_operators() {
+        let assembly = compile_program("int f(int x, long l) { return -x + ~x + !l; }");
+        assert!(assembly.contains("\tldr w0, [x29, #16]\t// x\n    neg w0, w0\n"));
+        assert!(assembly.contains("\tldr w1, [x29, #16]\t// x\n    mvn w1, w1\n"));
+        assert!(assembly.contains("\tldr x1, [x29, #24]\t// l\n    cmp x1, #0\n    cset w1, eq\n"));
+
+        // The int that `!` makes of a floating value goes in a general register
+        let assembly = compile_program("double g(double d) { return -d; } int h(float f) { return !f; }");
+        assert!(assembly.contains("\tldr d0, [x29, #16]\t// d\n    fneg d0, d0\n"));
+        assert!(assembly.contains("\tldr s0, [x29, #16]\t// f\n    fcmp s0, #0.0\n    cset w0, eq\n"));
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        let assembly = compile_program(
+            "int f(int g) { return g; }\nchar h(char *p, int *a, char c) { a[f(1)] += 2; c++; return *p++; }",
+        );
+
+        // The call in the subscript is made once, the address is kept for the store
+        assert_eq!(assembly.matches("bl _f\n").count(), 1);
+        assert!(assembly.contains(
+            "    add x2, x0, x3\n\tmov x3, x2\n\tldr w0, [x3]\n\tmov w3, #2\t// w3=2\n    add w1, w0, w3\n\tstr w1, [x2]\n"
+        ));
+        // A char is added to as an int and narrowed again
+        assert!(assembly.contains("    add w3, w0, w2\n\tsxtb w3, w3\n\tstrb w3, [x29, #32]\t// c\n"));
+        // The old pointer is kept for the value of `p++`
+        assert!(assembly.contains(
+            "\tldr x0, [x29, #16]\t// p\n\tmov x1, x0\n\tmov x2, #1\t// x2=1\n    add x3, x0, x2\n\
+             \tstr x3, [x29, #16]\t// p\n\tldrsb w3, [x1]\n"
+        ));
+    }
+
+    #[test]
+    fn test_switch() {
+        let assembly = compile_program(
+            "int f(int c) { switch (c) { case 1: c = 10; case 2: break; case 4: case 6: return 6; default: ; } return c; }\n\
+             long g(long x) { switch (x) { case 0 - 100000: return 1; case 7: return 3; } return 0; }",
+        );
+
+        // Dense cases jump through a table, the values in between go to default
+        assert!(assembly.contains(
+            "\tsub w9, w0, #1\n\tcmp w9, #5\n\tb.hi L5\n\tadr x10, L7\n\tldrsw x11, [x10, w9, uxtw #2]\n\
+             \tadd x10, x10, x11\n\tbr x10\nL7:\n\t.long L1 - L7\n\t.long L2 - L7\n\t.long L5 - L7\n\
+             \t.long L3 - L7\n\t.long L5 - L7\n\t.long L4 - L7\n"
+        ));
+        // Falling through to the next case, `break` goes past the end
+        assert!(assembly.contains("L1:\n\tmov w0, #10\t// w0=10\n\tstr w0, [x29, #16]\t// c\nL2:\n\tb L6\nL3:\nL4:\n"));
+        assert!(assembly.contains("L5:\nL6:\n"));
+
+        // Sparse ones are compared in order, without a default the switch is left
+        assert!(assembly.contains(
+            "\tmov x9, #31072\t// x9=-100000\n\tmovk x9, #65534, lsl #16\n\tmovk x9, #65535, lsl #32\n\
+             \tmovk x9, #65535, lsl #48\n\tcmp x0, x9\n\tb.eq L8\n\tcmp x0, #7\n\tb.eq L9\n\tb L10\n"
+        ));
+    }
+
+    #[test]
+    fn test_labels_and_goto() {
+        let assembly = compile_program("int f(int n) { again: n -= 1; if_zero: goto again; }\nint g() { again: goto again; }");
+
+        // Labels are kept apart by function, and from the labels the code generator makes
+        assert!(assembly.contains("L_f.again:\n\tldr w0, [x29, #16]\t// n\n"));
+        assert!(assembly.contains("L_f.if_zero:\n\tb L_f.again\n"));
+        assert!(assembly.contains("L_g.again:\n\tb L_g.again\n"));
+    }
+
+    #[test]
+    fn test_short_circuit_and_conditional() {
+        let assembly = compile_program("int f(int a, char *p, double d) { return a && p || d; }\nlong g(int a) { return a ? a : 2l; }");
+
+        // The result goes in x0, each operand jumps away as soon as it decides the result
+        assert!(assembly.contains(
+            "\tldr w2, [x29, #16]\t// a\n\tcbz w2, L3\n\tldr x2, [x29, #24]\t// p\n\tcbz x2, L3\n\
+             \tmov w2, #1\t// w2=1\n\tmov w1, w2\n\tb L4\nL3:\n\tmov w2, #0\t// w2=0\n\tmov w1, w2\nL4:\n\tcbnz w1, L1\n"
+        ));
+        assert!(assembly.contains("\tldr d0, [x29, #32]\t// d\n\tfcmp d0, #0.0\n\tb.ne L1\n"));
+        assert!(assembly.contains("L1:\n\tmov w1, #1\t// w1=1\n\tmov w0, w1\nL2:\n"));
+
+        // Only the chosen result is evaluated, both end up in the same register
+        assert!(assembly.contains(
+            "\tldr w1, [x29, #16]\t// a\n\tcbz w1, L5\n\tldr w1, [x29, #16]\t// a\n\tsxtw x1, w1\n\tmov x0, x1\n\tb L6\n\
+             L5:\n\tmov x1, #2\t// x1=2\n\tmov x0, x1\nL6:\n"
+        ));
+    }
+
+    #[test]
+    fn test_struct_copies() {
+        let assembly = compile_program(
+            "struct s { long a; int b; char c; }; int main() { struct s x; struct s *p = &x; struct s y = x; *p = y; return y.c; }",
+        );
+
+        // x at 16, p at 32, y at 40; a struct is its address and is copied including its padding
+        assert!(assembly.contains(
+            "\tadd x0, x29, #16\n\tadd x1, x29, #40\n\tmov x10, x0\n\tmov x11, x1\n\
+             \tldr x13, [x10], #8\n\tstr x13, [x11], #8\n\
+             \tldr x13, [x10], #8\n\tstr x13, [x11], #8\n\tadd x0, x29, #40\n"
+        ));
+        assert!(assembly.contains("\tadd x0, x29, #40\n\tldr x1, [x29, #32]\t// p\n\tmov x10, x0\n\tmov x11, x1\n"));
+        // y.c is the byte at offset 12
+        assert!(assembly.contains("\tmov x1, #12\t// x1=12\n    add x2, x0, x1\n\tldrsb w1, [x2]\n"));
+
+        // Odd sizes are copied in smaller pieces
+        let assembly = compile_program("struct s { char a[7]; }; int main() { struct s x; struct s y; x = y; }");
+        assert!(assembly.contains(
+            "\tldr w13, [x10], #4\n\tstr w13, [x11], #4\n\
+             \tldrh w13, [x10], #2\n\tstrh w13, [x11], #2\n\
+             \tldrb w13, [x10], #1\n\tstrb w13, [x11], #1\n"
+        ));
+
+        // Large copies loop
+        let assembly = compile_program("struct s { long a[9]; }; int main() { struct s x; struct s y; x = y; }");
+        assert!(assembly.contains("\tmov x12, #9\n1:\tldr x13, [x10], #8\n\tstr x13, [x11], #8\n\tsubs x12, x12, #1\n\tb.ne 1b\n"));
+    }
+
+    #[test]
+    fn test_calls() {
+        let assembly = compile_program(
+            "long f(char a, long b) { return a + b; }\n\
+             int main() { long x = 1; return x * f(2, x + 3); }",
+        );
+
+        // Parameters are stored in their locals
+        assert!(assembly.contains("\tmov x29, sp\n\tstrb w0, [x29, #16]\t// a\n\tstr x1, [x29, #24]\t// b\n"));
+        // x is live across the call and saved, the arguments are kept on the stack until all are evaluated
+        assert!(assembly.contains(
+            "\tldr x0, [x29, #16]\t// x\n\tstr x0, [sp, #-16]!\n\
+             \tmov w0, #2\t// w0=2\n\tsxtb w0, w0\n\tsub sp, sp, #16\n\tstr x0, [sp]\n"
+        ));
+        assert!(assembly.contains(
+            "\tsub sp, sp, #16\n\tstr x2, [sp]\n\tldr x0, [sp, #16]\n\tldr x1, [sp, #0]\n\tbl _f\n\
+             \tmov x9, x0\n\tadd sp, sp, #32\n\tldr x0, [sp], #16\n\tmov x1, x9\n    mul x2, x0, x1\n"
+        ));
+    }
+
+    #[test]
+    fn test_external_calls() {
+        let assembly = compile_program(
+            "int printf(char *format, ...);\n\
+             int main() { char c; float f; printf(\"%c %f %d\", c, f, 42); return puts(\"done\"); }",
+        );
+
+        // Nothing is written for the declaration, the linker finds the function in the C library
+        assert!(!assembly.contains("_printf:"));
+        // The format is in x0, the variadic arguments in 8 byte words on the stack
+        assert!(assembly.contains(
+            "\tsub sp, sp, #32\n\tldr x0, [sp, #80]\n\tldr x9, [sp, #64]\n\tstr w9, [sp, #0]\n\
+             \tldr x9, [sp, #48]\n\tstr x9, [sp, #8]\n\tldr x9, [sp, #32]\n\tstr w9, [sp, #16]\n\tbl _printf\n"
+        ));
+        assert!(assembly.contains("\tfcvt d1, s0\n"));
+        // A function that is not declared is called all the same
+        assert!(assembly.contains("\tldr x0, [sp, #0]\n\tbl _puts\n"));
+    }
+
+    #[test]
+    fn test_function_pointers() {
+        let assembly = compile_program(
+            "int add(int a, int b);\n\
+             int (*table[2])(int, int);\n\
+             int main() { int (*fp)(int, int) = add; table[1] = fp; return table[1](1, 2); }",
+        );
+
+        // The address of a function in another file comes from the global offset table
+        assert!(assembly.contains("\tadrp x0, _add@GOTPAGE\n\tldr x0, [x0, _add@GOTPAGEOFF]\n\tstr x0, [x29, #16]\t// fp\n"));
+        // The pointer is kept above the arguments and loaded into x16 for the call
+        assert!(assembly.contains("\tldr x3, [x2]\n\tstr x3, [sp, #-16]!\n"));
+        assert!(assembly.contains("\tldr x0, [sp, #16]\n\tldr x1, [sp, #0]\n\tldr x16, [sp, #32]\n\tblr x16\n"));
+        assert!(!assembly.contains("\tbl _table"));
+    }
+
+    #[test]
+    fn test_linkage() {
+        let assembly = compile_program(
+            "static int counter; extern int shared; int tentative; int tentative;\n\
+             static int helper(void) { return counter + shared; }\n\
+             int main() { extern int other; return helper() + other + tentative; }",
+        );
+
+        // Only what other files can see is global, a static function is a local symbol
+        assert!(!assembly.contains(".global _helper"));
+        assert!(assembly.contains("\n.align 2\n_helper:\n"));
+        assert!(assembly.contains(".global _main\n"));
+        assert!(assembly.contains("\n.zerofill __DATA,__bss,_counter,4,2\n.globl _tentative\n.zerofill __DATA,__bss,_tentative,4,2\n"));
+        assert_eq!(assembly.matches("_tentative,").count(), 1);
+
+        // A global defined in another file is reached through the global offset table
+        assert!(assembly.contains("\tadrp x16, _counter@PAGE\n\tldr w0, [x16, _counter@PAGEOFF]\t// counter\n"));
+        assert!(assembly.contains("\tadrp x16, _shared@GOTPAGE\n\tldr x16, [x16, _shared@GOTPAGEOFF]\n\tldr w1, [x16]\t// shared\n"));
+        assert!(assembly.contains("\tadrp x16, _other@GOTPAGE\n"));
+        assert!(!assembly.contains("_shared:") && !assembly.contains("_shared,"));
+    }
+
+    #[test]
+    fn test_variadic_functions() {
+        let assembly = compile_program(
+            "double f(char c, long l, ...) { __builtin_va_list ap; __builtin_va_start(ap, l);\n\
+             return __builtin_va_arg(ap, double); }",
+        );
+
+        // The variadic arguments are right above the frame, as no parameter is on the stack
+        assert!(assembly.contains("\tadd x0, x29, #48\n\tstr x0, [x29, #32]\t// ap\n"));
+        assert!(assembly.contains("\tstr x3, [x29, #32]\t// ap\n\tldr d0, [x1]\n"));
+
+        // With parameters on the stack they come in the next word after those
+        let assembly = compile_program(
+            "int f(long a, long b, long c, long d, long e, long f, long g, long h, long i, char j, ...) {\n\
+             __builtin_va_list ap; __builtin_va_start(ap, j); }",
+        );
+        assert!(assembly.contains("\tstrb w9, [x29, #88]\t// j\n\tadd x0, x29, #128\n\tstr x0, [x29, #96]\t// ap\n"));
+    }
+
+    #[test]
+    fn test_linux_target() {
+        let assembly = compile_program_for(
+            "static int counter; extern int shared; int tentative; int table[2] = {1, 2};\n\
+             static char helper(void) { return counter + shared; }\n\
+             int main() { char *s = \"hi\"; char *t = \"a\\0b\"; return helper() + table[1] + tentative; }",
+            SupportedArchitectures::ARM64Linux,
+        );
+
+        // ELF names have no underscore in front, local labels start with .L
+        assert!(assembly.contains("\n.align 2\n.type helper, %function\nhelper:\n"));
+        assert!(assembly.contains("\tret\n.size main, .-main\n"));
+        assert!(assembly.contains("\tbl helper\n\tmov x9, x0\n\tmov x0, x9\n\tsxtb w0, w0\n"));
+        assert!(assembly.contains("\tb .L_return_main\n"));
+
+        assert!(assembly.contains("\tadrp x16, counter\n\tldr w0, [x16, :lo12:counter]\t// counter\n"));
+        assert!(assembly.contains("\tadrp x16, :got:shared\n\tldr x16, [x16, :got_lo12:shared]\n"));
+        assert!(assembly.contains("\tadrp x0, .L_.str.0\n\tadd x0, x0, :lo12:.L_.str.0\n"));
+
+        assert!(assembly.contains("\n.data\n.globl table\n.type table, %object\n.size table, 8\n.p2align 2\ntable:\n"));
+        assert!(assembly.contains("\n.bss\n.type counter, %object\n.size counter, 4\n.p2align 2\ncounter:\n\t.zero 4\n"));
+        assert!(assembly.contains("\n.section .rodata.str1.1,\"aMS\",@progbits,1\n.L_.str.0:\n\t.asciz \"hi\"\n"));
+        assert!(assembly.contains("\n.section .rodata\n.L_.str.1:\n"));
+        assert!(assembly.ends_with("\n.section .note.GNU-stack,\"\",@progbits\n"));
+        assert!(!assembly.contains("__DATA") && !assembly.contains("@PAGE"));
+    }
+
+    #[test]
+    fn test_linux_variadic_functions() {
+        let assembly = compile_program_for(
+            "struct pair { double x, y; };\n\
+             double f(char c, long l, ...) { __builtin_va_list ap; __builtin_va_start(ap, l);\n\
+             struct pair p = __builtin_va_arg(ap, struct pair); return __builtin_va_arg(ap, int) + p.y; }",
+            SupportedArchitectures::ARM64Linux,
+        );
+
+        // The argument registers are saved after the locals, then the va_list that va_start copies
+        assert!(assembly.contains("\tadd x9, x29, #96\n\tstp x0, x1, [x9, #0]\n"));
+        assert!(assembly.contains("\tstp q6, q7, [x9, #160]\n\tadd x10, x29, #320\n\tstr x10, [x9, #192]\t// __stack\n"));
+        // Two general registers hold parameters, no floating point ones do
+        assert!(assembly.contains("\tmov w10, #-48\t// w10=-48\n\tstr w10, [x9, #216]\t// __gr_offs\n"));
+        assert!(assembly.contains("\tmov w10, #-128\t// w10=-128\n\tstr w10, [x9, #220]\t// __vr_offs\n"));
+        assert!(assembly.contains("\tadd x0, x29, #288\n\tadd x1, x29, #32\n"));
+
+        // The members of the pair are in two saved floating point registers, or together on the stack
+        assert!(assembly.contains("\tldrsw x9, [x0, #28]\n\ttbz w9, #31, .L1\n\tadd w10, w9, #32\n"));
+        assert!(assembly.contains("\tldr x10, [x9, #0]\n\tstr x10, [x1, #0]\n\tldr x10, [x9, #16]\n\tstr x10, [x1, #8]\n\tb .L2\n"));
+        assert!(assembly.contains(".L1:\n\tldr x9, [x0, #0]\n\tadd x10, x9, #16\n\tstr x10, [x0, #0]\n"));
+        // An int takes the next saved general register, or 8 bytes of the stack
+        assert!(assembly.contains("\tldrsw x9, [x0, #24]\n\ttbz w9, #31, .L3\n\tadd w10, w9, #8\n"));
+        assert!(assembly.contains("\tldr x10, [x0, #8]\n\tadd x9, x10, x9\n\tb .L4\n"));
+        assert!(assembly.contains(".L4:\n\tldr w1, [x9]\n"));
+    }
+
+    #[test]
+    fn test_struct_arguments_and_results() {
+        let assembly = compile_program(
+            "struct small { int x, y; }; struct big { long a, b, c; };\n\
+             struct small pass(struct small s, struct big b) { return s; }\n\
+             struct big back(void) { struct big b; return b; }\n\
+             int main() { struct small s; struct big b; return pass(s, back()).y; }",
+        );
+
+        // The big struct arrives by address and is copied, the small one in a register
+        assert!(assembly.contains("_pass:\n\tsub sp, sp, #48\n\tstp x29, x30, [sp]\n\tmov x29, sp\n\tstr x0, [x29, #16]\t// s\n\tadd x14, x29, #24\n\tmov x10, x1\n"));
+        // A small struct is returned in x0 and x1
+        assert!(assembly.contains("\tsub sp, sp, #16\n\tmov x10, x0\n\tmov x11, sp\n\tldr x13, [x10], #8\n\tstr x13, [x11], #8\n\tldp x0, x1, [sp], #16\n"));
+        // A big one through the address the caller passes in x8, which is kept with the locals
+        assert!(assembly.contains("_back:\n\tsub sp, sp, #48\n\tstp x29, x30, [sp]\n\tmov x29, sp\n\tstr x8, [x29, #40]\n"));
+        assert!(assembly.contains("\tldr x9, [x29, #40]\n\tmov x10, x0\n\tmov x11, x9\n"));
+
+        // main: s at 16, b at 24, the temporary for back() at 48 and the one for pass() at 72
+        // s is kept on the stack while back() is called, b is copied for pass()
+        assert!(assembly.contains("\tadd x8, x29, #48\n\tbl _back\n\tadd x0, x29, #48\n\tsub sp, sp, #32\n"));
+        assert!(assembly.contains("\tldr x0, [sp, #32]\n\tadd x1, sp, #0\n\tbl _pass\n\tstr x0, [x29, #72]\n\tadd sp, sp, #48\n"));
+    }
+
+    #[test]
+    fn test_deep_expressions() {
+        // Each operand on the left waits in a register while the right one is worked out, the
+        // ones that do not fit wait on the stack
+        let assembly = compile_program("int f(int a, int b) { return a + (b + (a + (b + (a + b)))); }");
+        assert!(assembly.contains("\tldr w3, [x29, #20]\t// b\n\tstr x3, [sp, #-16]!\n\tldr w3, [x29, #16]\t// a\n\tstr x3, [sp, #-16]!\n"));
+        assert!(assembly.contains("\tldr x4, [sp], #16\n    add w4, w4, w3\n\tldr x3, [sp], #16\n    add w3, w3, w4\n    add w4, w2, w3\n"));
+
+        let assembly = compile_program("int g(int *p, int i) { return p[i] + p[i+1] * p[i+2] - p[i+3] / (p[i+4] + 1); }");
+        assert!(assembly.contains("[sp, #-16]!"));
+        assert_eq!(assembly.matches("[sp, #-16]!").count(), assembly.matches("[sp], #16").count());
+        assert!(assembly.contains("    sdiv"));
+    }
+}
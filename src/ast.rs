@@ -1,13 +1,169 @@
-use crate::scan::{Token, TokenError};
-use std::iter::Peekable;
-use std::vec::IntoIter;
+use crate::assembly::SupportedArchitectures;
+use crate::scan::{Keyword, SpannedToken, Token, TokenError, TokenSource};
+use crate::typecheck::{check_expression, evaluate_constant, Constant};
+use crate::types::{Record, RecordKind, Type};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+
+pub mod statement;
 
 #[derive(Debug, Clone)]
-#[derive(PartialEq)]
 pub struct ASTNode {
     pub operation: Token,
     pub(crate) left: Option<Box<ASTNode>>,
     pub(crate) right: Option<Box<ASTNode>>,
+    /// Filled in by the type checker, except for [`Token::Cast`] where the parser sets the target type
+    pub ty: Option<Type>,
+    /// The variable an identifier refers to, found by the type checker
+    pub symbol: Option<Rc<Symbol>>,
+    /// The token the node was parsed from, like the operator of a binary operation, for errors
+    pub position: Option<SpannedToken>,
+}
+
+/// Nodes are the same when they compute the same thing, wherever they were written
+impl PartialEq for ASTNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.operation == other.operation
+            && self.left == other.left
+            && self.right == other.right
+            && self.ty == other.ty
+            && self.symbol == other.symbol
+    }
+}
+
+/// A variable or function the program declared, shared by every node that refers to it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub ty: Type,
+    /// Numbers the locals of a function in declaration order, the code generator gives each a stack slot.
+    /// Symbols with static storage have no slot and use 0.
+    pub slot: usize,
+    pub storage: Storage,
+}
+
+impl Symbol {
+    /// Whether the symbol lasts for the whole run of the program, like functions and globals
+    pub fn is_static(&self) -> bool {
+        matches!(self.storage, Storage::Static { .. })
+    }
+}
+
+/// How long a symbol lives and where the code generator finds it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Storage {
+    /// A local that lives while its function runs, in the stack slot [`Symbol::slot`]
+    Automatic,
+    /// Lives for the whole run of the program at the assembly label made from `label`, which is
+    /// the name unless that is taken, as for `static` locals. Only `external` symbols can be
+    /// seen from other files.
+    Static { label: String, external: bool },
+}
+
+/// What a tag names. Structs, unions and enums share one set of tags.
+#[derive(Debug, Clone)]
+enum Tag {
+    Record(Rc<Record>),
+    /// Every enum is an `int`, so there is nothing to keep but the tag itself
+    Enum,
+}
+
+/// What an ordinary identifier means to the parser
+#[derive(Debug, Clone, PartialEq)]
+enum Ordinary {
+    /// A variable or function, which hides a typedef name of the same name from outer scopes
+    Object,
+    Typedef(Type),
+    Constant(i32),
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    tags: HashMap<String, Tag>,
+    ordinary: HashMap<String, Ordinary>,
+}
+
+/// The names the parser has to know, innermost scope last: tags, and which identifiers name
+/// types or enum constants. `T * x;` declares `x` when `T` is a typedef name and multiplies
+/// otherwise, so everything that parses declarations or expressions gets passed this.
+#[derive(Debug)]
+pub(crate) struct TypeNames {
+    scopes: Vec<Scope>,
+    /// The type `__builtin_va_list` names, which depends on the target
+    va_list: Type,
+}
+
+impl Default for TypeNames {
+    fn default() -> Self {
+        Self::for_target(SupportedArchitectures::default())
+    }
+}
+
+impl TypeNames {
+    pub(crate) fn for_target(architecture: SupportedArchitectures) -> Self {
+        Self {
+            scopes: vec![Scope::default()],
+            va_list: architecture.va_list(),
+        }
+    }
+
+    pub(crate) fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    pub(crate) fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn tag(&self, name: &str) -> Option<&Tag> {
+        self.scopes.iter().rev().find_map(|scope| scope.tags.get(name))
+    }
+
+    /// The tag declared in the innermost scope, ignoring the ones outside it
+    fn scope_tag(&self, name: &str) -> Option<&Tag> {
+        self.scopes.last().and_then(|scope| scope.tags.get(name))
+    }
+
+    fn declare_tag(&mut self, name: String, tag: Tag) {
+        self.scopes.last_mut().expect("No scope for the tag").tags.insert(name, tag);
+    }
+
+    fn ordinary(&self, name: &str) -> Option<&Ordinary> {
+        self.scopes.iter().rev().find_map(|scope| scope.ordinary.get(name))
+    }
+
+    /// The type a typedef name in scope stands for
+    fn typedef(&self, name: &str) -> Option<&Type> {
+        match self.ordinary(name) {
+            Some(Ordinary::Typedef(ty)) => Some(ty),
+            _ => None,
+        }
+    }
+
+    /// Declare an ordinary identifier in the innermost scope. A typedef name can be declared
+    /// again for the same type. Variables declared twice are left to the type checker.
+    fn declare(&mut self, name: &str, meaning: Ordinary) -> Result<(), ASTError> {
+        let scope = self.scopes.last_mut().expect("No scope for the name");
+        match scope.ordinary.get(name) {
+            Some(previous) if *previous != meaning || matches!(meaning, Ordinary::Constant(_)) => {
+                Err(ASTError::Redeclaration(name.to_string()))
+            }
+            _ => {
+                scope.ordinary.insert(name.to_string(), meaning);
+                Ok(())
+            }
+        }
+    }
+
+    /// Declare a variable or function, which hides typedef names outside the current scope
+    pub(crate) fn declare_object(&mut self, name: &str) -> Result<(), ASTError> {
+        self.declare(name, Ordinary::Object)
+    }
+
+    pub(crate) fn declare_typedef(&mut self, name: &str, ty: Type) -> Result<(), ASTError> {
+        self.declare(name, Ordinary::Typedef(ty))
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -18,6 +174,170 @@ pub enum ASTError {
     ExpectedInteger,
     EmptyExpression,
     InvalidLeafNode,
+    /// A `(` without its `)`
+    MissingClosingParenthesis,
+    /// A token the grammar requires here is missing, such as the `;` after a statement
+    ExpectedToken(Token),
+    ExpectedIdentifier,
+    /// Type specifiers that do not name a type, like `short char`
+    InvalidTypeSpecifiers,
+    UndeclaredIdentifier(String),
+    /// A name declared twice in the same scope
+    Redeclaration(String),
+    /// A variable of type `void`
+    VoidVariable(String),
+    /// Operands the operator does not accept, such as a string literal in a multiplication
+    InvalidOperands(Token, Type, Type),
+    /// A conversion C does not allow, from the first type to the second
+    InvalidConversion(Type, Type),
+    /// `return` with a value in a `void` function, or without one in any other
+    InvalidReturn(String),
+    /// The operand a unary operator does not accept, like dereferencing an `int`
+    InvalidOperand(Token, Type),
+    /// Something that does not designate an object where one is needed, like `&1` or `1 = x`
+    ExpectedLvalue,
+    /// An array length that is not a positive integer constant
+    InvalidArraySize,
+    /// A type whose size is needed but not known, like a variable of a struct type whose members are not declared
+    IncompleteType(Type),
+    /// A tag used for a struct after being declared for a union, or the other way round
+    WrongTagKind(String),
+    DuplicateMember(String),
+    /// A member the struct or union does not have
+    UnknownMember(String),
+    /// An array without a length that is not the last member of a struct
+    MisplacedFlexibleArray(String),
+    /// A call of something that is not a function
+    NotAFunction(Type),
+    ArgumentCount {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    /// An enum constant whose value is not an integer constant or does not fit in an `int`
+    InvalidEnumerator(String),
+    /// An enum tag used before its constants are declared
+    UnknownEnum(String),
+    /// The initialiser of a variable with static storage that is not known before the program runs
+    NotConstant(String),
+    /// An initialiser with more values than the object has room for, like `int a[2] = {1, 2, 3};`
+    TooManyInitializers,
+    /// A `{ ... }` list or a string literal initialising an array or a record with automatic
+    /// storage, only variables with static storage can have those
+    UnsupportedInitializer(String),
+    /// A storage class where it cannot be used, like `static` on a parameter
+    InvalidStorageClass(Keyword),
+    /// A switch on a value that is not an integer
+    InvalidSwitch(Type),
+    /// A `case` label whose value is not an integer constant
+    NonConstantCase,
+    /// Two `case` labels of one switch with the same value, once converted to the type of the switch
+    DuplicateCase(i64),
+    /// A second `default` label in one switch
+    DuplicateDefault,
+    /// A `case`, `default` or `break` outside of a switch
+    MisplacedStatement(Keyword),
+    /// A `goto` to a label the function does not have
+    UndefinedLabel(String),
+    /// Two labels with the same name in one function
+    DuplicateLabel(String),
+    /// `va_start` in a function whose parameters do not end with `...`
+    NotVariadic,
+    /// Another error with the token it was found at, see [`ASTError::at`]
+    At(Box<SpannedToken>, Box<ASTError>),
+}
+
+impl ASTError {
+    /// The error found at `token`, unless it already knows where it was found. Lexical errors
+    /// always do, and without a token the error stays as it is.
+    pub fn at(self, token: Option<&SpannedToken>) -> Self {
+        match (self, token) {
+            (error @ (ASTError::At(..) | ASTError::LexicalError(_)), _) | (error, None) => error,
+            (error, Some(token)) => ASTError::At(Box::new(token.clone()), Box::new(error)),
+        }
+    }
+
+    /// The error without where it was found
+    pub fn without_position(self) -> Self {
+        match self {
+            ASTError::At(_, error) => *error,
+            error => error,
+        }
+    }
+}
+
+/// Where a token was written, the same way [`TokenError`] shows it
+fn write_position(f: &mut fmt::Formatter<'_>, file: Option<&std::path::Path>, line: usize, column: usize) -> fmt::Result {
+    if let Some(file) = file {
+        write!(f, "{}, ", file.display())?;
+    }
+    write!(f, "line {}, column {}", line, column)
+}
+
+/// The message for the error, after its position and followed by the macro expansions the
+/// token came from, innermost first
+impl fmt::Display for ASTError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ASTError::At(token, error) => {
+                write_position(f, token.file.as_deref(), token.line, token.column)?;
+                write!(f, ": {}", error)?;
+                let mut expansion = token.expansion.as_deref();
+                while let Some(current) = expansion {
+                    write!(f, "\n  in expansion of macro '{}' at ", current.name)?;
+                    write_position(f, current.file.as_deref(), current.line, current.column)?;
+                    expansion = current.parent.as_deref();
+                }
+                Ok(())
+            }
+            ASTError::LexicalError(error) => write!(f, "{}", error),
+            ASTError::UnexpectedToken(token) => write!(f, "unexpected {:?}", token),
+            ASTError::ExpectedOperator => write!(f, "expected an operator"),
+            ASTError::ExpectedInteger => write!(f, "expected an integer"),
+            ASTError::EmptyExpression => write!(f, "expected an expression"),
+            ASTError::InvalidLeafNode => write!(f, "invalid operand"),
+            ASTError::MissingClosingParenthesis => write!(f, "expected ')'"),
+            ASTError::ExpectedToken(token) => write!(f, "expected {:?}", token),
+            ASTError::ExpectedIdentifier => write!(f, "expected an identifier"),
+            ASTError::InvalidTypeSpecifiers => write!(f, "invalid combination of type specifiers"),
+            ASTError::UndeclaredIdentifier(name) => write!(f, "use of undeclared identifier '{}'", name),
+            ASTError::Redeclaration(name) => write!(f, "redeclaration of '{}'", name),
+            ASTError::VoidVariable(name) => write!(f, "variable '{}' has type 'void'", name),
+            ASTError::InvalidOperands(operator, left, right) => {
+                write!(f, "invalid operands to {:?} ('{}' and '{}')", operator, left, right)
+            }
+            ASTError::InvalidConversion(from, to) => write!(f, "cannot convert '{}' to '{}'", from, to),
+            ASTError::InvalidReturn(function) => write!(f, "return does not match the return type of '{}'", function),
+            ASTError::InvalidOperand(operator, ty) => write!(f, "invalid operand to {:?} ('{}')", operator, ty),
+            ASTError::ExpectedLvalue => write!(f, "expression is not an object that can be assigned or addressed"),
+            ASTError::InvalidArraySize => write!(f, "array length is not a positive integer constant"),
+            ASTError::IncompleteType(ty) => write!(f, "incomplete type '{}'", ty),
+            ASTError::WrongTagKind(tag) => write!(f, "'{}' was declared as a different kind of tag", tag),
+            ASTError::DuplicateMember(name) => write!(f, "duplicate member '{}'", name),
+            ASTError::UnknownMember(name) => write!(f, "no member named '{}'", name),
+            ASTError::MisplacedFlexibleArray(name) => write!(f, "flexible array member '{}' is not at the end of the struct", name),
+            ASTError::NotAFunction(ty) => write!(f, "called object of type '{}' is not a function", ty),
+            ASTError::ArgumentCount { function, expected, found } => {
+                write!(f, "function '{}' expects {} arguments, but {} given", function, expected, found)
+            }
+            ASTError::InvalidEnumerator(name) => write!(f, "value of enumerator '{}' is not an integer constant that fits in an int", name),
+            ASTError::UnknownEnum(tag) => write!(f, "enum '{}' has no constants declared", tag),
+            ASTError::NotConstant(name) => write!(f, "initialiser of '{}' is not a constant", name),
+            ASTError::TooManyInitializers => write!(f, "too many values in initialiser"),
+            ASTError::UnsupportedInitializer(name) => {
+                write!(f, "'{}' is not static, only variables with static storage take a list or string literal here", name)
+            }
+            ASTError::InvalidStorageClass(keyword) => write!(f, "'{}' is not allowed here", format!("{:?}", keyword).to_lowercase()),
+            ASTError::InvalidSwitch(ty) => write!(f, "switch on a value of type '{}', which is not an integer", ty),
+            ASTError::NonConstantCase => write!(f, "case value is not an integer constant"),
+            ASTError::DuplicateCase(value) => write!(f, "duplicate case value {}", value),
+            ASTError::DuplicateDefault => write!(f, "multiple default labels in one switch"),
+            ASTError::MisplacedStatement(keyword) => write!(f, "'{}' outside of a switch", format!("{:?}", keyword).to_lowercase()),
+            ASTError::UndefinedLabel(name) => write!(f, "use of undeclared label '{}'", name),
+            ASTError::DuplicateLabel(name) => write!(f, "redefinition of label '{}'", name),
+            ASTError::NotVariadic => write!(f, "'va_start' used in a function without variadic parameters"),
+        }
+    }
 }
 
 impl ASTNode {
@@ -27,6 +347,9 @@ impl ASTNode {
                 operation: op,
                 left: Some(left),
                 right: Some(right),
+                ty: None,
+                symbol: None,
+                position: None,
             }),
             Err(token) => Err(ASTError::LexicalError(token)),
         }
@@ -36,7 +359,8 @@ impl ASTNode {
     ///
     /// # Arguments
     ///
-    /// * `operation`: Has to be [`crate::scan::Token::INT`] otherwise will return [ASTError]
+    /// * `operation`: Has to be a literal such as [`crate::scan::Token::INT`] or [`crate::scan::Token::STRING`],
+    ///   or an identifier, otherwise will return [ASTError]
     ///
     /// returns: Result<ASTNode, ASTError>
     ///
@@ -59,39 +383,341 @@ impl ASTNode {
     /// ```
     pub fn make_leaf(operation: Token) -> Result<Self, ASTError> {
         match operation {
-            Token::INT(_) => Ok(Self {
-                operation,
-                left: None,
-                right: None,
-            }),
+            Token::STRING(_) | Token::IDENTIFIER(_) | Token::FLOAT(_) | Token::DOUBLE(_) => Ok(Self::leaf(operation)),
+            _ if operation.integer_value().is_some() => Ok(Self::leaf(operation)),
             _ => Err(ASTError::InvalidLeafNode),
         }
     }
 
-    /// Gets operator precedence - higher means higher precedence
+    fn leaf(operation: Token) -> Self {
+        Self {
+            operation,
+            left: None,
+            right: None,
+            ty: None,
+            symbol: None,
+            position: None,
+        }
+    }
+
+    /// The node, parsed from `token` unless it already knows where it was written
+    fn written_at(self, token: Option<SpannedToken>) -> Self {
+        Self {
+            position: self.position.or(token),
+            ..self
+        }
+    }
+
+    /// Make a node converting `operand` to `ty`
+    pub fn cast(operand: ASTNode, ty: Type) -> Self {
+        Self {
+            ty: Some(ty),
+            ..Self::unary(Token::Cast, operand)
+        }
+    }
+
+    /// Make a node for a unary operator, such as [`Token::Dereference`]
+    pub fn unary(operation: Token, operand: ASTNode) -> Self {
+        Self {
+            left: Some(Box::new(operand)),
+            ..Self::leaf(operation)
+        }
+    }
+
+    /// Replace the node with `operation` applied to it, giving the new node the type `ty`
+    pub(crate) fn wrap(&mut self, operation: Token, ty: Type) {
+        let inner = Self {
+            operation: std::mem::replace(&mut self.operation, operation),
+            left: self.left.take(),
+            right: self.right.take(),
+            ty: self.ty.take(),
+            symbol: self.symbol.take(),
+            position: self.position.clone(),
+        };
+        self.left = Some(Box::new(inner));
+        self.ty = Some(ty);
+    }
+
+    /// Whether the node designates an object, which can have its address taken.
+    /// String literals are lvalues too, but being arrays they cannot be assigned to.
+    pub fn is_lvalue(&self) -> bool {
+        matches!(self.operation, Token::IDENTIFIER(_) | Token::Dereference | Token::STRING(_))
+    }
+
+    /// Gets operator precedence - higher means higher precedence. The levels are the ones of
+    /// the binary operators in the C grammar (C11 6.5.5 to 6.5.16).
     fn get_precedence(token: &Token) -> Result<u8, ASTError> {
         match token {
-            Token::PLUS | Token::MINUS => Ok(1),
-            Token::ASTERISK | Token::SLASH => Ok(2),
-            Token::EndOfLine | Token::EndOfFile => Err(ASTError::ExpectedOperator),
+            _ if *token == Token::ASSIGN || token.compound_operator().is_some() => Ok(1),
+            Token::QUESTION => Ok(2),
+            Token::LOGOR => Ok(3),
+            Token::LOGAND => Ok(4),
+            Token::OR => Ok(5),
+            Token::XOR => Ok(6),
+            Token::AMPER => Ok(7),
+            Token::EQ | Token::NE => Ok(8),
+            Token::LT | Token::GT | Token::LE | Token::GE => Ok(9),
+            Token::LSHIFT | Token::RSHIFT => Ok(10),
+            Token::PLUS | Token::MINUS => Ok(11),
+            Token::ASTERISK | Token::SLASH | Token::PERCENT => Ok(12),
+            Token::RPAREN | Token::EndOfLine | Token::EndOfFile => Err(ASTError::ExpectedOperator),
             _ => Ok(0),
         }
     }
 
+    /// Parse a unary expression: the prefix operators `&`, `*`, `+`, `-`, `~`, `!`, `++`, `--`
+    /// and `sizeof`, or a primary factor with its subscripts
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens`: the [`TokenSource`] to read from
+    /// * `names`: the tags and typedef names in scope
+    ///
+    /// returns: Result<ASTNode, ASTError>
+    fn parse_unary<T: TokenSource>(tokens: &mut T, names: &mut TypeNames) -> Result<Self, ASTError> {
+        let operation = match tokens.peek() {
+            Some(Ok(SpannedToken { token: Token::AMPER, .. })) => Token::AddressOf,
+            Some(Ok(SpannedToken { token: Token::ASTERISK, .. })) => Token::Dereference,
+            Some(Ok(SpannedToken { token: Token::MINUS, .. })) => Token::Negate,
+            Some(Ok(SpannedToken { token: Token::PLUS, .. })) => Token::UnaryPlus,
+            Some(Ok(SpannedToken { token: Token::INVERT, .. })) => Token::INVERT,
+            Some(Ok(SpannedToken { token: Token::LOGNOT, .. })) => Token::LOGNOT,
+            Some(Ok(SpannedToken { token: Token::INC, .. })) => Token::ASPLUS,
+            Some(Ok(SpannedToken { token: Token::DEC, .. })) => Token::ASMINUS,
+            Some(Ok(SpannedToken { token, .. })) if token.keyword() == Some(Keyword::Sizeof) => Token::Sizeof,
+            _ => return Self::parse_postfix(tokens, names),
+        };
+        let written = tokens.next_token().and_then(Result::ok);
+
+        // `++x` is the same as `x += 1` in C
+        if matches!(operation, Token::ASPLUS | Token::ASMINUS) {
+            let operand = Self::parse_unary(tokens, names)?;
+            let node = Self::new(Ok(operation), Box::new(operand), Box::new(Self::leaf(Token::INT(1))))?;
+            return Ok(node.written_at(written));
+        }
+
+        // `sizeof(type)` is known right away, `sizeof expression` once the expression is type checked
+        let is_type_name = matches!(tokens.peek(), Some(Ok(SpannedToken { token: Token::LPAREN, .. })))
+            && starts_type_name(names, tokens.peek_nth(1));
+        if operation == Token::Sizeof && is_type_name {
+            tokens.next_token();
+            let ty = parse_type_name(tokens, names)?;
+            expect_token(tokens, Token::RPAREN, ASTError::MissingClosingParenthesis)?;
+            if !ty.is_complete() {
+                return Err(ASTError::IncompleteType(ty));
+            }
+            return Self::make_leaf(Token::ULONG(ty.size() as u64));
+        }
+        Ok(Self::unary(operation, Self::parse_unary(tokens, names)?).written_at(written))
+    }
+
+    /// Parse a primary factor followed by any number of subscripts, calls, member accesses
+    /// and postfix `++` and `--`. `a[i]` is the same as `*(a + i)` in C and `p->m` the same
+    /// as `(*p).m`, so those are the nodes they make.
+    fn parse_postfix<T: TokenSource>(tokens: &mut T, names: &mut TypeNames) -> Result<Self, ASTError> {
+        let mut node = Self::parse_primary(tokens, names)?;
+        while let Some(Ok(written)) = tokens.peek() {
+            let written = written.clone();
+            let operation = written.token.clone();
+            match operation {
+                Token::LBRACKET => {
+                    tokens.next_token();
+                    let index = Self::parse_one_line_expression(tokens, names, 0)?;
+                    expect_token(tokens, Token::RBRACKET, ASTError::ExpectedToken(Token::RBRACKET))?;
+                    let sum = Self::new(Ok(Token::PLUS), Box::new(node), Box::new(index))?.written_at(Some(written.clone()));
+                    node = Self::unary(Token::Dereference, sum);
+                }
+                Token::LPAREN => {
+                    tokens.next_token();
+                    node = Self::parse_call(tokens, names, node)?;
+                }
+                Token::DOT | Token::ARROW => {
+                    tokens.next_token();
+                    let member = Self::leaf(Token::IDENTIFIER(parse_identifier(tokens)?));
+                    if operation == Token::ARROW {
+                        node = Self::unary(Token::Dereference, node).written_at(Some(written.clone()));
+                    }
+                    node = Self::new(Ok(Token::DOT), Box::new(node), Box::new(member))?;
+                }
+                Token::INC | Token::DEC => {
+                    tokens.next_token();
+                    let operation = if operation == Token::INC { Token::PostIncrement } else { Token::PostDecrement };
+                    node = Self::new(Ok(operation), Box::new(node), Box::new(Self::leaf(Token::INT(1))))?;
+                }
+                _ => break,
+            }
+            node = node.written_at(Some(written));
+        }
+        Ok(node)
+    }
+
+    /// Parse the arguments of a call after the `(`, up to and including the `)`
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens`: the [`TokenSource`] to read from
+    /// * `names`: the tags and typedef names in scope
+    /// * `function`: what is called
+    ///
+    /// returns: Result<ASTNode, ASTError>
+    fn parse_call<T: TokenSource>(tokens: &mut T, names: &mut TypeNames, function: ASTNode) -> Result<Self, ASTError> {
+        let mut arguments = Vec::new();
+        if !matches!(tokens.peek(), Some(Ok(SpannedToken { token: Token::RPAREN, .. }))) {
+            loop {
+                arguments.push(Self::parse_one_line_expression(tokens, names, 0)?);
+                match tokens.peek() {
+                    Some(Ok(SpannedToken { token: Token::COMMA, .. })) => tokens.next_token(),
+                    _ => break,
+                };
+            }
+        }
+        expect_token(tokens, Token::RPAREN, ASTError::MissingClosingParenthesis)?;
+
+        Ok(Self {
+            left: Some(Box::new(function)),
+            right: Self::argument_chain(arguments),
+            ..Self::leaf(Token::Call)
+        })
+    }
+
+    /// Make a `{ ... }` initialiser of type `ty` with `elements`
+    pub fn initializer_list(elements: Vec<ASTNode>, ty: Option<Type>) -> Self {
+        Self {
+            right: Self::argument_chain(elements),
+            ty,
+            ..Self::leaf(Token::InitializerList)
+        }
+    }
+
+    /// The [`Token::Argument`] nodes holding `values`, each one holds the next so they are built from the end
+    fn argument_chain(values: Vec<ASTNode>) -> Option<Box<ASTNode>> {
+        values.into_iter().rev().fold(None, |next, value| {
+            Some(Box::new(Self {
+                left: Some(Box::new(value)),
+                right: next,
+                ..Self::leaf(Token::Argument)
+            }))
+        })
+    }
+
+    /// The argument values of a [`Token::Call`] node, or the elements of a [`Token::InitializerList`], in order
+    pub fn arguments(&self) -> Vec<&ASTNode> {
+        let mut arguments = Vec::new();
+        let mut argument = self.right.as_deref();
+        while let Some(current) = argument {
+            arguments.extend(current.left.as_deref());
+            argument = current.right.as_deref();
+        }
+        arguments
+    }
+
+    /// Like [`ASTNode::arguments`], but taking the values out of the node
+    pub(crate) fn into_arguments(self) -> Vec<ASTNode> {
+        let mut arguments = Vec::new();
+        let mut argument = self.right;
+        while let Some(current) = argument {
+            arguments.extend(current.left.map(|value| *value));
+            argument = current.right;
+        }
+        arguments
+    }
+
     /// Parse a primary factor (numbers or parenthesized expressions)
     ///
     /// # Arguments
     ///
-    /// * `tokens`: a vector of Result<[crate::Token], [crate::TokenError]>
+    /// * `tokens`: the [`TokenSource`] to read from
+    /// * `names`: the names in scope, enum constants become integer literals
     ///
     /// returns: Result<ASTNode, ASTError>
-    fn parse_primary(tokens: &mut Peekable<IntoIter<Result<Token, TokenError>>>) -> Result<Self, ASTError> {
-        match tokens.next() {
-            Some(Ok(Token::INT(n))) => Self::make_leaf(Token::INT(n)),
-            Some(Ok(token)) => Err(ASTError::UnexpectedToken(token)),
+    fn parse_primary<T: TokenSource>(tokens: &mut T, names: &mut TypeNames) -> Result<Self, ASTError> {
+        let next = tokens.next_token();
+        let written = next.as_ref().and_then(|token| token.as_ref().ok()).cloned();
+        let node = match next.map(|token| token.map(|spanned| spanned.token)) {
+            Some(Ok(token)) if token.integer_value().is_some() => Self::make_leaf(token),
+            Some(Ok(token @ (Token::FLOAT(_) | Token::DOUBLE(_)))) => Self::make_leaf(token),
+            Some(Ok(Token::IDENTIFIER(name))) if Keyword::from_name(&name).is_none() => match names.ordinary(&name) {
+                Some(Ordinary::Constant(value)) => Self::make_leaf(Token::INT(*value)),
+                _ => Self::make_leaf(Token::IDENTIFIER(name)),
+            },
+            Some(Ok(Token::STRING(mut bytes))) => {
+                // Adjacent string literals are a single literal, even when they are on different lines.
+                // Line breaks after the last string are left alone, they still end the expression.
+                let mut lookahead = 0;
+                let mut strings_end = 0;
+                loop {
+                    match tokens.peek_nth(lookahead) {
+                        Some(Ok(SpannedToken { token: Token::STRING(_), .. })) => {
+                            lookahead += 1;
+                            strings_end = lookahead;
+                        }
+                        Some(Ok(SpannedToken { token: Token::EndOfLine, .. })) => lookahead += 1,
+                        _ => break,
+                    }
+                }
+                for _ in 0..strings_end {
+                    if let Some(Ok(SpannedToken { token: Token::STRING(next), .. })) = tokens.next_token() {
+                        bytes.extend(next);
+                    }
+                }
+                Self::make_leaf(Token::STRING(bytes))
+            }
+            // `(` followed by a type is a cast, which applies to the unary expression after it
+            Some(Ok(Token::LPAREN)) if starts_type_name(names, tokens.peek()) => {
+                let ty = parse_type_name(tokens, names)?;
+                expect_token(tokens, Token::RPAREN, ASTError::MissingClosingParenthesis)?;
+                Ok(Self::cast(Self::parse_unary(tokens, names)?, ty))
+            }
+            Some(Ok(Token::LPAREN)) => {
+                let expression = Self::parse_one_line_expression(tokens, names, 0)?;
+                match tokens.next_token() {
+                    Some(Ok(SpannedToken { token: Token::RPAREN, .. })) => Ok(expression),
+                    Some(Err(token_error)) => Err(ASTError::LexicalError(token_error)),
+                    _ => Err(ASTError::MissingClosingParenthesis),
+                }
+            }
+            Some(Ok(token)) => match token.keyword() {
+                Some(keyword @ (Keyword::VaStart | Keyword::VaArg | Keyword::VaEnd | Keyword::VaCopy)) => {
+                    Self::parse_builtin(tokens, names, keyword)
+                }
+                _ => Err(ASTError::UnexpectedToken(token)),
+            },
             Some(Err(token_error)) => Err(ASTError::LexicalError(token_error)),
             None => Err(ASTError::EmptyExpression),
-        }
+        };
+        node.map(|node| node.written_at(written))
+    }
+
+    /// Parse the operands of a builtin after its name, from the `(` up to and including the `)`.
+    /// `__builtin_va_arg` takes a type name as its second operand, which no function could.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens`: the [`TokenSource`] to read from
+    /// * `names`: the names in scope
+    /// * `builtin`: which builtin it is
+    ///
+    /// returns: Result<ASTNode, ASTError>
+    fn parse_builtin<T: TokenSource>(tokens: &mut T, names: &mut TypeNames, builtin: Keyword) -> Result<Self, ASTError> {
+        expect_token(tokens, Token::LPAREN, ASTError::ExpectedToken(Token::LPAREN))?;
+        let list = Self::parse_one_line_expression(tokens, names, 0)?;
+        let node = match builtin {
+            Keyword::VaEnd => Self::unary(Token::VaEnd, list),
+            Keyword::VaArg => {
+                expect_token(tokens, Token::COMMA, ASTError::ExpectedToken(Token::COMMA))?;
+                Self {
+                    ty: Some(parse_type_name(tokens, names)?),
+                    ..Self::unary(Token::VaArg, list)
+                }
+            }
+            _ => {
+                expect_token(tokens, Token::COMMA, ASTError::ExpectedToken(Token::COMMA))?;
+                let operation = if builtin == Keyword::VaStart { Token::VaStart } else { Token::VaCopy };
+                Self::new(Ok(operation), Box::new(list), Box::new(Self::parse_one_line_expression(tokens, names, 0)?))?
+            }
+        };
+        expect_token(tokens, Token::RPAREN, ASTError::MissingClosingParenthesis)?;
+        Ok(node)
     }
 
 
@@ -99,35 +725,52 @@ impl ASTNode {
     ///
     /// # Arguments
     ///
-    /// * `tokens`: a peekable iterable of results of the token, as gotten from the scanner [`crate::scan_file`]
+    /// * `tokens`: the [`TokenSource`] to read from, usually a [`crate::scan::Lexer`]
+    /// * `names`: the names in scope, for casts, `sizeof` and enum constants
     /// * `min_precedence`: to be set to 0 in the call
     ///
     /// returns: Result<ASTNode, ASTError>
-    fn parse_one_line_expression(
-        tokens: &mut Peekable<IntoIter<Result<Token, TokenError>>>,
+    fn parse_one_line_expression<T: TokenSource>(
+        tokens: &mut T,
+        names: &mut TypeNames,
         min_precedence: u8,
     ) -> Result<Self, ASTError> {
-        let mut left: ASTNode = Self::parse_primary(tokens)?;
+        let mut left: ASTNode = Self::parse_unary(tokens, names)?;
 
-        while let Some(Ok(op)) = tokens.peek().cloned() {
+        while let Some(Ok(SpannedToken { token: op, .. })) = tokens.peek().cloned() {
             let precedence = match Self::get_precedence(&op) {
                 Ok(precedence) => precedence,
                 Err(_) => break,
             };
 
-            if precedence < min_precedence {
+            // Anything that is not a binary operator ends the expression, like the `;` of a statement
+            if precedence == 0 || precedence < min_precedence {
                 break;
             }
 
             // Consume the operator, handling potential errors
-            match tokens.next() {
-                Some(Ok(_)) => (), // We already know it's valid from the peek
+            let written = match tokens.next_token() {
+                Some(Ok(written)) => Some(written), // We already know it's valid from the peek
                 Some(Err(err)) => return Err(ASTError::LexicalError(err)),
                 None => return Err(ASTError::ExpectedOperator),
+            };
+
+            // `c ? a : b` keeps both results under a `:` node. The middle can be any expression,
+            // as it is closed by the `:`.
+            if op == Token::QUESTION {
+                let when_true = Self::parse_one_line_expression(tokens, names, 0)?;
+                expect_token(tokens, Token::COLON, ASTError::ExpectedToken(Token::COLON))?;
+                let when_false = Self::parse_one_line_expression(tokens, names, precedence)?;
+                let results = Self::new(Ok(Token::COLON), Box::new(when_true), Box::new(when_false))?;
+                left = Self::new(Ok(op), Box::new(left), Box::new(results))?.written_at(written);
+                continue;
             }
 
-            let right: ASTNode = Self::parse_one_line_expression(tokens, precedence + 1)?;
-            left = Self::new(Ok(op), Box::new(left), Box::new(right))?;
+            // Assignment groups right to left, `a = b += c` is `a = (b += c)`
+            let is_assignment = op == Token::ASSIGN || op.compound_operator().is_some();
+            let next_precedence = if is_assignment { precedence } else { precedence + 1 };
+            let right: ASTNode = Self::parse_one_line_expression(tokens, names, next_precedence)?;
+            left = Self::new(Ok(op), Box::new(left), Box::new(right))?.written_at(written);
         }
 
         // Handle error token if present
@@ -160,20 +803,47 @@ impl ASTNode {
     /// assert_eq!(ast.operation, Token::PLUS);
     /// ```
     pub fn parse(tokens: Vec<Result<Token, TokenError>>) -> Result<Self, ASTError> {
-        if tokens.is_empty() {
+        // Tokens built by hand have no position, everything is on line 0
+        let mut token_queue: VecDeque<Result<SpannedToken, TokenError>> = tokens
+            .into_iter()
+            .map(|token| token.map(|token| SpannedToken::new(token, 0, 0, 0)))
+            .collect();
+        Self::parse_stream(&mut token_queue)
+    }
+
+    /// Parse straight from a token source, without collecting the tokens first
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens`: where the tokens come from, usually a [`crate::scan::Lexer`]
+    ///
+    /// returns: Result<ASTNode, ASTError>
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::ast::*;
+    /// # use compiler::scan::*;
+    /// let mut lexer = Lexer::new("2 * 3 + 4".as_bytes());
+    /// let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+    /// assert_eq!(ast.operation, Token::PLUS);
+    /// ```
+    pub fn parse_stream<T: TokenSource>(tokens: &mut T) -> Result<Self, ASTError> {
+        if tokens.peek().is_none() {
             return Err(ASTError::EmptyExpression);
         }
 
-        let mut token_iter = tokens.into_iter().peekable();
-        Self::parse_one_line_expression(&mut token_iter, 0)
+        Self::parse_one_line_expression(tokens, &mut TypeNames::default(), 0)
     }
 
     /// ## *For testing only!*
     /// Helper method to test_evaluate the AST (for testing)
     /// Will test_evaluate the AST
-    fn test_evaluate(&self) -> Result<i32, ASTError> {
+    #[cfg(test)]
+    pub(crate) fn test_evaluate(&self) -> Result<i32, ASTError> {
         match &self.operation {
             Token::INT(n) => Ok(*n),
+            Token::CHAR(_) => Ok(self.operation.integer_value().unwrap_or_default() as i32),
             Token::PLUS => {
                 let left = self.left.as_ref().ok_or(ASTError::ExpectedInteger)?.test_evaluate()?;
                 let right = self.right.as_ref().ok_or(ASTError::ExpectedInteger)?.test_evaluate()?;
@@ -198,17 +868,500 @@ impl ASTNode {
                     Ok(left / right)
                 }
             }
-            Token::EndOfFile => Err(ASTError::UnexpectedToken(Token::EndOfFile)),
-            Token::EndOfLine => Err(ASTError::UnexpectedToken(Token::EndOfLine)),
+            other => Err(ASTError::UnexpectedToken(other.clone())),
         }
     }
 }
 
 
+/// Whether a token is a type specifier keyword, like `int` or `struct`
+fn is_type_specifier(token: Option<&Result<SpannedToken, TokenError>>) -> bool {
+    matches!(token, Some(Ok(spanned)) if spanned.token.keyword().is_some_and(|keyword| keyword.is_type_specifier()))
+}
+
+/// The type a token stands for if it is a typedef name in scope
+fn typedef_name<'a>(names: &'a TypeNames, token: Option<&Result<SpannedToken, TokenError>>) -> Option<&'a Type> {
+    match token {
+        Some(Ok(SpannedToken { token: Token::IDENTIFIER(name), .. })) => names.typedef(name),
+        _ => None,
+    }
+}
+
+/// Whether a token starts a type name, as in a cast, a parameter or a member
+pub(crate) fn starts_type_name(names: &TypeNames, token: Option<&Result<SpannedToken, TokenError>>) -> bool {
+    is_type_specifier(token) || typedef_name(names, token).is_some()
+}
+
+/// Whether a token starts a declaration, which can also begin with a storage class like `typedef`
+pub(crate) fn starts_declaration(names: &TypeNames, token: Option<&Result<SpannedToken, TokenError>>) -> bool {
+    let is_storage_class =
+        matches!(token, Some(Ok(spanned)) if spanned.token.keyword().is_some_and(|keyword| keyword.is_storage_class()));
+    is_storage_class || starts_type_name(names, token)
+}
+
+/// Parse the specifiers at the start of a declaration: an optional storage class followed
+/// by the type specifiers
+///
+/// # Arguments
+///
+/// * `tokens`: the [`TokenSource`] to read from, the next token has to start a declaration
+/// * `names`: the names in scope
+///
+/// returns: Result<(Option<Keyword>, Type), ASTError>, the storage class and the type
+pub(crate) fn parse_declaration_specifiers<T: TokenSource>(
+    tokens: &mut T,
+    names: &mut TypeNames,
+) -> Result<(Option<Keyword>, Type), ASTError> {
+    let storage_class = match tokens.peek() {
+        Some(Ok(SpannedToken { token, .. })) => token.keyword().filter(Keyword::is_storage_class),
+        _ => None,
+    };
+    if storage_class.is_some() {
+        tokens.next_token();
+    }
+    Ok((storage_class, parse_type_specifiers(tokens, names)?))
+}
+
+/// Parse the type specifiers at the start of a declaration or type name, like `unsigned long`.
+/// A struct, union, enum or typedef name is the whole type. An identifier after other
+/// specifiers is what they declare, even if it is a typedef name outside this scope.
+///
+/// # Arguments
+///
+/// * `tokens`: the [`TokenSource`] to read from, the next token has to start a type name
+/// * `names`: the names in scope, a struct, union or enum specifier can declare new ones
+///
+/// returns: Result<Type, ASTError>
+pub(crate) fn parse_type_specifiers<T: TokenSource>(tokens: &mut T, names: &mut TypeNames) -> Result<Type, ASTError> {
+    if let Some(ty) = typedef_name(names, tokens.peek()).cloned() {
+        tokens.next_token();
+        if is_type_specifier(tokens.peek()) {
+            return Err(ASTError::InvalidTypeSpecifiers);
+        }
+        return Ok(ty);
+    }
+
+    let mut specifiers: Vec<Keyword> = Vec::new();
+    while is_type_specifier(tokens.peek()) {
+        let keyword = match tokens.next_token() {
+            Some(Ok(SpannedToken { token, .. })) => token.keyword(),
+            _ => None,
+        };
+        let ty = match keyword {
+            Some(Keyword::Struct) => parse_record_specifier(tokens, names, RecordKind::Struct)?,
+            Some(Keyword::Union) => parse_record_specifier(tokens, names, RecordKind::Union)?,
+            Some(Keyword::Enum) => parse_enum_specifier(tokens, names)?,
+            Some(Keyword::VaList) => names.va_list.clone(),
+            _ => {
+                specifiers.extend(keyword);
+                continue;
+            }
+        };
+
+        if !specifiers.is_empty() || is_type_specifier(tokens.peek()) {
+            return Err(ASTError::InvalidTypeSpecifiers);
+        }
+        return Ok(ty);
+    }
+    Type::from_specifiers(&specifiers).ok_or(ASTError::InvalidTypeSpecifiers)
+}
+
+/// Parse what follows `struct` or `union`: a tag, the members in braces, or both.
+/// A definition, or a tag followed by `;` as in `struct node;`, declares a new type in the
+/// innermost scope. Any other tag refers to the type it names in scope, and declares it
+/// as an incomplete type if there is none.
+///
+/// # Arguments
+///
+/// * `tokens`: the [`TokenSource`] to read from, the keyword has been consumed
+/// * `names`: the tags in scope
+/// * `kind`: whether it is a struct or a union
+///
+/// returns: Result<Type, ASTError>
+fn parse_record_specifier<T: TokenSource>(tokens: &mut T, names: &mut TypeNames, kind: RecordKind) -> Result<Type, ASTError> {
+    let tag = match tokens.peek() {
+        Some(Ok(SpannedToken { token: Token::IDENTIFIER(name), .. })) if Keyword::from_name(name).is_none() => Some(name.clone()),
+        _ => None,
+    };
+    if tag.is_some() {
+        tokens.next_token();
+    }
+    let defines = matches!(tokens.peek(), Some(Ok(SpannedToken { token: Token::LBRACE, .. })));
+    let declares = defines || matches!(tokens.peek(), Some(Ok(SpannedToken { token: Token::SEMICOLON, .. })));
+
+    let record = match tag {
+        None if defines => Record::new(kind, None),
+        None => return Err(ASTError::ExpectedIdentifier),
+        Some(tag) => {
+            let visible = if declares { names.scope_tag(&tag) } else { names.tag(&tag) };
+            match visible {
+                Some(Tag::Record(record)) if record.kind != kind => return Err(ASTError::WrongTagKind(tag)),
+                Some(Tag::Enum) => return Err(ASTError::WrongTagKind(tag)),
+                Some(Tag::Record(record)) if defines && record.is_complete() => return Err(ASTError::Redeclaration(tag)),
+                Some(Tag::Record(record)) => record.clone(),
+                None => {
+                    let record = Record::new(kind, Some(tag.clone()));
+                    names.declare_tag(tag, Tag::Record(record.clone()));
+                    record
+                }
+            }
+        }
+    };
+
+    // The tag is declared before the members, so they can point to the type itself
+    if defines {
+        tokens.next_token();
+        let members = parse_members(tokens, names, kind)?;
+        record.define(members).map_err(ASTError::DuplicateMember)?;
+    }
+    Ok(Type::Record(record))
+}
+
+/// Parse what follows `enum`: a tag, the constants in braces, or both. The constants are
+/// declared in the innermost scope and count up from 0, or from the value of the last one
+/// given. Every enum is an `int`, so it has to be defined before its tag is used.
+///
+/// # Arguments
+///
+/// * `tokens`: the [`TokenSource`] to read from, the keyword has been consumed
+/// * `names`: the names in scope
+///
+/// returns: Result<Type, ASTError>
+fn parse_enum_specifier<T: TokenSource>(tokens: &mut T, names: &mut TypeNames) -> Result<Type, ASTError> {
+    let tag = match tokens.peek() {
+        Some(Ok(SpannedToken { token: Token::IDENTIFIER(name), .. })) if Keyword::from_name(name).is_none() => Some(name.clone()),
+        _ => None,
+    };
+    if tag.is_some() {
+        tokens.next_token();
+    }
+    if !matches!(tokens.peek(), Some(Ok(SpannedToken { token: Token::LBRACE, .. }))) {
+        let tag = tag.ok_or(ASTError::ExpectedIdentifier)?;
+        return match names.tag(&tag) {
+            Some(Tag::Enum) => Ok(Type::Int),
+            Some(Tag::Record(_)) => Err(ASTError::WrongTagKind(tag)),
+            None => Err(ASTError::UnknownEnum(tag)),
+        };
+    }
+
+    if let Some(tag) = tag {
+        match names.scope_tag(&tag) {
+            Some(Tag::Enum) => return Err(ASTError::Redeclaration(tag)),
+            Some(Tag::Record(_)) => return Err(ASTError::WrongTagKind(tag)),
+            None => names.declare_tag(tag, Tag::Enum),
+        }
+    }
+
+    tokens.next_token();
+    let mut next = Some(0);
+    loop {
+        let name = parse_identifier(tokens)?;
+        if let Some(Ok(SpannedToken { token: Token::ASSIGN, .. })) = tokens.peek() {
+            tokens.next_token();
+            next = integer_constant(ASTNode::parse_one_line_expression(tokens, names, 0)?);
+        }
+        let value = next
+            .and_then(|value| i32::try_from(value).ok())
+            .ok_or_else(|| ASTError::InvalidEnumerator(name.clone()))?;
+        names.declare(&name, Ordinary::Constant(value))?;
+        next = Some(i64::from(value) + 1);
+
+        // The list can end with a `,`
+        match tokens.peek() {
+            Some(Ok(SpannedToken { token: Token::COMMA, .. })) => tokens.next_token(),
+            _ => break,
+        };
+        if let Some(Ok(SpannedToken { token: Token::RBRACE, .. })) = tokens.peek() {
+            break;
+        }
+    }
+    expect_token(tokens, Token::RBRACE, ASTError::ExpectedToken(Token::RBRACE))?;
+    Ok(Type::Int)
+}
+
+/// The value of an integer constant expression, like the length of an array or the value of
+/// an enumerator, `None` if the expression is not one. It is worked out in the types the type
+/// checker gives it, as it would be at run time, so `(0u - 1) >> 28` is 15.
+fn integer_constant(mut node: ASTNode) -> Option<i64> {
+    check_expression(&mut node).ok()?;
+    match evaluate_constant(&node)? {
+        Constant::Integer(value) if node.ty.as_ref().is_some_and(Type::is_integer) => Some(value),
+        _ => None,
+    }
+}
+
+/// Parse the member declarations of a struct or union up to and including the `}`.
+/// Every member needs a complete type, except for an array without a length at the end of
+/// a struct with other members, its flexible array member.
+fn parse_members<T: TokenSource>(tokens: &mut T, names: &mut TypeNames, kind: RecordKind) -> Result<Vec<(Option<String>, Type)>, ASTError> {
+    let mut members = Vec::new();
+    loop {
+        match tokens.peek() {
+            Some(Ok(SpannedToken { token: Token::RBRACE, .. })) => {
+                tokens.next_token();
+                break;
+            }
+            token if starts_type_name(names, token) => {}
+            Some(Err(token_error)) => return Err(ASTError::LexicalError(token_error.clone())),
+            Some(Ok(SpannedToken { token, .. })) if *token != Token::EndOfFile => return Err(ASTError::UnexpectedToken(token.clone())),
+            _ => return Err(ASTError::ExpectedToken(Token::RBRACE)),
+        }
+
+        let base = parse_type_specifiers(tokens, names)?;
+        if let (Type::Record(record), Some(Ok(SpannedToken { token: Token::SEMICOLON, .. }))) = (&base, tokens.peek()) {
+            // A struct or union without a tag or a name is an anonymous member, whose members
+            // belong to this one. With a tag it only declares the tag.
+            if record.tag.is_none() {
+                members.push((None, base));
+            }
+            tokens.next_token();
+            continue;
+        }
+
+        loop {
+            let (name, ty) = parse_declarator(tokens, names, base.clone())?;
+            members.push((Some(name.ok_or(ASTError::ExpectedIdentifier)?), ty));
+            match tokens.peek() {
+                Some(Ok(SpannedToken { token: Token::COMMA, .. })) => tokens.next_token(),
+                _ => break,
+            };
+        }
+        expect_token(tokens, Token::SEMICOLON, ASTError::ExpectedToken(Token::SEMICOLON))?;
+    }
+
+    let last = members.len().saturating_sub(1);
+    for (index, (name, ty)) in members.iter().enumerate() {
+        let flexible = matches!(ty, Type::Array(element, 0) if element.is_complete());
+        match name {
+            _ if ty.is_complete() => {}
+            Some(_) if flexible && index == last && index > 0 && kind == RecordKind::Struct => {}
+            Some(name) if flexible => return Err(ASTError::MisplacedFlexibleArray(name.clone())),
+            _ => return Err(ASTError::IncompleteType(ty.clone())),
+        }
+    }
+    Ok(members)
+}
+
+/// Parse a type name, as in casts and `sizeof`: type specifiers and a declarator without a
+/// name, like `int (*)(int)`
+pub(crate) fn parse_type_name<T: TokenSource>(tokens: &mut T, names: &mut TypeNames) -> Result<Type, ASTError> {
+    let base = parse_type_specifiers(tokens, names)?;
+    match parse_declarator(tokens, names, base)? {
+        (None, ty) => Ok(ty),
+        (Some(name), _) => Err(ASTError::UnexpectedToken(Token::IDENTIFIER(name))),
+    }
+}
+
+/// Consume an identifier that is not a keyword and return its name
+pub(crate) fn parse_identifier<T: TokenSource>(tokens: &mut T) -> Result<String, ASTError> {
+    match tokens.next_token() {
+        Some(Ok(SpannedToken { token: Token::IDENTIFIER(name), .. })) if Keyword::from_name(&name).is_none() => Ok(name),
+        Some(Err(token_error)) => Err(ASTError::LexicalError(token_error)),
+        _ => Err(ASTError::ExpectedIdentifier),
+    }
+}
+
+/// Consume the next token, which has to be `expected`
+fn expect_token<T: TokenSource>(tokens: &mut T, expected: Token, error: ASTError) -> Result<(), ASTError> {
+    match tokens.next_token() {
+        Some(Ok(SpannedToken { token, .. })) if token == expected => Ok(()),
+        Some(Err(token_error)) => Err(ASTError::LexicalError(token_error)),
+        _ => Err(error),
+    }
+}
+
+/// A parameter in a function declarator, its name and its type
+pub(crate) type Parameter = (Option<String>, Type);
+
+/// What [`parse_function_declarator`] finds: the name, the type, and the parameters when the
+/// type is a function
+pub(crate) type FunctionDeclarator = (Option<String>, Type, Option<Vec<Parameter>>);
+
+/// One step from the type named by the specifiers towards the type of a declarator
+enum Derivation {
+    Pointer,
+    Array(usize),
+    Function(Vec<Parameter>, bool),
+}
+
+/// Parse a declarator after the type specifiers, like `*a[4]` or `(*handlers[2])(int)`:
+/// the name it declares and the type the name gets. The `[]`s and `()`s after a name bind
+/// tighter than the `*`s before it and parentheses group, so `int *f(int)` is a function
+/// returning a pointer while `int (*f)(int)` is a pointer to a function. The name is left out
+/// in type names and unnamed parameters.
+///
+/// # Arguments
+///
+/// * `tokens`: the [`TokenSource`] to read from
+/// * `names`: the names in scope, for array lengths and parameter types
+/// * `base`: the type named by the specifiers
+///
+/// returns: Result<(Option<String>, Type), ASTError>
+pub(crate) fn parse_declarator<T: TokenSource>(
+    tokens: &mut T,
+    names: &mut TypeNames,
+    base: Type,
+) -> Result<(Option<String>, Type), ASTError> {
+    let (name, derivations) = parse_derivations(tokens, names)?;
+    Ok((name, derived_type(base, derivations)))
+}
+
+/// Parse a declarator like [`parse_declarator`] does. When it declares a function, also
+/// return the names and types of the parameters, which a definition of the function needs.
+/// Those are the parameters right after the name, `int (*f(int a))(long)` declares a
+/// function with the parameter `a`.
+///
+/// returns: Result<FunctionDeclarator, ASTError>
+pub(crate) fn parse_function_declarator<T: TokenSource>(
+    tokens: &mut T,
+    names: &mut TypeNames,
+    base: Type,
+) -> Result<FunctionDeclarator, ASTError> {
+    let (name, derivations) = parse_derivations(tokens, names)?;
+    // The last derivation is the one applied to the name itself
+    let parameters = match derivations.last() {
+        Some(Derivation::Function(parameters, _)) => Some(parameters.clone()),
+        _ => None,
+    };
+    Ok((name, derived_type(base, derivations), parameters))
+}
+
+/// The type that `derivations` make of `base`, in order
+fn derived_type(base: Type, derivations: Vec<Derivation>) -> Type {
+    derivations.into_iter().fold(base, |ty, derivation| match derivation {
+        Derivation::Pointer => ty.pointer_to(),
+        Derivation::Array(length) => ty.array_of(length),
+        Derivation::Function(parameters, variadic) => Type::Function {
+            return_type: Box::new(ty),
+            parameters: parameters.into_iter().map(|(_, ty)| ty).collect(),
+            variadic,
+        },
+    })
+}
+
+/// The name of a declarator and its derivations in the order they apply to the base type
+fn parse_derivations<T: TokenSource>(
+    tokens: &mut T,
+    names: &mut TypeNames,
+) -> Result<(Option<String>, Vec<Derivation>), ASTError> {
+    let mut derivations = Vec::new();
+    while let Some(Ok(SpannedToken { token: Token::ASTERISK, .. })) = tokens.peek() {
+        tokens.next_token();
+        derivations.push(Derivation::Pointer);
+    }
+
+    // `(*` starts a declarator in parentheses, which applies after everything around it
+    let nested = matches!(tokens.peek(), Some(Ok(SpannedToken { token: Token::LPAREN, .. })))
+        && matches!(tokens.peek_nth(1), Some(Ok(SpannedToken { token: Token::ASTERISK, .. })));
+    let (name, inner) = if nested {
+        tokens.next_token();
+        let inner = parse_derivations(tokens, names)?;
+        expect_token(tokens, Token::RPAREN, ASTError::ExpectedToken(Token::RPAREN))?;
+        inner
+    } else {
+        let name = match tokens.peek() {
+            Some(Ok(SpannedToken { token: Token::IDENTIFIER(name), .. })) if Keyword::from_name(name).is_none() => {
+                Some(name.clone())
+            }
+            _ => None,
+        };
+        if name.is_some() {
+            tokens.next_token();
+        }
+        (name, Vec::new())
+    };
+
+    // The first suffix is the outermost, `int a[2][3]` is an array of two arrays of three
+    // ints. Only its length can be left out, `[]` makes an array of unknown length 0, which
+    // is up to the caller to allow.
+    let mut suffixes = Vec::new();
+    loop {
+        match tokens.peek() {
+            Some(Ok(SpannedToken { token: Token::LBRACKET, .. })) => {
+                tokens.next_token();
+                if suffixes.is_empty() && matches!(tokens.peek(), Some(Ok(SpannedToken { token: Token::RBRACKET, .. }))) {
+                    tokens.next_token();
+                    suffixes.push(Derivation::Array(0));
+                    continue;
+                }
+                let length = integer_constant(ASTNode::parse_one_line_expression(tokens, names, 0)?)
+                    .filter(|length| *length > 0)
+                    .ok_or(ASTError::InvalidArraySize)?;
+                expect_token(tokens, Token::RBRACKET, ASTError::ExpectedToken(Token::RBRACKET))?;
+                suffixes.push(Derivation::Array(length as usize));
+            }
+            Some(Ok(SpannedToken { token: Token::LPAREN, .. })) => {
+                // The names of the parameters are only visible up to the `)`
+                tokens.next_token();
+                names.push_scope();
+                let parameters = parse_parameters(tokens, names);
+                names.pop_scope();
+                let (parameters, variadic) = parameters?;
+                suffixes.push(Derivation::Function(parameters, variadic));
+            }
+            _ => break,
+        }
+    }
+
+    derivations.extend(suffixes.into_iter().rev());
+    derivations.extend(inner);
+    Ok((name, derivations))
+}
+
+/// Parse the parameters of a function after the `(`, up to and including the `)`, and
+/// whether they end with `...`. `()` and `(void)` both declare a function without
+/// parameters. A parameter declared as an array or a function is a pointer to one. Parameters
+/// can go without a name, the named ones are declared in the current scope.
+///
+/// # Arguments
+///
+/// * `tokens`: the [`TokenSource`] to read from
+/// * `names`: the names in scope, the parameters hide typedef names outside the current scope
+///
+/// returns: Result<(Vec<Parameter>, bool), ASTError>
+pub(crate) fn parse_parameters<T: TokenSource>(
+    tokens: &mut T,
+    names: &mut TypeNames,
+) -> Result<(Vec<Parameter>, bool), ASTError> {
+    let is_void = matches!(tokens.peek(), Some(Ok(SpannedToken { token, .. })) if token.keyword() == Some(Keyword::Void))
+        && matches!(tokens.peek_nth(1), Some(Ok(SpannedToken { token: Token::RPAREN, .. })));
+    if is_void {
+        tokens.next_token();
+    }
+    if !starts_type_name(names, tokens.peek()) {
+        expect_token(tokens, Token::RPAREN, ASTError::ExpectedToken(Token::RPAREN))?;
+        return Ok((Vec::new(), false));
+    }
+
+    let mut parameters = Vec::new();
+    let mut variadic = false;
+    loop {
+        let base = parse_type_specifiers(tokens, names)?;
+        let (name, ty) = parse_declarator(tokens, names, base)?;
+        if let Some(name) = &name {
+            names.declare_object(name)?;
+        }
+        parameters.push((name, ty.decayed()));
+
+        if !matches!(tokens.peek(), Some(Ok(SpannedToken { token: Token::COMMA, .. }))) {
+            break;
+        }
+        tokens.next_token();
+        if let Some(Ok(SpannedToken { token: Token::ELLIPSIS, .. })) = tokens.peek() {
+            tokens.next_token();
+            variadic = true;
+            break;
+        }
+    }
+    expect_token(tokens, Token::RPAREN, ASTError::ExpectedToken(Token::RPAREN))?;
+    Ok((parameters, variadic))
+}
+
 // Updated tests to handle Results
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scan::{Lexer, TokenErrorKind};
     
     #[test]
     fn test_get_precedence_enf_of_line() {
@@ -274,15 +1427,103 @@ mod tests {
         assert_eq!(ast.test_evaluate().unwrap(), 14);
     }
 
+    #[test]
+    fn test_bitwise_operator_precedence() {
+        let value = |source: &str| integer_constant(ASTNode::parse_stream(&mut Lexer::new(source.as_bytes())).unwrap());
+        assert_eq!(value("1 | 6 ^ 3 & 2"), Some(1 | (6 ^ (3 & 2))));
+        assert_eq!(value("1 << 2 + 1"), Some(8));
+        assert_eq!(value("32 >> 1 >> 2"), Some(4));
+        assert_eq!(value("1 + 17 % 5 * 2"), Some(5));
+        assert_eq!(value("1 << 64"), None);
+
+        // Comparisons bind tighter than the bitwise operators, shifts looser than arithmetic
+        let ast = ASTNode::parse_stream(&mut Lexer::new("a & b == c".as_bytes())).unwrap();
+        assert_eq!((ast.operation, ast.right.unwrap().operation), (Token::AMPER, Token::EQ));
+        let ast = ASTNode::parse_stream(&mut Lexer::new("a < b << c".as_bytes())).unwrap();
+        assert_eq!((ast.operation, ast.right.unwrap().operation), (Token::LT, Token::LSHIFT));
+        let ast = ASTNode::parse_stream(&mut Lexer::new("a | b && c".as_bytes())).unwrap();
+        assert_eq!((ast.operation, ast.left.unwrap().operation), (Token::LOGAND, Token::OR));
+    }
+
+    #[test]
+    fn test_logical_and_conditional_operators() {
+        let parse = |source: &str| ASTNode::parse_stream(&mut Lexer::new(source.as_bytes()));
+
+        // && binds tighter than ||, which binds tighter than ?:
+        let ast = parse("a || b && c == d").unwrap();
+        assert_eq!(ast.operation, Token::LOGOR);
+        assert_eq!(ast.right.as_ref().unwrap().operation, Token::LOGAND);
+        assert_eq!(ast.right.unwrap().right.unwrap().operation, Token::EQ);
+
+        // ?: groups right to left, and the middle can even be an assignment
+        let ast = parse("x = a || b ? c = 1 : d ? e : f").unwrap();
+        assert_eq!(ast.operation, Token::ASSIGN);
+        let conditional = ast.right.unwrap();
+        assert_eq!(conditional.operation, Token::QUESTION);
+        assert_eq!(conditional.left.unwrap().operation, Token::LOGOR);
+        let results = conditional.right.unwrap();
+        assert_eq!(results.operation, Token::COLON);
+        assert_eq!(results.left.unwrap().operation, Token::ASSIGN);
+        assert_eq!(results.right.unwrap().operation, Token::QUESTION);
+
+        assert_eq!(parse("a ? b\n"), Err(ASTError::ExpectedToken(Token::COLON)));
+        assert_eq!(parse("a ? b ; c"), Err(ASTError::ExpectedToken(Token::COLON)));
+    }
+
+    #[test]
+    fn test_assignment_operators() {
+        let parse = |source: &str| ASTNode::parse_stream(&mut Lexer::new(source.as_bytes())).unwrap();
+
+        // Every assignment groups right to left, and binds looser than ?:
+        let ast = parse("a += b <<= c ? d : e");
+        assert_eq!(ast.operation, Token::ASPLUS);
+        let right = ast.right.unwrap();
+        assert_eq!(right.operation, Token::ASLSHIFT);
+        assert_eq!(right.right.unwrap().operation, Token::QUESTION);
+
+        // Prefix `++` is `+= 1`, postfix binds tighter than `*`
+        let ast = parse("++*p");
+        assert_eq!((ast.operation, ast.right.unwrap().operation), (Token::ASPLUS, Token::INT(1)));
+        assert_eq!(ast.left.unwrap().operation, Token::Dereference);
+        let ast = parse("*p--");
+        assert_eq!(ast.operation, Token::Dereference);
+        let decrement = ast.left.unwrap();
+        assert_eq!(decrement.operation, Token::PostDecrement);
+        assert_eq!(decrement.left.unwrap().operation, Token::IDENTIFIER("p".to_string()));
+        let ast = parse("a[i]++ - --b");
+        assert_eq!(ast.operation, Token::MINUS);
+        assert_eq!(ast.left.unwrap().operation, Token::PostIncrement);
+        assert_eq!(ast.right.unwrap().operation, Token::ASMINUS);
+    }
+
+    #[test]
+    fn test_variadic_builtins() {
+        let parse = |source: &str| ASTNode::parse_stream(&mut Lexer::new(source.as_bytes()));
+
+        let ast = parse("__builtin_va_arg(*lists, unsigned long) + 1").unwrap();
+        let argument = ast.left.unwrap();
+        assert_eq!((&argument.operation, &argument.ty), (&Token::VaArg, &Some(Type::UnsignedLong)));
+        assert_eq!(argument.left.unwrap().operation, Token::Dereference);
+        let ast = parse("__builtin_va_copy(a, b)").unwrap();
+        assert_eq!(ast.operation, Token::VaCopy);
+        assert_eq!(ast.right.unwrap().operation, Token::IDENTIFIER("b".to_string()));
+
+        assert_eq!(parse("__builtin_va_end ap").unwrap_err(), ASTError::ExpectedToken(Token::LPAREN));
+        assert_eq!(parse("__builtin_va_arg(ap)").unwrap_err(), ASTError::ExpectedToken(Token::COMMA));
+        assert_eq!(parse("__builtin_va_start(ap, n, m)").unwrap_err(), ASTError::MissingClosingParenthesis);
+    }
+
     #[test]
     fn test_with_lexical_error() {
         let tokens = vec![
             Ok(Token::INT(5)),
             Ok(Token::PLUS),
             Err(TokenError {
+                file: None,
                 line: 1,
                 column: 5,
                 character: '@',
+                kind: TokenErrorKind::InvalidCharacter,
             }),
         ];
 
@@ -300,10 +1541,85 @@ mod tests {
     #[test]
     fn test_invalid_expression() {
         let tokens = vec![
-            Ok(Token::PLUS),
+            Ok(Token::SLASH),
             Ok(Token::INT(5)),
         ];
-        assert!(matches!(ASTNode::parse(tokens), Err(_)));
+        assert!(ASTNode::parse(tokens).is_err());
+    }
+
+    #[test]
+    fn test_parse_wide_literals() {
+        let tokens = vec![
+            Ok(Token::LONG(4294967296)),
+            Ok(Token::PLUS),
+            Ok(Token::UINT(7)),
+        ];
+
+        let ast = ASTNode::parse(tokens).unwrap();
+        assert_eq!(ast.operation, Token::PLUS);
+        assert_eq!(ast.left.unwrap().operation, Token::LONG(4294967296));
+        assert_eq!(ast.right.unwrap().operation, Token::UINT(7));
+    }
+
+    #[test]
+    fn test_parse_concatenates_adjacent_strings() {
+        let tokens = vec![
+            Ok(Token::STRING(b"Hello, ".to_vec())),
+            Ok(Token::EndOfLine),
+            Ok(Token::STRING(b"world".to_vec())),
+            Ok(Token::STRING(b"!\n".to_vec())),
+            Ok(Token::EndOfLine),
+            Ok(Token::EndOfFile),
+        ];
+
+        let ast = ASTNode::parse(tokens).unwrap();
+        assert_eq!(ast, ASTNode::make_leaf(Token::STRING(b"Hello, world!\n".to_vec())).unwrap());
+    }
+
+    #[test]
+    fn test_string_concatenation_leaves_the_line_break() {
+        let mut tokens: VecDeque<Result<SpannedToken, TokenError>> = VecDeque::from([
+            Ok(SpannedToken::new(Token::STRING(b"a".to_vec()), 1, 1, 0)),
+            Ok(SpannedToken::new(Token::STRING(b"b".to_vec()), 1, 5, 4)),
+            Ok(SpannedToken::new(Token::EndOfLine, 1, 8, 7)),
+            Ok(SpannedToken::new(Token::INT(1), 2, 1, 8)),
+        ]);
+
+        let ast = ASTNode::parse_stream(&mut tokens).unwrap();
+        assert_eq!(ast.operation, Token::STRING(b"ab".to_vec()));
+        assert_eq!(tokens.front().unwrap().as_ref().unwrap().token, Token::EndOfLine);
+    }
+
+    #[test]
+    fn test_parse_from_lexer() {
+        let mut lexer = Lexer::new("1 + 2 * 3 // seven\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(ast.test_evaluate().unwrap(), 7);
+
+        // Only the first line was read
+        assert_eq!(lexer.next().unwrap().unwrap().token, Token::EndOfLine);
+    }
+
+    #[test]
+    fn test_parenthesized_expression() {
+        let mut lexer = Lexer::new("(1 + 2) * (3 - (4))\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(ast.test_evaluate().unwrap(), -3);
+
+        let mut lexer = Lexer::new("(1 + 2\n".as_bytes());
+        assert_eq!(ASTNode::parse_stream(&mut lexer), Err(ASTError::MissingClosingParenthesis));
+    }
+
+    #[test]
+    fn test_character_constants_are_integers() {
+        let tokens = vec![
+            Ok(Token::CHAR(b'a')),
+            Ok(Token::PLUS),
+            Ok(Token::INT(1)),
+        ];
+
+        let ast = ASTNode::parse(tokens).unwrap();
+        assert_eq!(ast.test_evaluate().unwrap(), 98);
     }
 
     #[test]
@@ -320,6 +1636,117 @@ mod tests {
             operation: Token::INT(4),
             left: None,
             right: None,
+            ty: None,
+            symbol: None,
+            position: None,
         }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_unary_operators() {
+        let mut lexer = Lexer::new("*p * &x\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(ast.operation, Token::ASTERISK);
+        assert_eq!(ast.left.as_ref().unwrap().operation, Token::Dereference);
+        assert_eq!(ast.right.as_ref().unwrap().operation, Token::AddressOf);
+
+        let mut lexer = Lexer::new("(char **) **q\n".as_bytes());
+        let cast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(cast.ty, Some(Type::Char.pointer_to().pointer_to()));
+        let operand = cast.left.unwrap();
+        assert_eq!(operand.operation, Token::Dereference);
+        assert_eq!(operand.left.unwrap().operation, Token::Dereference);
+
+        // Prefix operators bind tighter than any binary one and nest to the right
+        let ast = ASTNode::parse_stream(&mut Lexer::new("-a * ~b - !-c + +d".as_bytes())).unwrap();
+        assert_eq!(ast.operation, Token::PLUS);
+        assert_eq!(ast.right.as_ref().unwrap().operation, Token::UnaryPlus);
+        let difference = ast.left.unwrap();
+        let product = difference.left.as_ref().unwrap();
+        assert_eq!(product.left.as_ref().unwrap().operation, Token::Negate);
+        assert_eq!(product.right.as_ref().unwrap().operation, Token::INVERT);
+        let not = difference.right.as_ref().unwrap();
+        assert_eq!((&not.operation, &not.left.as_ref().unwrap().operation), (&Token::LOGNOT, &Token::Negate));
+
+        let ast = ASTNode::parse_stream(&mut Lexer::new("a - -1".as_bytes())).unwrap();
+        assert_eq!((ast.operation, ast.right.unwrap().operation), (Token::MINUS, Token::Negate));
+    }
+
+    #[test]
+    fn test_assignment_and_comparison_precedence() {
+        let mut lexer = Lexer::new("a = b = 1 + 2 < 4 == 1\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(ast.operation, Token::ASSIGN);
+
+        // Assignment groups to the right
+        let inner = ast.right.unwrap();
+        assert_eq!(inner.operation, Token::ASSIGN);
+        let equality = inner.right.unwrap();
+        assert_eq!(equality.operation, Token::EQ);
+        let less = equality.left.unwrap();
+        assert_eq!(less.operation, Token::LT);
+        assert_eq!(less.left.unwrap().operation, Token::PLUS);
+    }
+
+    #[test]
+    fn test_subscripts_and_sizeof() {
+        let mut lexer = Lexer::new("a[1][i + 2]\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(ast.operation, Token::Dereference);
+        let sum = ast.left.unwrap();
+        assert_eq!(sum.operation, Token::PLUS);
+        assert_eq!(sum.right.unwrap().operation, Token::PLUS);
+        assert_eq!(sum.left.unwrap().operation, Token::Dereference);
+
+        let mut lexer = Lexer::new("sizeof(long *[3]) + sizeof x[0]\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(ast.left.unwrap().operation, Token::ULONG(24));
+        let sizeof = ast.right.unwrap();
+        assert_eq!(sizeof.operation, Token::Sizeof);
+        assert_eq!(sizeof.left.unwrap().operation, Token::Dereference);
+
+        let mut lexer = Lexer::new("(char [2 * 3]) x\n".as_bytes());
+        assert_eq!(ASTNode::parse_stream(&mut lexer).unwrap().ty, Some(Type::Char.array_of(6)));
+
+        let mut lexer = Lexer::new("a[1\n".as_bytes());
+        assert_eq!(ASTNode::parse_stream(&mut lexer), Err(ASTError::ExpectedToken(Token::RBRACKET)));
+        let mut lexer = Lexer::new("sizeof(int[0])\n".as_bytes());
+        assert_eq!(ASTNode::parse_stream(&mut lexer), Err(ASTError::InvalidArraySize));
+    }
+
+    #[test]
+    fn test_member_access_and_calls() {
+        // p->next.value is (*p).next.value
+        let mut lexer = Lexer::new("p->next.value\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(ast.operation, Token::DOT);
+        assert_eq!(ast.right.unwrap().operation, Token::IDENTIFIER("value".to_string()));
+        let inner = ast.left.unwrap();
+        assert_eq!(inner.operation, Token::DOT);
+        assert_eq!(inner.left.unwrap().operation, Token::Dereference);
+
+        let mut lexer = Lexer::new("f(1, g(), a[0] = 2).x * 3\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(ast.operation, Token::ASTERISK);
+        let call = ast.left.unwrap().left.unwrap();
+        assert_eq!(call.operation, Token::Call);
+        assert_eq!(call.left.as_ref().unwrap().operation, Token::IDENTIFIER("f".to_string()));
+        let arguments: Vec<&Token> = call.arguments().into_iter().map(|argument| &argument.operation).collect();
+        assert_eq!(arguments, vec![&Token::INT(1), &Token::Call, &Token::ASSIGN]);
+        assert!(call.arguments()[1].arguments().is_empty());
+
+        let mut lexer = Lexer::new("sizeof(struct { char c; long l; }) + sizeof(union { int i; char s[6]; } *)\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut lexer).unwrap();
+        assert_eq!(ast.left.unwrap().operation, Token::ULONG(16));
+        assert_eq!(ast.right.unwrap().operation, Token::ULONG(8));
+
+        let mut lexer = Lexer::new("f(1, 2\n".as_bytes());
+        assert_eq!(ASTNode::parse_stream(&mut lexer), Err(ASTError::MissingClosingParenthesis));
+        let mut lexer = Lexer::new("s. 1\n".as_bytes());
+        assert_eq!(ASTNode::parse_stream(&mut lexer), Err(ASTError::ExpectedIdentifier));
+        let mut lexer = Lexer::new("sizeof(struct s)\n".as_bytes());
+        assert!(matches!(ASTNode::parse_stream(&mut lexer), Err(ASTError::IncompleteType(Type::Record(_)))));
+        let mut lexer = Lexer::new("sizeof(int[])\n".as_bytes());
+        assert_eq!(ASTNode::parse_stream(&mut lexer), Err(ASTError::IncompleteType(Type::Int.array_of(0))));
+    }
+}
@@ -0,0 +1,1016 @@
+use crate::assembly::SupportedArchitectures;
+use crate::ast::{
+    parse_declaration_specifiers, parse_declarator, parse_function_declarator, parse_identifier,
+    starts_declaration, ASTError, ASTNode, Parameter, Storage, Symbol, TypeNames,
+};
+use crate::scan::{Keyword, SpannedToken, Token, TokenError, TokenSource};
+use crate::types::Type;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// An expression evaluated for its side effects, `None` is the empty statement `;`
+    Expression(Option<ASTNode>),
+    /// A local variable, it gets the value of the initialiser every time the declaration is reached.
+    /// A `static` one gets it once before the program starts, like a global. An `extern` one is
+    /// a global defined elsewhere, the declaration only brings it into scope.
+    Declaration(Rc<Symbol>, Option<ASTNode>),
+    /// `return value;`, with the `return` token for errors
+    Return(Option<ASTNode>, Option<SpannedToken>),
+    /// `{ ... }`, which opens a new scope
+    Block(Vec<Statement>),
+    /// `switch (value) body`. The `case` labels can be anywhere in the body, except inside
+    /// another switch. The type checker fills in `cases`, the value of each `case` label in the
+    /// order they appear, and whether there is a `default` label.
+    Switch {
+        value: ASTNode,
+        body: Box<Statement>,
+        cases: Vec<i64>,
+        default: bool,
+    },
+    /// `case value:` and the statement it labels, with the `case` token for errors
+    Case(ASTNode, Box<Statement>, Option<SpannedToken>),
+    /// `default:` and the statement it labels, with the `default` token for errors
+    Default(Box<Statement>, Option<SpannedToken>),
+    /// `break;`, which leaves the innermost switch, with the `break` token for errors
+    Break(Option<SpannedToken>),
+    /// `name:` and the statement it labels, with the name token for errors. Labels belong to the
+    /// whole function, whatever block they are in.
+    Label(String, Box<Statement>, Option<SpannedToken>),
+    /// `goto name;`, with the name token for errors
+    Goto(String, Option<SpannedToken>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub return_type: Type,
+    /// The parameters in order, which are also the first [`Function::locals`]
+    pub parameters: Vec<Rc<Symbol>>,
+    /// Whether the parameters end with `...`
+    pub variadic: bool,
+    /// Whether other files can call the function, a `static` one is only seen in its own file
+    pub external: bool,
+    pub body: Vec<Statement>,
+    /// Every local variable of the function, indexed by [`Symbol::slot`]
+    pub locals: Vec<Rc<Symbol>>,
+}
+
+impl Function {
+    /// The type of the function, as in calls
+    pub fn ty(&self) -> Type {
+        Type::Function {
+            return_type: Box::new(self.return_type.clone()),
+            parameters: self.parameters.iter().map(|parameter| parameter.ty.clone()).collect(),
+            variadic: self.variadic,
+        }
+    }
+}
+
+/// A definition at file scope
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternalDeclaration {
+    Function(Function),
+    /// A function declared without its body, like `int printf(char *format, ...);`. It can be
+    /// defined later in the file, or in another one such as the C library.
+    FunctionDeclaration(Rc<Symbol>),
+    /// A global variable and its initialiser, which has to be a constant expression
+    Variable(Rc<Symbol>, Option<ASTNode>),
+    /// A global variable declared `extern` without an initialiser, which is defined later in
+    /// the file or in another one
+    VariableDeclaration(Rc<Symbol>),
+}
+
+/// Everything in one source file after preprocessing
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TranslationUnit {
+    /// In the order they appear, a name can only be used after its declaration
+    pub declarations: Vec<ExternalDeclaration>,
+    /// The target the program was parsed for, which the type checker and the code generator follow
+    pub architecture: SupportedArchitectures,
+}
+
+impl TranslationUnit {
+    /// Parse a whole program. Unlike [`ASTNode::parse_stream`], line breaks mean nothing here.
+    /// Besides functions and global variables, the file can declare struct, union and enum
+    /// types and typedef names.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens`: where the tokens come from, usually a [`crate::preprocess::Preprocessor`]
+    ///
+    /// returns: Result<TranslationUnit, ASTError>, errors are [`ASTError::At`] the last token read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::ast::statement::*;
+    /// # use compiler::scan::*;
+    /// let mut lexer = Lexer::new("int main() {\n  long x = 2;\n  return x * 3;\n}\n".as_bytes());
+    /// let unit = TranslationUnit::parse(&mut lexer).unwrap();
+    /// assert_eq!(unit.functions()[0].name, "main");
+    /// assert_eq!(unit.functions()[0].locals[0].name, "x");
+    /// ```
+    pub fn parse<T: TokenSource>(tokens: &mut T) -> Result<Self, ASTError> {
+        Self::parse_for_target(tokens, SupportedArchitectures::default())
+    }
+
+    /// Parse a whole program for `architecture`, like [`Self::parse`] does for ARM64 Darwin.
+    /// Only the type of `va_list` depends on the target.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::assembly::SupportedArchitectures;
+    /// # use compiler::ast::statement::*;
+    /// # use compiler::scan::*;
+    /// let mut lexer = Lexer::new("__builtin_va_list ap;".as_bytes());
+    /// let unit = TranslationUnit::parse_for_target(&mut lexer, SupportedArchitectures::ARM64Linux).unwrap();
+    /// let ExternalDeclaration::Variable(ap, _) = &unit.declarations[0] else { panic!() };
+    /// assert_eq!(ap.ty.size(), 32);
+    /// ```
+    pub fn parse_for_target<T: TokenSource>(tokens: &mut T, architecture: SupportedArchitectures) -> Result<Self, ASTError> {
+        let mut parser = Parser {
+            tokens: IgnoreLineBreaks { tokens, last: None },
+            names: TypeNames::for_target(architecture),
+            locals: Vec::new(),
+            static_locals: 0,
+        };
+
+        let mut declarations = Vec::new();
+        loop {
+            match parser.tokens.peek() {
+                None | Some(Ok(SpannedToken { token: Token::EndOfFile, .. })) => break,
+                Some(Err(token_error)) => return Err(ASTError::LexicalError(token_error.clone())),
+                Some(Ok(_)) => {
+                    // Errors are reported at the last token read, which is where the parser noticed
+                    let declaration = parser.external_declaration();
+                    declarations.extend(declaration.map_err(|error| error.at(parser.tokens.last.as_ref()))?);
+                }
+            }
+        }
+        Ok(Self { declarations, architecture })
+    }
+
+    /// The functions defined, in order
+    pub fn functions(&self) -> Vec<&Function> {
+        self.declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                ExternalDeclaration::Function(function) => Some(function),
+                ExternalDeclaration::FunctionDeclaration(_)
+                | ExternalDeclaration::Variable(..)
+                | ExternalDeclaration::VariableDeclaration(_) => None,
+            })
+            .collect()
+    }
+}
+
+
+/// Hides line breaks from the parser, statements do not care where lines end
+struct IgnoreLineBreaks<'a, T: TokenSource> {
+    tokens: &'a mut T,
+    /// The last token read, for errors
+    last: Option<SpannedToken>,
+}
+
+impl<T: TokenSource> TokenSource for IgnoreLineBreaks<'_, T> {
+    fn next_token(&mut self) -> Option<Result<SpannedToken, TokenError>> {
+        loop {
+            match self.tokens.next_token() {
+                Some(Ok(SpannedToken { token: Token::EndOfLine, .. })) => continue,
+                Some(Ok(token)) => {
+                    self.last = Some(token.clone());
+                    return Some(Ok(token));
+                }
+                token => return token,
+            }
+        }
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&Result<SpannedToken, TokenError>> {
+        let mut index = 0;
+        let mut seen = 0;
+        loop {
+            match self.tokens.peek_nth(index)? {
+                Ok(SpannedToken { token: Token::EndOfLine, .. }) => {}
+                _ if seen == n => break,
+                _ => seen += 1,
+            }
+            index += 1;
+        }
+        self.tokens.peek_nth(index)
+    }
+}
+
+
+struct Parser<'a, T: TokenSource> {
+    tokens: IgnoreLineBreaks<'a, T>,
+    names: TypeNames,
+    /// Locals of the function being parsed
+    locals: Vec<Rc<Symbol>>,
+    /// How many `static` locals there have been, which numbers their labels
+    static_locals: usize,
+}
+
+impl<T: TokenSource> Parser<'_, T> {
+    fn peek_token(&mut self) -> Option<&Token> {
+        match self.tokens.peek() {
+            Some(Ok(spanned)) => Some(&spanned.token),
+            _ => None,
+        }
+    }
+
+    /// Consume the next token if it is `token`
+    fn next_is(&mut self, token: &Token) -> bool {
+        let found = self.peek_token() == Some(token);
+        if found {
+            self.tokens.next_token();
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ASTError> {
+        match self.tokens.next_token() {
+            Some(Ok(SpannedToken { token, .. })) if token == expected => Ok(()),
+            Some(Err(token_error)) => Err(ASTError::LexicalError(token_error)),
+            _ => Err(ASTError::ExpectedToken(expected)),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, ASTError> {
+        parse_identifier(&mut self.tokens)
+    }
+
+    fn expression(&mut self) -> Result<ASTNode, ASTError> {
+        ASTNode::parse_one_line_expression(&mut self.tokens, &mut self.names, 0)
+    }
+
+    /// Add a local to the function being parsed, it hides typedef names from here on
+    fn local(&mut self, name: String, ty: Type) -> Result<Rc<Symbol>, ASTError> {
+        self.names.declare_object(&name)?;
+        let symbol = Rc::new(Symbol {
+            name,
+            ty,
+            slot: self.locals.len(),
+            storage: Storage::Automatic,
+        });
+        self.locals.push(symbol.clone());
+        Ok(symbol)
+    }
+
+    /// Add a variable with static storage, global or `static` local
+    fn static_variable(&mut self, name: String, ty: Type, label: String, external: bool) -> Result<Rc<Symbol>, ASTError> {
+        self.names.declare_object(&name)?;
+        Ok(Rc::new(Symbol {
+            name,
+            ty,
+            slot: 0,
+            storage: Storage::Static { label, external },
+        }))
+    }
+
+    /// The `= value` after a declarator, if there is one
+    fn initializer(&mut self) -> Result<Option<ASTNode>, ASTError> {
+        if self.next_is(&Token::ASSIGN) {
+            Ok(Some(self.initial_value()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// An expression, or a `{ ... }` list of values, which can be lists themselves and end with a `,`
+    fn initial_value(&mut self) -> Result<ASTNode, ASTError> {
+        if !self.next_is(&Token::LBRACE) {
+            return self.expression();
+        }
+        let mut elements = Vec::new();
+        while !self.next_is(&Token::RBRACE) {
+            elements.push(self.initial_value()?);
+            if !self.next_is(&Token::COMMA) {
+                self.expect(Token::RBRACE)?;
+                break;
+            }
+        }
+        Ok(ASTNode::initializer_list(elements, None))
+    }
+
+    /// A declarator after the specifiers, like `*a[4]`: the name and the type it gets
+    fn declarator(&mut self, base: Type) -> Result<(String, Type), ASTError> {
+        // The `*`s belong to each declarator, not to the specifiers
+        let (name, ty) = parse_declarator(&mut self.tokens, &mut self.names, base)?;
+        Ok((name.ok_or(ASTError::ExpectedIdentifier)?, ty))
+    }
+
+    /// The declarators of a `typedef` up to and including the `;`, each one names a type
+    fn typedef(&mut self, base: Type) -> Result<(), ASTError> {
+        loop {
+            let (name, ty) = self.declarator(base.clone())?;
+            self.names.declare_typedef(&name, ty)?;
+            if !self.next_is(&Token::COMMA) {
+                break;
+            }
+        }
+        self.expect(Token::SEMICOLON)
+    }
+
+    /// A function or its declaration, global variables, a `typedef`, or a declaration of nothing but types like
+    /// `struct point { int x, y; };`. A `static` function or global cannot be seen from other files, an
+    /// `extern` global is only declared unless it is initialised.
+    fn external_declaration(&mut self) -> Result<Vec<ExternalDeclaration>, ASTError> {
+        if !starts_declaration(&self.names, self.tokens.peek()) {
+            return match self.tokens.next_token() {
+                Some(Ok(SpannedToken { token, .. })) => Err(ASTError::UnexpectedToken(token)),
+                Some(Err(token_error)) => Err(ASTError::LexicalError(token_error)),
+                None => Err(ASTError::EmptyExpression),
+            };
+        }
+        let (storage_class, base) = parse_declaration_specifiers(&mut self.tokens, &mut self.names)?;
+        let mut declarations = Vec::new();
+        if self.next_is(&Token::SEMICOLON) {
+            return Ok(declarations);
+        }
+        if storage_class == Some(Keyword::Typedef) {
+            self.typedef(base)?;
+            return Ok(declarations);
+        }
+
+        let external = storage_class != Some(Keyword::Static);
+        loop {
+            let (name, ty, parameters) = parse_function_declarator(&mut self.tokens, &mut self.names, base.clone())?;
+            let name = name.ok_or(ASTError::ExpectedIdentifier)?;
+            // A function declarator followed by its body is a definition, which is all the declaration has
+            if let (true, Type::Function { return_type, variadic, .. }, Some(parameters)) =
+                (declarations.is_empty(), &ty, parameters)
+            {
+                if self.peek_token() == Some(&Token::LBRACE) {
+                    declarations.push(self.function(name, (**return_type).clone(), parameters, *variadic, external)?);
+                    return Ok(declarations);
+                }
+            }
+
+            let symbol = self.static_variable(name.clone(), ty, name, external)?;
+            declarations.push(match symbol.ty {
+                Type::Function { .. } => ExternalDeclaration::FunctionDeclaration(symbol),
+                _ => match self.initializer()? {
+                    None if storage_class == Some(Keyword::Extern) => ExternalDeclaration::VariableDeclaration(symbol),
+                    initializer => ExternalDeclaration::Variable(symbol, initializer),
+                },
+            });
+            if !self.next_is(&Token::COMMA) {
+                break;
+            }
+        }
+        self.expect(Token::SEMICOLON)?;
+        Ok(declarations)
+    }
+
+    /// The body of `int main(int argc, char **argv) { ... }`, from the `{`. The parameters
+    /// are the first locals, in the same scope as the outermost block of the body.
+    fn function(
+        &mut self,
+        name: String,
+        return_type: Type,
+        parameters: Vec<Parameter>,
+        variadic: bool,
+        external: bool,
+    ) -> Result<ExternalDeclaration, ASTError> {
+        self.names.declare_object(&name)?;
+
+        self.names.push_scope();
+        // Only a declaration can leave out the names of the parameters
+        let result = parameters
+            .into_iter()
+            .map(|(name, ty)| self.local(name.ok_or(ASTError::ExpectedIdentifier)?, ty))
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|parameters| Ok((parameters, self.block_statements()?)));
+        self.names.pop_scope();
+        let (parameters, body) = result?;
+        let locals = std::mem::take(&mut self.locals);
+
+        Ok(ExternalDeclaration::Function(Function {
+            name,
+            return_type,
+            parameters,
+            variadic,
+            external,
+            body,
+            locals,
+        }))
+    }
+
+    /// `{ ... }`, returns the statements inside. Names declared inside are not visible after it.
+    fn block(&mut self) -> Result<Vec<Statement>, ASTError> {
+        self.names.push_scope();
+        let statements = self.block_statements();
+        self.names.pop_scope();
+        statements
+    }
+
+    fn block_statements(&mut self) -> Result<Vec<Statement>, ASTError> {
+        self.expect(Token::LBRACE)?;
+        let mut statements = Vec::new();
+        while !self.next_is(&Token::RBRACE) {
+            if matches!(self.peek_token(), None | Some(Token::EndOfFile)) {
+                return Err(ASTError::ExpectedToken(Token::RBRACE));
+            }
+
+            // Labels have names of their own, even a typedef name can be one
+            if starts_declaration(&self.names, self.tokens.peek()) && !self.at_label() {
+                statements.extend(self.declaration()?);
+            } else {
+                statements.push(self.statement()?);
+            }
+        }
+        Ok(statements)
+    }
+
+    /// `unsigned char a = 1, *b, c[4];`, one [`Statement::Declaration`] for each variable.
+    /// `struct s { int x; };` and `typedef` declare no variables at all. A `static` local
+    /// is not one of the function's locals, it gets a label of its own. An `extern` one is a
+    /// global declared inside the function, which cannot be initialised there.
+    fn declaration(&mut self) -> Result<Vec<Statement>, ASTError> {
+        let (storage_class, base) = parse_declaration_specifiers(&mut self.tokens, &mut self.names)?;
+        let mut declarations = Vec::new();
+        if self.next_is(&Token::SEMICOLON) {
+            return Ok(declarations);
+        }
+        if storage_class == Some(Keyword::Typedef) {
+            self.typedef(base)?;
+            return Ok(declarations);
+        }
+        loop {
+            // A variable is in scope in its own initialiser
+            let (name, ty) = self.declarator(base.clone())?;
+            let symbol = match storage_class {
+                Some(Keyword::Static) => {
+                    self.static_locals += 1;
+                    let label = format!("{}.{}", name, self.static_locals);
+                    self.static_variable(name, ty, label, false)?
+                }
+                Some(Keyword::Extern) => self.static_variable(name.clone(), ty, name, true)?,
+                _ => self.local(name, ty)?,
+            };
+            let initializer = self.initializer()?;
+            if initializer.is_some() && storage_class == Some(Keyword::Extern) {
+                return Err(ASTError::InvalidStorageClass(Keyword::Extern));
+            }
+            declarations.push(Statement::Declaration(symbol, initializer));
+
+            if !self.next_is(&Token::COMMA) {
+                break;
+            }
+        }
+        self.expect(Token::SEMICOLON)?;
+        Ok(declarations)
+    }
+
+    /// Whether the next tokens are a name and a `:`, which start a labelled statement
+    fn at_label(&mut self) -> bool {
+        let is_name = matches!(self.peek_token(), Some(token @ Token::IDENTIFIER(_)) if token.keyword().is_none());
+        is_name && matches!(self.tokens.peek_nth(1), Some(Ok(SpannedToken { token: Token::COLON, .. })))
+    }
+
+    fn statement(&mut self) -> Result<Statement, ASTError> {
+        if self.at_label() {
+            let name = self.identifier()?;
+            let written = self.tokens.last.clone();
+            self.expect(Token::COLON)?;
+            return Ok(Statement::Label(name, Box::new(self.statement()?), written));
+        }
+        match self.peek_token() {
+            Some(Token::LBRACE) => Ok(Statement::Block(self.block()?)),
+            Some(Token::SEMICOLON) => {
+                self.tokens.next_token();
+                Ok(Statement::Expression(None))
+            }
+            Some(token) if token.keyword() == Some(Keyword::Return) => {
+                let written = self.tokens.next_token().and_then(Result::ok);
+                let value = match self.peek_token() {
+                    Some(Token::SEMICOLON) => None,
+                    _ => Some(self.expression()?),
+                };
+                self.expect(Token::SEMICOLON)?;
+                Ok(Statement::Return(value, written))
+            }
+            Some(token) if token.keyword() == Some(Keyword::Switch) => {
+                self.tokens.next_token();
+                self.expect(Token::LPAREN)?;
+                let value = self.expression()?;
+                self.expect(Token::RPAREN)?;
+                Ok(Statement::Switch {
+                    value,
+                    body: Box::new(self.statement()?),
+                    cases: Vec::new(),
+                    default: false,
+                })
+            }
+            Some(token) if token.keyword() == Some(Keyword::Case) => {
+                let written = self.tokens.next_token().and_then(Result::ok);
+                let value = self.expression()?;
+                self.expect(Token::COLON)?;
+                Ok(Statement::Case(value, Box::new(self.statement()?), written))
+            }
+            Some(token) if token.keyword() == Some(Keyword::Default) => {
+                let written = self.tokens.next_token().and_then(Result::ok);
+                self.expect(Token::COLON)?;
+                Ok(Statement::Default(Box::new(self.statement()?), written))
+            }
+            Some(token) if token.keyword() == Some(Keyword::Break) => {
+                let written = self.tokens.next_token().and_then(Result::ok);
+                self.expect(Token::SEMICOLON)?;
+                Ok(Statement::Break(written))
+            }
+            Some(token) if token.keyword() == Some(Keyword::Goto) => {
+                self.tokens.next_token();
+                let name = self.identifier()?;
+                let written = self.tokens.last.clone();
+                self.expect(Token::SEMICOLON)?;
+                Ok(Statement::Goto(name, written))
+            }
+            _ => {
+                let expression = self.expression()?;
+                self.expect(Token::SEMICOLON)?;
+                Ok(Statement::Expression(Some(expression)))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::Lexer;
+
+    fn parse(source: &str) -> Result<TranslationUnit, ASTError> {
+        TranslationUnit::parse(&mut Lexer::new(source.as_bytes())).map_err(ASTError::without_position)
+    }
+
+    #[test]
+    fn test_parse_function() {
+        let unit = parse("int main(void)\n{\n  return 1 +\n 2;\n}\n").unwrap();
+        assert_eq!(unit.functions().len(), 1);
+
+        let main = unit.functions()[0];
+        assert_eq!((main.name.as_str(), &main.return_type), ("main", &Type::Int));
+        assert!(main.locals.is_empty());
+        let [Statement::Return(Some(value), _)] = main.body.as_slice() else {
+            panic!("unexpected body {:?}", main.body);
+        };
+        assert_eq!(value.test_evaluate().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_parse_declarations() {
+        let unit = parse("unsigned long f() { short a = 1, b; { char c; } unsigned d; ; }").unwrap();
+        let function = unit.functions()[0];
+        assert_eq!(function.return_type, Type::UnsignedLong);
+
+        let locals: Vec<(&str, &Type, usize)> = function
+            .locals
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), &symbol.ty, symbol.slot))
+            .collect();
+        assert_eq!(locals, vec![
+            ("a", &Type::Short, 0),
+            ("b", &Type::Short, 1),
+            ("c", &Type::Char, 2),
+            ("d", &Type::UnsignedInt, 3),
+        ]);
+
+        assert!(matches!(&function.body[0], Statement::Declaration(symbol, Some(_)) if symbol.name == "a"));
+        assert!(matches!(&function.body[1], Statement::Declaration(symbol, None) if symbol.name == "b"));
+        assert!(matches!(&function.body[2], Statement::Block(inner) if inner.len() == 1));
+        assert_eq!(function.body[4], Statement::Expression(None));
+    }
+
+    #[test]
+    fn test_parse_casts_and_variables() {
+        let unit = parse("int main() { long x; return (unsigned char) x + (short)(1); }").unwrap();
+        let Statement::Return(Some(value), _) = &unit.functions()[0].body[1] else {
+            panic!("expected a return");
+        };
+        let left = value.left.as_ref().unwrap();
+        assert_eq!(left.operation, Token::Cast);
+        assert_eq!(left.ty, Some(Type::UnsignedChar));
+        assert_eq!(left.left.as_ref().unwrap().operation, Token::IDENTIFIER("x".to_string()));
+        assert_eq!(value.right.as_ref().unwrap().ty, Some(Type::Short));
+    }
+
+    #[test]
+    fn test_parse_pointer_declarators() {
+        let unit = parse("char **f() { int *p = &x, q, **r; *p = q = 1; }").unwrap();
+        let function = unit.functions()[0];
+        assert_eq!(function.return_type, Type::Char.pointer_to().pointer_to());
+
+        let types: Vec<&Type> = function.locals.iter().map(|symbol| &symbol.ty).collect();
+        assert_eq!(types, vec![&Type::Int.pointer_to(), &Type::Int, &Type::Int.pointer_to().pointer_to()]);
+
+        let Statement::Expression(Some(assignment)) = &function.body[3] else {
+            panic!("expected an expression");
+        };
+        assert_eq!(assignment.operation, Token::ASSIGN);
+        assert_eq!(assignment.left.as_ref().unwrap().operation, Token::Dereference);
+        assert_eq!(assignment.right.as_ref().unwrap().operation, Token::ASSIGN);
+    }
+
+    #[test]
+    fn test_parse_array_declarators() {
+        let unit = parse("int main() { long a[2][3], *b[4]; }").unwrap();
+        let types: Vec<&Type> = unit.functions()[0].locals.iter().map(|symbol| &symbol.ty).collect();
+        assert_eq!(types, vec![&Type::Long.array_of(3).array_of(2), &Type::Long.pointer_to().array_of(4)]);
+
+        // Lengths are integer constant expressions, worked out in their own types
+        let unit = parse("int main() { int a[1 ? 2 : 3], b[(0u - 1) / 0x20000000u], c[(unsigned char) 258], d[3 > 2]; }").unwrap();
+        let lengths: Vec<usize> = unit.functions()[0].locals.iter().map(|symbol| symbol.ty.size() / 4).collect();
+        assert_eq!(lengths, vec![2, 7, 2, 1]);
+
+        assert_eq!(parse("int main() { int a[x]; }"), Err(ASTError::InvalidArraySize));
+        assert_eq!(parse("int main() { int a[1 - 1]; }"), Err(ASTError::InvalidArraySize));
+        assert_eq!(parse("int main() { int a[2.0]; }"), Err(ASTError::InvalidArraySize));
+        assert_eq!(parse("int main() { int a[2; }"), Err(ASTError::ExpectedToken(Token::RBRACKET)));
+    }
+
+    #[test]
+    fn test_parse_function_pointer_declarators() {
+        let unit = parse(
+            "int (*table[2])(int, int), *(*next)(void);\n\
+             int main() { int (*fp)(int a, int b); int *f(long), (*g)[3]; return (void (*)(char)) 0; }",
+        )
+        .unwrap();
+        let globals: Vec<String> = unit
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                ExternalDeclaration::Variable(symbol, _) => Some(symbol.ty.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(globals, vec!["int (int, int) * [2]", "int * () *"]);
+
+        // Without the parentheses the declarator is a function, not a pointer to one
+        let types: Vec<String> = unit.functions()[0].locals.iter().map(|symbol| symbol.ty.to_string()).collect();
+        assert_eq!(types, vec!["int (int, int) *", "int * (long)", "int [3] *"]);
+        let Some(Statement::Return(Some(cast), _)) = unit.functions()[0].body.last() else {
+            panic!("expected a return");
+        };
+        assert_eq!(cast.ty.as_ref().unwrap().to_string(), "void (char) *");
+
+        // A parameter declared as a function is a pointer to one
+        let unit = parse("void sort(int compare(int, int)) {}").unwrap();
+        assert_eq!(unit.functions()[0].ty().to_string(), "void (int (int, int) *)");
+        assert_eq!(parse("int main() { int (*)(int); }"), Err(ASTError::ExpectedIdentifier));
+        assert_eq!(parse("int main() { int (*fp(int); }"), Err(ASTError::ExpectedToken(Token::RPAREN)));
+
+        // A function returning a pointer to a function can be defined too, its parameters are
+        // the ones right after the name
+        let unit = parse(
+            "int inc(int x) { return x + 1; }\n\
+             int (*get(int which))(int) { return which ? inc : 0; }",
+        )
+        .unwrap();
+        let get = unit.functions()[1];
+        assert_eq!((get.name.as_str(), get.return_type.to_string()), ("get", "int (int) *".to_string()));
+        let parameters: Vec<&str> = get.parameters.iter().map(|parameter| parameter.name.as_str()).collect();
+        assert_eq!(parameters, vec!["which"]);
+        assert_eq!(parse("int (*get(int))(int a) { return 0; }"), Err(ASTError::ExpectedIdentifier));
+        assert_eq!(parse("int x, f(void) { return 0; }"), Err(ASTError::ExpectedToken(Token::SEMICOLON)));
+    }
+
+    #[test]
+    fn test_parse_several_functions() {
+        let unit = parse("void a() {}\nint b() { int x; }\n").unwrap();
+        let names: Vec<&str> = unit.functions().iter().map(|function| function.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        // Slots start again in every function
+        assert_eq!(unit.functions()[1].locals[0].slot, 0);
+    }
+
+    #[test]
+    fn test_parse_parameters() {
+        let unit = parse("long f(char c, int *p, short a[4][2]) { return c; } int g(void) { return f(1, 0, 0); }").unwrap();
+        let f = unit.functions()[0];
+        let parameters: Vec<(&str, &Type, usize)> = f
+            .parameters
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), &symbol.ty, symbol.slot))
+            .collect();
+        // An array parameter is a pointer to the first element
+        assert_eq!(parameters, vec![
+            ("c", &Type::Char, 0),
+            ("p", &Type::Int.pointer_to(), 1),
+            ("a", &Type::Short.array_of(2).pointer_to(), 2),
+        ]);
+        assert_eq!(f.locals, f.parameters);
+        assert_eq!(f.ty().to_string(), "long (char, int *, short [2] *)");
+        assert!(unit.functions()[1].parameters.is_empty());
+
+        assert_eq!(parse("int f(int) {}"), Err(ASTError::ExpectedIdentifier));
+        assert_eq!(parse("int f(int a,) {}"), Err(ASTError::InvalidTypeSpecifiers));
+    }
+
+    #[test]
+    fn test_parse_function_declarations() {
+        let unit = parse("int printf(char *format, ...); long f(int, char *[]); int main() { return f(1, 0); }").unwrap();
+        let types: Vec<String> = unit
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                ExternalDeclaration::FunctionDeclaration(symbol) => Some(symbol.ty.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(types, vec!["int (char *, ...)", "long (int, char * *)"]);
+        // The parameters of a declaration are not locals of anything
+        assert!(unit.functions()[0].locals.is_empty());
+        assert!(!unit.functions()[0].variadic);
+
+        let unit = parse("void log(int level, ...) {}").unwrap();
+        assert_eq!(unit.functions()[0].ty().to_string(), "void (int, ...)");
+        assert_eq!(parse("int f(...);"), Err(ASTError::ExpectedToken(Token::RPAREN)));
+        assert_eq!(parse("int f(int, ..., int);"), Err(ASTError::ExpectedToken(Token::RPAREN)));
+    }
+
+    #[test]
+    fn test_parse_struct_declarations() {
+        let unit = parse(
+            "struct list { struct list *next; int value; };\n\
+             int main() { struct list head; union { char c; long l; } u; struct list; struct list *other; }",
+        )
+        .unwrap();
+        let locals = &unit.functions()[0].locals;
+        let Type::Record(list) = &locals[0].ty else {
+            panic!("expected a struct, found {:?}", locals[0].ty);
+        };
+        assert_eq!(list.member("next").unwrap().ty, locals[0].ty.clone().pointer_to());
+        assert_eq!(locals[1].ty.to_string(), "union <anonymous>");
+        assert_eq!(locals[1].ty.size(), 8);
+        // `struct list;` declared a new, incomplete type inside the function
+        assert_ne!(locals[2].ty, locals[0].ty.clone().pointer_to());
+        assert!(!locals[2].ty.pointee().unwrap().is_complete());
+
+        // Tags end with their block
+        let unit = parse("int main() { { struct s { int a; } x; } struct s *y; }").unwrap();
+        let locals = &unit.functions()[0].locals;
+        assert!(locals[0].ty.is_complete());
+        assert!(!locals[1].ty.pointee().unwrap().is_complete());
+
+        // Flexible array members and anonymous members
+        let unit = parse("struct s { long n; struct { char a, b; }; union { int i; }; short data[]; }; int main() { struct s v; }").unwrap();
+        let Type::Record(s) = &unit.functions()[0].locals[0].ty else {
+            panic!("expected a struct");
+        };
+        let offsets: Vec<(String, usize)> = s.members().into_iter().map(|member| (member.name, member.offset)).collect();
+        assert_eq!(offsets, vec![
+            ("n".to_string(), 0),
+            ("a".to_string(), 8),
+            ("b".to_string(), 9),
+            ("i".to_string(), 12),
+            ("data".to_string(), 16),
+        ]);
+        assert_eq!(unit.functions()[0].locals[0].ty.size(), 16);
+    }
+
+    #[test]
+    fn test_parse_struct_errors() {
+        assert_eq!(parse("struct s { int a; long a; };"), Err(ASTError::DuplicateMember("a".to_string())));
+        assert_eq!(parse("struct s { int a; }; struct s { int b; };"), Err(ASTError::Redeclaration("s".to_string())));
+        assert_eq!(parse("struct s { int a; }; int main() { union s *p; }"), Err(ASTError::WrongTagKind("s".to_string())));
+        let Err(ASTError::IncompleteType(incomplete)) = parse("struct s { struct s inner; };") else {
+            panic!("a struct cannot contain itself");
+        };
+        assert_eq!(incomplete.to_string(), "struct s");
+        assert_eq!(parse("struct s { int data[]; long n; };"), Err(ASTError::MisplacedFlexibleArray("data".to_string())));
+        assert_eq!(parse("struct s { int data[]; };"), Err(ASTError::MisplacedFlexibleArray("data".to_string())));
+        assert_eq!(parse("union u { int n; int data[]; };"), Err(ASTError::MisplacedFlexibleArray("data".to_string())));
+        assert_eq!(parse("unsigned struct s { int a; } f() {}"), Err(ASTError::InvalidTypeSpecifiers));
+        assert_eq!(parse("struct s { int a; } long f() {}"), Err(ASTError::InvalidTypeSpecifiers));
+        assert_eq!(parse("struct { int a; ;"), Err(ASTError::UnexpectedToken(Token::SEMICOLON)));
+        assert_eq!(parse("struct *p;"), Err(ASTError::ExpectedIdentifier));
+    }
+
+    #[test]
+    fn test_parse_enums() {
+        let unit = parse(
+            "enum color { RED, GREEN = 5, BLUE, };\n\
+             int main() { enum color c = BLUE; enum { A = BLUE * 2, B } d; return A + B + sizeof(enum color); }",
+        )
+        .unwrap();
+        let main = unit.functions()[0];
+        assert_eq!(main.locals[0].ty, Type::Int);
+        // Constants are integer literals by the time they reach the type checker
+        let Statement::Declaration(_, Some(value)) = &main.body[0] else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(value.operation, Token::INT(6));
+        let Statement::Return(Some(value), _) = &main.body[2] else {
+            panic!("expected a return");
+        };
+        assert_eq!(crate::ast::integer_constant(value.clone()), Some(12 + 13 + 4));
+
+        // A constant hides a variable of an outer scope, a variable a constant
+        let unit = parse("int main() { int A; { enum { A = 3 }; return A; } }").unwrap();
+        let Statement::Block(inner) = &unit.functions()[0].body[1] else {
+            panic!("expected a block");
+        };
+        assert!(matches!(&inner[0], Statement::Return(Some(value), _) if *value == ASTNode::leaf(Token::INT(3))));
+        let unit = parse("enum { A }; int main() { int A; return A; }").unwrap();
+        let identifier = ASTNode::leaf(Token::IDENTIFIER("A".to_string()));
+        assert!(matches!(&unit.functions()[0].body[1], Statement::Return(Some(value), _) if *value == identifier));
+
+        assert_eq!(parse("enum { A, A };"), Err(ASTError::Redeclaration("A".to_string())));
+        assert_eq!(parse("enum e { A }; enum e { B };"), Err(ASTError::Redeclaration("e".to_string())));
+        assert_eq!(parse("struct e { int a; }; enum e x;"), Err(ASTError::WrongTagKind("e".to_string())));
+        assert_eq!(parse("int main() { enum e x; }"), Err(ASTError::UnknownEnum("e".to_string())));
+        assert_eq!(parse("enum { A = 2147483647, B };"), Err(ASTError::InvalidEnumerator("B".to_string())));
+        assert_eq!(parse("int main() { int x; enum { A = x }; }"), Err(ASTError::InvalidEnumerator("A".to_string())));
+        assert_eq!(parse("enum { A = 1.5 };"), Err(ASTError::InvalidEnumerator("A".to_string())));
+
+        // Values are worked out in the types of C, unsigned ones included
+        let unit = parse("int main() { enum { Z = (0u - 1) >> 28, X = 5 > 3, Y = (char) 300, W = 1 ? 7 : 8 }; return Z + X + Y + W; }").unwrap();
+        let Statement::Return(Some(value), _) = &unit.functions()[0].body[0] else {
+            panic!("expected a return");
+        };
+        let (z, x, y, w) = (15, 1, 44, 7);
+        assert_eq!(*value, ASTNode::parse_stream(&mut Lexer::new(format!("{} + {} + {} + {}", z, x, y, w).as_bytes())).unwrap());
+        assert_eq!(parse("enum { A = 0u - 1 };"), Err(ASTError::InvalidEnumerator("A".to_string())));
+        assert_eq!(parse("enum { };"), Err(ASTError::ExpectedIdentifier));
+    }
+
+    #[test]
+    fn test_parse_typedefs() {
+        let unit = parse(
+            "typedef struct node Node, *Link; typedef long Pair[2];\n\
+             struct node { Link next; };\n\
+             int main() { Node n; Link * p; Pair pair; return (Link) p == n.next; }",
+        )
+        .unwrap();
+        let main = unit.functions()[0];
+        let types: Vec<String> = main.locals.iter().map(|symbol| symbol.ty.to_string()).collect();
+        assert_eq!(types, vec!["struct node", "struct node * *", "long [2]"]);
+        assert!(main.locals[0].ty.is_complete());
+        let Statement::Return(Some(value), _) = &main.body[3] else {
+            panic!("expected a return");
+        };
+        assert_eq!(value.left.as_ref().unwrap().operation, Token::Cast);
+
+        // `T * x;` multiplies once `T` is a variable, until the end of its block
+        let unit = parse("typedef int T; int main() { int x; { int T; T * x; } T * y; unsigned T; }").unwrap();
+        let main = unit.functions()[0];
+        let Statement::Block(inner) = &main.body[1] else {
+            panic!("expected a block");
+        };
+        assert!(matches!(&inner[1], Statement::Expression(Some(product)) if product.operation == Token::ASTERISK));
+        assert_eq!(main.locals[2].ty, Type::Int.pointer_to());
+        // After `unsigned`, `T` is the name being declared
+        assert_eq!((main.locals[3].name.as_str(), &main.locals[3].ty), ("T", &Type::UnsignedInt));
+
+        // Parameters hide typedef names too, and typedef names can be declared in blocks
+        let unit = parse("typedef char T; long f(T T) { return T; } int g() { typedef short T; T x; }").unwrap();
+        assert_eq!(unit.functions()[0].parameters[0].ty, Type::Char);
+        assert_eq!(unit.functions()[1].locals[0].ty, Type::Short);
+
+        // The same type can be given the same name again
+        assert!(parse("typedef int T; typedef int T;").is_ok());
+        assert_eq!(parse("typedef int T; typedef long T;"), Err(ASTError::Redeclaration("T".to_string())));
+        assert_eq!(parse("typedef int T; int main() { int T; typedef int T; }"), Err(ASTError::Redeclaration("T".to_string())));
+        assert_eq!(parse("typedef int T; unsigned T() {}"), Err(ASTError::Redeclaration("T".to_string())));
+        assert_eq!(parse("typedef int T; int main() { unsigned T x; }"), Err(ASTError::ExpectedToken(Token::SEMICOLON)));
+        assert_eq!(parse("typedef int T; T long x;"), Err(ASTError::InvalidTypeSpecifiers));
+    }
+
+    #[test]
+    fn test_parse_globals_and_statics() {
+        let unit = parse(
+            "int a, *b = &a; static char c[2]; extern int g, h = 1;\n\
+             int f() { static long d; long e; static long f = 1; extern int g; }",
+        )
+        .unwrap();
+        let storage: Vec<(&str, &Storage)> = unit
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                ExternalDeclaration::Variable(symbol, _) => Some((symbol.name.as_str(), &symbol.storage)),
+                _ => None,
+            })
+            .collect();
+        let global = |label: &str, external| Storage::Static { label: label.to_string(), external };
+        assert_eq!(storage, vec![
+            ("a", &global("a", true)),
+            ("b", &global("b", true)),
+            ("c", &global("c", false)),
+            ("h", &global("h", true)),
+        ]);
+        assert!(matches!(&unit.declarations[1], ExternalDeclaration::Variable(_, Some(_))));
+        // An `extern` global without an initialiser is only declared
+        assert!(matches!(&unit.declarations[3], ExternalDeclaration::VariableDeclaration(symbol) if symbol.name == "g"));
+
+        // `static` locals are not in the frame and get labels of their own
+        let f = unit.functions()[0];
+        assert_eq!(f.locals.len(), 1);
+        let symbols: Vec<&Storage> = f
+            .body
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::Declaration(symbol, _) => Some(&symbol.storage),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(symbols, vec![&global("d.1", false), &Storage::Automatic, &global("f.2", false), &global("g", true)]);
+
+        // A `static` function is only seen in its own file
+        let unit = parse("static int f(void); static int g() {} int h() {}").unwrap();
+        assert!(matches!(&unit.declarations[0], ExternalDeclaration::FunctionDeclaration(symbol) if symbol.storage == global("f", false)));
+        let external: Vec<bool> = unit.functions().iter().map(|function| function.external).collect();
+        assert_eq!(external, vec![false, true]);
+        assert_eq!(parse("int f() { extern int x = 1; }"), Err(ASTError::InvalidStorageClass(Keyword::Extern)));
+        assert_eq!(parse("int x = 1"), Err(ASTError::ExpectedToken(Token::SEMICOLON)));
+    }
+
+    #[test]
+    fn test_parse_initializer_lists() {
+        let unit = parse("int a[] = {1, {2, 3}, {}, }; char s[4] = \"ab\"; int f() { static int b[2] = {4}; }").unwrap();
+        let ExternalDeclaration::Variable(_, Some(list)) = &unit.declarations[0] else {
+            panic!("expected a list for a");
+        };
+        assert_eq!(list.operation, Token::InitializerList);
+        let elements = list.arguments();
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[0].operation, Token::INT(1));
+        assert_eq!((elements[1].arguments().len(), elements[2].arguments().len()), (2, 0));
+        assert!(matches!(&unit.declarations[1], ExternalDeclaration::Variable(_, Some(value)) if value.operation == Token::STRING(b"ab".to_vec())));
+        assert!(matches!(&unit.functions()[0].body[0], Statement::Declaration(_, Some(value)) if value.arguments().len() == 1));
+
+        assert_eq!(parse("int a[] = {1 2};").unwrap_err(), ASTError::ExpectedToken(Token::RBRACE));
+        assert_eq!(parse("int a[] = {1,, 2};").unwrap_err(), ASTError::UnexpectedToken(Token::COMMA));
+    }
+
+    #[test]
+    fn test_parse_switch() {
+        let unit = parse("int f(int x) { switch (x) { case 1: case 2: x = 3; break; default: ; } switch (x) break; }").unwrap();
+        let body = &unit.functions()[0].body;
+        let Statement::Switch { value, body: switch_body, cases, default } = &body[0] else {
+            panic!("expected a switch, found {:?}", body[0]);
+        };
+        assert_eq!(value.operation, Token::IDENTIFIER("x".to_string()));
+        // The cases are only collected by the type checker
+        assert!(cases.is_empty() && !default);
+        let Statement::Block(statements) = switch_body.as_ref() else {
+            panic!("expected a block");
+        };
+        // A label holds the statement after it, which can have a label too
+        let [Statement::Case(one, labelled, case), Statement::Break(_), Statement::Default(empty, _)] = statements.as_slice() else {
+            panic!("unexpected switch body {:?}", statements);
+        };
+        assert_eq!(one.operation, Token::INT(1));
+        // Errors point at the keyword
+        assert_eq!(case.as_ref().map(|token| (token.line, token.column)), Some((1, 29)));
+        assert!(matches!(labelled.as_ref(), Statement::Case(_, assignment, _) if matches!(**assignment, Statement::Expression(Some(_)))));
+        assert_eq!(**empty, Statement::Expression(None));
+        assert!(matches!(&body[1], Statement::Switch { body, .. } if matches!(**body, Statement::Break(_))));
+
+        assert_eq!(parse("int f(int x) { switch x { } }"), Err(ASTError::ExpectedToken(Token::LPAREN)));
+        assert_eq!(parse("int f(int x) { switch (x) { case 1 x; } }"), Err(ASTError::ExpectedToken(Token::COLON)));
+        assert_eq!(parse("int f(int x) { switch (x) { default } }"), Err(ASTError::ExpectedToken(Token::COLON)));
+        assert_eq!(parse("int f(int x) { break }"), Err(ASTError::ExpectedToken(Token::SEMICOLON)));
+    }
+
+    #[test]
+    fn test_parse_labels() {
+        let unit = parse("typedef int T; int f() { T: a: goto T; { b: ; } x ? y : z; }").unwrap();
+        let body = &unit.functions()[0].body;
+        // A typedef name followed by `:` is a label, not a declaration
+        let Statement::Label(outer, inner, _) = &body[0] else {
+            panic!("expected a label, found {:?}", body[0]);
+        };
+        assert_eq!(outer, "T");
+        let Statement::Label(name, goto, Some(written)) = inner.as_ref() else {
+            panic!("expected a label, found {:?}", inner);
+        };
+        // Errors point at the name, for a goto the one after `goto`
+        assert_eq!((name.as_str(), written.column), ("a", 29));
+        let Statement::Goto(target, Some(written)) = goto.as_ref() else {
+            panic!("expected a goto, found {:?}", goto);
+        };
+        assert_eq!((target.as_str(), written.column), ("T", 37));
+        assert!(matches!(&body[1], Statement::Block(inner) if matches!(&inner[0], Statement::Label(name, ..) if name == "b")));
+        assert!(matches!(&body[2], Statement::Expression(Some(node)) if node.operation == Token::QUESTION));
+
+        assert_eq!(parse("int f() { goto; }"), Err(ASTError::ExpectedIdentifier));
+        assert_eq!(parse("int f() { goto a }"), Err(ASTError::ExpectedToken(Token::SEMICOLON)));
+        assert_eq!(parse("int f() { a: }"), Err(ASTError::UnexpectedToken(Token::RBRACE)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("int main() { return 1 }"), Err(ASTError::ExpectedToken(Token::SEMICOLON)));
+        assert_eq!(parse("int main() { return 1;"), Err(ASTError::ExpectedToken(Token::RBRACE)));
+        assert_eq!(parse("int main( { }"), Err(ASTError::ExpectedToken(Token::RPAREN)));
+        assert_eq!(parse("short char main() {}"), Err(ASTError::InvalidTypeSpecifiers));
+        assert_eq!(parse("int return() {}"), Err(ASTError::ExpectedIdentifier));
+        assert_eq!(parse("int main() { int 3; }"), Err(ASTError::ExpectedIdentifier));
+        assert_eq!(parse("main() {}"), Err(ASTError::UnexpectedToken(Token::IDENTIFIER("main".to_string()))));
+        assert!(matches!(parse("int main() { return @; }"), Err(ASTError::LexicalError(_))));
+    }
+}
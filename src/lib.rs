@@ -1,3 +1,7 @@
 pub mod assembly;
 pub mod ast;
+pub mod preprocess;
 pub mod scan;
+pub mod toolchain;
+pub mod typecheck;
+pub mod types;
@@ -1,39 +1,271 @@
-use crate::assembly::assembly_writer_arm64::ARM64Writer;
-use crate::ast::ASTNode;
-use crate::scan::scan_file;
-use std::io;
+use compiler::assembly::assembly_writer_arm64::ARM64Writer;
+use compiler::assembly::SupportedArchitectures;
+use compiler::ast::statement::TranslationUnit;
+use compiler::ast::ASTError;
+use compiler::preprocess::Preprocessor;
+use compiler::toolchain::{Temporaries, Toolchain};
+use compiler::typecheck::check_translation_unit;
+use std::fs::File;
 use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::exit;
 
-mod scan;
-mod ast;
-mod assembly;
+/// A `-D` or `-U` from the command line, they are applied in the order given
+enum MacroOption {
+    Define(String),
+    Undefine(String),
+}
 
-// Example usage
-fn main() -> io::Result<()> {
-    use std::fs::File;
-    use std::io::BufReader;
+/// How far to take the input files
+#[derive(PartialEq)]
+enum Stage {
+    /// `-S`: write the assembly
+    Assembly,
+    /// `-c`: assemble each file into an object file
+    Object,
+    /// Neither `-S` nor `-c`: link everything into an executable
+    Executable,
+}
 
-    let file = File::open("scanner_example.test")?;
-    let mut reader = BufReader::new(file);
+struct Options {
+    include_paths: Vec<PathBuf>,
+    macro_options: Vec<MacroOption>,
+    /// `-l` and `-L` for the linker, in the order given
+    link_options: Vec<String>,
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    stage: Stage,
+    /// `--target=<triple>`, Darwin unless given
+    architecture: SupportedArchitectures,
+}
 
-    let tokens = scan_file(&mut reader)?;
+// Example usage: compiler [--target=triple] [-I dir] [-D name[=value]] [-U name] [-S | -c] [-o output] [-L dir] [-l library]... file...
+//
+// -S writes file.s and -c file.o for each file, and without either the files are linked into
+// a.out. Object files given are only linked. The assembler and the linker are `as`
+// and `cc`, or the commands in the AS and CC environment variables. The target is ARM64
+// Darwin, or ARM64 Linux with a triple like --target=aarch64-linux-gnu.
+fn main() {
+    let result = parse_options(std::env::args().skip(1)).and_then(|options| run(&options));
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        exit(1);
+    }
+}
 
+/// The options in `args`, the command line without the program name
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        include_paths: Vec::new(),
+        macro_options: Vec::new(),
+        link_options: Vec::new(),
+        inputs: Vec::new(),
+        output: None,
+        stage: Stage::Executable,
+        architecture: SupportedArchitectures::default(),
+    };
 
-    for token in tokens.clone() {
-        match token {
-            Ok(token) => println!("Token: {:?}", token),
-            Err(err) => println!("Error at line {}, column {}: invalid character '{}'",
-                                 err.line, err.column, err.character),
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-S" => {
+                options.stage = Stage::Assembly;
+                continue;
+            }
+            "-c" => {
+                options.stage = Stage::Object;
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(triple) = arg.strip_prefix("--target=") {
+            options.architecture =
+                SupportedArchitectures::from_triple(triple).ok_or_else(|| format!("Unsupported target {}", triple))?;
+            continue;
+        }
+        let option = ["-I", "-D", "-U", "-o", "-l", "-L"].into_iter().find(|option| arg.starts_with(option));
+        let Some(option) = option else {
+            options.inputs.push(arg.into());
+            continue;
+        };
+
+        // Both `-Idir` and `-I dir` work
+        let value = match &arg[option.len()..] {
+            "" => args.next().ok_or_else(|| format!("Missing argument after {}", option))?,
+            value => value.to_string(),
+        };
+        match option {
+            "-I" => options.include_paths.push(value.into()),
+            "-D" => options.macro_options.push(MacroOption::Define(value)),
+            "-U" => options.macro_options.push(MacroOption::Undefine(value)),
+            "-o" => options.output = Some(value.into()),
+            _ => options.link_options.push(format!("{}{}", option, value)),
         }
     }
 
-    let node = ASTNode::parse(tokens).unwrap();
-    let file = BufWriter::new(File::create("scanner_example.asm").unwrap());
+    if options.inputs.is_empty() {
+        return Err("No input files".to_string());
+    }
+    if options.stage != Stage::Executable && options.output.is_some() && options.inputs.len() > 1 {
+        return Err("Cannot write one output for several files with -S or -c".to_string());
+    }
+    Ok(options)
+}
 
-    let mut writer = ARM64Writer::new(file);
+/// Compile every input as far as the options say, the temporary files are gone afterwards
+fn run(options: &Options) -> Result<(), String> {
+    let toolchain = Toolchain::from_env(options.architecture);
+    let mut temporaries = Temporaries::default();
+    let mut objects = Vec::new();
+    for input in &options.inputs {
+        // Object files only need linking
+        if input.extension().is_some_and(|extension| extension == "o") {
+            objects.push(input.clone());
+            continue;
+        }
 
-    writer.compile_ast(&node).expect("Failed to write Assembly");
+        let assembly = assembly_output(input, options).unwrap_or_else(|| temporaries.path("s"));
+        compile(input, &assembly, options)?;
+        if options.stage == Stage::Assembly {
+            continue;
+        }
 
+        let object = match (&options.stage, &options.output) {
+            (Stage::Object, Some(output)) => output.clone(),
+            (Stage::Object, None) => output_name(input, "o"),
+            _ => temporaries.path("o"),
+        };
+        toolchain
+            .assemble(&assembly, &object)
+            .map_err(|err| format!("Assembler error in {}: {}", input.display(), err))?;
+        objects.push(object);
+    }
 
+    if options.stage == Stage::Executable {
+        let output = options.output.clone().unwrap_or_else(|| PathBuf::from("a.out"));
+        toolchain
+            .link(&objects, &output, &options.link_options)
+            .map_err(|err| format!("Linker error: {}", err))?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Where the assembly for `input` goes when it is kept, `None` when it is only assembled
+fn assembly_output(input: &Path, options: &Options) -> Option<PathBuf> {
+    match (&options.stage, &options.output) {
+        (Stage::Assembly, Some(output)) => Some(output.clone()),
+        (Stage::Assembly, None) => Some(output_name(input, "s")),
+        _ => None,
+    }
+}
+
+/// Where `-S` or `-c` writes the output for `input` without `-o`: its name with the new
+/// extension, in the current directory
+fn output_name(input: &Path, extension: &str) -> PathBuf {
+    PathBuf::from(input.file_name().unwrap_or_default()).with_extension(extension)
+}
+
+/// Preprocess, parse and check `input` and write its assembly to `assembly`
+fn compile(input: &Path, assembly: &Path, options: &Options) -> Result<(), String> {
+    let mut preprocessor = Preprocessor::open(input).map_err(|err| format!("Cannot read {}: {}", input.display(), err))?;
+    preprocessor.define_target_macros(&options.architecture);
+    for directory in &options.include_paths {
+        preprocessor.add_include_path(directory.clone());
+    }
+    for option in &options.macro_options {
+        match option {
+            MacroOption::Define(definition) => preprocessor
+                .define_macro(definition)
+                .map_err(|err| format!("Invalid macro definition -D{}: {}", definition, err))?,
+            MacroOption::Undefine(name) => preprocessor.undefine_macro(name),
+        }
+    }
+
+    let result = TranslationUnit::parse_for_target(&mut preprocessor, options.architecture);
+    for warning in preprocessor.warnings() {
+        eprintln!("Warning at {}", warning);
+    }
+
+    let mut unit = result.map_err(|err| error_message("Parse error", input, err))?;
+    check_translation_unit(&mut unit).map_err(|err| error_message("Type error", input, err))?;
+
+    let file = File::create(assembly).map_err(|err| format!("Cannot write {}: {}", assembly.display(), err))?;
+    let mut writer = ARM64Writer::new(BufWriter::new(file));
+    writer
+        .compile_translation_unit(&unit)
+        .map_err(|err| format!("Failed to write assembly for {}: {}", input.display(), err))
+}
+
+/// The message for an error found in `input`. Errors that know where they were found name the
+/// file, the position and the macros the tokens came from, the others only the file.
+fn error_message(kind: &str, input: &Path, error: ASTError) -> String {
+    match error {
+        ASTError::LexicalError(err) => format!("Error at {}", err),
+        err @ ASTError::At(..) => format!("{} at {}", kind, err),
+        err => format!("{} in {}: {}", kind, input.display(), err),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compiler::scan::{SpannedToken, Token};
+    use std::rc::Rc;
+
+    fn options(args: &[&str]) -> Result<Options, String> {
+        parse_options(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn assembly(args: &[&str]) -> Option<PathBuf> {
+        let options = options(args).unwrap();
+        assembly_output(&options.inputs[0], &options)
+    }
+
+    #[test]
+    fn test_assembly_output() {
+        assert_eq!(assembly(&["-S", "dir/foo.c"]), Some(PathBuf::from("foo.s")));
+        assert_eq!(assembly(&["-I", "include", "dir/foo.c", "-S"]), Some(PathBuf::from("foo.s")));
+        assert_eq!(assembly(&["-S", "-o", "out.s", "foo.c"]), Some(PathBuf::from("out.s")));
+        assert_eq!(assembly(&["-S", "foo.c", "bar.c"]), Some(PathBuf::from("foo.s")));
+        assert_eq!(assembly(&["-c", "foo.c"]), None);
+        assert_eq!(assembly(&["-o", "program", "foo.c"]), None);
+        assert_eq!(assembly(&["dir/foo.c"]), None);
+    }
+
+    #[test]
+    fn test_stages() {
+        // Like cc, a file on its own is linked into a.out
+        assert!(options(&["dir/foo.c"]).is_ok_and(|options| options.stage == Stage::Executable && options.output.is_none()));
+        assert!(options(&["foo.c", "-S"]).is_ok_and(|options| options.stage == Stage::Assembly));
+    }
+
+    #[test]
+    fn test_target() {
+        assert!(options(&["foo.c"]).is_ok_and(|options| options.architecture == SupportedArchitectures::ARM64));
+        let linux = options(&["--target=aarch64-linux-gnu", "foo.c"]);
+        assert!(linux.is_ok_and(|options| options.architecture == SupportedArchitectures::ARM64Linux));
+        assert_eq!(options(&["--target=x86_64-linux-gnu", "foo.c"]).err(), Some("Unsupported target x86_64-linux-gnu".to_string()));
+    }
+
+    #[test]
+    fn test_option_errors() {
+        assert_eq!(options(&["foo.c", "-I"]).err(), Some("Missing argument after -I".to_string()));
+        assert_eq!(options(&["-S"]).err(), Some("No input files".to_string()));
+        assert!(options(&["-c", "-o", "out.o", "foo.c", "bar.c"]).is_err());
+        assert!(options(&["-o", "program", "foo.c", "bar.c"]).is_ok_and(|options| options.stage == Stage::Executable));
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let input = Path::new("dir/foo.c");
+        let error = ASTError::NotConstant("x".to_string());
+        assert_eq!(error_message("Type error", input, error.clone()), "Type error in dir/foo.c: initialiser of 'x' is not a constant");
+
+        let token = SpannedToken {
+            file: Some(Rc::from(input)),
+            ..SpannedToken::new(Token::INT(1), 2, 9, 20)
+        };
+        let error = error.at(Some(&token));
+        assert_eq!(error_message("Type error", input, error), "Type error at dir/foo.c, line 2, column 9: initialiser of 'x' is not a constant");
+    }
+}
@@ -0,0 +1,1931 @@
+use crate::assembly::SupportedArchitectures;
+use crate::scan::{scan_line, scan_logical_line, Lexer, LogicalLine, ScanState, ScannedToken, SpannedToken, Token, TokenError, TokenErrorKind, TokenSource};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Includes nested deeper than this are assumed to be including themselves forever
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+/// Macros every translation unit starts with, as `-D` would define them
+const STANDARD_MACROS: [&str; 3] = ["__STDC__=1", "__STDC_VERSION__=201112L", "__STDC_HOSTED__=1"];
+
+/// Headers the compiler provides itself, found when no directory searched has a file of the name.
+/// `<stdarg.h>` only names the builtins, the parser knows what they do on the target.
+const BUILTIN_HEADERS: [(&str, &str); 1] = [(
+    "stdarg.h",
+    "#ifndef __STDARG_H\n\
+     #define __STDARG_H\n\
+     typedef __builtin_va_list va_list;\n\
+     #define va_start(ap, last) __builtin_va_start(ap, last)\n\
+     #define va_arg(ap, type) __builtin_va_arg(ap, type)\n\
+     #define va_end(ap) __builtin_va_end(ap)\n\
+     #define va_copy(dest, src) __builtin_va_copy(dest, src)\n\
+     #endif\n",
+)];
+
+/// The name the body of a variadic macro uses for the arguments that match its `...`
+const VARIADIC_PARAMETER: &str = "__VA_ARGS__";
+
+/// Macros whose value depends on where they are used, they cannot be defined or undefined
+const BUILTIN_MACROS: [&str; 2] = ["__LINE__", "__FILE__"];
+
+/// Records that a token was produced by expanding a macro, and where that macro was used
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MacroExpansion {
+    pub name: String,
+    pub file: Option<Rc<Path>>,
+    pub line: usize,
+    pub column: usize,
+    /// Set when the macro was itself used inside the expansion of another macro
+    pub parent: Option<Rc<MacroExpansion>>,
+}
+
+/// A token on its way through the preprocessor
+#[derive(Debug, Clone)]
+struct PPToken {
+    token: SpannedToken,
+    /// How the token is written in the source, `#` and `##` work on this
+    spelling: String,
+    leading_space: bool,
+    /// Macros that must not be expanded from this token again, this is what stops recursive macros
+    hide_set: Rc<HashSet<String>>,
+}
+
+impl PPToken {
+    fn from_scanned(line: &LogicalLine, scanned: ScannedToken) -> Result<Self, TokenError> {
+        Ok(Self {
+            token: scanned.result?,
+            spelling: line.text[scanned.spelling].to_string(),
+            leading_space: scanned.leading_space,
+            hide_set: Rc::default(),
+        })
+    }
+
+    /// The name of the macro this token would invoke, if it is an identifier
+    fn name(&self) -> Option<&str> {
+        match &self.token.token {
+            Token::IDENTIFIER(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn is(&self, token: &Token) -> bool {
+        self.token.token == *token
+    }
+
+    fn error(&self, kind: TokenErrorKind) -> TokenError {
+        TokenError {
+            file: self.token.file.clone(),
+            line: self.token.line,
+            column: self.token.column,
+            character: self.spelling.chars().next().unwrap_or('#'),
+            kind,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Macro {
+    /// `None` for an object-like macro, the parameter names of a function-like one. The `...` of a
+    /// variadic macro is the last parameter, named `__VA_ARGS__`.
+    parameters: Option<Vec<String>>,
+    /// Whether the parameters end with `...`, which takes the arguments left over, commas and all
+    variadic: bool,
+    body: Vec<PPToken>,
+}
+
+/// An `#if`, `#ifdef` or `#ifndef` whose `#endif` has not been seen yet
+#[derive(Debug)]
+struct Conditional {
+    /// Whether the lines of the current group are kept
+    active: bool,
+    /// Set once a group has been kept, or when the whole conditional sits in a skipped group,
+    /// the remaining groups are then skipped without looking at their conditions
+    done: bool,
+    seen_else: bool,
+    /// The directive that opened the conditional
+    start: PPToken,
+}
+
+/// A file being read, with the files it includes stacked on top of it
+struct SourceFile {
+    lexer: Lexer<Box<dyn BufRead>>,
+    /// Searched first for `#include "..."`
+    directory: PathBuf,
+    /// Open conditionals, they have to be closed in the file that opened them
+    conditions: Vec<Conditional>,
+}
+
+
+/// Runs the C preprocessor over a source file, producing the tokens the parser sees.
+///
+/// Lines starting with `#` are directives and never reach the parser, `#include` switches to reading
+/// the included file until it is done, and every other line is macro expanded.
+/// Tokens keep the position they were written at, and tokens that come out of a macro
+/// carry a [`MacroExpansion`] pointing at where the macro was used.
+///
+/// # Examples
+///
+/// ```
+/// # use compiler::preprocess::*;
+/// # use compiler::scan::*;
+/// let source = "#define SQUARE(x) ((x) * (x))\nSQUARE(3)\n";
+/// let tokens: Vec<Token> = Preprocessor::new(source.as_bytes())
+///     .map(|token| token.unwrap().token)
+///     .collect();
+/// assert_eq!(tokens[..5], [Token::LPAREN, Token::LPAREN, Token::INT(3), Token::RPAREN, Token::ASTERISK]);
+/// ```
+pub struct Preprocessor {
+    files: Vec<SourceFile>,
+    /// `-I` directories, searched for both forms of `#include`
+    include_paths: Vec<PathBuf>,
+    /// Searched for `#include <...>` after the `-I` directories
+    system_include_paths: Vec<PathBuf>,
+    macros: HashMap<String, Rc<Macro>>,
+    /// Files that asked to be included only once with `#pragma once`
+    once_files: HashSet<PathBuf>,
+    /// `#warning` messages, these do not stop preprocessing
+    warnings: Vec<TokenError>,
+    /// Tokens waiting to be macro expanded, expansions are pushed back here so they get rescanned
+    pending: VecDeque<Result<PPToken, TokenError>>,
+    /// Fully preprocessed tokens, kept for lookahead
+    output: VecDeque<Result<SpannedToken, TokenError>>,
+}
+
+impl Preprocessor {
+    /// Preprocess input that does not come from a file, `#include "..."` is then relative to the
+    /// current directory
+    pub fn new<R: BufRead + 'static>(reader: R) -> Self {
+        Self::with_source(SourceFile {
+            lexer: Lexer::new(Box::new(reader)),
+            directory: PathBuf::from("."),
+            conditions: Vec::new(),
+        })
+    }
+
+    /// Preprocess the file at `path`
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self::with_source(Self::open_source(path)?))
+    }
+
+    fn with_source(source: SourceFile) -> Self {
+        let mut preprocessor = Self {
+            files: vec![source],
+            include_paths: Vec::new(),
+            system_include_paths: Vec::new(),
+            macros: HashMap::new(),
+            once_files: HashSet::new(),
+            warnings: Vec::new(),
+            pending: VecDeque::new(),
+            output: VecDeque::new(),
+        };
+        for definition in STANDARD_MACROS {
+            preprocessor.define_macro(definition).expect("standard macros are valid definitions");
+        }
+        preprocessor
+    }
+
+    fn open_source(path: &Path) -> io::Result<SourceFile> {
+        let reader = BufReader::new(File::open(path)?);
+        let directory = path.parent().map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+        Ok(SourceFile {
+            lexer: Lexer::with_file(Box::new(reader), Rc::from(path)),
+            directory,
+            conditions: Vec::new(),
+        })
+    }
+
+    /// Add a directory to search for included files, like `-I`
+    pub fn add_include_path<P: Into<PathBuf>>(&mut self, directory: P) {
+        self.include_paths.push(directory.into());
+    }
+
+    /// Add a directory to search for `#include <...>` once the `-I` directories have been tried
+    pub fn add_system_include_path<P: Into<PathBuf>>(&mut self, directory: P) {
+        self.system_include_paths.push(directory.into());
+    }
+
+    /// Define a macro the way `-D` does, `NAME` defines it as `1` and `NAME=VALUE` as `VALUE`.
+    /// Function-like macros work too, as in `MAX(a,b)=((a) > (b) ? (a) : (b))`.
+    ///
+    /// # Arguments
+    ///
+    /// * `definition`: the text after `-D`
+    ///
+    /// returns: Result<(), TokenError>
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::preprocess::*;
+    /// # use compiler::scan::*;
+    /// let mut preprocessor = Preprocessor::new("#if DEBUG\nLEVEL\n#endif\n".as_bytes());
+    /// preprocessor.define_macro("DEBUG").unwrap();
+    /// preprocessor.define_macro("LEVEL=3").unwrap();
+    /// assert_eq!(preprocessor.next().unwrap().unwrap().token, Token::INT(3));
+    /// ```
+    pub fn define_macro(&mut self, definition: &str) -> Result<(), TokenError> {
+        let (name, value) = definition.split_once('=').unwrap_or((definition, "1"));
+        // Scanned as the rest of a `#define` line, line 0 marks it as coming from the command line
+        let line = LogicalLine::physical(&format!("define {} {}", name, value), 0, 0);
+        let tokens = scan_logical_line(&line, None, &mut ScanState::default())
+            .into_iter()
+            .map(|token| PPToken::from_scanned(&line, token))
+            .filter(|token| !token.as_ref().is_ok_and(|token| token.is(&Token::EndOfLine)))
+            .collect::<Result<Vec<PPToken>, TokenError>>()?;
+        self.define(&tokens)
+    }
+
+    /// Remove a macro the way `-U` does
+    pub fn undefine_macro(&mut self, name: &str) {
+        self.macros.remove(name);
+    }
+
+    /// Define the macros that describe the target, such as `__aarch64__`
+    pub fn define_target_macros(&mut self, architecture: &SupportedArchitectures) {
+        for definition in architecture.predefined_macros() {
+            self.define_macro(definition).expect("target macros are valid definitions");
+        }
+    }
+
+    /// The `#warning` messages seen so far
+    pub fn warnings(&self) -> &[TokenError] {
+        &self.warnings
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.macros.contains_key(name) || BUILTIN_MACROS.contains(&name)
+    }
+
+    /// Read lines until there are tokens pending, running any directive on the way.
+    /// Returns false once every file has been read.
+    fn read_line(&mut self) -> bool {
+        loop {
+            let is_main_file = self.files.len() == 1;
+            let Some(source) = self.files.last_mut() else {
+                return false;
+            };
+
+            match source.lexer.next_line() {
+                Some((line, tokens)) => {
+                    let is_directive = matches!(
+                        tokens.first(),
+                        Some(ScannedToken { result: Ok(SpannedToken { token: Token::HASH, .. }), .. })
+                    );
+                    let tokens: Vec<Result<PPToken, TokenError>> = tokens
+                        .into_iter()
+                        .map(|token| PPToken::from_scanned(&line, token))
+                        .collect();
+
+                    // Lines in a group skipped by a conditional are dropped, scan errors and all
+                    let skipping = source.conditions.last().is_some_and(|conditional| !conditional.active);
+                    if is_directive {
+                        self.run_directive(&line, tokens);
+                        if self.pending.is_empty() {
+                            continue;
+                        }
+                    } else if skipping {
+                        continue;
+                    } else {
+                        self.pending.extend(tokens);
+                    }
+                    return true;
+                }
+                None => {
+                    if let Some(conditional) = source.conditions.first() {
+                        self.pending.push_back(Err(conditional.start.error(TokenErrorKind::UnterminatedConditional)));
+                    }
+                    // Only the main file ends the token stream
+                    let closing_tokens = source.lexer.finish(is_main_file);
+                    self.files.pop();
+
+                    for token in closing_tokens {
+                        self.pending.push_back(token.map(|token| PPToken {
+                            token,
+                            spelling: String::new(),
+                            leading_space: false,
+                            hide_set: Rc::default(),
+                        }));
+                    }
+                    if !self.pending.is_empty() {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Take the next token waiting to be expanded, reading more lines if `from_files` is set
+    fn next_pending(&mut self, from_files: bool) -> Option<Result<PPToken, TokenError>> {
+        if self.pending.is_empty() && (!from_files || !self.read_line()) {
+            return None;
+        }
+        self.pending.pop_front()
+    }
+
+    /// Whether a `(` comes next, ignoring line breaks. Nothing is consumed.
+    fn lparen_follows(&mut self, from_files: bool) -> bool {
+        let mut index = 0;
+        loop {
+            while self.pending.len() <= index {
+                if !from_files || !self.read_line() {
+                    return false;
+                }
+            }
+            match &self.pending[index] {
+                Ok(token) if token.is(&Token::EndOfLine) => index += 1,
+                Ok(token) => return token.is(&Token::LPAREN),
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Produce the next fully macro expanded token.
+    ///
+    /// # Arguments
+    ///
+    /// * `from_files`: read more lines when the pending tokens run out, not set when expanding a
+    ///   macro argument on its own
+    ///
+    /// returns: Option<Result<PPToken, TokenError>>
+    fn expand_next(&mut self, from_files: bool) -> Option<Result<PPToken, TokenError>> {
+        loop {
+            let token = match self.next_pending(from_files)? {
+                Ok(token) => token,
+                Err(error) => return Some(Err(error)),
+            };
+
+            if let Some(builtin) = builtin_macro(&token) {
+                return Some(Ok(builtin));
+            }
+
+            let Some(definition) = token
+                .name()
+                .filter(|name| !token.hide_set.contains(*name))
+                .and_then(|name| self.macros.get(name))
+                .cloned()
+            else {
+                return Some(Ok(token));
+            };
+            let name = token.name().unwrap_or_default().to_string();
+
+            let expansion = match &definition.parameters {
+                None => {
+                    let hide_set = with_name(&token.hide_set, &name);
+                    self.substitute(&token, &name, &definition, &[], hide_set)
+                }
+                Some(parameters) => {
+                    // A function-like macro name without arguments is just an identifier
+                    if !self.lparen_follows(from_files) {
+                        return Some(Ok(token));
+                    }
+                    self.collect_arguments(&token, &name, parameters.len(), definition.variadic, from_files)
+                        .and_then(|(arguments, rparen)| {
+                            let hide_set: HashSet<String> = token.hide_set.intersection(&rparen.hide_set).cloned().collect();
+                            let hide_set = with_name(&Rc::new(hide_set), &name);
+                            self.substitute(&token, &name, &definition, &arguments, hide_set)
+                        })
+                }
+            };
+
+            match expansion {
+                Ok(tokens) => {
+                    for token in tokens.into_iter().rev() {
+                        self.pending.push_front(Ok(token));
+                    }
+                }
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+
+    /// Read the arguments of a function-like macro call, up to and including the closing `)`.
+    ///
+    /// # Arguments
+    ///
+    /// * `invocation`: the macro name token, for errors
+    /// * `name`: the macro name
+    /// * `parameter_count`: how many arguments the macro expects
+    /// * `variadic`: whether the last parameter is `...`, then it takes the commas after it as well,
+    ///   and can be left out altogether
+    /// * `from_files`: whether the arguments may continue on the following lines
+    ///
+    /// returns: Result<(Vec<Vec<PPToken>>, PPToken), TokenError>, the arguments and the `)` token
+    fn collect_arguments(
+        &mut self,
+        invocation: &PPToken,
+        name: &str,
+        parameter_count: usize,
+        variadic: bool,
+        from_files: bool,
+    ) -> Result<(Vec<Vec<PPToken>>, PPToken), TokenError> {
+        let unterminated = || invocation.error(TokenErrorKind::UnterminatedMacroCall(name.to_string()));
+
+        // Line breaks before the `(`, which lparen_follows has already seen
+        while let Some(Ok(token)) = self.next_pending(from_files) {
+            if token.is(&Token::LPAREN) {
+                break;
+            }
+        }
+
+        let mut arguments: Vec<Vec<PPToken>> = vec![Vec::new()];
+        let mut depth = 0;
+        let mut after_line_break = false;
+
+        loop {
+            let mut token = match self.next_pending(from_files) {
+                Some(Ok(token)) => token,
+                Some(Err(error)) => return Err(error),
+                None => return Err(unterminated()),
+            };
+
+            match token.token.token {
+                Token::EndOfLine => {
+                    after_line_break = true;
+                    continue;
+                }
+                Token::EndOfFile => {
+                    // The parser still needs to see the end of the file
+                    self.pending.push_front(Ok(token));
+                    return Err(unterminated());
+                }
+                Token::RPAREN if depth == 0 => {
+                    let found = arguments.len();
+                    // `F(1)` for `F(x, ...)` passes nothing for the `...`
+                    if variadic && found + 1 == parameter_count {
+                        arguments.push(Vec::new());
+                    }
+                    // `()` passes a single empty argument, which is no argument at all for `M()`
+                    let empty = parameter_count == 0 && found == 1 && arguments[0].is_empty();
+                    if arguments.len() != parameter_count && !empty {
+                        return Err(invocation.error(TokenErrorKind::MacroArgumentCount {
+                            name: name.to_string(),
+                            expected: parameter_count,
+                            found,
+                        }));
+                    }
+                    if parameter_count == 0 {
+                        arguments.clear();
+                    }
+                    return Ok((arguments, token));
+                }
+                Token::COMMA if depth == 0 && !(variadic && arguments.len() == parameter_count) => {
+                    arguments.push(Vec::new());
+                    after_line_break = false;
+                    continue;
+                }
+                Token::LPAREN => depth += 1,
+                Token::RPAREN => depth -= 1,
+                _ => {}
+            }
+
+            token.leading_space |= after_line_break;
+            after_line_break = false;
+            if let Some(argument) = arguments.last_mut() {
+                argument.push(token);
+            }
+        }
+    }
+
+    /// Fully macro expand a macro argument on its own, as done before it replaces its parameter
+    fn expand_argument(&mut self, argument: &[PPToken]) -> Result<Vec<PPToken>, TokenError> {
+        let saved = std::mem::replace(&mut self.pending, argument.iter().cloned().map(Ok).collect());
+        let mut expanded = Vec::new();
+        let mut result = Ok(());
+
+        while let Some(token) = self.expand_next(false) {
+            match token {
+                Ok(token) => expanded.push(token),
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+
+        self.pending = saved;
+        result.map(|_| expanded)
+    }
+
+    /// Build the replacement for one macro invocation (C11 6.10.3.1 to 6.10.3.3):
+    /// parameters are replaced by their expanded argument, or the argument as written next to `#` and `##`,
+    /// then `#` stringifies and `##` pastes.
+    ///
+    /// # Arguments
+    ///
+    /// * `invocation`: the macro name token where the macro is used
+    /// * `name`: the macro name
+    /// * `definition`: the macro being expanded
+    /// * `arguments`: one token list per parameter, empty for object-like macros
+    /// * `hide_set`: the hide set every token of the replacement gets
+    ///
+    /// returns: Result<Vec<PPToken>, TokenError>
+    fn substitute(
+        &mut self,
+        invocation: &PPToken,
+        name: &str,
+        definition: &Macro,
+        arguments: &[Vec<PPToken>],
+        hide_set: Rc<HashSet<String>>,
+    ) -> Result<Vec<PPToken>, TokenError> {
+        let parameters = definition.parameters.as_deref().unwrap_or_default();
+        let parameter_index = |token: &PPToken| token.name().and_then(|name| parameters.iter().position(|p| p == name));
+        let body = &definition.body;
+
+        // `None` is a placemarker, what an empty argument next to `##` becomes
+        let mut replacement: Vec<Option<PPToken>> = Vec::new();
+        let mut paste_next = false;
+        let mut index = 0;
+
+        while index < body.len() {
+            let token = &body[index];
+            if token.is(&Token::HASHHASH) {
+                paste_next = true;
+                index += 1;
+                continue;
+            }
+
+            let items: Vec<Option<PPToken>> = if token.is(&Token::HASH) && definition.parameters.is_some() {
+                // The definition made sure a parameter follows
+                let argument = parameter_index(&body[index + 1]).map_or(&[][..], |i| &arguments[i]);
+                index += 2;
+                vec![Some(stringify(token, argument))]
+            } else if let Some(parameter) = parameter_index(token) {
+                index += 1;
+                let argument = &arguments[parameter];
+                let next_is_paste = body.get(index).is_some_and(|next| next.is(&Token::HASHHASH));
+                if paste_next || next_is_paste {
+                    if argument.is_empty() {
+                        vec![None]
+                    } else {
+                        argument.iter().cloned().map(Some).collect()
+                    }
+                } else {
+                    self.expand_argument(argument)?.into_iter().map(Some).collect()
+                }
+            } else {
+                index += 1;
+                vec![Some(token.clone())]
+            };
+
+            let mut items = items.into_iter();
+            if paste_next {
+                let left = replacement.pop().flatten();
+                let right = items.next().flatten();
+                replacement.push(match (left, right) {
+                    (Some(left), Some(right)) => Some(paste(left, right)?),
+                    (left, right) => left.or(right),
+                });
+                paste_next = false;
+            }
+            replacement.extend(items);
+        }
+
+        let expansion = Rc::new(MacroExpansion {
+            name: name.to_string(),
+            file: invocation.token.file.clone(),
+            line: invocation.token.line,
+            column: invocation.token.column,
+            parent: invocation.token.expansion.clone(),
+        });
+
+        let mut tokens: Vec<PPToken> = replacement.into_iter().flatten().collect();
+        for (position, token) in tokens.iter_mut().enumerate() {
+            token.hide_set = Rc::new(token.hide_set.union(&hide_set).cloned().collect());
+            token.token.expansion = Some(expansion.clone());
+            if position == 0 {
+                token.leading_space = invocation.leading_space;
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// Run a directive line, the tokens start with the `#`
+    fn run_directive(&mut self, line: &LogicalLine, tokens: Vec<Result<PPToken, TokenError>>) {
+        let mut valid: Vec<PPToken> = Vec::new();
+        let mut scan_error = None;
+        for token in tokens {
+            match token {
+                Ok(token) if !token.is(&Token::EndOfLine) => valid.push(token),
+                Ok(_) => {}
+                Err(error) => {
+                    scan_error = Some(error);
+                    break;
+                }
+            }
+        }
+        let tokens = valid;
+
+        let Some(directive) = tokens.get(1) else {
+            // A `#` on its own is the null directive
+            return;
+        };
+        let name = directive.name().unwrap_or_default();
+        let skipping = self.files.last().is_some_and(|source| source.conditions.last().is_some_and(|conditional| !conditional.active));
+
+        let result = match name {
+            "if" | "ifdef" | "ifndef" | "elif" | "else" | "endif" => {
+                self.conditional(name, directive, &tokens[2..], scan_error)
+            }
+            // Only conditionals matter in a skipped group, not even unknown directives
+            _ if skipping => Ok(()),
+            // These read the rest of the line as text, which does not have to be made of tokens
+            "include" => self.include(line, directive, &tokens[2..]),
+            "error" => Err(directive.error(TokenErrorKind::ErrorDirective(directive_text(line, directive).to_string()))),
+            "warning" => {
+                let message = directive_text(line, directive).to_string();
+                self.warnings.push(directive.error(TokenErrorKind::WarningDirective(message)));
+                Ok(())
+            }
+            "pragma" => {
+                self.pragma(&tokens[2..]);
+                Ok(())
+            }
+            _ => match scan_error {
+                Some(error) => Err(error),
+                None => match name {
+                    "define" => self.define(&tokens[1..]),
+                    "undef" => match tokens.get(2).and_then(PPToken::name) {
+                        Some(name) => {
+                            self.macros.remove(name);
+                            Ok(())
+                        }
+                        None => Err(directive.error(TokenErrorKind::MissingMacroName)),
+                    },
+                    _ => Err(directive.error(TokenErrorKind::InvalidDirective(directive.spelling.clone()))),
+                },
+            },
+        };
+
+        if let Err(error) = result {
+            self.pending.push_back(Err(error));
+        }
+    }
+
+    /// `#if`, `#ifdef`, `#ifndef`, `#elif`, `#else` and `#endif`
+    ///
+    /// # Arguments
+    ///
+    /// * `name`: which of them
+    /// * `directive`: the directive name token
+    /// * `operands`: the tokens after the directive name
+    /// * `scan_error`: the first scan error on the line, only reported if the condition is evaluated
+    ///
+    /// returns: Result<(), TokenError>
+    fn conditional(
+        &mut self,
+        name: &str,
+        directive: &PPToken,
+        operands: &[PPToken],
+        scan_error: Option<TokenError>,
+    ) -> Result<(), TokenError> {
+        let Some(source) = self.files.last() else {
+            return Ok(());
+        };
+        let skipping = source.conditions.last().is_some_and(|conditional| !conditional.active);
+        let current = source.conditions.last().map(|conditional| (conditional.done, conditional.seen_else));
+        let unmatched = || directive.error(TokenErrorKind::UnmatchedConditional(name.to_string()));
+
+        match (name, current) {
+            ("if" | "ifdef" | "ifndef", _) => {
+                // A conditional inside a skipped group is skipped whole, its condition is never looked at
+                let condition = if skipping {
+                    Ok(false)
+                } else {
+                    self.condition(name, directive, operands, scan_error)
+                };
+                let active = condition.as_ref().is_ok_and(|active| *active);
+                self.conditions_mut().push(Conditional {
+                    active,
+                    done: active || skipping || condition.is_err(),
+                    seen_else: false,
+                    start: directive.clone(),
+                });
+                condition.map(|_| ())
+            }
+            (_, None) => Err(unmatched()),
+            ("elif" | "else", Some((_, true))) => {
+                Err(directive.error(TokenErrorKind::ConditionalAfterElse(name.to_string())))
+            }
+            ("elif", Some((done, _))) => {
+                let condition = if done {
+                    Ok(false)
+                } else {
+                    self.condition("if", directive, operands, scan_error)
+                };
+                let active = condition.as_ref().is_ok_and(|active| *active);
+                if let Some(conditional) = self.conditions_mut().last_mut() {
+                    conditional.active = active;
+                    conditional.done |= active || condition.is_err();
+                }
+                condition.map(|_| ())
+            }
+            ("else", Some((done, _))) => {
+                if let Some(conditional) = self.conditions_mut().last_mut() {
+                    conditional.active = !done;
+                    conditional.done = true;
+                    conditional.seen_else = true;
+                }
+                Ok(())
+            }
+            _ => {
+                self.conditions_mut().pop();
+                Ok(())
+            }
+        }
+    }
+
+    fn conditions_mut(&mut self) -> &mut Vec<Conditional> {
+        &mut self.files.last_mut().expect("directives are read from a file").conditions
+    }
+
+    /// Evaluate the condition of `#if`, `#ifdef` or `#ifndef`
+    fn condition(
+        &mut self,
+        name: &str,
+        directive: &PPToken,
+        operands: &[PPToken],
+        scan_error: Option<TokenError>,
+    ) -> Result<bool, TokenError> {
+        if let Some(error) = scan_error {
+            return Err(error);
+        }
+
+        if name == "ifdef" || name == "ifndef" {
+            let macro_name = operands
+                .first()
+                .and_then(PPToken::name)
+                .ok_or_else(|| directive.error(TokenErrorKind::MissingMacroName))?;
+            return Ok(self.is_defined(macro_name) == (name == "ifdef"));
+        }
+
+        // `defined` is handled before macro expansion, so the macro it names is not expanded
+        let mut operands_defined: Vec<PPToken> = Vec::new();
+        let mut index = 0;
+        while index < operands.len() {
+            let token = &operands[index];
+            if token.name() != Some("defined") {
+                operands_defined.push(token.clone());
+                index += 1;
+                continue;
+            }
+
+            let parenthesized = operands.get(index + 1).is_some_and(|next| next.is(&Token::LPAREN));
+            let name_index = index + 1 + parenthesized as usize;
+            let macro_name = operands
+                .get(name_index)
+                .and_then(PPToken::name)
+                .ok_or_else(|| token.error(TokenErrorKind::MissingMacroName))?;
+            if parenthesized && !operands.get(name_index + 1).is_some_and(|next| next.is(&Token::RPAREN)) {
+                return Err(token.error(TokenErrorKind::InvalidIfExpression));
+            }
+
+            let defined = self.is_defined(macro_name);
+            operands_defined.push(PPToken {
+                token: SpannedToken {
+                    token: Token::INT(defined as i32),
+                    ..token.token.clone()
+                },
+                spelling: (defined as i32).to_string(),
+                ..token.clone()
+            });
+            index = name_index + 1 + parenthesized as usize;
+        }
+
+        let expanded = self.expand_argument(&operands_defined)?;
+        let value = IfExpression { tokens: &expanded, position: 0, directive }.evaluate()?;
+        Ok(value.is_true())
+    }
+
+    /// `#pragma once`, any other pragma is ignored
+    fn pragma(&mut self, operands: &[PPToken]) {
+        if operands.first().and_then(PPToken::name) != Some("once") {
+            return;
+        }
+        if let Some(file) = self.files.last().and_then(|source| source.lexer.file()) {
+            self.once_files.insert(canonical_path(file));
+        }
+    }
+
+    /// `#define`, `tokens` starts with the `define` token
+    fn define(&mut self, tokens: &[PPToken]) -> Result<(), TokenError> {
+        let Some(name_token) = tokens.get(1) else {
+            return Err(tokens[0].error(TokenErrorKind::MissingMacroName));
+        };
+        let name = name_token.name().ok_or(name_token.error(TokenErrorKind::MissingMacroName))?;
+
+        let mut body_start = 2;
+        let mut variadic = false;
+        // Only a `(` right after the name, with no space, makes a function-like macro
+        let parameters = match tokens.get(2) {
+            Some(lparen) if lparen.is(&Token::LPAREN) && !lparen.leading_space => {
+                let invalid = || lparen.error(TokenErrorKind::InvalidMacroParameters(name.to_string()));
+                let mut parameters: Vec<String> = Vec::new();
+                let mut index = 3;
+                if tokens.get(index).is_some_and(|token| token.is(&Token::RPAREN)) {
+                    index += 1;
+                } else {
+                    loop {
+                        // `...` has to be the last parameter, the body names it `__VA_ARGS__`
+                        if tokens.get(index).is_some_and(|token| token.is(&Token::ELLIPSIS)) {
+                            variadic = true;
+                            parameters.push(VARIADIC_PARAMETER.to_string());
+                            if !tokens.get(index + 1).is_some_and(|token| token.is(&Token::RPAREN)) {
+                                return Err(invalid());
+                            }
+                            index += 2;
+                            break;
+                        }
+                        let parameter = tokens.get(index).and_then(PPToken::name).ok_or_else(invalid)?;
+                        if parameter == VARIADIC_PARAMETER || parameters.iter().any(|existing| existing == parameter) {
+                            return Err(invalid());
+                        }
+                        parameters.push(parameter.to_string());
+
+                        match tokens.get(index + 1) {
+                            Some(token) if token.is(&Token::COMMA) => index += 2,
+                            Some(token) if token.is(&Token::RPAREN) => {
+                                index += 2;
+                                break;
+                            }
+                            _ => return Err(invalid()),
+                        }
+                    }
+                }
+                body_start = index;
+                Some(parameters)
+            }
+            _ => None,
+        };
+
+        let body = tokens[body_start..].to_vec();
+
+        if let (Some(first), Some(last)) = (body.first(), body.last()) {
+            for edge in [first, last] {
+                if edge.is(&Token::HASHHASH) {
+                    return Err(edge.error(TokenErrorKind::PasteAtMacroEdge));
+                }
+            }
+        }
+        if let Some(parameters) = &parameters {
+            for (index, token) in body.iter().enumerate() {
+                let names_parameter = |token: &PPToken| token.name().is_some_and(|name| parameters.iter().any(|p| p == name));
+                if token.is(&Token::HASH) && !body.get(index + 1).is_some_and(names_parameter) {
+                    return Err(token.error(TokenErrorKind::StringifyWithoutParameter));
+                }
+            }
+        }
+
+        // Only the body of a variadic macro can use `__VA_ARGS__`
+        if let Some(token) = body.iter().find(|token| token.name() == Some(VARIADIC_PARAMETER)).filter(|_| !variadic) {
+            return Err(token.error(TokenErrorKind::MisplacedVariadicArguments));
+        }
+
+        self.macros.insert(name.to_string(), Rc::new(Macro { parameters, variadic, body }));
+        Ok(())
+    }
+
+    /// `#include "file"` or `#include <file>`, also accepts macros that expand to a string literal
+    ///
+    /// # Arguments
+    ///
+    /// * `line`: the directive line, header names are taken from its text
+    /// * `directive`: the `include` token
+    /// * `operands`: the tokens after `include`
+    ///
+    /// returns: Result<(), TokenError>
+    fn include(&mut self, line: &LogicalLine, directive: &PPToken, operands: &[PPToken]) -> Result<(), TokenError> {
+        let invalid = || directive.error(TokenErrorKind::InvalidInclude);
+        // `<stdio.h>` is not made of tokens, so header names are read straight from the text after `include`
+        let rest = directive_text(line, directive);
+
+        let (header, quoted) = if let Some(quoted_name) = rest.strip_prefix('"') {
+            (quoted_name.split('"').next().filter(|_| quoted_name.contains('"')).ok_or_else(invalid)?.to_string(), true)
+        } else if let Some(angled_name) = rest.strip_prefix('<') {
+            (angled_name.split('>').next().filter(|_| angled_name.contains('>')).ok_or_else(invalid)?.to_string(), false)
+        } else {
+            match self.expand_argument(operands)?.first().map(|token| &token.token.token) {
+                Some(Token::STRING(bytes)) => (String::from_utf8_lossy(bytes).into_owned(), true),
+                _ => return Err(invalid()),
+            }
+        };
+
+        if self.files.len() >= MAX_INCLUDE_DEPTH {
+            return Err(directive.error(TokenErrorKind::IncludeTooDeep));
+        }
+
+        let current_directory = self.files.last().map(|source| source.directory.clone());
+        let candidates = quoted
+            .then_some(current_directory)
+            .flatten()
+            .into_iter()
+            .chain(self.include_paths.iter().cloned())
+            .chain(self.system_include_paths.iter().cloned())
+            .map(|directory| directory.join(&header));
+
+        for candidate in candidates {
+            if candidate.is_file() {
+                if self.once_files.contains(&canonical_path(&candidate)) {
+                    return Ok(());
+                }
+                let source = Self::open_source(&candidate)
+                    .map_err(|error| directive.error(TokenErrorKind::Io(error.kind())))?;
+                self.files.push(source);
+                return Ok(());
+            }
+        }
+        if let Some((name, contents)) = BUILTIN_HEADERS.iter().find(|(name, _)| *name == header) {
+            self.files.push(SourceFile {
+                lexer: Lexer::with_file(Box::new(contents.as_bytes()), Rc::from(Path::new(name))),
+                directory: PathBuf::from("."),
+                conditions: Vec::new(),
+            });
+            return Ok(());
+        }
+        Err(directive.error(TokenErrorKind::IncludeNotFound(header)))
+    }
+
+    /// Expand tokens until at least `count` are ready for the parser, false if the input runs out first
+    fn fill_output(&mut self, count: usize) -> bool {
+        while self.output.len() < count {
+            match self.expand_next(true) {
+                Some(token) => self.output.push_back(token.map(|token| token.token)),
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Iterator for Preprocessor {
+    type Item = Result<SpannedToken, TokenError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fill_output(1);
+        self.output.pop_front()
+    }
+}
+
+impl TokenSource for Preprocessor {
+    fn next_token(&mut self) -> Option<Result<SpannedToken, TokenError>> {
+        self.next()
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&Result<SpannedToken, TokenError>> {
+        self.fill_output(n + 1);
+        self.output.get(n)
+    }
+}
+
+
+/// The text of a directive line after the directive name, for directives that do not work on tokens
+fn directive_text<'a>(line: &'a LogicalLine, directive: &PPToken) -> &'a str {
+    line.text
+        .find('#')
+        .and_then(|hash| line.text[hash..].find(directive.spelling.as_str()).map(|at| hash + at + directive.spelling.len()))
+        .map_or("", |end| line.text[end..].trim())
+}
+
+/// The same file can be included through different paths, `#pragma once` has to recognise all of them
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Expand `__LINE__` or `__FILE__`, `None` for any other token
+fn builtin_macro(token: &PPToken) -> Option<PPToken> {
+    let name = token.name().filter(|name| BUILTIN_MACROS.contains(name))?;
+
+    // Inside a macro, what counts is where the outermost macro was used
+    let mut file = token.token.file.clone();
+    let mut line = token.token.line;
+    let mut expansion = token.token.expansion.clone();
+    while let Some(outer) = expansion {
+        file = outer.file.clone();
+        line = outer.line;
+        expansion = outer.parent.clone();
+    }
+
+    let (value, spelling) = if name == "__LINE__" {
+        (Token::INT(line as i32), line.to_string())
+    } else {
+        let path = file.map_or_else(|| "<stdin>".to_string(), |file| file.display().to_string());
+        (Token::STRING(path.clone().into_bytes()), format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\"")))
+    };
+
+    Some(PPToken {
+        token: SpannedToken {
+            token: value,
+            expansion: Some(Rc::new(MacroExpansion {
+                name: name.to_string(),
+                file: token.token.file.clone(),
+                line: token.token.line,
+                column: token.token.column,
+                parent: token.token.expansion.clone(),
+            })),
+            ..token.token.clone()
+        },
+        spelling,
+        ..token.clone()
+    })
+}
+
+/// A hide set with `name` added to it
+fn with_name(hide_set: &Rc<HashSet<String>>, name: &str) -> Rc<HashSet<String>> {
+    let mut hide_set = HashSet::clone(hide_set);
+    hide_set.insert(name.to_string());
+    Rc::new(hide_set)
+}
+
+/// The `#` operator: turn an argument, as it was written, into a string literal
+fn stringify(hash: &PPToken, argument: &[PPToken]) -> PPToken {
+    let mut text = String::new();
+    for (index, token) in argument.iter().enumerate() {
+        if index > 0 && token.leading_space {
+            text.push(' ');
+        }
+        text.push_str(&token.spelling);
+    }
+
+    // Quotes and backslashes can only come from string and character literals,
+    // which need them escaped to keep their meaning inside the new literal
+    let mut spelling = String::from("\"");
+    for ch in text.chars() {
+        if ch == '"' || ch == '\\' {
+            spelling.push('\\');
+        }
+        spelling.push(ch);
+    }
+    spelling.push('"');
+
+    PPToken {
+        token: SpannedToken {
+            token: Token::STRING(text.into_bytes()),
+            ..hash.token.clone()
+        },
+        spelling,
+        leading_space: hash.leading_space,
+        hide_set: Rc::default(),
+    }
+}
+
+/// The `##` operator: join two tokens, the result has to scan as a single token
+fn paste(left: PPToken, right: PPToken) -> Result<PPToken, TokenError> {
+    let spelling = format!("{}{}", left.spelling, right.spelling);
+    let mut tokens = scan_line(&spelling, left.token.line);
+    // A single token followed by the end of the line
+    match (tokens.len(), tokens.first()) {
+        (2, Some(Ok(_))) => {
+            let Ok(token) = tokens.remove(0) else {
+                unreachable!("checked above")
+            };
+            Ok(PPToken {
+                token: SpannedToken { token, ..left.token },
+                spelling,
+                ..left
+            })
+        }
+        _ => Err(left.error(TokenErrorKind::InvalidPaste(spelling))),
+    }
+}
+
+
+/// The value of a `#if` expression, which is computed in `intmax_t` or `uintmax_t` (C11 6.10.1)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum IfValue {
+    Signed(i64),
+    Unsigned(u64),
+}
+
+impl IfValue {
+    fn from_token(token: &Token) -> Option<Self> {
+        match *token {
+            Token::UINT(n) => Some(Self::Unsigned(n as u64)),
+            Token::ULONG(n) | Token::ULONGLONG(n) => Some(Self::Unsigned(n)),
+            _ => token.integer_value().map(Self::Signed),
+        }
+    }
+
+    fn boolean(value: bool) -> Self {
+        Self::Signed(value as i64)
+    }
+
+    fn bits(self) -> u64 {
+        match self {
+            Self::Signed(n) => n as u64,
+            Self::Unsigned(n) => n,
+        }
+    }
+
+    fn is_true(self) -> bool {
+        self.bits() != 0
+    }
+
+    fn is_unsigned(self) -> bool {
+        matches!(self, Self::Unsigned(_))
+    }
+
+    /// A value of the same type as `self` with the given bits
+    fn with_bits(self, bits: u64) -> Self {
+        match self {
+            Self::Signed(_) => Self::Signed(bits as i64),
+            Self::Unsigned(_) => Self::Unsigned(bits),
+        }
+    }
+
+    /// Apply a binary operator, converting both sides to unsigned if either is, like C does
+    fn binary(operator: &Token, left: Self, right: Self) -> Result<Self, TokenErrorKind> {
+        let unsigned = left.is_unsigned() || right.is_unsigned();
+        let (a, b) = (left.bits(), right.bits());
+        let common = if unsigned { Self::Unsigned(0) } else { Self::Signed(0) };
+        let ordering = if unsigned { a.cmp(&b) } else { (a as i64).cmp(&(b as i64)) };
+
+        Ok(match operator {
+            // Two's complement makes these the same for signed and unsigned values
+            Token::PLUS => common.with_bits(a.wrapping_add(b)),
+            Token::MINUS => common.with_bits(a.wrapping_sub(b)),
+            Token::ASTERISK => common.with_bits(a.wrapping_mul(b)),
+            Token::AMPER => common.with_bits(a & b),
+            Token::OR => common.with_bits(a | b),
+            Token::XOR => common.with_bits(a ^ b),
+            Token::EQ => Self::boolean(a == b),
+            Token::NE => Self::boolean(a != b),
+            Token::LT => Self::boolean(ordering.is_lt()),
+            Token::GT => Self::boolean(ordering.is_gt()),
+            Token::LE => Self::boolean(ordering.is_le()),
+            Token::GE => Self::boolean(ordering.is_ge()),
+            Token::LOGAND => Self::boolean(left.is_true() && right.is_true()),
+            Token::LOGOR => Self::boolean(left.is_true() || right.is_true()),
+            Token::SLASH | Token::PERCENT if b == 0 => return Err(TokenErrorKind::DivisionByZero),
+            Token::SLASH if unsigned => Self::Unsigned(a / b),
+            Token::PERCENT if unsigned => Self::Unsigned(a % b),
+            Token::SLASH => Self::Signed((a as i64).wrapping_div(b as i64)),
+            Token::PERCENT => Self::Signed((a as i64).wrapping_rem(b as i64)),
+            // Shifts keep the type of the left side, shifting by the width or more gives 0 or the sign
+            Token::LSHIFT => left.with_bits(a.checked_shl(b.min(64) as u32).unwrap_or(0)),
+            Token::RSHIFT => match left {
+                Self::Signed(n) => Self::Signed(n.checked_shr(b.min(64) as u32).unwrap_or(if n < 0 { -1 } else { 0 })),
+                Self::Unsigned(n) => Self::Unsigned(n.checked_shr(b.min(64) as u32).unwrap_or(0)),
+            },
+            _ => return Err(TokenErrorKind::InvalidIfExpression),
+        })
+    }
+}
+
+/// Precedence of the binary operators allowed in `#if`, higher binds tighter
+fn if_precedence(token: &Token) -> Option<u8> {
+    match token {
+        Token::ASTERISK | Token::SLASH | Token::PERCENT => Some(10),
+        Token::PLUS | Token::MINUS => Some(9),
+        Token::LSHIFT | Token::RSHIFT => Some(8),
+        Token::LT | Token::GT | Token::LE | Token::GE => Some(7),
+        Token::EQ | Token::NE => Some(6),
+        Token::AMPER => Some(5),
+        Token::XOR => Some(4),
+        Token::OR => Some(3),
+        Token::LOGAND => Some(2),
+        Token::LOGOR => Some(1),
+        _ => None,
+    }
+}
+
+/// Evaluates the macro expanded tokens of a `#if` or `#elif`.
+/// Operands that are not evaluated, like the right side of `0 && x`, are still parsed but cannot fail.
+struct IfExpression<'a> {
+    tokens: &'a [PPToken],
+    position: usize,
+    /// The `if` or `elif` token, for errors at the end of the line
+    directive: &'a PPToken,
+}
+
+impl IfExpression<'_> {
+    fn evaluate(mut self) -> Result<IfValue, TokenError> {
+        let value = self.conditional(true)?;
+        if self.position < self.tokens.len() {
+            return Err(self.error(TokenErrorKind::InvalidIfExpression));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, kind: TokenErrorKind) -> TokenError {
+        self.tokens.get(self.position).unwrap_or(self.directive).error(kind)
+    }
+
+    fn next_is(&mut self, token: &Token) -> bool {
+        let found = self.tokens.get(self.position).is_some_and(|next| next.is(token));
+        self.position += found as usize;
+        found
+    }
+
+    /// `condition ? a : b`, only the branch that is chosen gets evaluated
+    fn conditional(&mut self, evaluate: bool) -> Result<IfValue, TokenError> {
+        let condition = self.binary(1, evaluate)?;
+        if !self.next_is(&Token::QUESTION) {
+            return Ok(condition);
+        }
+
+        let then = self.conditional(evaluate && condition.is_true())?;
+        if !self.next_is(&Token::COLON) {
+            return Err(self.error(TokenErrorKind::InvalidIfExpression));
+        }
+        let otherwise = self.conditional(evaluate && !condition.is_true())?;
+
+        let chosen = if condition.is_true() { then } else { otherwise };
+        Ok(if then.is_unsigned() || otherwise.is_unsigned() {
+            IfValue::Unsigned(chosen.bits())
+        } else {
+            chosen
+        })
+    }
+
+    fn binary(&mut self, min_precedence: u8, evaluate: bool) -> Result<IfValue, TokenError> {
+        let mut left = self.unary(evaluate)?;
+
+        while let Some(operator) = self.tokens.get(self.position) {
+            let Some(precedence) = if_precedence(&operator.token.token).filter(|precedence| *precedence >= min_precedence) else {
+                break;
+            };
+            self.position += 1;
+
+            let evaluate_right = match operator.token.token {
+                Token::LOGAND => evaluate && left.is_true(),
+                Token::LOGOR => evaluate && !left.is_true(),
+                _ => evaluate,
+            };
+            let right = self.binary(precedence + 1, evaluate_right)?;
+
+            left = if evaluate {
+                IfValue::binary(&operator.token.token, left, right).map_err(|kind| operator.error(kind))?
+            } else {
+                IfValue::Signed(0)
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self, evaluate: bool) -> Result<IfValue, TokenError> {
+        let Some(token) = self.tokens.get(self.position) else {
+            return Err(self.error(TokenErrorKind::InvalidIfExpression));
+        };
+        self.position += 1;
+
+        match &token.token.token {
+            Token::PLUS => self.unary(evaluate),
+            Token::MINUS => self.unary(evaluate).map(|value| value.with_bits(value.bits().wrapping_neg())),
+            Token::INVERT => self.unary(evaluate).map(|value| value.with_bits(!value.bits())),
+            Token::LOGNOT => self.unary(evaluate).map(|value| IfValue::boolean(!value.is_true())),
+            Token::LPAREN => {
+                let value = self.conditional(evaluate)?;
+                if !self.next_is(&Token::RPAREN) {
+                    return Err(self.error(TokenErrorKind::InvalidIfExpression));
+                }
+                Ok(value)
+            }
+            // Identifiers that are still there after macro expansion count as 0
+            Token::IDENTIFIER(_) => Ok(IfValue::Signed(0)),
+            other => IfValue::from_token(other).ok_or_else(|| token.error(TokenErrorKind::InvalidIfExpression)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::statement::TranslationUnit;
+    use crate::ast::ASTNode;
+    use crate::typecheck::check_translation_unit;
+    use std::fs;
+
+    fn preprocess(source: &str) -> Vec<Result<SpannedToken, TokenError>> {
+        Preprocessor::new(io::Cursor::new(source.to_string())).collect()
+    }
+
+    /// The tokens of a preprocessed source, without the line and file ends
+    fn preprocess_tokens(source: &str) -> Vec<Token> {
+        preprocess(source)
+            .into_iter()
+            .map(|token| token.unwrap().token)
+            .filter(|token| !matches!(token, Token::EndOfLine | Token::EndOfFile))
+            .collect()
+    }
+
+    fn preprocess_error(source: &str) -> TokenErrorKind {
+        preprocess(source)
+            .into_iter()
+            .find_map(|token| token.err())
+            .expect("no error reported")
+            .kind
+    }
+
+    fn identifier(name: &str) -> Token {
+        Token::IDENTIFIER(name.to_string())
+    }
+
+    /// A directory under the system temporary directory that is removed again when dropped
+    struct TempDirectory(PathBuf);
+
+    impl TempDirectory {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("compiler_preprocess_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_lines_without_directives_pass_through() {
+        let tokens: Vec<Token> = preprocess("1 + 2\n3\n").into_iter().map(|token| token.unwrap().token).collect();
+        assert_eq!(tokens, vec![
+            Token::INT(1),
+            Token::PLUS,
+            Token::INT(2),
+            Token::EndOfLine,
+            Token::INT(3),
+            Token::EndOfLine,
+            Token::EndOfFile,
+        ]);
+    }
+
+    #[test]
+    fn test_object_like_macro() {
+        let tokens = preprocess_tokens("#define TWO 2\n#define FOUR TWO * TWO\nFOUR + TWO\n");
+        assert_eq!(tokens, vec![Token::INT(2), Token::ASTERISK, Token::INT(2), Token::PLUS, Token::INT(2)]);
+
+        // Directive lines produce no tokens, not even their line break
+        let tokens: Vec<Token> = preprocess("#define X 1\nX\n").into_iter().map(|token| token.unwrap().token).collect();
+        assert_eq!(tokens, vec![Token::INT(1), Token::EndOfLine, Token::EndOfFile]);
+    }
+
+    #[test]
+    fn test_undef() {
+        let tokens = preprocess_tokens("#define X 1\nX\n#undef X\nX\n");
+        assert_eq!(tokens, vec![Token::INT(1), identifier("X")]);
+    }
+
+    #[test]
+    fn test_function_like_macro() {
+        let tokens = preprocess_tokens("#define SUB(a, b) a - b\nSUB(SUB(1, 2), (3, 4))\n");
+        assert_eq!(tokens, vec![
+            Token::INT(1),
+            Token::MINUS,
+            Token::INT(2),
+            Token::MINUS,
+            Token::LPAREN,
+            Token::INT(3),
+            Token::COMMA,
+            Token::INT(4),
+            Token::RPAREN,
+        ]);
+    }
+
+    #[test]
+    fn test_function_like_macro_without_arguments() {
+        // Not followed by `(`, so not a call
+        assert_eq!(preprocess_tokens("#define F() 1\nF + F()\n"), vec![identifier("F"), Token::PLUS, Token::INT(1)]);
+        // A space before the `(` makes it object-like
+        assert_eq!(preprocess_tokens("#define G (x)\nG\n"), vec![Token::LPAREN, identifier("x"), Token::RPAREN]);
+    }
+
+    #[test]
+    fn test_lparen_from_a_later_expansion_is_not_a_call() {
+        // Only a `(` in the source text calls the macro, not one that a following macro expands to
+        let call = vec![identifier("g"), Token::LPAREN, Token::INT(1), Token::RPAREN];
+        assert_eq!(preprocess_tokens("#define obj (1)\n#define g(x) x\ng obj\n"), call);
+        assert_eq!(preprocess_tokens("#define obj (1)\n#define g(x) x\n#define h g obj\nh\n"), call);
+        assert_eq!(preprocess_tokens("#define obj (1)\n#define g(x) x\ng\nobj\n"), call);
+        // A macro that expands to the name does take a `(` from the source
+        assert_eq!(preprocess_tokens("#define g(x) x\n#define h g\nh (2)\n"), vec![Token::INT(2)]);
+    }
+
+    #[test]
+    fn test_macro_call_across_lines() {
+        let tokens = preprocess_tokens("#define ADD(a, b) a + b\nADD(1,\n  2)\n#define LONG 1 + \\\n  2\nLONG\n");
+        assert_eq!(tokens, vec![Token::INT(1), Token::PLUS, Token::INT(2), Token::INT(1), Token::PLUS, Token::INT(2)]);
+    }
+
+    #[test]
+    fn test_comments_across_lines_in_directives() {
+        // A comment is a single space, even when it spans lines, so the definition goes on after it
+        let tokens = preprocess_tokens("#define M 1 /* c\n */ + 2\nM\n");
+        assert_eq!(tokens, vec![Token::INT(1), Token::PLUS, Token::INT(2)]);
+        // The token after the comment is still where it was written, on the next line
+        let tokens = preprocess("#define N /*\n*/ 3\nN\n");
+        assert!(matches!(&tokens[0], Ok(SpannedToken { token: Token::INT(3), line: 2, column: 4, .. })));
+        // `/*` in a string or a line comment opens nothing
+        let tokens = preprocess_tokens("#define S \"/*\" // /*\nS 4\n");
+        assert_eq!(tokens, vec![Token::STRING(b"/*".to_vec()), Token::INT(4)]);
+    }
+
+    #[test]
+    fn test_recursive_macros_stop() {
+        assert_eq!(preprocess_tokens("#define foo foo + 1\nfoo\n"), vec![identifier("foo"), Token::PLUS, Token::INT(1)]);
+        assert_eq!(preprocess_tokens("#define a b\n#define b a\na b\n"), vec![identifier("a"), identifier("b")]);
+        assert_eq!(
+            preprocess_tokens("#define f(x) x + f(x)\nf(1)\n"),
+            vec![Token::INT(1), Token::PLUS, identifier("f"), Token::LPAREN, Token::INT(1), Token::RPAREN]
+        );
+    }
+
+    #[test]
+    fn test_stringify() {
+        let tokens = preprocess_tokens("#define STR(x) #x\nSTR(a  +\"b\\n\"  'c')\nSTR()\n");
+        assert_eq!(tokens, vec![
+            Token::STRING(b"a +\"b\\n\" 'c'".to_vec()),
+            Token::STRING(b"".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn test_arguments_are_not_expanded_for_stringify() {
+        let source = "#define ONE 1\n#define STR(x) #x\n#define XSTR(x) STR(x)\nSTR(ONE) XSTR(ONE)\n";
+        assert_eq!(preprocess_tokens(source), vec![Token::STRING(b"ONE".to_vec()), Token::STRING(b"1".to_vec())]);
+    }
+
+    #[test]
+    fn test_paste() {
+        let source = "#define CAT(a, b) a ## b\nCAT(x, 12) CAT(1, 2) CAT(, y) CAT(z, ) CAT(#, #)\n";
+        assert_eq!(preprocess_tokens(source), vec![
+            identifier("x12"),
+            Token::INT(12),
+            identifier("y"),
+            identifier("z"),
+            Token::HASHHASH,
+        ]);
+    }
+
+    #[test]
+    fn test_pasted_result_is_rescanned() {
+        let source = "#define FOO_BAR 42\n#define CAT(a, b) a ## _ ## b\nCAT(FOO, BAR)\n";
+        assert_eq!(preprocess_tokens(source), vec![Token::INT(42)]);
+    }
+
+    #[test]
+    fn test_invalid_paste() {
+        assert_eq!(
+            preprocess_error("#define CAT(a, b) a ## b\nCAT(+, 1)\n"),
+            TokenErrorKind::InvalidPaste("+1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        assert_eq!(preprocess_error("#define\n"), TokenErrorKind::MissingMacroName);
+        assert_eq!(preprocess_error("#define 1 2\n"), TokenErrorKind::MissingMacroName);
+        assert_eq!(
+            preprocess_error("#define F(a, a) a\n"),
+            TokenErrorKind::InvalidMacroParameters("F".to_string())
+        );
+        assert_eq!(
+            preprocess_error("#define F(a b) a\n"),
+            TokenErrorKind::InvalidMacroParameters("F".to_string())
+        );
+        assert_eq!(
+            preprocess_error("#define F(..., a) a\n"),
+            TokenErrorKind::InvalidMacroParameters("F".to_string())
+        );
+        assert_eq!(
+            preprocess_error("#define F(__VA_ARGS__) 1\n"),
+            TokenErrorKind::InvalidMacroParameters("F".to_string())
+        );
+        assert_eq!(preprocess_error("#define F(a) __VA_ARGS__\n"), TokenErrorKind::MisplacedVariadicArguments);
+        assert_eq!(preprocess_error("#define F __VA_ARGS__\n"), TokenErrorKind::MisplacedVariadicArguments);
+        assert_eq!(preprocess_error("#define F(a) #b\n"), TokenErrorKind::StringifyWithoutParameter);
+        assert_eq!(preprocess_error("#define F ## 1\n"), TokenErrorKind::PasteAtMacroEdge);
+        assert_eq!(preprocess_error("#define F(a) a ##\n"), TokenErrorKind::PasteAtMacroEdge);
+        assert_eq!(
+            preprocess_error("#frobnicate\n"),
+            TokenErrorKind::InvalidDirective("frobnicate".to_string())
+        );
+    }
+
+    #[test]
+    fn test_variadic_macros() {
+        let source = "#define F(x, ...) x + __VA_ARGS__\n#define G(...) f(__VA_ARGS__) #__VA_ARGS__\nF(1, 2)\nG(a, (b, c), d)\n";
+        assert_eq!(
+            preprocess_tokens(source),
+            vec![
+                Token::INT(1),
+                Token::PLUS,
+                Token::INT(2),
+                identifier("f"),
+                Token::LPAREN,
+                identifier("a"),
+                Token::COMMA,
+                Token::LPAREN,
+                identifier("b"),
+                Token::COMMA,
+                identifier("c"),
+                Token::RPAREN,
+                Token::COMMA,
+                identifier("d"),
+                Token::RPAREN,
+                Token::STRING(b"a, (b, c), d".to_vec()),
+            ]
+        );
+        // The `...` can get nothing at all
+        assert_eq!(
+            preprocess_tokens("#define F(x, ...) g(x __VA_ARGS__)\nF(1)\nF()\n"),
+            vec![identifier("g"), Token::LPAREN, Token::INT(1), Token::RPAREN, identifier("g"), Token::LPAREN, Token::RPAREN]
+        );
+        assert_eq!(
+            preprocess_error("#define F(x, y, ...) x\nF(1)\n"),
+            TokenErrorKind::MacroArgumentCount { name: "F".to_string(), expected: 3, found: 1 }
+        );
+    }
+
+    #[test]
+    fn test_invalid_calls() {
+        assert_eq!(
+            preprocess_error("#define F(a, b) a\nF(1)\n"),
+            TokenErrorKind::MacroArgumentCount { name: "F".to_string(), expected: 2, found: 1 }
+        );
+        assert_eq!(
+            preprocess_error("#define F() 1\nF(2)\n"),
+            TokenErrorKind::MacroArgumentCount { name: "F".to_string(), expected: 0, found: 1 }
+        );
+        assert_eq!(
+            preprocess_error("#define F(a) a\nF(1\n"),
+            TokenErrorKind::UnterminatedMacroCall("F".to_string())
+        );
+        // The end of the file still gets through
+        assert_eq!(preprocess("#define F(a) a\nF(1\n").last().unwrap().as_ref().unwrap().token, Token::EndOfFile);
+    }
+
+    #[test]
+    fn test_tokens_remember_their_expansion() {
+        let tokens: Vec<SpannedToken> = preprocess("#define ONE 1\n#define INC(x) x + ONE\n\n  INC(2)\n")
+            .into_iter()
+            .map(|token| token.unwrap())
+            .collect();
+
+        // The blank line is still a line break
+        assert_eq!(tokens[0].token, Token::EndOfLine);
+        let tokens = &tokens[1..];
+
+        // `2` was written on line 4, `+` in the definition of INC
+        assert_eq!((tokens[0].token.clone(), tokens[0].line, tokens[0].column), (Token::INT(2), 4, 7));
+        assert_eq!((tokens[1].token.clone(), tokens[1].line), (Token::PLUS, 2));
+
+        let inc = tokens[1].expansion.as_ref().unwrap();
+        assert_eq!((inc.name.as_str(), inc.line, inc.column), ("INC", 4, 3));
+        assert!(inc.parent.is_none());
+
+        // ONE was used inside INC, which was used on line 4
+        let one = tokens[2].expansion.as_ref().unwrap();
+        assert_eq!(tokens[2].token, Token::INT(1));
+        assert_eq!((one.name.as_str(), one.line), ("ONE", 2));
+        assert_eq!(one.parent.as_ref().unwrap().name, "INC");
+
+        assert!(tokens[3].expansion.is_none());
+    }
+
+    #[test]
+    fn test_include() {
+        let directory = TempDirectory::new("include");
+        directory.write("local.h", "#define LOCAL 1\n");
+        directory.write("sub/nested.h", "#include \"sibling.h\"\n");
+        directory.write("sub/sibling.h", "SIBLING\n");
+        directory.write("flags/flag.h", "FLAG\n");
+        directory.write("system/sys.h", "SYSTEM\n");
+        let main = directory.write(
+            "main.c",
+            "#include \"local.h\"\n#include \"sub/nested.h\"\n#include <flag.h>\n#include <sys.h>\nLOCAL\n",
+        );
+
+        let mut preprocessor = Preprocessor::open(&main).unwrap();
+        preprocessor.add_include_path(directory.0.join("flags"));
+        preprocessor.add_system_include_path(directory.0.join("system"));
+        let tokens: Vec<SpannedToken> = preprocessor.map(|token| token.unwrap()).collect();
+
+        let names: Vec<Token> = tokens.iter().map(|token| token.token.clone()).collect();
+        assert_eq!(names, vec![
+            identifier("SIBLING"),
+            Token::EndOfLine,
+            identifier("FLAG"),
+            Token::EndOfLine,
+            identifier("SYSTEM"),
+            Token::EndOfLine,
+            Token::INT(1),
+            Token::EndOfLine,
+            Token::EndOfFile,
+        ]);
+
+        // Every token knows which file it came from
+        assert!(tokens[0].file.as_ref().unwrap().ends_with("sub/sibling.h"));
+        assert_eq!(tokens[0].line, 1);
+        assert!(tokens[6].file.as_ref().unwrap().ends_with("local.h"));
+        assert!(tokens[8].file.as_ref().unwrap().ends_with("main.c"));
+        assert_eq!(tokens[8].line, 6);
+    }
+
+    #[test]
+    fn test_builtin_headers() {
+        let tokens = preprocess_tokens("#include <stdarg.h>\n#include \"stdarg.h\"\nva_arg(ap, int)\n");
+        assert_eq!(tokens[..3], [identifier("typedef"), identifier("__builtin_va_list"), identifier("va_list")]);
+        // The second include adds nothing, the header has a guard
+        assert_eq!(tokens[4..], [
+            identifier("__builtin_va_arg"),
+            Token::LPAREN,
+            identifier("ap"),
+            Token::COMMA,
+            identifier("int"),
+            Token::RPAREN,
+        ]);
+    }
+
+    #[test]
+    fn test_include_from_macro() {
+        let directory = TempDirectory::new("include_macro");
+        directory.write("config.h", "7\n");
+        let main = directory.write("main.c", "#define CONFIG \"config.h\"\n#include CONFIG\n");
+
+        let tokens: Vec<Token> = Preprocessor::open(&main).unwrap().map(|token| token.unwrap().token).collect();
+        assert_eq!(tokens[0], Token::INT(7));
+    }
+
+    #[test]
+    fn test_include_errors() {
+        assert_eq!(
+            preprocess_error("#include \"surely_missing_header.h\"\n"),
+            TokenErrorKind::IncludeNotFound("surely_missing_header.h".to_string())
+        );
+        // Angle brackets do not look next to the including file
+        let directory = TempDirectory::new("include_errors");
+        directory.write("here.h", "1\n");
+        let main = directory.write("main.c", "#include <here.h>\n#include\n#include here.h\n#include \"self.h\"\n");
+        directory.write("self.h", "#include \"self.h\"\n");
+
+        let errors: Vec<TokenErrorKind> = Preprocessor::open(&main)
+            .unwrap()
+            .filter_map(|token| token.err())
+            .map(|error| error.kind)
+            .collect();
+        assert_eq!(errors, vec![
+            TokenErrorKind::IncludeNotFound("here.h".to_string()),
+            TokenErrorKind::InvalidInclude,
+            TokenErrorKind::InvalidInclude,
+            TokenErrorKind::IncludeTooDeep,
+        ]);
+    }
+
+    #[test]
+    fn test_error_positions_name_the_file() {
+        let directory = TempDirectory::new("error_file");
+        let main = directory.write("main.c", "1\n  #bogus\n");
+
+        let error = Preprocessor::open(&main).unwrap().find_map(|token| token.err()).unwrap();
+        assert!(error.to_string().ends_with("main.c, line 2, column 4: unknown preprocessing directive 'bogus'"));
+    }
+
+    #[test]
+    fn test_parse_preprocessed_source() {
+        let mut preprocessor = Preprocessor::new("#define SQUARE(x) ((x) * (x))\nSQUARE(1 + 2) - 1\n".as_bytes());
+        let ast = ASTNode::parse_stream(&mut preprocessor).unwrap();
+        assert_eq!(ast.test_evaluate().unwrap(), 8);
+    }
+
+    #[test]
+    fn test_errors_show_the_macro_expansions() {
+        let source = "#define ADD(a) a + missing\n#define TWICE(a) ADD(ADD(a))\nint main() {\n  return TWICE(1);\n}\n";
+        let mut unit = TranslationUnit::parse(&mut Preprocessor::new(source.as_bytes())).unwrap();
+        let error = check_translation_unit(&mut unit).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1, column 20: use of undeclared identifier 'missing'\n  \
+             in expansion of macro 'ADD' at line 2, column 18\n  \
+             in expansion of macro 'TWICE' at line 4, column 10"
+        );
+
+        let source = "#define END }\nint main() { return 1 END\n";
+        let error = TranslationUnit::parse(&mut Preprocessor::new(source.as_bytes())).unwrap_err();
+        assert_eq!(error.to_string(), "line 1, column 13: expected SEMICOLON\n  in expansion of macro 'END' at line 2, column 23");
+    }
+
+    /// Whether `#if condition` keeps its group, after the given definitions
+    fn if_condition(definitions: &str, condition: &str) -> bool {
+        let source = format!("{}#if {}\nyes\n#else\nno\n#endif\n", definitions, condition);
+        match preprocess_tokens(&source).as_slice() {
+            [Token::IDENTIFIER(answer)] => answer == "yes",
+            other => panic!("unexpected tokens {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ifdef_and_ifndef() {
+        let source = "#define A\n#ifdef A\n1\n#else\n2\n#endif\n#ifndef A\n3\n#else\n4\n#endif\n#ifdef B\n5\n#endif\n";
+        assert_eq!(preprocess_tokens(source), vec![Token::INT(1), Token::INT(4)]);
+    }
+
+    #[test]
+    fn test_if_expressions() {
+        assert!(if_condition("", "1 + 2 * 3 == 7"));
+        assert!(if_condition("", "(1 + 2) * 3 == 9 && 10 / 3 == 3 && 10 % 3 == 1"));
+        assert!(if_condition("", "1 << 4 == 16 && -16 >> 2 == -4 && (6 & 3) == 2 && (6 | 3) == 7 && (6 ^ 3) == 5"));
+        assert!(if_condition("", "!0 && ~0 == -1 && -1 < 0 && 2 >= 2 && 1 <= 0 == 0 && 3 > 2 && 1 != 2"));
+        assert!(if_condition("", "1 ? 2 : 0"));
+        assert!(!if_condition("", "0 ? 2 : 0"));
+        assert!(if_condition("", "'A' == 65 && 0x10 == 16"));
+        assert!(!if_condition("", "0 || 0"));
+    }
+
+    #[test]
+    fn test_if_uses_unsigned_arithmetic_when_needed() {
+        // -1 converted to uintmax_t is the largest value there is
+        assert!(if_condition("", "-1 > 0u"));
+        assert!(!if_condition("", "-1 > 0"));
+        assert!(if_condition("", "(0 ? 1u : -1) > 0"));
+        assert!(if_condition("", "0xffffffffffffffff == -1"));
+    }
+
+    #[test]
+    fn test_if_defined_and_macros() {
+        assert!(if_condition("#define A\n", "defined A && defined(A) && !defined B"));
+        assert!(if_condition("#define VERSION 3\n#define AT_LEAST(v) (VERSION >= (v))\n", "AT_LEAST(2)"));
+        // Unknown identifiers are 0, and `defined` does not expand its operand
+        assert!(if_condition("", "UNKNOWN == 0"));
+        assert!(if_condition("#define A B\n", "defined(A) && !defined(B)"));
+        assert!(if_condition("", "defined __LINE__ && defined(__FILE__)"));
+    }
+
+    #[test]
+    fn test_if_short_circuits() {
+        assert!(!if_condition("", "0 && 1 / 0"));
+        assert!(if_condition("", "1 || 1 / 0"));
+        assert!(if_condition("", "1 ? 1 : 1 / 0"));
+        assert_eq!(preprocess_error("#if 1 / 0\n#endif\n"), TokenErrorKind::DivisionByZero);
+        assert_eq!(preprocess_error("#if 1 % (2 - 2)\n#endif\n"), TokenErrorKind::DivisionByZero);
+    }
+
+    #[test]
+    fn test_elif_chain() {
+        let source = "#define N 2\n#if N == 1\none\n#elif N == 2\ntwo\n#elif N == 2\nagain\n#else\nother\n#endif\n";
+        assert_eq!(preprocess_tokens(source), vec![identifier("two")]);
+        let source = "#if 0\none\n#elif 0\ntwo\n#else\nother\n#endif\n";
+        assert_eq!(preprocess_tokens(source), vec![identifier("other")]);
+    }
+
+    #[test]
+    fn test_nested_conditionals() {
+        let source = "#if 1\n#if 0\na\n#else\nb\n#endif\n#else\n#if 1\nc\n#else\nd\n#endif\n#endif\n";
+        assert_eq!(preprocess_tokens(source), vec![identifier("b")]);
+    }
+
+    #[test]
+    fn test_skipped_groups_are_not_looked_at() {
+        // Errors, unknown directives, and conditions that would fail are all skipped
+        let source = "#if 0\ndon't @\n#bogus\n#error stop\n#if 1 / 0\n#elif )\n#endif\n#define A 1\n#endif\nA\n";
+        assert_eq!(preprocess_tokens(source), vec![identifier("A")]);
+        // The first group was kept, so the condition of `#elif` is never evaluated
+        assert_eq!(preprocess_tokens("#if 1\na\n#elif 1 / 0\nb\n#endif\n"), vec![identifier("a")]);
+    }
+
+    #[test]
+    fn test_conditional_errors() {
+        assert_eq!(preprocess_error("#endif\n"), TokenErrorKind::UnmatchedConditional("endif".to_string()));
+        assert_eq!(preprocess_error("#else\n"), TokenErrorKind::UnmatchedConditional("else".to_string()));
+        assert_eq!(preprocess_error("#elif 1\n"), TokenErrorKind::UnmatchedConditional("elif".to_string()));
+        assert_eq!(
+            preprocess_error("#if 1\n#else\n#else\n#endif\n"),
+            TokenErrorKind::ConditionalAfterElse("else".to_string())
+        );
+        assert_eq!(
+            preprocess_error("#if 1\n#else\n#elif 1\n#endif\n"),
+            TokenErrorKind::ConditionalAfterElse("elif".to_string())
+        );
+        assert_eq!(preprocess_error("#if 1 +\n#endif\n"), TokenErrorKind::InvalidIfExpression);
+        assert_eq!(preprocess_error("#if (1\n#endif\n"), TokenErrorKind::InvalidIfExpression);
+        assert_eq!(preprocess_error("#if 1 2\n#endif\n"), TokenErrorKind::InvalidIfExpression);
+        assert_eq!(preprocess_error("#if \"s\"\n#endif\n"), TokenErrorKind::InvalidIfExpression);
+        assert_eq!(preprocess_error("#if defined(A\n#endif\n"), TokenErrorKind::InvalidIfExpression);
+        assert_eq!(preprocess_error("#ifdef\n#endif\n"), TokenErrorKind::MissingMacroName);
+
+        let tokens = preprocess("1\n  #ifdef A\n2\n");
+        let error = tokens.iter().find_map(|token| token.clone().err()).unwrap();
+        assert_eq!((error.kind, error.line, error.column), (TokenErrorKind::UnterminatedConditional, 2, 4));
+        assert_eq!(tokens.last().unwrap().as_ref().unwrap().token, Token::EndOfFile);
+    }
+
+    #[test]
+    fn test_conditionals_end_with_their_file() {
+        let directory = TempDirectory::new("conditional_file");
+        directory.write("open.h", "#if 1\n");
+        let main = directory.write("main.c", "#include \"open.h\"\n#endif\n");
+
+        let errors: Vec<TokenErrorKind> = Preprocessor::open(&main)
+            .unwrap()
+            .filter_map(|token| token.err())
+            .map(|error| error.kind)
+            .collect();
+        assert_eq!(errors, vec![
+            TokenErrorKind::UnterminatedConditional,
+            TokenErrorKind::UnmatchedConditional("endif".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_command_line_macros() {
+        let mut preprocessor = Preprocessor::new("#ifdef GONE\ngone\n#endif\nFLAG VALUE MAX(1, 2)\n".as_bytes());
+        preprocessor.define_macro("FLAG").unwrap();
+        preprocessor.define_macro("VALUE=0x10").unwrap();
+        preprocessor.define_macro("MAX(a,b)=((a) > (b) ? (a) : (b))").unwrap();
+        preprocessor.define_macro("GONE").unwrap();
+        preprocessor.undefine_macro("GONE");
+
+        let tokens: Vec<Token> = preprocessor.map(|token| token.unwrap().token).collect();
+        assert_eq!(tokens[..3], [Token::INT(1), Token::INT(16), Token::LPAREN]);
+
+        let mut preprocessor = Preprocessor::new("".as_bytes());
+        assert_eq!(preprocessor.define_macro("1=2").unwrap_err().kind, TokenErrorKind::MissingMacroName);
+    }
+
+    #[test]
+    fn test_predefined_macros() {
+        assert!(if_condition("", "__STDC__ == 1 && __STDC_VERSION__ >= 201112L && __STDC_HOSTED__"));
+        assert!(if_condition("", "!defined(__aarch64__) && !defined(__APPLE__)"));
+
+        let mut preprocessor = Preprocessor::new("#if defined(__aarch64__) && __APPLE__ && __LP64__\narm\n#endif\n".as_bytes());
+        preprocessor.define_target_macros(&SupportedArchitectures::ARM64);
+        assert_eq!(preprocessor.next().unwrap().unwrap().token, identifier("arm"));
+
+        let source = "#if __aarch64__ && __linux__ && __ELF__ && !defined(__APPLE__)\nlinux\n#endif\n";
+        let mut preprocessor = Preprocessor::new(source.as_bytes());
+        preprocessor.define_target_macros(&SupportedArchitectures::ARM64Linux);
+        assert_eq!(preprocessor.next().unwrap().unwrap().token, identifier("linux"));
+    }
+
+    #[test]
+    fn test_line_and_file_macros() {
+        let tokens = preprocess_tokens("__LINE__\n#define HERE __LINE__\n\nHERE __FILE__\n#if __LINE__ == 5\nfive\n#endif\n");
+        assert_eq!(tokens, vec![
+            Token::INT(1),
+            Token::INT(4),
+            Token::STRING(b"<stdin>".to_vec()),
+            identifier("five"),
+        ]);
+
+        // A macro call spread over lines counts as the line it starts on
+        assert_eq!(preprocess_tokens("#define ID(x) x\nID(\n__LINE__)\n"), vec![Token::INT(3)]);
+        assert_eq!(preprocess_tokens("#define LINE() __LINE__\n\nLINE(\n)\n"), vec![Token::INT(3)]);
+
+        let directory = TempDirectory::new("file_macro");
+        directory.write("name.h", "__FILE__\n");
+        let main = directory.write("main.c", "#include \"name.h\"\n");
+        let tokens: Vec<Token> = Preprocessor::open(&main).unwrap().map(|token| token.unwrap().token).collect();
+        let Token::STRING(name) = &tokens[0] else {
+            panic!("__FILE__ is not a string: {:?}", tokens[0]);
+        };
+        assert!(name.ends_with(b"name.h"));
+    }
+
+    #[test]
+    fn test_error_and_warning_directives() {
+        assert_eq!(
+            preprocess_error("#error don't build this\n"),
+            TokenErrorKind::ErrorDirective("don't build this".to_string())
+        );
+
+        let mut preprocessor = Preprocessor::new("#warning careful now\n1\n".as_bytes());
+        let tokens: Vec<Token> = preprocessor.by_ref().map(|token| token.unwrap().token).collect();
+        assert_eq!(tokens, vec![Token::INT(1), Token::EndOfLine, Token::EndOfFile]);
+        assert_eq!(preprocessor.warnings().len(), 1);
+        assert_eq!(preprocessor.warnings()[0].to_string(), "line 1, column 2: #warning careful now");
+    }
+
+    #[test]
+    fn test_pragma_once() {
+        let directory = TempDirectory::new("pragma_once");
+        directory.write("once.h", "#pragma once\nONCE\n");
+        directory.write("twice.h", "TWICE\n");
+        directory.write("sub/again.h", "#include \"../once.h\"\n");
+        let main = directory.write(
+            "main.c",
+            "#pragma unknown things\n#include \"once.h\"\n#include \"twice.h\"\n#include \"sub/again.h\"\n#include \"twice.h\"\n",
+        );
+
+        let tokens: Vec<Token> = Preprocessor::open(&main)
+            .unwrap()
+            .map(|token| token.unwrap().token)
+            .filter(|token| matches!(token, Token::IDENTIFIER(_)))
+            .collect();
+        assert_eq!(tokens, vec![identifier("ONCE"), identifier("TWICE"), identifier("TWICE")]);
+    }
+}
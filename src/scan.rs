@@ -1,5 +1,10 @@
+use crate::preprocess::MacroExpansion;
+use std::collections::VecDeque;
+use std::fmt;
 use std::io;
 use std::io::BufRead;
+use std::path::Path;
+use std::rc::Rc;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Token {
@@ -7,16 +12,754 @@ pub enum Token {
     MINUS,
     ASTERISK,
     SLASH,
+    PERCENT,
+    ASSIGN,
+    /// `+=`, in an [`crate::ast::ASTNode`] the object on the left and the value added to it on the right.
+    /// Every compound assignment is kept like this, see [`Token::compound_operator`].
+    ASPLUS,
+    /// `-=`
+    ASMINUS,
+    /// `*=`
+    ASSTAR,
+    /// `/=`
+    ASSLASH,
+    /// `%=`
+    ASMOD,
+    /// `&=`
+    ASAMPER,
+    /// `|=`
+    ASOR,
+    /// `^=`
+    ASXOR,
+    /// `<<=`
+    ASLSHIFT,
+    /// `>>=`
+    ASRSHIFT,
+    /// `++`, prefix `++x` is parsed as `x += 1`
+    INC,
+    /// `--`, prefix `--x` is parsed as `x -= 1`
+    DEC,
+    /// `==`
+    EQ,
+    /// `!=`
+    NE,
+    LT,
+    GT,
+    /// `<=`
+    LE,
+    /// `>=`
+    GE,
+    /// `&&`
+    LOGAND,
+    /// `||`
+    LOGOR,
+    /// `!`, in an [`crate::ast::ASTNode`] with the operand on the left
+    LOGNOT,
+    /// `&`
+    AMPER,
+    /// `|`
+    OR,
+    /// `^`
+    XOR,
+    /// `~`, in an [`crate::ast::ASTNode`] with the operand on the left
+    INVERT,
+    LSHIFT,
+    RSHIFT,
+    /// `?`, in an [`crate::ast::ASTNode`] the condition on the left and a [`Token::COLON`]
+    /// on the right, with the result when it holds on its left and the other result on its right
+    QUESTION,
+    COLON,
     INT(i32),
+    UINT(u32),
+    LONG(i64),
+    ULONG(u64),
+    LONGLONG(i64),
+    ULONGLONG(u64),
+    /// A `float` literal, held as the bits of its value so tokens can still be compared with `Eq`
+    FLOAT(u32),
+    /// A `double` or `long double` literal, held as the bits of its value
+    DOUBLE(u64),
+    CHAR(u8),
+    STRING(Vec<u8>),
+    IDENTIFIER(String),
+    LPAREN,
+    RPAREN,
+    COMMA,
+    SEMICOLON,
+    LBRACE,
+    RBRACE,
+    LBRACKET,
+    RBRACKET,
+    /// `.`
+    DOT,
+    /// `->`
+    ARROW,
+    /// `...`, which ends the parameters of a variadic function
+    ELLIPSIS,
+    HASH,
+    HASHHASH,
+    /// Never scanned, an [`crate::ast::ASTNode`] converting its operand to the type of the node
+    Cast,
+    /// Never scanned, unary `&` in an [`crate::ast::ASTNode`]
+    AddressOf,
+    /// Never scanned, unary `*` in an [`crate::ast::ASTNode`]
+    Dereference,
+    /// Never scanned, unary `-` in an [`crate::ast::ASTNode`]
+    Negate,
+    /// Never scanned, unary `+` in an [`crate::ast::ASTNode`], which the type checker turns
+    /// into a conversion to the promoted type of the operand
+    UnaryPlus,
+    /// Never scanned, `sizeof` applied to an expression in an [`crate::ast::ASTNode`]
+    Sizeof,
+    /// Never scanned, a function call in an [`crate::ast::ASTNode`]: the function on the left,
+    /// the first [`Token::Argument`] on the right
+    Call,
+    /// Never scanned, one argument of a [`Token::Call`]: the value on the left, the next argument on the right
+    Argument,
+    /// Never scanned, a `{ ... }` initialiser in an [`crate::ast::ASTNode`]: the first element on the
+    /// right, chained through [`Token::Argument`] nodes like the arguments of a [`Token::Call`]
+    InitializerList,
+    /// Never scanned, postfix `++` in an [`crate::ast::ASTNode`]: the object on the left and the
+    /// amount added on the right, like [`Token::ASPLUS`], but the value is the one from before
+    PostIncrement,
+    /// Never scanned, postfix `--` in an [`crate::ast::ASTNode`], see [`Token::PostIncrement`]
+    PostDecrement,
+    /// Never scanned, `__builtin_va_start` in an [`crate::ast::ASTNode`]: the `va_list` on the
+    /// left and the last parameter on the right. The type checker lowers it to an assignment of
+    /// [`Token::VariadicArguments`].
+    VaStart,
+    /// Never scanned, `__builtin_va_arg` in an [`crate::ast::ASTNode`]: the `va_list` on the left,
+    /// the type of the argument is the type of the node until the type checker lowers it to
+    /// pointer arithmetic
+    VaArg,
+    /// Never scanned, `__builtin_va_end` in an [`crate::ast::ASTNode`] with the `va_list` on the left
+    VaEnd,
+    /// Never scanned, `__builtin_va_copy` in an [`crate::ast::ASTNode`]: the destination on the
+    /// left and the source on the right
+    VaCopy,
+    /// Never scanned, the address of the first variadic argument of the function being compiled
+    VariadicArguments,
     EndOfFile,
     EndOfLine
 }
 
+impl Token {
+    /// The keyword this token spells, if it is one
+    pub fn keyword(&self) -> Option<Keyword> {
+        match self {
+            Token::IDENTIFIER(name) => Keyword::from_name(name),
+            _ => None,
+        }
+    }
+
+    /// The binary operator an assignment applies before storing, `None` for anything that is not
+    /// a compound assignment or a postfix `++` or `--`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::scan::Token;
+    /// assert_eq!(Token::ASLSHIFT.compound_operator(), Some(Token::LSHIFT));
+    /// assert_eq!(Token::PostDecrement.compound_operator(), Some(Token::MINUS));
+    /// assert_eq!(Token::ASSIGN.compound_operator(), None);
+    /// ```
+    pub fn compound_operator(&self) -> Option<Token> {
+        match self {
+            Token::ASPLUS | Token::PostIncrement => Some(Token::PLUS),
+            Token::ASMINUS | Token::PostDecrement => Some(Token::MINUS),
+            Token::ASSTAR => Some(Token::ASTERISK),
+            Token::ASSLASH => Some(Token::SLASH),
+            Token::ASMOD => Some(Token::PERCENT),
+            Token::ASAMPER => Some(Token::AMPER),
+            Token::ASOR => Some(Token::OR),
+            Token::ASXOR => Some(Token::XOR),
+            Token::ASLSHIFT => Some(Token::LSHIFT),
+            Token::ASRSHIFT => Some(Token::RSHIFT),
+            _ => None,
+        }
+    }
+
+    /// The value of an integer literal token as a 64-bit pattern, `None` for any other token.
+    /// Unsigned values above `i64::MAX` wrap around, which keeps their bits intact.
+    pub fn integer_value(&self) -> Option<i64> {
+        match *self {
+            Token::INT(n) => Some(n as i64),
+            Token::UINT(n) => Some(n as i64),
+            Token::LONG(n) | Token::LONGLONG(n) => Some(n),
+            Token::ULONG(n) | Token::ULONGLONG(n) => Some(n as i64),
+            // Character constants have type int, plain char is signed on Darwin ARM64
+            Token::CHAR(c) => Some(c as i8 as i64),
+            _ => None,
+        }
+    }
+}
+
+/// Identifiers the parser gives a meaning of their own.
+/// They are scanned as [`Token::IDENTIFIER`], so the preprocessor can still use them as macro names.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Keyword {
+    Void,
+    Char,
+    Short,
+    Int,
+    Long,
+    Signed,
+    Unsigned,
+    Return,
+    Sizeof,
+    Struct,
+    Union,
+    Enum,
+    Typedef,
+    Static,
+    Extern,
+    Float,
+    Double,
+    Switch,
+    Case,
+    Default,
+    Break,
+    Goto,
+    /// `__builtin_va_list`, the type `va_list` in `<stdarg.h>` stands for
+    VaList,
+    VaStart,
+    VaArg,
+    VaEnd,
+    VaCopy,
+}
+
+impl Keyword {
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::scan::*;
+    /// assert_eq!(Keyword::from_name("unsigned"), Some(Keyword::Unsigned));
+    /// assert_eq!(Keyword::from_name("main"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "void" => Some(Keyword::Void),
+            "char" => Some(Keyword::Char),
+            "short" => Some(Keyword::Short),
+            "int" => Some(Keyword::Int),
+            "long" => Some(Keyword::Long),
+            "signed" => Some(Keyword::Signed),
+            "unsigned" => Some(Keyword::Unsigned),
+            "return" => Some(Keyword::Return),
+            "sizeof" => Some(Keyword::Sizeof),
+            "struct" => Some(Keyword::Struct),
+            "union" => Some(Keyword::Union),
+            "enum" => Some(Keyword::Enum),
+            "typedef" => Some(Keyword::Typedef),
+            "static" => Some(Keyword::Static),
+            "extern" => Some(Keyword::Extern),
+            "float" => Some(Keyword::Float),
+            "double" => Some(Keyword::Double),
+            "switch" => Some(Keyword::Switch),
+            "case" => Some(Keyword::Case),
+            "default" => Some(Keyword::Default),
+            "break" => Some(Keyword::Break),
+            "goto" => Some(Keyword::Goto),
+            "__builtin_va_list" => Some(Keyword::VaList),
+            "__builtin_va_start" => Some(Keyword::VaStart),
+            "__builtin_va_arg" => Some(Keyword::VaArg),
+            "__builtin_va_end" => Some(Keyword::VaEnd),
+            "__builtin_va_copy" => Some(Keyword::VaCopy),
+            _ => None,
+        }
+    }
+
+    /// Whether the keyword names a type or part of one, like `unsigned` or `struct`
+    pub fn is_type_specifier(&self) -> bool {
+        !matches!(
+            self,
+            Keyword::Return
+                | Keyword::Sizeof
+                | Keyword::Typedef
+                | Keyword::Static
+                | Keyword::Extern
+                | Keyword::Switch
+                | Keyword::Case
+                | Keyword::Default
+                | Keyword::Break
+                | Keyword::Goto
+                | Keyword::VaStart
+                | Keyword::VaArg
+                | Keyword::VaEnd
+                | Keyword::VaCopy
+        )
+    }
+
+    /// Whether the keyword says how a declaration stores what it declares. `typedef` counts,
+    /// as in C, although it declares a name for a type instead.
+    pub fn is_storage_class(&self) -> bool {
+        matches!(self, Keyword::Typedef | Keyword::Static | Keyword::Extern)
+    }
+}
+
+/// Tabs advance the display column to the next multiple of this, plus one
+pub const TAB_WIDTH: usize = 8;
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct TokenError {
+    /// File the error is in, `None` for input that does not come from a file
+    pub(crate) file: Option<Rc<Path>>,
     pub(crate) line: usize,
+    /// 1-based display column, see [`display_columns`]
     pub(crate) column: usize,
     pub(crate) character: char,
+    pub(crate) kind: TokenErrorKind,
+}
+
+/// What went wrong while scanning, the position is stored in [`TokenError`]
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum TokenErrorKind {
+    InvalidCharacter,
+    UnterminatedBlockComment,
+    InvalidIntegerLiteral,
+    InvalidFloatLiteral,
+    IntegerTooLarge,
+    UnterminatedCharacterLiteral,
+    UnterminatedString,
+    EmptyCharacterLiteral,
+    MultiCharacterLiteral,
+    InvalidEscapeSequence,
+    /// Reading the input failed, the lexer stops after reporting it
+    Io(io::ErrorKind),
+    /// A `#` line whose name is not a directive we know
+    InvalidDirective(String),
+    /// `#include` without a `"file"` or `<file>`
+    InvalidInclude,
+    IncludeNotFound(String),
+    /// Includes nested too deep, most likely a file including itself
+    IncludeTooDeep,
+    MissingMacroName,
+    InvalidMacroParameters(String),
+    /// `#` in a function-like macro that is not followed by a parameter
+    StringifyWithoutParameter,
+    /// `##` at the start or end of a macro body
+    PasteAtMacroEdge,
+    /// `__VA_ARGS__` in a macro whose parameters do not end with `...`
+    MisplacedVariadicArguments,
+    MacroArgumentCount { name: String, expected: usize, found: usize },
+    UnterminatedMacroCall(String),
+    /// `##` produced something that is not a single token
+    InvalidPaste(String),
+    /// `#elif`, `#else` or `#endif` without an `#if`
+    UnmatchedConditional(String),
+    /// `#elif` or `#else` after the `#else` of the same conditional
+    ConditionalAfterElse(String),
+    /// An `#if` still open at the end of its file
+    UnterminatedConditional,
+    InvalidIfExpression,
+    DivisionByZero,
+    /// `#error`, with its message
+    ErrorDirective(String),
+    /// `#warning`, with its message, reported through [`crate::preprocess::Preprocessor::warnings`]
+    WarningDirective(String),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}, ", file.display())?;
+        }
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match &self.kind {
+            TokenErrorKind::InvalidCharacter => write!(f, "invalid character '{}'", self.character),
+            TokenErrorKind::UnterminatedBlockComment => write!(f, "unterminated block comment"),
+            TokenErrorKind::InvalidIntegerLiteral => write!(f, "invalid integer literal"),
+            TokenErrorKind::InvalidFloatLiteral => write!(f, "invalid floating constant"),
+            TokenErrorKind::IntegerTooLarge => write!(f, "integer literal is too large for any integer type"),
+            TokenErrorKind::UnterminatedCharacterLiteral => write!(f, "missing terminating ' character"),
+            TokenErrorKind::UnterminatedString => write!(f, "missing terminating \" character"),
+            TokenErrorKind::EmptyCharacterLiteral => write!(f, "empty character constant"),
+            TokenErrorKind::MultiCharacterLiteral => write!(f, "character constant holds more than one character"),
+            TokenErrorKind::InvalidEscapeSequence => write!(f, "invalid escape sequence '\\{}'", self.character),
+            TokenErrorKind::Io(kind) => write!(f, "failed to read input: {}", kind),
+            TokenErrorKind::InvalidDirective(name) => write!(f, "unknown preprocessing directive '{}'", name),
+            TokenErrorKind::InvalidInclude => write!(f, "#include expects \"FILENAME\" or <FILENAME>"),
+            TokenErrorKind::IncludeNotFound(name) => write!(f, "'{}' file not found", name),
+            TokenErrorKind::IncludeTooDeep => write!(f, "#include nested too deeply"),
+            TokenErrorKind::MissingMacroName => write!(f, "macro name must be an identifier"),
+            TokenErrorKind::InvalidMacroParameters(name) => write!(f, "invalid parameter list for macro '{}'", name),
+            TokenErrorKind::StringifyWithoutParameter => write!(f, "'#' is not followed by a macro parameter"),
+            TokenErrorKind::PasteAtMacroEdge => write!(f, "'##' cannot appear at either end of a macro expansion"),
+            TokenErrorKind::MisplacedVariadicArguments => {
+                write!(f, "__VA_ARGS__ can only appear in the expansion of a variadic macro")
+            }
+            TokenErrorKind::MacroArgumentCount { name, expected, found } => {
+                write!(f, "macro '{}' expects {} arguments, but {} given", name, expected, found)
+            }
+            TokenErrorKind::UnterminatedMacroCall(name) => write!(f, "unterminated call to macro '{}'", name),
+            TokenErrorKind::InvalidPaste(spelling) => write!(f, "pasting forms '{}', an invalid preprocessing token", spelling),
+            TokenErrorKind::UnmatchedConditional(name) => write!(f, "#{} without #if", name),
+            TokenErrorKind::ConditionalAfterElse(name) => write!(f, "#{} after #else", name),
+            TokenErrorKind::UnterminatedConditional => write!(f, "unterminated conditional directive"),
+            TokenErrorKind::InvalidIfExpression => write!(f, "invalid expression in #if"),
+            TokenErrorKind::DivisionByZero => write!(f, "division by zero in #if"),
+            TokenErrorKind::ErrorDirective(message) => write!(f, "#error {}", message),
+            TokenErrorKind::WarningDirective(message) => write!(f, "#warning {}", message),
+        }
+    }
+}
+
+/// State the scanner has to carry from one line to the next
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct ScanState {
+    /// Line and display column of the `/*` of the block comment we are currently inside
+    block_comment_start: Option<(usize, usize)>,
+}
+
+impl ScanState {
+    /// Called once the input is exhausted, returns an error if a block comment was never closed
+    pub fn finish(&self) -> Option<TokenError> {
+        self.block_comment_start.map(|(line, column)| TokenError {
+            file: None,
+            line,
+            column,
+            character: '/',
+            kind: TokenErrorKind::UnterminatedBlockComment,
+        })
+    }
+}
+
+
+/// A token together with where it starts in the input
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based display column, the way an editor counts it (see [`display_columns`])
+    pub column: usize,
+    /// Byte offset of the token from the start of the input
+    pub offset: usize,
+    /// File the token was read from, `None` for input that does not come from a file
+    pub file: Option<Rc<Path>>,
+    /// The macro expansion that produced this token, if any
+    pub expansion: Option<Rc<MacroExpansion>>,
+}
+
+impl SpannedToken {
+    pub fn new(token: Token, line: usize, column: usize, offset: usize) -> Self {
+        Self {
+            token,
+            line,
+            column,
+            offset,
+            file: None,
+            expansion: None,
+        }
+    }
+}
+
+
+/// Where a character of a [`LogicalLine`] is in the input
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) struct CharPosition {
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    pub(crate) offset: usize,
+}
+
+/// A line of source once every backslash-newline has been spliced out, with the position of each character
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct LogicalLine {
+    pub(crate) text: String,
+    positions: Vec<CharPosition>,
+    /// Position of the end of the line, right after its last character
+    end: CharPosition,
+}
+
+impl LogicalLine {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            positions: Vec::new(),
+            end: CharPosition { line: 1, column: 1, offset: 0 },
+        }
+    }
+
+    /// A line that fits on a single physical line
+    pub(crate) fn physical(text: &str, line: usize, line_offset: usize) -> Self {
+        let mut logical_line = Self::new();
+        logical_line.append(text, line, line_offset);
+        logical_line
+    }
+
+    /// Add a physical line, without its line break and splicing backslash, to the end of this line
+    fn append(&mut self, text: &str, line: usize, line_offset: usize) {
+        let columns = display_columns(text);
+        for ((offset, _), &column) in text.char_indices().zip(&columns) {
+            self.positions.push(CharPosition { line, column, offset: line_offset + offset });
+        }
+        self.end = CharPosition {
+            line,
+            column: columns[columns.len() - 1],
+            offset: line_offset + text.len(),
+        };
+        self.text.push_str(text);
+    }
+
+    /// Position of the character at `index`, or of the end of the line past the last character
+    fn position(&self, index: usize) -> CharPosition {
+        self.positions.get(index).copied().unwrap_or(self.end)
+    }
+}
+
+
+/// Whether a block comment is still open at the end of `text`, which starts inside one when
+/// `in_comment` is set. Comment markers inside string and character literals do not count.
+fn ends_in_block_comment(text: &str, mut in_comment: bool) -> bool {
+    let mut chars = text.chars().peekable();
+    let mut quote = None;
+    while let Some(ch) = chars.next() {
+        match (in_comment, quote, ch) {
+            (true, _, '*') if chars.next_if_eq(&'/').is_some() => in_comment = false,
+            (true, _, _) => {}
+            (false, Some(_), '\\') => {
+                chars.next();
+            }
+            (false, Some(open), _) if ch == open => quote = None,
+            (false, Some(_), _) => {}
+            (false, None, '"' | '\'') => quote = Some(ch),
+            (false, None, '/') if chars.next_if_eq(&'*').is_some() => in_comment = true,
+            // The rest of the line is a line comment
+            (false, None, '/') if chars.peek() == Some(&'/') => return false,
+            _ => {}
+        }
+    }
+    in_comment
+}
+
+
+/// A token as scanned from a [`LogicalLine`], with the details the preprocessor needs on top of the position
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct ScannedToken {
+    pub(crate) result: Result<SpannedToken, TokenError>,
+    /// Byte range of the token's spelling in the line text
+    pub(crate) spelling: std::ops::Range<usize>,
+    /// Whether whitespace or a comment comes right before the token
+    pub(crate) leading_space: bool,
+}
+
+
+/// Anything the parser can pull tokens from, with as much lookahead as it needs
+pub trait TokenSource {
+    /// Take the next token out of the source
+    fn next_token(&mut self) -> Option<Result<SpannedToken, TokenError>>;
+
+    /// Look `n` tokens ahead without consuming anything, `peek_nth(0)` is the next token
+    fn peek_nth(&mut self, n: usize) -> Option<&Result<SpannedToken, TokenError>>;
+
+    fn peek(&mut self) -> Option<&Result<SpannedToken, TokenError>> {
+        self.peek_nth(0)
+    }
+}
+
+/// Tokens that are already in memory, e.g. built by hand in tests
+impl TokenSource for VecDeque<Result<SpannedToken, TokenError>> {
+    fn next_token(&mut self) -> Option<Result<SpannedToken, TokenError>> {
+        self.pop_front()
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&Result<SpannedToken, TokenError>> {
+        self.get(n)
+    }
+}
+
+
+/// Streaming scanner, reads its input one line at a time as tokens are requested.
+/// Only the lines needed to satisfy the current lookahead are ever held in memory.
+///
+/// # Examples
+///
+/// ```
+/// # use compiler::scan::*;
+/// let mut lexer = Lexer::new("1 +\n 2".as_bytes());
+/// assert_eq!(lexer.peek_nth(3).unwrap().as_ref().unwrap().token, Token::INT(2));
+///
+/// let tokens: Vec<Token> = lexer.map(|token| token.unwrap().token).collect();
+/// assert_eq!(tokens, vec![
+///     Token::INT(1),
+///     Token::PLUS,
+///     Token::EndOfLine,
+///     Token::INT(2),
+///     Token::EndOfLine,
+///     Token::EndOfFile,
+/// ]);
+/// ```
+pub struct Lexer<R: BufRead> {
+    reader: R,
+    /// Set when the input is a file, copied to every token and error
+    file: Option<Rc<Path>>,
+    line_num: usize,
+    /// Bytes read so far, which is where the next line starts
+    offset: usize,
+    state: ScanState,
+    buffer: VecDeque<Result<SpannedToken, TokenError>>,
+    finished: bool,
+}
+
+impl<R: BufRead> Lexer<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            file: None,
+            line_num: 0,
+            offset: 0,
+            state: ScanState::default(),
+            buffer: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// A lexer for the contents of `file`, positions will name the file
+    pub fn with_file(reader: R, file: Rc<Path>) -> Self {
+        Self {
+            file: Some(file),
+            ..Self::new(reader)
+        }
+    }
+
+    pub fn file(&self) -> Option<&Rc<Path>> {
+        self.file.as_ref()
+    }
+
+    /// Read the next logical line, splicing lines that end in a backslash onto the next one.
+    /// A block comment is a single space (C11 5.1.1.2), so a line that ends inside one goes on
+    /// with the next line too. Returns `Ok(None)` at the end of the input.
+    fn read_logical_line(&mut self) -> io::Result<Option<LogicalLine>> {
+        let mut logical_line: Option<LogicalLine> = None;
+        let in_comment = self.state.block_comment_start.is_some();
+
+        loop {
+            let mut physical_line = String::new();
+            let length = self.reader.read_line(&mut physical_line)?;
+            if length == 0 {
+                return Ok(logical_line);
+            }
+
+            self.line_num += 1;
+            let line_offset = self.offset;
+            self.offset += length;
+
+            let text = physical_line.strip_suffix('\n').unwrap_or(&physical_line);
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            let (text, spliced) = match text.strip_suffix('\\') {
+                Some(text) => (text, true),
+                None => (text, false),
+            };
+
+            let line = logical_line.get_or_insert_with(LogicalLine::new);
+            line.append(text, self.line_num, line_offset);
+            if !spliced && !ends_in_block_comment(&line.text, in_comment) {
+                return Ok(logical_line);
+            }
+        }
+    }
+
+    /// Scan the next logical line, ending with its [`Token::EndOfLine`].
+    /// Returns `None` at the end of the input, a read failure is returned as a single error token
+    /// after which the lexer is finished.
+    pub(crate) fn next_line(&mut self) -> Option<(LogicalLine, Vec<ScannedToken>)> {
+        if self.finished {
+            return None;
+        }
+
+        match self.read_logical_line() {
+            Ok(Some(line)) => {
+                let tokens = scan_logical_line(&line, self.file.as_ref(), &mut self.state);
+                Some((line, tokens))
+            }
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(error) => {
+                self.finished = true;
+                let error = TokenError {
+                    file: self.file.clone(),
+                    line: self.line_num + 1,
+                    column: 1,
+                    character: '\0',
+                    kind: TokenErrorKind::Io(error.kind()),
+                };
+                let token = ScannedToken {
+                    result: Err(error),
+                    spelling: 0..0,
+                    leading_space: false,
+                };
+                Some((LogicalLine::new(), vec![token]))
+            }
+        }
+    }
+
+    /// The tokens that close the input: an error for an unterminated block comment,
+    /// then [`Token::EndOfFile`] when `end_of_file` is set
+    pub(crate) fn finish(&mut self, end_of_file: bool) -> Vec<Result<SpannedToken, TokenError>> {
+        let mut tokens = Vec::new();
+        if let Some(error) = self.state.finish() {
+            tokens.push(Err(TokenError {
+                file: self.file.clone(),
+                ..error
+            }));
+        }
+        self.state = ScanState::default();
+        if end_of_file {
+            tokens.push(Ok(SpannedToken {
+                file: self.file.clone(),
+                ..SpannedToken::new(Token::EndOfFile, self.line_num + 1, 1, self.offset)
+            }));
+        }
+        tokens
+    }
+
+    /// Scan the next line into the buffer, at the end of the input this adds [`Token::EndOfFile`].
+    /// Returns false once there is nothing left to read.
+    fn scan_next_line(&mut self) -> bool {
+        if self.finished {
+            return false;
+        }
+
+        match self.next_line() {
+            Some((_, tokens)) => self.buffer.extend(tokens.into_iter().map(|token| token.result)),
+            None => {
+                let tokens = self.finish(true);
+                self.buffer.extend(tokens);
+            }
+        }
+        true
+    }
+}
+
+impl<R: BufRead> Iterator for Lexer<R> {
+    type Item = Result<SpannedToken, TokenError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.is_empty() {
+            if !self.scan_next_line() {
+                return None;
+            }
+        }
+        self.buffer.pop_front()
+    }
+}
+
+impl<R: BufRead> TokenSource for Lexer<R> {
+    fn next_token(&mut self) -> Option<Result<SpannedToken, TokenError>> {
+        self.next()
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&Result<SpannedToken, TokenError>> {
+        while self.buffer.len() <= n {
+            if !self.scan_next_line() {
+                return None;
+            }
+        }
+        self.buffer.get(n)
+    }
 }
 
 
@@ -29,15 +772,14 @@ pub struct TokenError {
 /// returns: Result<Vec<Result<Token, TokenError>, Global>, Error>
 pub fn scan_file<R: BufRead>(reader: &mut R) -> io::Result<Vec<Result<Token, TokenError>>> {
     let mut tokens: Vec<Result<Token, TokenError>> = Vec::new();
-    let mut line_num: usize = 1;
 
-    for line in reader.lines() {
-        let line = line?;
-        let line_tokens = scan_line(&line, line_num);
-        tokens.extend(line_tokens);
-        line_num += 1;
+    for token in Lexer::new(reader) {
+        match token {
+            Ok(spanned) => tokens.push(Ok(spanned.token)),
+            Err(TokenError { kind: TokenErrorKind::Io(kind), .. }) => return Err(kind.into()),
+            Err(error) => tokens.push(Err(error)),
+        }
     }
-    tokens.push(Ok(Token::EndOfFile));
     Ok(tokens)
 }
 
@@ -60,22 +802,185 @@ pub fn scan_file<R: BufRead>(reader: &mut R) -> io::Result<Vec<Result<Token, Tok
 /// assert_eq!(tokens.len(), 6);
 /// ```
 pub fn scan_line(line: &str, line_num: usize) -> Vec<Result<Token, TokenError>> {
+    scan_line_with_state(line, line_num, &mut ScanState::default())
+}
+
+
+/// Scan a single line, continuing from the state left behind by the previous line.
+/// Needed for block comments, which can span several lines.
+///
+/// # Arguments
+///
+/// * `line`: The line that will be scanned
+/// * `line_num`: the line number of the given line, for debugging purposes
+/// * `state`: the scanner state, updated in place
+///
+/// returns: Vec<Result<Token, TokenError>, Global>
+///
+/// # Examples
+///
+/// ```
+/// # use compiler::scan::*;
+/// let mut state = ScanState::default();
+/// let first = scan_line_with_state("1 + /* a comment", 1, &mut state);
+/// let second = scan_line_with_state("that ends here */ 2", 2, &mut state);
+/// assert_eq!(first, vec![Ok(Token::INT(1)), Ok(Token::PLUS), Ok(Token::EndOfLine)]);
+/// assert_eq!(second, vec![Ok(Token::INT(2)), Ok(Token::EndOfLine)]);
+/// assert_eq!(state.finish(), None);
+/// ```
+pub fn scan_line_with_state(line: &str, line_num: usize, state: &mut ScanState) -> Vec<Result<Token, TokenError>> {
+    scan_line_spanned(line, line_num, 0, state)
+        .into_iter()
+        .map(|token| token.map(|spanned| spanned.token))
+        .collect()
+}
+
+
+/// Same as [`scan_line_with_state`] but keeps the position of every token.
+///
+/// # Arguments
+///
+/// * `line`: The line that will be scanned, without its line break
+/// * `line_num`: the line number of the given line
+/// * `line_offset`: byte offset of the start of the line in the input, added to every token offset
+/// * `state`: the scanner state, updated in place
+///
+/// returns: Vec<Result<SpannedToken, TokenError>, Global>
+///
+/// # Examples
+///
+/// ```
+/// # use compiler::scan::*;
+/// let tokens = scan_line_spanned("\t1 + é", 4, 100, &mut ScanState::default());
+/// assert_eq!(tokens[0], Ok(SpannedToken::new(Token::INT(1), 4, 9, 101)));
+/// assert_eq!(tokens[1], Ok(SpannedToken::new(Token::PLUS, 4, 11, 103)));
+/// assert_eq!(tokens[2].as_ref().unwrap_err().to_string(), "line 4, column 13: invalid character 'é'");
+/// assert_eq!(tokens[3], Ok(SpannedToken::new(Token::EndOfLine, 4, 14, 107)));
+/// ```
+pub fn scan_line_spanned(
+    line: &str,
+    line_num: usize,
+    line_offset: usize,
+    state: &mut ScanState,
+) -> Vec<Result<SpannedToken, TokenError>> {
+    let line = LogicalLine::physical(line, line_num, line_offset);
+    scan_logical_line(&line, None, state)
+        .into_iter()
+        .map(|token| token.result)
+        .collect()
+}
+
+
+/// Scan a logical line, the last token is always [`Token::EndOfLine`]
+///
+/// # Arguments
+///
+/// * `line`: the line to scan, which knows the position of each of its characters
+/// * `file`: the file the line comes from, copied into every token and error
+/// * `state`: the scanner state, updated in place
+///
+/// returns: Vec<ScannedToken, Global>
+pub(crate) fn scan_logical_line(line: &LogicalLine, file: Option<&Rc<Path>>, state: &mut ScanState) -> Vec<ScannedToken> {
     let mut tokens = Vec::new();
-    let mut chars = line.chars().enumerate().peekable();
+    // The scanning functions work with character indexes, positions are resolved once a token is done
+    let offsets: Vec<usize> = line.text.char_indices().map(|(offset, _)| offset).chain([line.text.len()]).collect();
+    let mut chars = line.text.chars().enumerate().peekable();
+    let mut leading_space = false;
+
+    while let Some((index, ch)) = chars.next() {
+        if state.block_comment_start.is_some() {
+            if ch == '*' && matches!(chars.peek(), Some(&(_, '/'))) {
+                chars.next();
+                state.block_comment_start = None;
+            }
+            leading_space = true;
+            continue;
+        }
 
-    while let Some((column, ch)) = chars.next() {
         if ch.is_whitespace() {
+            leading_space = true;
             continue;
         }
 
-        let token_result = scan_token(ch, &mut chars, line_num, column);
-        tokens.push(token_result);
+        if ch == '/' {
+            match chars.peek() {
+                // Line comment, nothing else on this line matters
+                Some(&(_, '/')) => break,
+                Some(&(_, '*')) => {
+                    chars.next();
+                    let position = line.position(index);
+                    state.block_comment_start = Some((position.line, position.column));
+                    leading_space = true;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        let position = line.position(index);
+        let result = match scan_token(ch, &mut chars, position.line, index) {
+            Ok(token) => Ok(SpannedToken {
+                file: file.cloned(),
+                ..SpannedToken::new(token, position.line, position.column, position.offset)
+            }),
+            Err(error) => {
+                // Errors can point inside the token, e.g. at a bad escape sequence
+                let error_position = line.position(error.column);
+                Err(TokenError {
+                    file: file.cloned(),
+                    line: error_position.line,
+                    column: error_position.column,
+                    ..error
+                })
+            }
+        };
+        let end = chars.peek().map_or(offsets.len() - 1, |&(next, _)| next);
+        tokens.push(ScannedToken {
+            result,
+            spelling: offsets[index]..offsets[end],
+            leading_space,
+        });
+        leading_space = false;
     }
-    tokens.push(Ok(Token::EndOfLine));
+
+    let end = line.position(offsets.len() - 1);
+    tokens.push(ScannedToken {
+        result: Ok(SpannedToken {
+            file: file.cloned(),
+            ..SpannedToken::new(Token::EndOfLine, end.line, end.column, end.offset)
+        }),
+        spelling: line.text.len()..line.text.len(),
+        leading_space,
+    });
     tokens
 }
 
 
+/// The 1-based display column of every character in `line`, plus one for the end of the line.
+/// Each character takes a single column whatever its UTF-8 length, tabs move on to the next tab stop.
+///
+/// # Examples
+///
+/// ```
+/// # use compiler::scan::*;
+/// assert_eq!(display_columns("a\tb"), vec![1, 2, 9, 10]);
+/// assert_eq!(display_columns("ü=1"), vec![1, 2, 3, 4]);
+/// ```
+pub fn display_columns(line: &str) -> Vec<usize> {
+    let mut columns = Vec::with_capacity(line.len() + 1);
+    let mut column = 1;
+    for ch in line.chars() {
+        columns.push(column);
+        column = match ch {
+            '\t' => (column - 1) / TAB_WIDTH * TAB_WIDTH + TAB_WIDTH + 1,
+            _ => column + 1,
+        };
+    }
+    columns.push(column);
+    columns
+}
+
+
 /// Function to scan a single token
 ///
 /// # Arguments
@@ -83,7 +988,7 @@ pub fn scan_line(line: &str, line_num: usize) -> Vec<Result<Token, TokenError>>
 /// * `current_char`: the current character in the line
 /// * `chars`: a peekable iterator for the current line (allows to parse numbers)
 /// * `line`: line number, for debugging
-/// * `column`: column  number for debugging
+/// * `column`: index of `current_char` in the line, [`scan_line_spanned`] turns it into a display column
 ///
 /// returns: Result<Token, TokenError>
 ///
@@ -92,7 +997,7 @@ pub fn scan_line(line: &str, line_num: usize) -> Vec<Result<Token, TokenError>>
 /// ```
 /// # use compiler::scan::*;
 /// let mut peekable_chars = "-123".chars().enumerate().peekable();
-/// let current_char = '-';
+/// let (_, current_char) = peekable_chars.next().unwrap();
 /// let token = scan_token(current_char, &mut peekable_chars, 0, 0).unwrap();
 /// assert_eq!(token, Token::MINUS);
 /// ```
@@ -103,44 +1008,441 @@ pub fn scan_token(
     column: usize,
 ) -> Result<Token, TokenError> {
     match current_char {
+        '+' if next_is(chars, '+') => Ok(Token::INC),
+        '+' if next_is(chars, '=') => Ok(Token::ASPLUS),
         '+' => Ok(Token::PLUS),
+        '-' if next_is(chars, '>') => Ok(Token::ARROW),
+        '-' if next_is(chars, '-') => Ok(Token::DEC),
+        '-' if next_is(chars, '=') => Ok(Token::ASMINUS),
         '-' => Ok(Token::MINUS),
+        '*' if next_is(chars, '=') => Ok(Token::ASSTAR),
         '*' => Ok(Token::ASTERISK),
+        '/' if next_is(chars, '=') => Ok(Token::ASSLASH),
         '/' => Ok(Token::SLASH),
-        '0'..='9' => {
-            let mut number = current_char.to_string();
-
-            // Using peek() to look ahead without consuming
+        '%' if next_is(chars, '=') => Ok(Token::ASMOD),
+        '%' => Ok(Token::PERCENT),
+        '=' if next_is(chars, '=') => Ok(Token::EQ),
+        '=' => Ok(Token::ASSIGN),
+        '!' if next_is(chars, '=') => Ok(Token::NE),
+        '!' => Ok(Token::LOGNOT),
+        '<' if next_is(chars, '<') => Ok(if next_is(chars, '=') { Token::ASLSHIFT } else { Token::LSHIFT }),
+        '<' if next_is(chars, '=') => Ok(Token::LE),
+        '<' => Ok(Token::LT),
+        '>' if next_is(chars, '>') => Ok(if next_is(chars, '=') { Token::ASRSHIFT } else { Token::RSHIFT }),
+        '>' if next_is(chars, '=') => Ok(Token::GE),
+        '>' => Ok(Token::GT),
+        '&' if next_is(chars, '&') => Ok(Token::LOGAND),
+        '&' if next_is(chars, '=') => Ok(Token::ASAMPER),
+        '&' => Ok(Token::AMPER),
+        '|' if next_is(chars, '|') => Ok(Token::LOGOR),
+        '|' if next_is(chars, '=') => Ok(Token::ASOR),
+        '|' => Ok(Token::OR),
+        '^' if next_is(chars, '=') => Ok(Token::ASXOR),
+        '^' => Ok(Token::XOR),
+        '~' => Ok(Token::INVERT),
+        '?' => Ok(Token::QUESTION),
+        ':' => Ok(Token::COLON),
+        '(' => Ok(Token::LPAREN),
+        ')' => Ok(Token::RPAREN),
+        ',' => Ok(Token::COMMA),
+        ';' => Ok(Token::SEMICOLON),
+        '{' => Ok(Token::LBRACE),
+        '}' => Ok(Token::RBRACE),
+        '[' => Ok(Token::LBRACKET),
+        '.' if chars.peek().is_some_and(|&(_, next_char)| next_char.is_ascii_digit()) => {
+            scan_number(current_char, chars, line, column)
+        }
+        '.' if is_ellipsis(chars) => Ok(Token::ELLIPSIS),
+        '.' => Ok(Token::DOT),
+        ']' => Ok(Token::RBRACKET),
+        '#' => match chars.peek() {
+            Some(&(_, '#')) => {
+                chars.next();
+                Ok(Token::HASHHASH)
+            }
+            _ => Ok(Token::HASH),
+        },
+        'a'..='z' | 'A'..='Z' | '_' => {
+            let mut identifier = current_char.to_string();
             while let Some(&(_, next_char)) = chars.peek() {
-                if !next_char.is_digit(10) {
+                if !(next_char.is_ascii_alphanumeric() || next_char == '_') {
                     break;
                 }
-                number.push(next_char);
-                chars.next(); // Now consume the character we peeked at
+                identifier.push(next_char);
+                chars.next();
             }
-
-            match number.parse::<i32>() {
-                Ok(num) => Ok(Token::INT(num)),
-                Err(_) => Err(TokenError {
-                    line,
-                    column,
-                    character: current_char,
-                }),
+            Ok(Token::IDENTIFIER(identifier))
+        }
+        '0'..='9' => scan_number(current_char, chars, line, column),
+        '\'' => {
+            let bytes = scan_quoted('\'', chars, line, column)?;
+            let error = |kind| TokenError {
+                file: None,
+                line,
+                column,
+                character: current_char,
+                kind,
+            };
+            match bytes.as_slice() {
+                [byte] => Ok(Token::CHAR(*byte)),
+                [] => Err(error(TokenErrorKind::EmptyCharacterLiteral)),
+                _ => Err(error(TokenErrorKind::MultiCharacterLiteral)),
             }
         }
+        '"' => Ok(Token::STRING(scan_quoted('"', chars, line, column)?)),
         _ => Err(TokenError {
+            file: None,
             line,
             column,
             character: current_char,
+            kind: TokenErrorKind::InvalidCharacter,
         }),
     }
 }
 
 
+/// Consume the next character if it is `expected`, for operators of two characters
+fn next_is(chars: &mut std::iter::Peekable<std::iter::Enumerate<std::str::Chars>>, expected: char) -> bool {
+    chars.next_if(|&(_, next_char)| next_char == expected).is_some()
+}
+
+
+/// Whether the next two chars are the rest of a `...`, which are consumed if so.
+/// Two dots on their own are two [`Token::DOT`]s.
+fn is_ellipsis(chars: &mut std::iter::Peekable<std::iter::Enumerate<std::str::Chars>>) -> bool {
+    let mut ahead = chars.clone();
+    let found = matches!((ahead.next(), ahead.next()), (Some((_, '.')), Some((_, '.'))));
+    if found {
+        chars.nth(1);
+    }
+    found
+}
+
+
+/// The C types an integer literal can have, on an LP64 target
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum IntegerLiteralType {
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+impl IntegerLiteralType {
+    fn max_value(&self) -> u64 {
+        match self {
+            IntegerLiteralType::Int => i32::MAX as u64,
+            IntegerLiteralType::UnsignedInt => u32::MAX as u64,
+            IntegerLiteralType::Long | IntegerLiteralType::LongLong => i64::MAX as u64,
+            IntegerLiteralType::UnsignedLong | IntegerLiteralType::UnsignedLongLong => u64::MAX,
+        }
+    }
+
+    fn to_token(self, value: u64) -> Token {
+        match self {
+            IntegerLiteralType::Int => Token::INT(value as i32),
+            IntegerLiteralType::UnsignedInt => Token::UINT(value as u32),
+            IntegerLiteralType::Long => Token::LONG(value as i64),
+            IntegerLiteralType::UnsignedLong => Token::ULONG(value),
+            IntegerLiteralType::LongLong => Token::LONGLONG(value as i64),
+            IntegerLiteralType::UnsignedLongLong => Token::ULONGLONG(value),
+        }
+    }
+}
+
+
+/// Scan a number: an integer literal, or a floating literal when it has a `.` or an exponent.
+///
+/// Integer literals are decimal, octal (leading `0`), hexadecimal (`0x`) or binary (`0b`),
+/// followed by an optional `u`/`l`/`ll` suffix.
+/// The token type is the first type of the list in C11 6.4.4.1 that can hold the value.
+/// Note that unsuffixed decimal literals never become unsigned.
+///
+/// Floating literals are decimal, with an optional `e` exponent, or hexadecimal with a
+/// required `p` exponent, followed by an optional `f` (`float`) or `l` (`long double`) suffix.
+///
+/// # Arguments
+///
+/// * `first_digit`: the first character of the literal, already consumed, a digit or `.`
+/// * `chars`: a peekable iterator for the current line, positioned after `first_digit`
+/// * `line`: line number, for debugging
+/// * `column`: column  number for debugging
+///
+/// returns: Result<Token, TokenError>
+fn scan_number(
+    first_digit: char,
+    chars: &mut std::iter::Peekable<std::iter::Enumerate<std::str::Chars>>,
+    line: usize,
+    column: usize,
+) -> Result<Token, TokenError> {
+    let error = |kind| TokenError {
+        file: None,
+        line,
+        column,
+        character: first_digit,
+        kind,
+    };
+
+    // Take the whole literal, suffix included, so `12abc` is one bad literal and not `12` `abc`.
+    // A sign belongs to the literal right after an exponent letter, as in `1e-5`.
+    let hexadecimal = first_digit == '0' && chars.peek().is_some_and(|&(_, next_char)| matches!(next_char, 'x' | 'X'));
+    let mut literal = first_digit.to_string();
+    while let Some(&(_, next_char)) = chars.peek() {
+        let exponent = match hexadecimal {
+            true => literal.ends_with(['p', 'P']),
+            false => literal.ends_with(['e', 'E']),
+        };
+        if !(next_char.is_ascii_alphanumeric() || next_char == '.' || (exponent && matches!(next_char, '+' | '-'))) {
+            break;
+        }
+        literal.push(next_char);
+        chars.next();
+    }
+
+    let floating = match hexadecimal {
+        true => literal.contains(['.', 'p', 'P']),
+        false => literal.contains(['.', 'e', 'E']),
+    };
+    if floating {
+        return float_literal(&literal, hexadecimal).ok_or(error(TokenErrorKind::InvalidFloatLiteral));
+    }
+
+    let (digits, radix) = if let Some(rest) = literal.strip_prefix("0x").or_else(|| literal.strip_prefix("0X")) {
+        (rest, 16)
+    } else if let Some(rest) = literal.strip_prefix("0b").or_else(|| literal.strip_prefix("0B")) {
+        (rest, 2)
+    } else if let Some(rest) = literal.strip_prefix('0') {
+        (rest, 8)
+    } else {
+        (literal.as_str(), 10)
+    };
+
+    let digit_count = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+    let (digits, suffix) = digits.split_at(digit_count);
+
+    // A lone `0` is an octal literal without digits after the prefix, `0x` and `0b` need some
+    let value = if digits.is_empty() {
+        if radix != 8 {
+            return Err(error(TokenErrorKind::InvalidIntegerLiteral));
+        }
+        0
+    } else {
+        u64::from_str_radix(digits, radix).map_err(|_| error(TokenErrorKind::IntegerTooLarge))?
+    };
+
+    let (unsigned, longs) = parse_integer_suffix(suffix).ok_or(error(TokenErrorKind::InvalidIntegerLiteral))?;
+
+    use IntegerLiteralType::*;
+    let candidates: &[IntegerLiteralType] = match (unsigned, longs, radix == 10) {
+        (false, 0, true) => &[Int, Long, LongLong],
+        (false, 0, false) => &[Int, UnsignedInt, Long, UnsignedLong, LongLong, UnsignedLongLong],
+        (true, 0, _) => &[UnsignedInt, UnsignedLong, UnsignedLongLong],
+        (false, 1, true) => &[Long, LongLong],
+        (false, 1, false) => &[Long, UnsignedLong, LongLong, UnsignedLongLong],
+        (true, 1, _) => &[UnsignedLong, UnsignedLongLong],
+        (false, _, true) => &[LongLong],
+        (false, _, false) => &[LongLong, UnsignedLongLong],
+        (true, _, _) => &[UnsignedLongLong],
+    };
+
+    candidates
+        .iter()
+        .find(|candidate| value <= candidate.max_value())
+        .map(|candidate| candidate.to_token(value))
+        .ok_or(error(TokenErrorKind::IntegerTooLarge))
+}
+
+
+/// The token for a floating literal, `None` if it is not a valid one.
+/// Values too large for their type become infinity, as with other compilers.
+fn float_literal(literal: &str, hexadecimal: bool) -> Option<Token> {
+    let (number, suffix) = match literal.char_indices().last() {
+        Some((index, 'f' | 'F' | 'l' | 'L')) if !hexadecimal || literal[..index].contains(['p', 'P']) => {
+            literal.split_at(index)
+        }
+        _ => (literal, ""),
+    };
+    let single = matches!(suffix, "f" | "F");
+
+    let value = if hexadecimal {
+        hexadecimal_float(&number[2..])?
+    } else if number.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-')) {
+        // Parse a float directly, rounding through a double first can be off by one bit
+        if single {
+            return number.parse::<f32>().ok().map(|value| Token::FLOAT(value.to_bits()));
+        }
+        number.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+
+    Some(match single {
+        true => Token::FLOAT((value as f32).to_bits()),
+        false => Token::DOUBLE(value.to_bits()),
+    })
+}
+
+
+/// The value of a hexadecimal floating literal after its `0x`, like `1.8p3`
+fn hexadecimal_float(number: &str) -> Option<f64> {
+    let (mantissa, exponent) = number.split_once(['p', 'P'])?;
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+
+    let mut value = 0.0;
+    for digit in whole.chars().chain(fraction.chars()) {
+        value = value * 16.0 + digit.to_digit(16)? as f64;
+    }
+    let exponent: i32 = exponent.parse().ok()?;
+    Some(value * 2f64.powi(exponent - 4 * fraction.len() as i32))
+}
+
+
+/// Parse an integer suffix into (is unsigned, number of `l`s), `None` if it is not a valid suffix.
+/// `u` can come before or after the `l`s, `ll` has to be written in a single case.
+fn parse_integer_suffix(suffix: &str) -> Option<(bool, u8)> {
+    let mut rest = suffix;
+    let mut unsigned = false;
+
+    if let Some(after) = rest.strip_prefix(['u', 'U']) {
+        unsigned = true;
+        rest = after;
+    }
+
+    let longs = if let Some(after) = rest.strip_prefix("ll").or_else(|| rest.strip_prefix("LL")) {
+        rest = after;
+        2
+    } else if let Some(after) = rest.strip_prefix(['l', 'L']) {
+        rest = after;
+        1
+    } else {
+        0
+    };
+
+    if !unsigned {
+        if let Some(after) = rest.strip_prefix(['u', 'U']) {
+            unsigned = true;
+            rest = after;
+        }
+    }
+
+    rest.is_empty().then_some((unsigned, longs))
+}
+
+
+/// Scan the body of a character constant or string literal, up to and including the closing `quote`.
+/// Escape sequences are resolved and anything else is kept as its UTF-8 bytes.
+///
+/// # Arguments
+///
+/// * `quote`: `'` or `"`, the opening quote has already been consumed
+/// * `chars`: a peekable iterator for the current line, positioned after the opening quote
+/// * `line`: line number, for debugging
+/// * `column`: column of the opening quote, for debugging
+///
+/// returns: Result<Vec<u8>, TokenError>
+fn scan_quoted(
+    quote: char,
+    chars: &mut std::iter::Peekable<std::iter::Enumerate<std::str::Chars>>,
+    line: usize,
+    column: usize,
+) -> Result<Vec<u8>, TokenError> {
+    let mut bytes = Vec::new();
+    // Keep going after a bad escape so the rest of the literal is not scanned as code
+    let mut first_error = None;
+
+    loop {
+        let Some((position, ch)) = chars.next() else {
+            let kind = if quote == '"' {
+                TokenErrorKind::UnterminatedString
+            } else {
+                TokenErrorKind::UnterminatedCharacterLiteral
+            };
+            return Err(TokenError { file: None, line, column, character: quote, kind });
+        };
+
+        match ch {
+            _ if ch == quote => break,
+            '\\' => match scan_escape(chars) {
+                Ok(byte) => bytes.push(byte),
+                Err(character) => {
+                    first_error.get_or_insert(TokenError {
+                        file: None,
+                        line,
+                        column: position,
+                        character,
+                        kind: TokenErrorKind::InvalidEscapeSequence,
+                    });
+                }
+            },
+            _ => bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+
+    match first_error {
+        Some(error) => Err(error),
+        None => Ok(bytes),
+    }
+}
+
+
+/// Resolve the escape sequence following a `\`, returning the offending character if it is invalid.
+/// Octal escapes take up to three digits, hex escapes take every hex digit that follows,
+/// both have to fit in a byte.
+fn scan_escape(chars: &mut std::iter::Peekable<std::iter::Enumerate<std::str::Chars>>) -> Result<u8, char> {
+    let Some((_, ch)) = chars.next() else {
+        return Err('\\');
+    };
+
+    let byte = match ch {
+        'n' => b'\n',
+        't' => b'\t',
+        'r' => b'\r',
+        'a' => 0x07,
+        'b' => 0x08,
+        'f' => 0x0c,
+        'v' => 0x0b,
+        '\\' | '\'' | '"' | '?' => ch as u8,
+        '0'..='7' => {
+            let mut value = ch.to_digit(8).unwrap_or_default();
+            for _ in 0..2 {
+                match chars.peek().and_then(|&(_, digit)| digit.to_digit(8)) {
+                    Some(digit) => {
+                        value = value * 8 + digit;
+                        chars.next();
+                    }
+                    None => break,
+                }
+            }
+            u8::try_from(value).map_err(|_| ch)?
+        }
+        'x' => {
+            let mut value: u32 = 0;
+            let mut digit_count = 0;
+            while let Some(digit) = chars.peek().and_then(|&(_, digit)| digit.to_digit(16)) {
+                value = value.saturating_mul(16).saturating_add(digit);
+                digit_count += 1;
+                chars.next();
+            }
+            if digit_count == 0 {
+                return Err(ch);
+            }
+            u8::try_from(value).map_err(|_| ch)?
+        }
+        _ => return Err(ch),
+    };
+    Ok(byte)
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use std::io::{Cursor, Read};
 
     // Helper function to create a BufReader from a string
     fn create_reader(input: &str) -> Cursor<Vec<u8>> {
@@ -263,7 +1565,7 @@ mod tests {
         assert!(matches!(tokens[0], Ok(Token::INT(1))));
         assert_eq!(
             tokens[1].clone().err().unwrap(),
-            TokenError { line: 1, column: 2, character: '@' }
+            TokenError { file: None, line: 1, column: 3, character: '@', kind: TokenErrorKind::InvalidCharacter }
         );
         assert!(matches!(tokens[2], Ok(Token::INT(2))));
     }
@@ -304,7 +1606,7 @@ mod tests {
         assert!(matches!(result[4], Ok(Token::INT(3))));
         assert_eq!(
             result[5].clone().err().unwrap(),
-            TokenError { line: 2, column: 4, character: '@' }
+            TokenError { file: None, line: 2, column: 5, character: '@', kind: TokenErrorKind::InvalidCharacter }
         );
         assert!(matches!(result[6], Ok(Token::INT(4))));
         assert!(matches!(result[7], Ok(Token::EndOfLine)));
@@ -333,4 +1635,492 @@ mod tests {
         assert_eq!(result[3], Ok(Token::EndOfFile));
         assert_eq!(result.len(), 4);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_scan_line_comment() {
+        let tokens = scan_line("1 + 2 // 3 * 4", 1);
+        assert_eq!(tokens, vec![
+            Ok(Token::INT(1)),
+            Ok(Token::PLUS),
+            Ok(Token::INT(2)),
+            Ok(Token::EndOfLine),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_inline_block_comment() {
+        let tokens = scan_line("1 /* plus */ + 2/**/3", 1);
+        assert_eq!(tokens, vec![
+            Ok(Token::INT(1)),
+            Ok(Token::PLUS),
+            Ok(Token::INT(2)),
+            Ok(Token::INT(3)),
+            Ok(Token::EndOfLine),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_division_is_not_a_comment() {
+        let tokens = scan_line("8 / 2 /3", 1);
+        assert_eq!(tokens, vec![
+            Ok(Token::INT(8)),
+            Ok(Token::SLASH),
+            Ok(Token::INT(2)),
+            Ok(Token::SLASH),
+            Ok(Token::INT(3)),
+            Ok(Token::EndOfLine),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_block_comment_does_not_close_on_its_own_star() {
+        // `/*/` opens the comment, the `*` cannot be reused to close it
+        let tokens = scan_line("1 /*/ 2 */ 3", 1);
+        assert_eq!(tokens, vec![
+            Ok(Token::INT(1)),
+            Ok(Token::INT(3)),
+            Ok(Token::EndOfLine),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_file_multi_line_block_comment() {
+        let input = "1 + /* start\n  * still a comment // with a line comment in it\n end */ 2\n";
+        let mut reader = create_reader(input);
+
+        // The comment is a single space, so the lines it spans are one line
+        let result = scan_file(&mut reader).unwrap();
+        assert_eq!(result, vec![
+            Ok(Token::INT(1)),
+            Ok(Token::PLUS),
+            Ok(Token::INT(2)),
+            Ok(Token::EndOfLine),
+            Ok(Token::EndOfFile),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_file_unterminated_block_comment() {
+        let input = "1 + 2\n3 /* never\nclosed\n";
+        let mut reader = create_reader(input);
+
+        let result = scan_file(&mut reader).unwrap();
+        assert_eq!(result.len(), 8);
+        assert_eq!(
+            result[6].clone().err().unwrap(),
+            TokenError { file: None, line: 2, column: 3, character: '/', kind: TokenErrorKind::UnterminatedBlockComment }
+        );
+        assert_eq!(result[7], Ok(Token::EndOfFile));
+    }
+
+    #[test]
+    fn test_token_error_display() {
+        let invalid = TokenError { file: None, line: 3, column: 7, character: '@', kind: TokenErrorKind::InvalidCharacter };
+        assert_eq!(invalid.to_string(), "line 3, column 7: invalid character '@'");
+
+        let comment = TokenError { file: None, line: 1, column: 1, character: '/', kind: TokenErrorKind::UnterminatedBlockComment };
+        assert_eq!(comment.to_string(), "line 1, column 1: unterminated block comment");
+    }
+
+    fn scan_single(literal: &str) -> Result<Token, TokenError> {
+        let mut tokens = scan_line(literal, 1);
+        assert_eq!(tokens.len(), 2, "'{}' should be a single token", literal);
+        tokens.remove(0)
+    }
+
+    #[test]
+    fn test_scan_integer_radixes() {
+        assert_eq!(scan_single("0x1F"), Ok(Token::INT(31)));
+        assert_eq!(scan_single("0XfF"), Ok(Token::INT(255)));
+        assert_eq!(scan_single("017"), Ok(Token::INT(15)));
+        assert_eq!(scan_single("0b101"), Ok(Token::INT(5)));
+        assert_eq!(scan_single("0"), Ok(Token::INT(0)));
+        assert_eq!(scan_single("00"), Ok(Token::INT(0)));
+    }
+
+    #[test]
+    fn test_scan_integer_suffixes() {
+        assert_eq!(scan_single("10u"), Ok(Token::UINT(10)));
+        assert_eq!(scan_single("10L"), Ok(Token::LONG(10)));
+        assert_eq!(scan_single("10ll"), Ok(Token::LONGLONG(10)));
+        assert_eq!(scan_single("10ul"), Ok(Token::ULONG(10)));
+        assert_eq!(scan_single("10LU"), Ok(Token::ULONG(10)));
+        assert_eq!(scan_single("0x10ULL"), Ok(Token::ULONGLONG(16)));
+        assert_eq!(scan_single("10llu"), Ok(Token::ULONGLONG(10)));
+    }
+
+    #[test]
+    fn test_scan_integer_type_follows_value() {
+        // Decimal literals only ever become signed types
+        assert_eq!(scan_single("2147483647"), Ok(Token::INT(i32::MAX)));
+        assert_eq!(scan_single("2147483648"), Ok(Token::LONG(2147483648)));
+        assert_eq!(scan_single("4294967296"), Ok(Token::LONG(4294967296)));
+
+        // Hex and octal literals try the unsigned type of each rank as well
+        assert_eq!(scan_single("0x80000000"), Ok(Token::UINT(0x80000000)));
+        assert_eq!(scan_single("037777777777"), Ok(Token::UINT(u32::MAX)));
+        assert_eq!(scan_single("0x100000000"), Ok(Token::LONG(0x100000000)));
+        assert_eq!(scan_single("0xFFFFFFFFFFFFFFFF"), Ok(Token::ULONG(u64::MAX)));
+
+        assert_eq!(scan_single("4294967296u"), Ok(Token::ULONG(4294967296)));
+        assert_eq!(scan_single("0x8000000000000000ll"), Ok(Token::ULONGLONG(0x8000000000000000)));
+    }
+
+    #[test]
+    fn test_scan_integer_too_large() {
+        let error = scan_single("18446744073709551615").unwrap_err();
+        assert_eq!(error.kind, TokenErrorKind::IntegerTooLarge);
+
+        let error = scan_single("0x10000000000000000").unwrap_err();
+        assert_eq!(error.kind, TokenErrorKind::IntegerTooLarge);
+
+        assert_eq!(scan_single("18446744073709551615u"), Ok(Token::ULONG(u64::MAX)));
+    }
+
+    #[test]
+    fn test_scan_invalid_integer_literals() {
+        for literal in ["08", "0x", "0b2", "12abc", "1lul", "1lL", "1uu"] {
+            let error = scan_single(literal).unwrap_err();
+            assert_eq!(error.kind, TokenErrorKind::InvalidIntegerLiteral, "{}", literal);
+            assert_eq!(error.character, literal.chars().next().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_scan_float_literals() {
+        let double = |value: f64| Ok(Token::DOUBLE(value.to_bits()));
+        assert_eq!(scan_single("1.5"), double(1.5));
+        assert_eq!(scan_single(".25"), double(0.25));
+        assert_eq!(scan_single("3."), double(3.0));
+        assert_eq!(scan_single("1e3"), double(1000.0));
+        assert_eq!(scan_single("2.5E-2"), double(0.025));
+        assert_eq!(scan_single("1.e+2"), double(100.0));
+        assert_eq!(scan_single("010.5"), double(10.5));
+        assert_eq!(scan_single("0x1.8p1"), double(3.0));
+        assert_eq!(scan_single("0x.4P-2"), double(0.0625));
+        assert_eq!(scan_single("1.5L"), double(1.5));
+        assert_eq!(scan_single("0.1f"), Ok(Token::FLOAT(0.1f32.to_bits())));
+        assert_eq!(scan_single("0x1p-1F"), Ok(Token::FLOAT(0.5f32.to_bits())));
+        assert_eq!(scan_single("1e999"), double(f64::INFINITY));
+
+        // A dot before something other than a digit is still member access
+        let tokens: Vec<Token> = scan_line("s.x", 1).into_iter().map(Result::unwrap).collect();
+        assert_eq!(tokens, vec![Token::IDENTIFIER("s".to_string()), Token::DOT, Token::IDENTIFIER("x".to_string()), Token::EndOfLine]);
+    }
+
+    #[test]
+    fn test_scan_invalid_float_literals() {
+        for literal in ["1.5x", "1e", "1e+", "1.2.3", "0x1.8", "1.5u", "1.5ff", ".5e"] {
+            let error = scan_single(literal).unwrap_err();
+            assert_eq!(error.kind, TokenErrorKind::InvalidFloatLiteral, "{}", literal);
+        }
+    }
+
+    #[test]
+    fn test_scan_character_literals() {
+        assert_eq!(scan_single("'a'"), Ok(Token::CHAR(b'a')));
+        assert_eq!(scan_single("' '"), Ok(Token::CHAR(b' ')));
+        assert_eq!(scan_single("'\\n'"), Ok(Token::CHAR(b'\n')));
+        assert_eq!(scan_single("'\\''"), Ok(Token::CHAR(b'\'')));
+        assert_eq!(scan_single("'\"'"), Ok(Token::CHAR(b'"')));
+        assert_eq!(scan_single("'\\0'"), Ok(Token::CHAR(0)));
+        assert_eq!(scan_single("'\\101'"), Ok(Token::CHAR(b'A')));
+        assert_eq!(scan_single("'\\x41'"), Ok(Token::CHAR(b'A')));
+        assert_eq!(scan_single("'\\xff'"), Ok(Token::CHAR(0xff)));
+        assert_eq!(Token::CHAR(0xff).integer_value(), Some(-1));
+    }
+
+    #[test]
+    fn test_scan_string_literals() {
+        assert_eq!(scan_single("\"hello\""), Ok(Token::STRING(b"hello".to_vec())));
+        assert_eq!(scan_single("\"\""), Ok(Token::STRING(vec![])));
+        assert_eq!(
+            scan_single("\"a\\tb\\\\c\\\"d\\?\\n\""),
+            Ok(Token::STRING(b"a\tb\\c\"d?\n".to_vec()))
+        );
+        // Octal escapes stop after three digits, hex escapes do not
+        assert_eq!(scan_single("\"\\1234\""), Ok(Token::STRING(vec![0o123, b'4'])));
+        assert_eq!(scan_single("\"\\x41g\""), Ok(Token::STRING(b"Ag".to_vec())));
+        // Comment markers inside a string are just characters
+        assert_eq!(scan_single("\"// /*\""), Ok(Token::STRING(b"// /*".to_vec())));
+        assert_eq!(scan_single("\"é\""), Ok(Token::STRING("é".as_bytes().to_vec())));
+    }
+
+    #[test]
+    fn test_scan_string_next_to_other_tokens() {
+        let tokens = scan_line("\"a\" \"b\"+'c'", 1);
+        assert_eq!(tokens, vec![
+            Ok(Token::STRING(b"a".to_vec())),
+            Ok(Token::STRING(b"b".to_vec())),
+            Ok(Token::PLUS),
+            Ok(Token::CHAR(b'c')),
+            Ok(Token::EndOfLine),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_invalid_character_and_string_literals() {
+        let kind = |literal: &str| scan_line(literal, 1).remove(0).unwrap_err().kind;
+
+        assert_eq!(kind("''"), TokenErrorKind::EmptyCharacterLiteral);
+        assert_eq!(kind("'ab'"), TokenErrorKind::MultiCharacterLiteral);
+        assert_eq!(kind("'a"), TokenErrorKind::UnterminatedCharacterLiteral);
+        assert_eq!(kind("\"abc"), TokenErrorKind::UnterminatedString);
+        assert_eq!(kind("\"abc\\\""), TokenErrorKind::UnterminatedString);
+        assert_eq!(kind("\"\\q\""), TokenErrorKind::InvalidEscapeSequence);
+        assert_eq!(kind("\"\\x\""), TokenErrorKind::InvalidEscapeSequence);
+        assert_eq!(kind("\"\\x100\""), TokenErrorKind::InvalidEscapeSequence);
+        assert_eq!(kind("'\\777'"), TokenErrorKind::InvalidEscapeSequence);
+    }
+
+    #[test]
+    fn test_scan_invalid_escape_recovers_at_closing_quote() {
+        let tokens = scan_line("\"a\\qb\" + 1", 1);
+        assert_eq!(
+            tokens[0],
+            Err(TokenError { file: None, line: 1, column: 3, character: 'q', kind: TokenErrorKind::InvalidEscapeSequence })
+        );
+        assert_eq!(tokens[1..], [Ok(Token::PLUS), Ok(Token::INT(1)), Ok(Token::EndOfLine)]);
+    }
+
+    #[test]
+    fn test_lexer_matches_scan_file() {
+        let input = "1 + 2 /* across\n lines */ * 3\n'a' \"b\"\n\n0x10 @\n";
+        let expected = scan_file(&mut create_reader(input)).unwrap();
+
+        let lexed: Vec<Result<Token, TokenError>> = Lexer::new(create_reader(input))
+            .map(|token| token.map(|spanned| spanned.token))
+            .collect();
+        assert_eq!(lexed, expected);
+    }
+
+    #[test]
+    fn test_lexer_positions() {
+        let tokens: Vec<SpannedToken> = Lexer::new(create_reader("1 +\r\n  23\n"))
+            .map(|token| token.unwrap())
+            .collect();
+        assert_eq!(tokens, vec![
+            SpannedToken::new(Token::INT(1), 1, 1, 0),
+            SpannedToken::new(Token::PLUS, 1, 3, 2),
+            SpannedToken::new(Token::EndOfLine, 1, 4, 3),
+            SpannedToken::new(Token::INT(23), 2, 3, 7),
+            SpannedToken::new(Token::EndOfLine, 2, 5, 9),
+            SpannedToken::new(Token::EndOfFile, 3, 1, 10),
+        ]);
+    }
+
+    #[test]
+    fn test_display_columns_with_tabs() {
+        assert_eq!(display_columns(""), vec![1]);
+        assert_eq!(display_columns("\t\t"), vec![1, 9, 17]);
+        // A tab only moves to the next tab stop, not a full tab width
+        assert_eq!(display_columns("abc\td"), vec![1, 2, 3, 4, 9, 10]);
+        assert_eq!(display_columns("1234567\tx"), vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(display_columns("12345678\tx"), vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 17, 18]);
+    }
+
+    #[test]
+    fn test_token_positions_with_tabs() {
+        let tokens: Vec<SpannedToken> = Lexer::new(create_reader("\t1\t+ 2\n  \t3"))
+            .map(|token| token.unwrap())
+            .collect();
+        assert_eq!(tokens, vec![
+            SpannedToken::new(Token::INT(1), 1, 9, 1),
+            SpannedToken::new(Token::PLUS, 1, 17, 3),
+            SpannedToken::new(Token::INT(2), 1, 19, 5),
+            SpannedToken::new(Token::EndOfLine, 1, 20, 6),
+            SpannedToken::new(Token::INT(3), 2, 9, 10),
+            SpannedToken::new(Token::EndOfLine, 2, 10, 11),
+            SpannedToken::new(Token::EndOfFile, 3, 1, 11),
+        ]);
+
+        let errors: Vec<TokenError> = Lexer::new(create_reader("1\t@\n\t/* open"))
+            .filter_map(|token| token.err())
+            .collect();
+        assert_eq!(errors, vec![
+            TokenError { file: None, line: 1, column: 9, character: '@', kind: TokenErrorKind::InvalidCharacter },
+            TokenError { file: None, line: 2, column: 9, character: '/', kind: TokenErrorKind::UnterminatedBlockComment },
+        ]);
+    }
+
+    #[test]
+    fn test_token_positions_with_multibyte_characters() {
+        // 'é' is two bytes and 'ü' is two bytes, each one display column
+        let tokens: Vec<Result<SpannedToken, TokenError>> = Lexer::new(create_reader("\"é\" + 'a' ü 7\n"))
+            .collect();
+        assert_eq!(tokens[0], Ok(SpannedToken::new(Token::STRING("é".as_bytes().to_vec()), 1, 1, 0)));
+        assert_eq!(tokens[1], Ok(SpannedToken::new(Token::PLUS, 1, 5, 5)));
+        assert_eq!(tokens[2], Ok(SpannedToken::new(Token::CHAR(b'a'), 1, 7, 7)));
+        assert_eq!(
+            tokens[3],
+            Err(TokenError { file: None, line: 1, column: 11, character: 'ü', kind: TokenErrorKind::InvalidCharacter })
+        );
+        assert_eq!(tokens[4], Ok(SpannedToken::new(Token::INT(7), 1, 13, 14)));
+        assert_eq!(tokens[5], Ok(SpannedToken::new(Token::EndOfLine, 1, 14, 15)));
+    }
+
+    #[test]
+    fn test_lexer_lookahead() {
+        let mut lexer = Lexer::new(create_reader("1\n2\n3\n"));
+
+        // Looking ahead reads only as many lines as it needs
+        assert_eq!(lexer.peek_nth(2).unwrap().as_ref().unwrap().token, Token::INT(2));
+        assert_eq!(lexer.line_num, 2);
+        assert_eq!(lexer.peek().unwrap().as_ref().unwrap().token, Token::INT(1));
+
+        assert_eq!(lexer.next_token().unwrap().unwrap().token, Token::INT(1));
+        assert_eq!(lexer.peek_nth(5).unwrap().as_ref().unwrap().token, Token::EndOfFile);
+        assert!(lexer.peek_nth(6).is_none());
+        assert_eq!(lexer.count(), 6);
+    }
+
+    /// Input that never ends, `1 + 2 * 3` on every line
+    struct EndlessReader {
+        position: usize,
+    }
+
+    impl Read for EndlessReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let line = b"1 + 2 * 3\n";
+            for byte in buf.iter_mut() {
+                *byte = line[self.position % line.len()];
+                self.position += 1;
+            }
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_lexer_reads_lazily() {
+        let mut lexer = Lexer::new(io::BufReader::new(EndlessReader { position: 0 }));
+
+        for _ in 0..1000 {
+            lexer.next().unwrap().unwrap();
+        }
+        assert!(lexer.buffer.len() < 10);
+        assert!(lexer.line_num <= 200);
+    }
+
+    #[test]
+    fn test_lexer_reports_read_errors() {
+        let mut lexer = Lexer::new(create_reader("1 + 2\n").chain(&[0xff, 0xfe, b'\n'][..]));
+        let tokens: Vec<_> = lexer.by_ref().collect();
+
+        let error = tokens.last().unwrap().clone().unwrap_err();
+        assert_eq!(error.kind, TokenErrorKind::Io(io::ErrorKind::InvalidData));
+        assert_eq!(error.line, 2);
+        assert!(lexer.next().is_none());
+
+        let mut reader = create_reader("1\n").chain(&[0xff, b'\n'][..]);
+        assert_eq!(scan_file(&mut reader).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_scan_identifiers_and_punctuation() {
+        let tokens = scan_line("_foo1 (a, b[]) # ## 9x", 1);
+        assert_eq!(tokens[..11], [
+            Ok(Token::IDENTIFIER("_foo1".to_string())),
+            Ok(Token::LPAREN),
+            Ok(Token::IDENTIFIER("a".to_string())),
+            Ok(Token::COMMA),
+            Ok(Token::IDENTIFIER("b".to_string())),
+            Ok(Token::LBRACKET),
+            Ok(Token::RBRACKET),
+            Ok(Token::RPAREN),
+            Ok(Token::HASH),
+            Ok(Token::HASHHASH),
+            Err(TokenError { file: None, line: 1, column: 21, character: '9', kind: TokenErrorKind::InvalidIntegerLiteral }),
+        ]);
+    }
+
+    #[test]
+    fn test_lexer_splices_lines() {
+        let tokens: Vec<SpannedToken> = Lexer::new(create_reader("1 + \\\n  2\n3\n"))
+            .map(|token| token.unwrap())
+            .collect();
+        // The spliced tokens keep the line they were written on
+        assert_eq!(tokens, vec![
+            SpannedToken::new(Token::INT(1), 1, 1, 0),
+            SpannedToken::new(Token::PLUS, 1, 3, 2),
+            SpannedToken::new(Token::INT(2), 2, 3, 8),
+            SpannedToken::new(Token::EndOfLine, 2, 4, 9),
+            SpannedToken::new(Token::INT(3), 3, 1, 10),
+            SpannedToken::new(Token::EndOfLine, 3, 2, 11),
+            SpannedToken::new(Token::EndOfFile, 4, 1, 12),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_operators() {
+        let tokens = scan_line("% = == != ! < <= << > >= >> & && | || ^ ~ ? : <<= . -> - >", 1);
+        assert_eq!(tokens, vec![
+            Ok(Token::PERCENT),
+            Ok(Token::ASSIGN),
+            Ok(Token::EQ),
+            Ok(Token::NE),
+            Ok(Token::LOGNOT),
+            Ok(Token::LT),
+            Ok(Token::LE),
+            Ok(Token::LSHIFT),
+            Ok(Token::GT),
+            Ok(Token::GE),
+            Ok(Token::RSHIFT),
+            Ok(Token::AMPER),
+            Ok(Token::LOGAND),
+            Ok(Token::OR),
+            Ok(Token::LOGOR),
+            Ok(Token::XOR),
+            Ok(Token::INVERT),
+            Ok(Token::QUESTION),
+            Ok(Token::COLON),
+            Ok(Token::ASLSHIFT),
+            Ok(Token::DOT),
+            Ok(Token::ARROW),
+            Ok(Token::MINUS),
+            Ok(Token::GT),
+            Ok(Token::EndOfLine),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_assignment_operators() {
+        let tokens = scan_line("+= -= *= /= %= &= |= ^= <<= >>= ++ -- +++ - -= >>", 1);
+        assert_eq!(tokens, vec![
+            Ok(Token::ASPLUS),
+            Ok(Token::ASMINUS),
+            Ok(Token::ASSTAR),
+            Ok(Token::ASSLASH),
+            Ok(Token::ASMOD),
+            Ok(Token::ASAMPER),
+            Ok(Token::ASOR),
+            Ok(Token::ASXOR),
+            Ok(Token::ASLSHIFT),
+            Ok(Token::ASRSHIFT),
+            Ok(Token::INC),
+            Ok(Token::DEC),
+            // The longest operator is taken first
+            Ok(Token::INC),
+            Ok(Token::PLUS),
+            Ok(Token::MINUS),
+            Ok(Token::ASMINUS),
+            Ok(Token::RSHIFT),
+            Ok(Token::EndOfLine),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_ellipsis() {
+        let tokens = scan_line("(x, ...) .. .5", 1);
+        assert_eq!(tokens, vec![
+            Ok(Token::LPAREN),
+            Ok(Token::IDENTIFIER("x".to_string())),
+            Ok(Token::COMMA),
+            Ok(Token::ELLIPSIS),
+            Ok(Token::RPAREN),
+            Ok(Token::DOT),
+            Ok(Token::DOT),
+            Ok(Token::DOUBLE(0.5f64.to_bits())),
+            Ok(Token::EndOfLine),
+        ]);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::assembly::SupportedArchitectures;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Temporary files made so far by this process, each one gets a new number
+static TEMPORARY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The programs that turn the assembly the compiler writes into object files and link those,
+/// `as` and `cc` unless the `AS` and `CC` environment variables name others. Either can come
+/// with arguments of its own, like `CC="clang -fuse-ld=lld"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toolchain {
+    assembler: Vec<String>,
+    linker: Vec<String>,
+    /// The target, Darwin's tools are told the architecture since they can build for several
+    architecture: SupportedArchitectures,
+}
+
+/// Why the assembler or the linker did not make its output
+#[derive(Debug)]
+pub enum ToolchainError {
+    /// The program could not be started, usually because it is not installed
+    Spawn { program: String, error: io::Error },
+    /// The program ran and failed, with what it wrote to stderr
+    Failed { program: String, status: ExitStatus, stderr: String },
+}
+
+impl fmt::Display for ToolchainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolchainError::Spawn { program, error } => write!(f, "could not run `{}`: {}", program, error),
+            ToolchainError::Failed { program, status, stderr } => {
+                write!(f, "`{}` failed ({})", program, status)?;
+                if !stderr.trim().is_empty() {
+                    write!(f, ":\n{}", stderr.trim_end())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Toolchain {
+    /// # Arguments
+    ///
+    /// * `assembler`: the command for the assembler, split at whitespace
+    /// * `linker`: the command for the compiler driver that links, split at whitespace
+    /// * `architecture`: the target the assembly is written for
+    ///
+    /// returns: Toolchain
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::assembly::SupportedArchitectures;
+    /// # use compiler::toolchain::Toolchain;
+    /// let toolchain = Toolchain::new("clang -c", "cc", SupportedArchitectures::ARM64);
+    /// assert_eq!(toolchain, Toolchain::new("clang  -c ", "cc", SupportedArchitectures::ARM64));
+    /// ```
+    pub fn new(assembler: &str, linker: &str, architecture: SupportedArchitectures) -> Self {
+        let split = |command: &str| command.split_whitespace().map(str::to_string).collect();
+        Self {
+            assembler: split(assembler),
+            linker: split(linker),
+            architecture,
+        }
+    }
+
+    /// The toolchain named by the `AS` and `CC` environment variables, `as` and `cc` for the ones not set
+    pub fn from_env(architecture: SupportedArchitectures) -> Self {
+        let variable = |name: &str, default: &str| {
+            std::env::var(name).ok().filter(|value| !value.trim().is_empty()).unwrap_or(default.to_string())
+        };
+        Self::new(&variable("AS", "as"), &variable("CC", "cc"), architecture)
+    }
+
+    /// Assemble `assembly` into the object file `object` for the target
+    pub fn assemble(&self, assembly: &Path, object: &Path) -> Result<(), ToolchainError> {
+        run(self.assemble_command(assembly, object))
+    }
+
+    /// Link `objects` into the executable `output`. `options` are `-l` and `-L` options for the
+    /// linker, passed after the objects in the order given, as the order of libraries matters.
+    pub fn link(&self, objects: &[PathBuf], output: &Path, options: &[String]) -> Result<(), ToolchainError> {
+        run(self.link_command(objects, output, options))
+    }
+
+    fn assemble_command(&self, assembly: &Path, object: &Path) -> Command {
+        let mut command = command(&self.assembler);
+        command.args(self.architecture_options()).arg("-o").arg(object).arg(assembly);
+        command
+    }
+
+    fn link_command(&self, objects: &[PathBuf], output: &Path, options: &[String]) -> Command {
+        let mut command = command(&self.linker);
+        command.args(self.architecture_options()).arg("-o").arg(output).args(objects).args(options);
+        command
+    }
+
+    /// The options that pick the architecture, a Linux toolchain only builds for its own
+    fn architecture_options(&self) -> &'static [&'static str] {
+        match self.architecture {
+            SupportedArchitectures::ARM64 => &["-arch", "arm64"],
+            SupportedArchitectures::ARM64Linux => &[],
+        }
+    }
+}
+
+/// A command with the program and arguments in `words`, an empty command runs nothing that exists
+fn command(words: &[String]) -> Command {
+    let mut command = Command::new(words.first().map(String::as_str).unwrap_or_default());
+    command.args(&words[words.len().min(1)..]);
+    command
+}
+
+fn run(mut command: Command) -> Result<(), ToolchainError> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|error| ToolchainError::Spawn { program: program.clone(), error })?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(ToolchainError::Failed {
+        program,
+        status: output.status,
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// Files that only live while the compiler runs, like the assembly for an object file. They
+/// are removed when this is dropped, whether or not they were made.
+#[derive(Debug, Default)]
+pub struct Temporaries {
+    paths: Vec<PathBuf>,
+}
+
+impl Temporaries {
+    /// A new path in the temporary directory ending in `extension`, for a file that goes away with this
+    pub fn path(&mut self, extension: &str) -> PathBuf {
+        let number = TEMPORARY_COUNT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("compiler-{}-{}.{}", std::process::id(), number, extension);
+        let path = std::env::temp_dir().join(name);
+        self.paths.push(path.clone());
+        path
+    }
+}
+
+impl Drop for Temporaries {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            // A file that was never made is fine, there is nothing else to do about the others
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(command: &Command) -> Vec<String> {
+        command.get_args().map(|argument| argument.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn test_commands() {
+        let toolchain = Toolchain::new("as", "clang -fuse-ld=lld", SupportedArchitectures::ARM64);
+        let assemble = toolchain.assemble_command(Path::new("a.s"), Path::new("a.o"));
+        assert_eq!(assemble.get_program(), "as");
+        assert_eq!(arguments(&assemble), vec!["-arch", "arm64", "-o", "a.o", "a.s"]);
+
+        // Libraries come after the objects that use them
+        let objects = [PathBuf::from("a.o"), PathBuf::from("b.o")];
+        let options = ["-L/opt/lib".to_string(), "-lm".to_string()];
+        let link = toolchain.link_command(&objects, Path::new("program"), &options);
+        assert_eq!(link.get_program(), "clang");
+        assert_eq!(arguments(&link), vec!["-fuse-ld=lld", "-arch", "arm64", "-o", "program", "a.o", "b.o", "-L/opt/lib", "-lm"]);
+
+        let toolchain = Toolchain::new("aarch64-linux-gnu-as", "aarch64-linux-gnu-gcc", SupportedArchitectures::ARM64Linux);
+        let assemble = toolchain.assemble_command(Path::new("a.s"), Path::new("a.o"));
+        assert_eq!(arguments(&assemble), vec!["-o", "a.o", "a.s"]);
+        let link = toolchain.link_command(&objects, Path::new("program"), &options);
+        assert_eq!(arguments(&link), vec!["-o", "program", "a.o", "b.o", "-L/opt/lib", "-lm"]);
+    }
+
+    #[test]
+    fn test_failures() {
+        let toolchain = Toolchain::new("false", "compiler-no-such-linker", SupportedArchitectures::ARM64);
+        let error = toolchain.assemble(Path::new("a.s"), Path::new("a.o")).unwrap_err();
+        assert!(matches!(&error, ToolchainError::Failed { program, .. } if program == "false"));
+        assert!(error.to_string().starts_with("`false` failed"));
+
+        let error = toolchain.link(&[], Path::new("a.out"), &[]).unwrap_err();
+        assert!(matches!(error, ToolchainError::Spawn { .. }));
+        assert!(error.to_string().starts_with("could not run `compiler-no-such-linker`: "));
+    }
+
+    #[test]
+    fn test_temporaries_are_removed() {
+        let mut temporaries = Temporaries::default();
+        let (made, never_made) = (temporaries.path("s"), temporaries.path("o"));
+        assert_ne!(made, never_made);
+        assert_eq!(made.extension().unwrap(), "s");
+        std::fs::write(&made, "").unwrap();
+        drop(temporaries);
+        assert!(!made.exists() && !never_made.exists());
+    }
+}
@@ -0,0 +1,2338 @@
+use crate::assembly::SupportedArchitectures;
+use crate::ast::statement::{ExternalDeclaration, Function, Statement, TranslationUnit};
+use crate::ast::{ASTError, ASTNode, Storage, Symbol};
+use crate::scan::{Keyword, Token};
+use crate::types::{Member, Type};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Type check a whole program. Afterwards every expression node has its [`ASTNode::ty`],
+/// identifiers have their [`ASTNode::symbol`], and every implicit conversion is an explicit
+/// [`Token::Cast`] node, so the code generator never has to think about C's conversion rules.
+/// Member accesses become pointer arithmetic, and calls returning a struct or union get a
+/// temporary local for the result as their [`ASTNode::symbol`]. The initialisers of variables
+/// with static storage are checked to be constants, see [`evaluate_constant`], and a
+/// [`Token::InitializerList`] gets a list of its own for every aggregate it sets. A global
+/// can be declared any number of times but defined once: of the declarations without an
+/// initialiser, only the first one defines it, as 0, and only if none has an initialiser
+/// (C11 6.9.2). The others become [`ExternalDeclaration::VariableDeclaration`]s. Errors in
+/// expressions are [`ASTError::At`] the token the innermost expression they concern was written at,
+/// errors in `return`, `case`, `default` and `break` at the keyword, and errors in labels and
+/// `goto` at the label name.
+///
+/// # Arguments
+///
+/// * `unit`: the parsed program, changed in place
+///
+/// returns: Result<(), ASTError>
+///
+/// # Examples
+///
+/// ```
+/// # use compiler::ast::statement::*;
+/// # use compiler::ast::ASTError;
+/// # use compiler::scan::Lexer;
+/// # use compiler::typecheck::check_translation_unit;
+/// let mut unit = TranslationUnit::parse(&mut Lexer::new("int main() { return x; }".as_bytes())).unwrap();
+/// let error = check_translation_unit(&mut unit).unwrap_err();
+/// assert_eq!(error.to_string(), "line 1, column 21: use of undeclared identifier 'x'");
+/// assert_eq!(error.without_position(), ASTError::UndeclaredIdentifier("x".to_string()));
+/// ```
+pub fn check_translation_unit(unit: &mut TranslationUnit) -> Result<(), ASTError> {
+    let mut checker = Checker {
+        scopes: vec![HashMap::new()],
+        locals: Vec::new(),
+        switches: Vec::new(),
+        labels: HashSet::new(),
+        variadic: false,
+        architecture: unit.architecture,
+    };
+    let mut defined = HashSet::new();
+    for declaration in &mut unit.declarations {
+        match declaration {
+            ExternalDeclaration::Function(function) => {
+                if !defined.insert(function.name.clone()) {
+                    return Err(ASTError::Redeclaration(function.name.clone()));
+                }
+                // A function can be called from its own body on
+                let symbol = checker.declare_global(Rc::new(Symbol {
+                    name: function.name.clone(),
+                    ty: function.ty(),
+                    slot: 0,
+                    storage: Storage::Static {
+                        label: function.name.clone(),
+                        external: function.external,
+                    },
+                }))?;
+                function.external = is_external(&symbol);
+                checker.function(function)?;
+            }
+            ExternalDeclaration::FunctionDeclaration(symbol) | ExternalDeclaration::VariableDeclaration(symbol) => {
+                *symbol = checker.declare_global(symbol.clone())?;
+            }
+            ExternalDeclaration::Variable(symbol, initializer) => {
+                if initializer.is_some() && !defined.insert(symbol.name.clone()) {
+                    return Err(ASTError::Redeclaration(symbol.name.clone()));
+                }
+                checker.global_variable(symbol, initializer)?;
+            }
+        }
+    }
+
+    let mut tentative = HashSet::new();
+    for declaration in &mut unit.declarations {
+        let ExternalDeclaration::Variable(symbol, None) = declaration else {
+            continue;
+        };
+        if defined.contains(&symbol.name) || !tentative.insert(symbol.name.clone()) {
+            *declaration = ExternalDeclaration::VariableDeclaration(symbol.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Whether other files can see a symbol with static storage
+fn is_external(symbol: &Symbol) -> bool {
+    matches!(symbol.storage, Storage::Static { external: true, .. })
+}
+
+/// Type check an expression on its own, it cannot refer to any variables
+///
+/// # Examples
+///
+/// ```
+/// # use compiler::ast::ASTNode;
+/// # use compiler::scan::Lexer;
+/// # use compiler::typecheck::check_expression;
+/// # use compiler::types::Type;
+/// let mut node = ASTNode::parse_stream(&mut Lexer::new("'a' + 1ul".as_bytes())).unwrap();
+/// check_expression(&mut node).unwrap();
+/// assert_eq!(node.ty, Some(Type::UnsignedLong));
+/// ```
+pub fn check_expression(node: &mut ASTNode) -> Result<(), ASTError> {
+    Checker {
+        scopes: vec![HashMap::new()],
+        locals: Vec::new(),
+        switches: Vec::new(),
+        labels: HashSet::new(),
+        variadic: false,
+        architecture: SupportedArchitectures::default(),
+    }
+    .rvalue(node)
+}
+
+/// What `return` statements have to agree with
+struct ReturnContext<'a> {
+    function: &'a str,
+    return_type: &'a Type,
+}
+
+/// The labels found so far in the body of a `switch`
+struct SwitchCases {
+    /// The promoted type of the value switched on, which the `case` values are converted to
+    ty: Type,
+    values: Vec<i64>,
+    default: bool,
+}
+
+struct Checker {
+    /// Innermost scope last, the functions are in the outermost one
+    scopes: Vec<HashMap<String, Rc<Symbol>>>,
+    /// Locals of the function being checked, which gets the temporaries the checker adds
+    locals: Vec<Rc<Symbol>>,
+    /// The switches the statement being checked is in, innermost last
+    switches: Vec<SwitchCases>,
+    /// The labels of the function being checked, a `goto` can jump ahead to one
+    labels: HashSet<String>,
+    /// Whether the function being checked is variadic, only then can it use `va_start`
+    variadic: bool,
+    /// The target, which decides what a `va_list` is
+    architecture: SupportedArchitectures,
+}
+
+impl Checker {
+    fn lookup(&self, name: &str) -> Option<&Rc<Symbol>> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn declare(&mut self, symbol: Rc<Symbol>) -> Result<(), ASTError> {
+        let scope = self.scopes.last_mut().expect("Declaration outside of a scope");
+        if scope.insert(symbol.name.clone(), symbol.clone()).is_some() {
+            return Err(ASTError::Redeclaration(symbol.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Declare a function or a global at file scope, which can be declared any number of times for
+    /// the same type. The first declaration decides whether other files can see it, a `static` one
+    /// cannot follow one they can. Returns the symbol the name stands for from then on.
+    fn declare_global(&mut self, symbol: Rc<Symbol>) -> Result<Rc<Symbol>, ASTError> {
+        match self.scopes[0].get(&symbol.name) {
+            Some(previous) if previous.ty == symbol.ty && (is_external(&symbol) || !is_external(previous)) => {
+                Ok(previous.clone())
+            }
+            Some(_) => Err(ASTError::Redeclaration(symbol.name.clone())),
+            None => {
+                self.declare(symbol.clone())?;
+                Ok(symbol)
+            }
+        }
+    }
+
+    /// Check a global variable that is defined here, unless another declaration of it has an
+    /// initialiser, which needs a type whose size is known
+    fn global_variable(&mut self, symbol: &mut Rc<Symbol>, initializer: &mut Option<ASTNode>) -> Result<(), ASTError> {
+        if symbol.ty == Type::Void {
+            return Err(ASTError::VoidVariable(symbol.name.clone()));
+        }
+        if let Some(value) = initializer.take() {
+            let value = self.initial_value(value, &symbol.ty)?;
+            if !is_constant_initializer(&value) {
+                return Err(ASTError::NotConstant(symbol.name.clone()));
+            }
+            complete_type(symbol, &value);
+            *initializer = Some(value);
+        }
+        if !symbol.ty.is_complete() {
+            return Err(ASTError::IncompleteType(symbol.ty.clone()));
+        }
+        *symbol = self.declare_global(symbol.clone())?;
+        Ok(())
+    }
+
+    /// Declare a variable, which needs a type whose size is known
+    fn declare_variable(&mut self, symbol: Rc<Symbol>) -> Result<(), ASTError> {
+        if symbol.ty == Type::Void {
+            return Err(ASTError::VoidVariable(symbol.name.clone()));
+        }
+        if !symbol.ty.is_complete() {
+            return Err(ASTError::IncompleteType(symbol.ty.clone()));
+        }
+        self.declare(symbol)
+    }
+
+    /// Add an unnamed local to the function, for a value the code generator has to keep in memory
+    fn temporary(&mut self, ty: Type) -> Rc<Symbol> {
+        let symbol = Rc::new(Symbol {
+            name: format!("<temporary {}>", self.locals.len()),
+            ty,
+            slot: self.locals.len(),
+            storage: Storage::Automatic,
+        });
+        self.locals.push(symbol.clone());
+        symbol
+    }
+
+    fn function(&mut self, function: &mut Function) -> Result<(), ASTError> {
+        if function.return_type != Type::Void && !function.return_type.is_complete() {
+            return Err(ASTError::IncompleteType(function.return_type.clone()));
+        }
+        let context = ReturnContext {
+            function: &function.name,
+            return_type: &function.return_type,
+        };
+
+        self.variadic = function.variadic;
+        self.labels.clear();
+        for statement in &function.body {
+            collect_labels(statement, &mut self.labels)?;
+        }
+
+        // The parameters are in the same scope as the outermost block of the body
+        self.locals = std::mem::take(&mut function.locals);
+        self.scopes.push(HashMap::new());
+        let result = function
+            .parameters
+            .iter()
+            .try_for_each(|parameter| self.declare_variable(parameter.clone()))
+            .and_then(|()| function.body.iter_mut().try_for_each(|statement| self.statement(statement, &context)));
+        self.scopes.pop();
+        function.locals = std::mem::take(&mut self.locals);
+        result
+    }
+
+    fn block(&mut self, statements: &mut [Statement], context: &ReturnContext) -> Result<(), ASTError> {
+        self.scopes.push(HashMap::new());
+        let result = statements.iter_mut().try_for_each(|statement| self.statement(statement, context));
+        self.scopes.pop();
+        result
+    }
+
+    fn statement(&mut self, statement: &mut Statement, context: &ReturnContext) -> Result<(), ASTError> {
+        match statement {
+            Statement::Expression(None) => Ok(()),
+            Statement::Expression(Some(expression)) => self.rvalue(expression),
+            Statement::Declaration(symbol, initializer) => self.variable(symbol, initializer),
+            Statement::Return(value, written) => match (value.take(), context.return_type) {
+                (None, Type::Void) => Ok(()),
+                (Some(returned), return_type) if *return_type != Type::Void => {
+                    *value = Some(self.converted(returned, return_type)?);
+                    Ok(())
+                }
+                _ => Err(ASTError::InvalidReturn(context.function.to_string()).at(written.as_ref())),
+            },
+            Statement::Block(statements) => self.block(statements, context),
+            Statement::Switch { value, body, cases, default } => {
+                self.rvalue(value)?;
+                let value_type = value.ty.clone().unwrap_or(Type::Void);
+                if !value_type.is_integer() {
+                    return Err(ASTError::InvalidSwitch(value_type).at(value.position.as_ref()));
+                }
+                let ty = value_type.promoted();
+                if ty != value_type {
+                    value.wrap(Token::Cast, ty.clone());
+                }
+
+                self.switches.push(SwitchCases {
+                    ty,
+                    values: Vec::new(),
+                    default: false,
+                });
+                let result = self.statement(body, context);
+                let switch = self.switches.pop().expect("The switch was pushed");
+                result?;
+                (*cases, *default) = (switch.values, switch.default);
+                Ok(())
+            }
+            Statement::Case(value, statement, written) => {
+                let error = |error: ASTError| error.at(written.as_ref());
+                let ty = self.switches.last().ok_or_else(|| error(ASTError::MisplacedStatement(Keyword::Case)))?.ty.clone();
+                self.rvalue(value)?;
+                let constant = match evaluate_constant(value) {
+                    Some(Constant::Integer(constant)) if value.ty.as_ref().is_some_and(Type::is_integer) => constant,
+                    _ => return Err(error(ASTError::NonConstantCase)),
+                };
+                // The value is compared as the type of the switch, which decides what counts as the same
+                if value.ty.as_ref() != Some(&ty) {
+                    value.wrap(Token::Cast, ty.clone());
+                }
+                let constant = wrapped(constant, &ty);
+                let switch = self.switches.last_mut().expect("Checked above");
+                if switch.values.contains(&constant) {
+                    return Err(error(ASTError::DuplicateCase(constant)));
+                }
+                switch.values.push(constant);
+                self.statement(statement, context)
+            }
+            Statement::Default(statement, written) => {
+                let error = |error: ASTError| error.at(written.as_ref());
+                let switch = self.switches.last_mut().ok_or_else(|| error(ASTError::MisplacedStatement(Keyword::Default)))?;
+                if std::mem::replace(&mut switch.default, true) {
+                    return Err(error(ASTError::DuplicateDefault));
+                }
+                self.statement(statement, context)
+            }
+            Statement::Break(written) if self.switches.is_empty() => {
+                Err(ASTError::MisplacedStatement(Keyword::Break).at(written.as_ref()))
+            }
+            Statement::Break(_) => Ok(()),
+            Statement::Label(_, statement, _) => self.statement(statement, context),
+            Statement::Goto(name, _) if self.labels.contains(name) => Ok(()),
+            Statement::Goto(name, written) => Err(ASTError::UndefinedLabel(name.clone()).at(written.as_ref())),
+        }
+    }
+
+    /// Check the initialiser of a variable and declare it. A variable with static storage is
+    /// initialised before the program runs, so its initialiser has to be a constant. An `extern`
+    /// one has to agree with any global of the same name.
+    fn variable(&mut self, symbol: &mut Rc<Symbol>, initializer: &mut Option<ASTNode>) -> Result<(), ASTError> {
+        if symbol.ty == Type::Void {
+            return Err(ASTError::VoidVariable(symbol.name.clone()));
+        }
+        if is_external(symbol) {
+            if self.scopes[0].get(&symbol.name).is_some_and(|global| global.ty != symbol.ty) {
+                return Err(ASTError::Redeclaration(symbol.name.clone()));
+            }
+            return self.declare(symbol.clone());
+        }
+        // The initialiser is checked first, `int x = x;` refers to an outer `x`
+        if let Some(value) = initializer.take() {
+            let value = self.initial_value(value, &symbol.ty)?;
+            if symbol.is_static() && !is_constant_initializer(&value) {
+                return Err(ASTError::NotConstant(symbol.name.clone()));
+            }
+            // The code generator only stores single values into locals
+            if !symbol.is_static() && is_aggregate_initializer(&value) {
+                return Err(ASTError::UnsupportedInitializer(symbol.name.clone()));
+            }
+            complete_type(symbol, &value);
+            *initializer = Some(value);
+        }
+        self.declare_variable(symbol.clone())
+    }
+
+    /// Check the initialiser of an object of type `ty`: an expression converted to the type, or for
+    /// an array or a record a `{ ... }` list, with a list for each member or element that is an
+    /// aggregate itself, even where the program leaves out the braces. A `char` array can also
+    /// take a string literal, whose node gets the type of the array. An array without a length
+    /// gets it from the initialiser, in the type of the result.
+    fn initial_value(&mut self, value: ASTNode, ty: &Type) -> Result<ASTNode, ASTError> {
+        match (&value.operation, ty) {
+            (Token::InitializerList, _) => self.braced(value, ty),
+            (Token::STRING(bytes), Type::Array(element, length)) if element.is_character() => {
+                // The terminating NUL is left out when the array only has room for the characters
+                let length = match *length {
+                    0 => bytes.len() + 1,
+                    length if bytes.len() <= length => length,
+                    _ => return Err(ASTError::TooManyInitializers),
+                };
+                let ty = (**element).clone().array_of(length);
+                Ok(ASTNode { ty: Some(ty), ..value })
+            }
+            _ => self.converted(value, ty),
+        }
+    }
+
+    /// Check a `{ ... }` initialiser for an object of type `ty`, every element has to be used
+    fn braced(&mut self, list: ASTNode, ty: &Type) -> Result<ASTNode, ASTError> {
+        let mut elements = list.into_arguments().into_iter().peekable();
+        let string = matches!(elements.peek(), Some(ASTNode { operation: Token::STRING(_), .. }));
+        let value = match ty {
+            // `char s[] = {"ab"};` is the same as `char s[] = "ab";`
+            Type::Array(element, _) if element.is_character() && string => self.subobject(&mut elements, ty)?,
+            Type::Array(..) | Type::Record(_) => self.aggregate(&mut elements, ty)?,
+            // `int x = { 1 };` is the same as `int x = 1;`
+            _ => self.subobject(&mut elements, ty)?,
+        };
+        match elements.next() {
+            Some(_) => Err(ASTError::TooManyInitializers),
+            None => Ok(value),
+        }
+    }
+
+    /// The list for the array or record `ty`, with values for its elements or members in order
+    /// from `elements`, as far as they go. The rest of the object is zero.
+    fn aggregate(&mut self, elements: &mut Elements, ty: &Type) -> Result<ASTNode, ASTError> {
+        let mut values = Vec::new();
+        let ty = match ty {
+            Type::Array(element, length) => {
+                while elements.peek().is_some() && (*length == 0 || values.len() < *length) {
+                    values.push(self.subobject(elements, element)?);
+                }
+                match length {
+                    0 => (**element).clone().array_of(values.len()),
+                    _ => ty.clone(),
+                }
+            }
+            Type::Record(record) if record.is_complete() => {
+                for member in record.initialized_members() {
+                    if elements.peek().is_none() {
+                        break;
+                    }
+                    values.push(self.subobject(elements, &member.ty)?);
+                }
+                ty.clone()
+            }
+            _ => return Err(ASTError::IncompleteType(ty.clone())),
+        };
+        Ok(ASTNode::initializer_list(values, Some(ty)))
+    }
+
+    /// The value for a member or element of type `ty`. A list or a string literal for a `char` array
+    /// is its whole value, otherwise an aggregate takes as many of `elements` as it needs (C11 6.7.9).
+    fn subobject(&mut self, elements: &mut Elements, ty: &Type) -> Result<ASTNode, ASTError> {
+        let Some(element) = elements.peek() else {
+            return Err(ASTError::EmptyExpression);
+        };
+        let whole = match ty {
+            Type::Array(inner, _) if inner.is_character() => {
+                matches!(element.operation, Token::InitializerList | Token::STRING(_))
+            }
+            Type::Array(..) | Type::Record(_) => element.operation == Token::InitializerList,
+            _ => true,
+        };
+        if !whole {
+            return self.aggregate(elements, ty);
+        }
+        let element = elements.next().ok_or(ASTError::EmptyExpression)?;
+        self.initial_value(element, ty)
+    }
+
+    /// Check `value` and convert it to `ty` as if by assignment
+    fn converted(&mut self, mut value: ASTNode, ty: &Type) -> Result<ASTNode, ASTError> {
+        self.rvalue(&mut value)?;
+        let position = value.position.clone();
+        assigned(value, ty).map_err(|error| error.at(position.as_ref()))
+    }
+
+    /// Check an expression whose value is used. An array used as a value is the address
+    /// of its first element, and a function the address of the function, which is the
+    /// only place `&` and `sizeof` do not apply this.
+    fn rvalue(&mut self, node: &mut ASTNode) -> Result<(), ASTError> {
+        self.expression(node)?;
+        if let Some(ty @ (Type::Array(..) | Type::Function { .. })) = &node.ty {
+            let pointer = ty.decayed();
+            node.wrap(Token::AddressOf, pointer);
+        }
+        Ok(())
+    }
+
+    /// Check an expression, errors are reported where the innermost node they come from was written
+    fn expression(&mut self, node: &mut ASTNode) -> Result<(), ASTError> {
+        let position = node.position.clone();
+        self.operation(node).map_err(|error| error.at(position.as_ref()))
+    }
+
+    fn operation(&mut self, node: &mut ASTNode) -> Result<(), ASTError> {
+        match &node.operation {
+            Token::IDENTIFIER(name) => {
+                let symbol = self
+                    .lookup(name)
+                    .ok_or_else(|| ASTError::UndeclaredIdentifier(name.clone()))?
+                    .clone();
+                node.ty = Some(symbol.ty.clone());
+                node.symbol = Some(symbol);
+                Ok(())
+            }
+            Token::Cast => {
+                let operand = node.left.as_mut().ok_or(ASTError::ExpectedInteger)?;
+                self.rvalue(operand)?;
+                let from = operand.ty.clone().unwrap_or(Type::Void);
+                let to = node.ty.clone().unwrap_or(Type::Void);
+                // Anything can be thrown away with `(void)`, otherwise both sides have to be scalars,
+                // and pointers only convert to and from integers
+                let pointer_and_floating = (from.pointee().is_some() && to.is_floating())
+                    || (from.is_floating() && to.pointee().is_some());
+                if to == Type::Void || (from.is_scalar() && to.is_scalar() && !pointer_and_floating) {
+                    Ok(())
+                } else {
+                    Err(ASTError::InvalidConversion(from, to))
+                }
+            }
+            Token::Sizeof => {
+                let operand = node.left.as_mut().ok_or(ASTError::ExpectedInteger)?;
+                self.expression(operand)?;
+                // The operand is never evaluated, only its type matters
+                let ty = operand.ty.clone().unwrap_or(Type::Void);
+                if !ty.is_complete() {
+                    return Err(ASTError::InvalidOperand(Token::Sizeof, ty));
+                }
+                *node = ASTNode::make_leaf(Token::ULONG(ty.size() as u64))?;
+                node.ty = Some(Type::UnsignedLong);
+                Ok(())
+            }
+            Token::DOT => {
+                let (Some(record), Some(member)) = (node.left.as_mut(), node.right.as_ref()) else {
+                    return Err(ASTError::ExpectedIdentifier);
+                };
+                self.expression(record)?;
+                let record_type = record.ty.clone().unwrap_or(Type::Void);
+                let Type::Record(definition) = &record_type else {
+                    return Err(ASTError::InvalidOperand(Token::DOT, record_type));
+                };
+                if !definition.is_complete() {
+                    return Err(ASTError::IncompleteType(record_type));
+                }
+                let Token::IDENTIFIER(name) = &member.operation else {
+                    return Err(ASTError::ExpectedIdentifier);
+                };
+                let member = definition.member(name).ok_or_else(|| ASTError::UnknownMember(name.clone()))?;
+                member_access(node, member)
+            }
+            Token::Call => self.call(node),
+            Token::VaStart | Token::VaArg | Token::VaEnd | Token::VaCopy => self.variadic_builtin(node),
+            Token::VariadicArguments => {
+                node.ty = Some(self.architecture.va_list());
+                Ok(())
+            }
+            Token::LOGAND | Token::LOGOR => {
+                let (Some(left), Some(right)) = (node.left.as_mut(), node.right.as_mut()) else {
+                    return Err(ASTError::ExpectedInteger);
+                };
+                self.rvalue(left)?;
+                self.rvalue(right)?;
+                // Each operand is compared with 0 on its own, they are not converted to a common type
+                let (left_type, right_type) = (left.ty.clone().unwrap_or(Type::Void), right.ty.clone().unwrap_or(Type::Void));
+                if !left_type.is_scalar() || !right_type.is_scalar() {
+                    return Err(ASTError::InvalidOperands(node.operation.clone(), left_type, right_type));
+                }
+                node.ty = Some(Type::Int);
+                Ok(())
+            }
+            Token::QUESTION => self.conditional(node),
+            operation if operation.compound_operator().is_some() => self.compound_assignment(node),
+            Token::AddressOf | Token::Dereference => {
+                let operation = node.operation.clone();
+                let operand = node.left.as_mut().ok_or(ASTError::ExpectedInteger)?;
+                if operation == Token::AddressOf {
+                    self.expression(operand)?;
+                } else {
+                    self.rvalue(operand)?;
+                }
+                let operand_type = operand.ty.clone().unwrap_or(Type::Void);
+                node.ty = Some(match operation {
+                    Token::AddressOf if operand.is_lvalue() => operand_type.pointer_to(),
+                    Token::AddressOf => return Err(ASTError::ExpectedLvalue),
+                    _ => match operand_type.pointee() {
+                        Some(pointee) if *pointee != Type::Void => pointee.clone(),
+                        _ => return Err(ASTError::InvalidOperand(operation, operand_type)),
+                    },
+                });
+                Ok(())
+            }
+            Token::Negate | Token::UnaryPlus | Token::INVERT | Token::LOGNOT => self.unary(node),
+            Token::ASSIGN
+            | Token::EQ
+            | Token::NE
+            | Token::LT
+            | Token::GT
+            | Token::LE
+            | Token::GE
+            | Token::PLUS
+            | Token::MINUS
+            | Token::ASTERISK
+            | Token::SLASH
+            | Token::PERCENT
+            | Token::AMPER
+            | Token::OR
+            | Token::XOR
+            | Token::LSHIFT
+            | Token::RSHIFT => {
+                let (Some(left), Some(right)) = (node.left.as_mut(), node.right.as_mut()) else {
+                    return Err(ASTError::ExpectedInteger);
+                };
+                // The left side of an assignment is the object itself, not its value
+                if node.operation == Token::ASSIGN {
+                    self.expression(left)?;
+                } else {
+                    self.rvalue(left)?;
+                }
+                self.rvalue(right)?;
+                binary(node)
+            }
+            literal => match Type::of_literal(literal) {
+                Some(ty) => {
+                    node.ty = Some(ty);
+                    Ok(())
+                }
+                None => Err(ASTError::UnexpectedToken(literal.clone())),
+            },
+        }
+    }
+
+    /// Check `-a`, `+a`, `~a` and `!a` (C11 6.5.3.3). The first three promote their operand,
+    /// `~` only takes integers. `!` takes any scalar, compares it with 0 and makes an int.
+    fn unary(&mut self, node: &mut ASTNode) -> Result<(), ASTError> {
+        let operation = node.operation.clone();
+        let operand = node.left.as_mut().ok_or(ASTError::ExpectedInteger)?;
+        self.rvalue(operand)?;
+        let operand_type = operand.ty.clone().unwrap_or(Type::Void);
+        let valid = match operation {
+            Token::INVERT => operand_type.is_integer(),
+            Token::LOGNOT => operand_type.is_scalar(),
+            _ => operand_type.is_arithmetic(),
+        };
+        if !valid {
+            return Err(ASTError::InvalidOperand(operation, operand_type));
+        }
+
+        match operation {
+            Token::LOGNOT => node.ty = Some(Type::Int),
+            // Converting is all `+` does
+            Token::UnaryPlus => {
+                let operand = node.left.take().ok_or(ASTError::ExpectedInteger)?;
+                *node = ASTNode::cast(*operand, operand_type.promoted());
+            }
+            _ => {
+                let ty = operand_type.promoted();
+                convert_operand(&mut node.left, &ty);
+                node.ty = Some(ty);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check `c ? a : b`. Both results are converted to the type of the whole expression,
+    /// which follows from the types of the two (C11 6.5.15).
+    fn conditional(&mut self, node: &mut ASTNode) -> Result<(), ASTError> {
+        let (Some(condition), Some(results)) = (node.left.as_mut(), node.right.as_mut()) else {
+            return Err(ASTError::ExpectedInteger);
+        };
+        self.rvalue(condition)?;
+        let condition_type = condition.ty.clone().unwrap_or(Type::Void);
+        if !condition_type.is_scalar() {
+            return Err(ASTError::InvalidOperand(Token::QUESTION, condition_type));
+        }
+
+        let (Some(when_true), Some(when_false)) = (results.left.as_mut(), results.right.as_mut()) else {
+            return Err(ASTError::ExpectedInteger);
+        };
+        self.rvalue(when_true)?;
+        self.rvalue(when_false)?;
+        let true_type = when_true.ty.clone().unwrap_or(Type::Void);
+        let false_type = when_false.ty.clone().unwrap_or(Type::Void);
+        let ty = match (true_type.pointee(), false_type.pointee()) {
+            _ if true_type.is_arithmetic() && false_type.is_arithmetic() => Type::common(&true_type, &false_type),
+            _ if true_type == false_type => true_type.clone(),
+            // Any pointer goes with `void *`, the result only points to void
+            (Some(Type::Void), Some(_)) => true_type.clone(),
+            (Some(_), Some(Type::Void)) => false_type.clone(),
+            (Some(_), None) if is_null_pointer_constant(Some(when_false)) => true_type.clone(),
+            (None, Some(_)) if is_null_pointer_constant(Some(when_true)) => false_type.clone(),
+            _ => return Err(ASTError::InvalidOperands(Token::COLON, true_type, false_type)),
+        };
+        convert_operand(&mut results.left, &ty);
+        convert_operand(&mut results.right, &ty);
+        results.ty = Some(ty.clone());
+        node.ty = Some(ty);
+        Ok(())
+    }
+
+    /// Check `a op= b`, or `a++` and `a--`, which add or subtract 1 the same way. The operation
+    /// is typed as `a op b` would be, the right operand ends up with the type it is done in, or
+    /// converted to a number of bytes for a pointer. The left side is converted to that type,
+    /// and the result back to the type of the left side, by the code generator, as the left
+    /// side is only evaluated once.
+    fn compound_assignment(&mut self, node: &mut ASTNode) -> Result<(), ASTError> {
+        let operator = node.operation.compound_operator().ok_or(ASTError::ExpectedOperator)?;
+        let (Some(target), Some(value)) = (node.left.as_mut(), node.right.take()) else {
+            return Err(ASTError::ExpectedInteger);
+        };
+        self.expression(target)?;
+        let target_type = target.ty.clone().unwrap_or(Type::Void);
+        if !target.is_lvalue() || matches!(target_type, Type::Array(..) | Type::Function { .. }) {
+            return Err(ASTError::ExpectedLvalue);
+        }
+
+        let mut value = *value;
+        self.rvalue(&mut value)?;
+        let value_type = value.ty.clone().unwrap_or(Type::Void);
+        let mut operation = ASTNode::new(Ok(operator), target.clone(), Box::new(value))?;
+        binary(&mut operation).map_err(|error| match error {
+            ASTError::InvalidOperands(_, left, right) => ASTError::InvalidOperands(node.operation.clone(), left, right),
+            error => error,
+        })?;
+        // `i += p` would make a pointer, and `p -= q` a difference, neither goes back into the left side
+        let result_type = operation.ty.unwrap_or(Type::Void);
+        let fits = match target_type.pointee() {
+            Some(_) => result_type == target_type,
+            None => result_type.is_arithmetic(),
+        };
+        if !fits {
+            return Err(ASTError::InvalidOperands(node.operation.clone(), target_type, value_type));
+        }
+        node.right = operation.right;
+        // A constant amount, like the 1 of `p++`, is scaled to bytes now rather than when the program runs
+        if target_type.pointee().is_some() {
+            if let Some(Constant::Integer(bytes)) = node.right.as_deref().and_then(evaluate_constant) {
+                node.right = Some(Box::new(typed_leaf(Token::LONG(bytes), Type::Long)));
+            }
+        }
+        node.ty = Some(target_type);
+        Ok(())
+    }
+
+    /// Check a call, converting the arguments to the types of the parameters as if by assignment.
+    /// The arguments a variadic function has no parameters for get the default argument
+    /// promotions. A function that is not declared is taken to return `int` and to have
+    /// parameters of the promoted types of the arguments, as in C89, which lets programs
+    /// call into the C library without its headers. Anything but the name of a function is
+    /// called through a pointer to one.
+    fn call(&mut self, node: &mut ASTNode) -> Result<(), ASTError> {
+        let found = node.arguments().len();
+        let function = node.left.as_mut().ok_or(ASTError::ExpectedIdentifier)?;
+        if let Token::IDENTIFIER(name) = &function.operation {
+            if self.lookup(name).is_none() {
+                let name = name.clone();
+                return self.implicit_call(node, name);
+            }
+        }
+        self.expression(function)?;
+        if !matches!(function.operation, Token::IDENTIFIER(_)) {
+            if let Some(ty @ Type::Function { .. }) = &function.ty {
+                let pointer = ty.decayed();
+                function.wrap(Token::AddressOf, pointer);
+            }
+        }
+        let callee = function.ty.clone().unwrap_or(Type::Void);
+        let function_type = match &callee {
+            Type::Pointer(pointee) => (**pointee).clone(),
+            ty => ty.clone(),
+        };
+        let Type::Function { return_type, parameters, variadic } = function_type else {
+            return Err(ASTError::NotAFunction(callee));
+        };
+
+        if found < parameters.len() || (found > parameters.len() && !variadic) {
+            let function = match &function.operation {
+                Token::IDENTIFIER(name) => name.clone(),
+                _ => String::new(),
+            };
+            return Err(ASTError::ArgumentCount {
+                function,
+                expected: parameters.len(),
+                found,
+            });
+        }
+        let mut argument = node.right.as_deref_mut();
+        let mut parameters = parameters.iter();
+        while let Some(current) = argument {
+            let value = current.left.take().ok_or(ASTError::EmptyExpression)?;
+            let value = match parameters.next() {
+                Some(parameter) => self.converted(*value, parameter)?,
+                None => self.promoted_argument(*value)?,
+            };
+            current.left = Some(Box::new(value));
+            argument = current.right.as_deref_mut();
+        }
+
+        // A struct or union is returned into memory the caller provides
+        if return_type.is_record() {
+            node.symbol = Some(self.temporary((*return_type).clone()));
+        }
+        node.ty = Some(*return_type);
+        Ok(())
+    }
+
+    /// Check `va_start`, `va_arg`, `va_end` or `va_copy` and lower it to what it does with a
+    /// `va_list` pointing to the next variadic argument on the stack: `va_start` assigns it
+    /// [`Token::VariadicArguments`], `va_arg` moves it past the argument and loads the value
+    /// from where it was, `va_end` does nothing and `va_copy` is an assignment. A struct or
+    /// union larger than 16 bytes is found by the address passed in its place.
+    ///
+    /// Where the `va_list` is a struct, [`Token::VariadicArguments`] is one the code generator
+    /// fills in, and `va_arg` stays as it is for the code generator. A struct or union it loads
+    /// is copied into a temporary local, the [`ASTNode::symbol`] of the node.
+    fn variadic_builtin(&mut self, node: &mut ASTNode) -> Result<(), ASTError> {
+        let operation = node.operation.clone();
+        let mut list = node.left.take().ok_or(ASTError::EmptyExpression)?;
+        let mut lowered = match operation {
+            Token::VaStart => {
+                if !self.variadic {
+                    return Err(ASTError::NotVariadic);
+                }
+                // The arguments are found without the last parameter, but it still has to make sense
+                self.rvalue(node.right.as_mut().ok_or(ASTError::EmptyExpression)?)?;
+                let start = ASTNode {
+                    operation: Token::VariadicArguments,
+                    left: None,
+                    right: None,
+                    ty: None,
+                    symbol: None,
+                    position: None,
+                };
+                ASTNode::new(Ok(Token::ASSIGN), list, Box::new(start))?
+            }
+            Token::VaArg => {
+                let ty = node.ty.take().unwrap_or(Type::Void);
+                if !ty.is_complete() {
+                    return Err(ASTError::IncompleteType(ty));
+                }
+                if matches!(ty, Type::Array(..)) {
+                    return Err(ASTError::InvalidOperand(Token::VaArg, ty));
+                }
+                if self.architecture.va_list().is_record() {
+                    self.expression(&mut list)?;
+                    let list_type = list.ty.clone().unwrap_or(Type::Void);
+                    if list_type != self.architecture.va_list() {
+                        return Err(ASTError::InvalidOperand(operation, list_type));
+                    }
+                    if !list.is_lvalue() {
+                        return Err(ASTError::ExpectedLvalue);
+                    }
+                    if ty.is_record() {
+                        node.symbol = Some(self.temporary(ty.clone()));
+                    }
+                    node.left = Some(list);
+                    node.ty = Some(ty);
+                    return Ok(());
+                }
+                let indirect = ty.is_record() && ty.size() > 16;
+                let slot = if indirect { 8 } else { ty.size().next_multiple_of(8) };
+                let next = ASTNode::new(Ok(Token::PostIncrement), list, Box::new(ASTNode::make_leaf(Token::LONG(slot as i64))?))?;
+                let address = match indirect {
+                    true => ASTNode::unary(Token::Dereference, ASTNode::cast(next, ty.clone().pointer_to().pointer_to())),
+                    false => ASTNode::cast(next, ty.clone().pointer_to()),
+                };
+                ASTNode::unary(Token::Dereference, address)
+            }
+            Token::VaEnd => *list,
+            _ => ASTNode::new(Ok(Token::ASSIGN), list, node.right.take().ok_or(ASTError::EmptyExpression)?)?,
+        };
+        self.rvalue(&mut lowered)?;
+
+        // Whatever the lowering did to it, the operand has to be a `va_list`
+        let mut list = &lowered;
+        if operation != Token::VaEnd {
+            while !matches!(list.operation, Token::ASSIGN | Token::PostIncrement) {
+                list = list.left.as_deref().expect("The lowering keeps the list on the left");
+            }
+            list = list.left.as_deref().expect("The list is the left operand");
+        }
+        let list_type = list.ty.clone().unwrap_or(Type::Void);
+        if list_type != self.architecture.va_list() {
+            return Err(ASTError::InvalidOperand(operation, list_type));
+        }
+
+        *node = match operation {
+            Token::VaArg => lowered,
+            _ => ASTNode::cast(lowered, Type::Void),
+        };
+        Ok(())
+    }
+
+    /// Check an argument that matches no parameter, which gets the default argument promotions
+    fn promoted_argument(&mut self, mut value: ASTNode) -> Result<ASTNode, ASTError> {
+        self.rvalue(&mut value)?;
+        let from = value.ty.clone().unwrap_or(Type::Void);
+        if !from.is_complete() {
+            return Err(ASTError::IncompleteType(from));
+        }
+        assigned(value, &from.argument_promoted())
+    }
+
+    /// Check a call of the function `name`, which is not declared
+    fn implicit_call(&mut self, node: &mut ASTNode, name: String) -> Result<(), ASTError> {
+        let mut parameters = Vec::new();
+        let mut argument = node.right.as_deref_mut();
+        while let Some(current) = argument {
+            let value = current.left.take().ok_or(ASTError::EmptyExpression)?;
+            let value = self.promoted_argument(*value)?;
+            parameters.push(value.ty.clone().unwrap_or(Type::Void));
+            current.left = Some(Box::new(value));
+            argument = current.right.as_deref_mut();
+        }
+
+        let ty = Type::Function {
+            return_type: Box::new(Type::Int),
+            parameters,
+            variadic: false,
+        };
+        let function = node.left.as_mut().expect("Checked by the caller");
+        function.ty = Some(ty.clone());
+        function.symbol = Some(Rc::new(Symbol {
+            name: name.clone(),
+            ty,
+            slot: 0,
+            storage: Storage::Static { label: name, external: true },
+        }));
+        node.ty = Some(Type::Int);
+        Ok(())
+    }
+}
+
+/// Add the labels in `statement` to `labels`, a name can only label one statement in a function
+fn collect_labels(statement: &Statement, labels: &mut HashSet<String>) -> Result<(), ASTError> {
+    match statement {
+        Statement::Label(name, statement, written) => {
+            if !labels.insert(name.clone()) {
+                return Err(ASTError::DuplicateLabel(name.clone()).at(written.as_ref()));
+            }
+            collect_labels(statement, labels)
+        }
+        Statement::Block(statements) => statements.iter().try_for_each(|statement| collect_labels(statement, labels)),
+        Statement::Switch { body: statement, .. } | Statement::Case(_, statement, _) | Statement::Default(statement, _) => {
+            collect_labels(statement, labels)
+        }
+        Statement::Expression(_) | Statement::Declaration(..) | Statement::Return(..) | Statement::Break(_) | Statement::Goto(..) => {
+            Ok(())
+        }
+    }
+}
+
+/// The value of a constant expression, as known before the program runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Integer(i64),
+    /// A `float` or `double`, a `float` holds a value it can represent
+    Float(f64),
+    /// The address of a variable or function with static storage, plus a number of bytes
+    SymbolAddress(Rc<Symbol>, i64),
+    /// The address of a string literal, plus a number of bytes
+    StringAddress(Vec<u8>, i64),
+}
+
+impl Constant {
+    /// The constant moved by `bytes`, which only makes sense for an address
+    fn offset(self, bytes: i64) -> Option<Constant> {
+        match self {
+            Constant::Integer(_) | Constant::Float(_) => None,
+            Constant::SymbolAddress(symbol, offset) => Some(Constant::SymbolAddress(symbol, offset.wrapping_add(bytes))),
+            Constant::StringAddress(string, offset) => Some(Constant::StringAddress(string, offset.wrapping_add(bytes))),
+        }
+    }
+}
+
+/// Evaluate an expression that has been type checked, if it is a constant: arithmetic on
+/// integer or floating constants, or an address with static storage plus or minus an integer constant.
+/// Those are what variables with static storage can be initialised with.
+///
+/// # Examples
+///
+/// ```
+/// # use compiler::ast::ASTNode;
+/// # use compiler::scan::Lexer;
+/// # use compiler::typecheck::*;
+/// let mut node = ASTNode::parse_stream(&mut Lexer::new("(unsigned char) 300 + 1".as_bytes())).unwrap();
+/// check_expression(&mut node).unwrap();
+/// assert_eq!(evaluate_constant(&node), Some(Constant::Integer(45)));
+///
+/// let mut node = ASTNode::parse_stream(&mut Lexer::new("\"abc\" + 2".as_bytes())).unwrap();
+/// check_expression(&mut node).unwrap();
+/// assert_eq!(evaluate_constant(&node), Some(Constant::StringAddress(b"abc".to_vec(), 2)));
+///
+/// let mut node = ASTNode::parse_stream(&mut Lexer::new("(int) (7 / 2.0)".as_bytes())).unwrap();
+/// check_expression(&mut node).unwrap();
+/// assert_eq!(evaluate_constant(&node), Some(Constant::Integer(3)));
+/// ```
+pub fn evaluate_constant(node: &ASTNode) -> Option<Constant> {
+    let ty = node.ty.as_ref()?;
+    if let Some(value) = node.operation.integer_value() {
+        return Some(Constant::Integer(wrapped(value, ty)));
+    }
+
+    match node.operation {
+        Token::FLOAT(bits) => Some(Constant::Float(f32::from_bits(bits) as f64)),
+        Token::DOUBLE(bits) => Some(Constant::Float(f64::from_bits(bits))),
+        Token::Cast => match evaluate_constant(node.left.as_ref()?)? {
+            Constant::Integer(value) if ty.is_floating() => {
+                let unsigned = node.left.as_ref()?.ty.as_ref().is_some_and(|from| !from.is_signed());
+                Some(Constant::Float(rounded(if unsigned { value as u64 as f64 } else { value as f64 }, ty)))
+            }
+            Constant::Integer(value) if ty.is_scalar() => Some(Constant::Integer(wrapped(value, ty))),
+            Constant::Float(value) if ty.is_floating() => Some(Constant::Float(rounded(value, ty))),
+            Constant::Float(value) if ty.is_integer() => float_to_integer(value, ty).map(Constant::Integer),
+            // An address only survives a conversion to something as wide as a pointer
+            address if ty.is_scalar() && ty.size() == 8 => Some(address),
+            _ => None,
+        },
+        Token::AddressOf => constant_address(node.left.as_ref()?),
+        Token::Negate => match evaluate_constant(node.left.as_ref()?)? {
+            Constant::Integer(value) => Some(Constant::Integer(wrapped(value.wrapping_neg(), ty))),
+            Constant::Float(value) => Some(Constant::Float(-value)),
+            _ => None,
+        },
+        Token::INVERT => match evaluate_constant(node.left.as_ref()?)? {
+            Constant::Integer(value) => Some(Constant::Integer(wrapped(!value, ty))),
+            _ => None,
+        },
+        Token::LOGNOT => Some(Constant::Integer(!is_true(evaluate_constant(node.left.as_ref()?)?)? as i64)),
+        // The right operand does not have to be constant when the left one decides the result
+        Token::LOGAND | Token::LOGOR => {
+            let decided = node.operation == Token::LOGOR;
+            let left = is_true(evaluate_constant(node.left.as_ref()?)?)?;
+            let value = if left == decided { decided } else { is_true(evaluate_constant(node.right.as_ref()?)?)? };
+            Some(Constant::Integer(value as i64))
+        }
+        Token::QUESTION => {
+            let results = node.right.as_ref()?;
+            match is_true(evaluate_constant(node.left.as_ref()?)?)? {
+                true => evaluate_constant(results.left.as_ref()?),
+                false => evaluate_constant(results.right.as_ref()?),
+            }
+        }
+        Token::PLUS
+        | Token::MINUS
+        | Token::ASTERISK
+        | Token::SLASH
+        | Token::PERCENT
+        | Token::AMPER
+        | Token::OR
+        | Token::XOR
+        | Token::LSHIFT
+        | Token::RSHIFT
+        | Token::EQ
+        | Token::NE
+        | Token::LT
+        | Token::GT
+        | Token::LE
+        | Token::GE => {
+            let (left, right) = (node.left.as_ref()?, node.right.as_ref()?);
+            let operand_type = left.ty.as_ref()?;
+            match (&node.operation, evaluate_constant(left)?, evaluate_constant(right)?) {
+                (operation, Constant::Integer(left), Constant::Integer(right)) => {
+                    integer_operation(operation, left, right, operand_type).map(|value| Constant::Integer(wrapped(value, ty)))
+                }
+                (operation, Constant::Float(left), Constant::Float(right)) => float_operation(operation, left, right, ty),
+                // Pointer arithmetic has been scaled to bytes already
+                (Token::PLUS, address, Constant::Integer(bytes)) | (Token::PLUS, Constant::Integer(bytes), address) => {
+                    address.offset(bytes)
+                }
+                (Token::MINUS, address, Constant::Integer(bytes)) => address.offset(bytes.wrapping_neg()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The address of an lvalue, if it is a constant
+fn constant_address(node: &ASTNode) -> Option<Constant> {
+    match (&node.operation, &node.symbol) {
+        (Token::IDENTIFIER(_), Some(symbol)) if symbol.is_static() => Some(Constant::SymbolAddress(symbol.clone(), 0)),
+        (Token::STRING(bytes), _) => Some(Constant::StringAddress(bytes.clone(), 0)),
+        (Token::Dereference, _) => evaluate_constant(node.left.as_ref()?),
+        _ => None,
+    }
+}
+
+/// Whether a constant is true as a condition, `None` for an address
+fn is_true(constant: Constant) -> Option<bool> {
+    match constant {
+        Constant::Integer(value) => Some(value != 0),
+        Constant::Float(value) => Some(value != 0.0),
+        Constant::SymbolAddress(..) | Constant::StringAddress(..) => None,
+    }
+}
+
+/// Apply a binary operator to two integer constants of type `ty`, `None` when the result is undefined
+fn integer_operation(operation: &Token, left: i64, right: i64, ty: &Type) -> Option<i64> {
+    let signed = ty.is_signed();
+    // Both are sign or zero extended from `ty` already, as the type demands
+    let ordering = if signed { left.cmp(&right) } else { (left as u64).cmp(&(right as u64)) };
+    Some(match operation {
+        Token::PLUS => left.wrapping_add(right),
+        Token::MINUS => left.wrapping_sub(right),
+        Token::ASTERISK => left.wrapping_mul(right),
+        Token::SLASH if signed => left.checked_div(right)?,
+        Token::SLASH => (left as u64).checked_div(right as u64)? as i64,
+        Token::PERCENT if signed => left.checked_rem(right)?,
+        Token::PERCENT => (left as u64).checked_rem(right as u64)? as i64,
+        Token::AMPER => left & right,
+        Token::OR => left | right,
+        Token::XOR => left ^ right,
+        // Shifting by the width of the type or more is undefined
+        Token::LSHIFT | Token::RSHIFT if !(0..8 * ty.size() as i64).contains(&right) => return None,
+        Token::LSHIFT => left << right,
+        Token::RSHIFT if signed => left >> right,
+        Token::RSHIFT => ((left as u64) >> right) as i64,
+        Token::EQ => ordering.is_eq() as i64,
+        Token::NE => ordering.is_ne() as i64,
+        Token::LT => ordering.is_lt() as i64,
+        Token::GT => ordering.is_gt() as i64,
+        Token::LE => ordering.is_le() as i64,
+        Token::GE => ordering.is_ge() as i64,
+        _ => return None,
+    })
+}
+
+/// Apply a binary operator to two floating constants, giving a result of type `ty`
+fn float_operation(operation: &Token, left: f64, right: f64, ty: &Type) -> Option<Constant> {
+    let compared = |result: bool| Some(Constant::Integer(result as i64));
+    match operation {
+        Token::PLUS => Some(Constant::Float(rounded(left + right, ty))),
+        Token::MINUS => Some(Constant::Float(rounded(left - right, ty))),
+        Token::ASTERISK => Some(Constant::Float(rounded(left * right, ty))),
+        Token::SLASH => Some(Constant::Float(rounded(left / right, ty))),
+        Token::EQ => compared(left == right),
+        Token::NE => compared(left != right),
+        Token::LT => compared(left < right),
+        Token::GT => compared(left > right),
+        Token::LE => compared(left <= right),
+        Token::GE => compared(left >= right),
+        _ => None,
+    }
+}
+
+/// A floating value converted to the floating type `ty`
+fn rounded(value: f64, ty: &Type) -> f64 {
+    match ty {
+        Type::Float => value as f32 as f64,
+        _ => value,
+    }
+}
+
+/// A floating value converted to the integer type `ty` by dropping its fraction,
+/// `None` when the result does not fit, which is undefined
+fn float_to_integer(value: f64, ty: &Type) -> Option<i64> {
+    let value = value.trunc();
+    let bits = 8 * ty.size() as i32;
+    let (min, max) = match ty.is_signed() {
+        true => (-(2f64.powi(bits - 1)), 2f64.powi(bits - 1)),
+        false => (0.0, 2f64.powi(bits)),
+    };
+    if !(min..max).contains(&value) {
+        return None;
+    }
+    Some(if ty.is_signed() { value as i64 } else { value as u64 as i64 })
+}
+
+/// An integer converted to the type `ty`, keeping as many of its low bits as fit
+fn wrapped(value: i64, ty: &Type) -> i64 {
+    let unused = 64 - 8 * ty.size().clamp(1, 8) as u32;
+    if ty.is_signed() {
+        (value << unused) >> unused
+    } else {
+        ((value as u64) << unused >> unused) as i64
+    }
+}
+
+/// Turn `s.m` into `*(type of m *)((char *) &s + offset of m)`, so the code generator
+/// only sees pointers. A struct or union that is not an lvalue, like the result of a call,
+/// is still kept in memory, and its address is taken the same way.
+fn member_access(node: &mut ASTNode, member: Member) -> Result<(), ASTError> {
+    let record = node.left.take().ok_or(ASTError::ExpectedIdentifier)?;
+    let record_type = record.ty.clone().unwrap_or(Type::Void);
+    let mut address = ASTNode::unary(Token::AddressOf, *record);
+    address.ty = Some(record_type.pointer_to());
+
+    let bytes = Type::Char.pointer_to();
+    let mut address = ASTNode::cast(address, bytes.clone());
+    if member.offset > 0 {
+        let offset = typed_leaf(Token::LONG(member.offset as i64), Type::Long);
+        address = ASTNode::new(Ok(Token::PLUS), Box::new(address), Box::new(offset))?;
+        address.ty = Some(bytes);
+    }
+
+    *node = ASTNode::unary(Token::Dereference, ASTNode::cast(address, member.ty.clone().pointer_to()));
+    node.ty = Some(member.ty);
+    Ok(())
+}
+
+/// Type a binary operator whose operands have been checked, converting them as the operator requires
+fn binary(node: &mut ASTNode) -> Result<(), ASTError> {
+    let operand_type = |operand: &Option<Box<ASTNode>>| operand.as_ref().and_then(|operand| operand.ty.clone());
+    let left_type = operand_type(&node.left).unwrap_or(Type::Void);
+    let right_type = operand_type(&node.right).unwrap_or(Type::Void);
+    let invalid = || ASTError::InvalidOperands(node.operation.clone(), left_type.clone(), right_type.clone());
+
+    if node.operation == Token::ASSIGN {
+        // Arrays and functions are lvalues, but there is no assigning to them
+        let assignable = !matches!(left_type, Type::Array(..) | Type::Function { .. });
+        if !node.left.as_ref().is_some_and(|left| left.is_lvalue()) || !assignable {
+            return Err(ASTError::ExpectedLvalue);
+        }
+        let value = node.right.take().ok_or(ASTError::ExpectedInteger)?;
+        node.right = Some(Box::new(assigned(*value, &left_type)?));
+        node.ty = Some(left_type);
+        return Ok(());
+    }
+
+    // These only take integers. A shift has the type of its promoted left operand, the right
+    // one is converted to that too, which keeps any count that is not undefined anyway.
+    if matches!(
+        node.operation,
+        Token::PERCENT | Token::AMPER | Token::OR | Token::XOR | Token::LSHIFT | Token::RSHIFT
+    ) {
+        if !left_type.is_integer() || !right_type.is_integer() {
+            return Err(invalid());
+        }
+        let ty = match node.operation {
+            Token::LSHIFT | Token::RSHIFT => left_type.promoted(),
+            _ => Type::common(&left_type, &right_type),
+        };
+        convert_operand(&mut node.left, &ty);
+        convert_operand(&mut node.right, &ty);
+        node.ty = Some(ty);
+        return Ok(());
+    }
+
+    let is_comparison = matches!(
+        node.operation,
+        Token::EQ | Token::NE | Token::LT | Token::GT | Token::LE | Token::GE
+    );
+    if left_type.is_arithmetic() && right_type.is_arithmetic() {
+        let common = Type::common(&left_type, &right_type);
+        convert_operand(&mut node.left, &common);
+        convert_operand(&mut node.right, &common);
+        node.ty = Some(if is_comparison { Type::Int } else { common });
+        return Ok(());
+    }
+
+    // Only pointers are left, which cannot be multiplied or divided
+    let element_size = |pointer: &Type| pointer.pointee().map(Type::size).filter(|size| *size > 0);
+    match (&node.operation, left_type.pointee(), right_type.pointee()) {
+        (operation, Some(left_pointee), Some(right_pointee)) if is_comparison => {
+            let equality = matches!(operation, Token::EQ | Token::NE);
+            if left_pointee != right_pointee && !(equality && (*left_pointee == Type::Void || *right_pointee == Type::Void)) {
+                return Err(invalid());
+            }
+        }
+        // Only `0` compares with a pointer
+        (Token::EQ | Token::NE, Some(_), None) if is_null_pointer_constant(node.right.as_deref()) => {
+            convert_operand(&mut node.right, &left_type)
+        }
+        (Token::EQ | Token::NE, None, Some(_)) if is_null_pointer_constant(node.left.as_deref()) => {
+            convert_operand(&mut node.left, &right_type)
+        }
+        (Token::PLUS | Token::MINUS, Some(_), None) if right_type.is_integer() => {
+            let size = element_size(&left_type).ok_or_else(invalid)?;
+            scale_operand(&mut node.right, size);
+            node.ty = Some(left_type);
+            return Ok(());
+        }
+        (Token::PLUS, None, Some(_)) if left_type.is_integer() => {
+            let size = element_size(&right_type).ok_or_else(invalid)?;
+            scale_operand(&mut node.left, size);
+            node.ty = Some(right_type);
+            return Ok(());
+        }
+        // The difference of two pointers counts elements, not bytes
+        (Token::MINUS, Some(left_pointee), Some(right_pointee)) if left_pointee == right_pointee => {
+            let size = element_size(&left_type).ok_or_else(invalid)?;
+            node.ty = Some(Type::Long);
+            if size > 1 {
+                let size = typed_leaf(Token::LONG(size as i64), Type::Long);
+                let difference = std::mem::replace(node, size.clone());
+                *node = ASTNode::new(Ok(Token::SLASH), Box::new(difference), Box::new(size))?;
+                node.ty = Some(Type::Long);
+            }
+            return Ok(());
+        }
+        _ => return Err(invalid()),
+    }
+    node.ty = Some(Type::Int);
+    Ok(())
+}
+
+/// The elements of a `{ ... }` initialiser still to be used
+type Elements = std::iter::Peekable<std::vec::IntoIter<ASTNode>>;
+
+/// Whether a checked initialiser is a list, or a string literal for a `char` array
+fn is_aggregate_initializer(value: &ASTNode) -> bool {
+    match (&value.operation, &value.ty) {
+        (Token::InitializerList, _) => true,
+        (Token::STRING(_), Some(ty)) => matches!(ty, Type::Array(..)),
+        _ => false,
+    }
+}
+
+/// Whether every value in a checked initialiser is a constant, see [`evaluate_constant`]
+fn is_constant_initializer(value: &ASTNode) -> bool {
+    match value.operation {
+        Token::InitializerList => value.arguments().into_iter().all(is_constant_initializer),
+        _ if is_aggregate_initializer(value) => true,
+        _ => evaluate_constant(value).is_some(),
+    }
+}
+
+/// Give `symbol` the length its initialiser gives an array declared without one, like `int a[] = {1, 2};`
+fn complete_type(symbol: &mut Rc<Symbol>, value: &ASTNode) {
+    if let (Type::Array(_, 0), Some(ty)) = (&symbol.ty, &value.ty) {
+        *symbol = Rc::new(Symbol {
+            ty: ty.clone(),
+            ..(**symbol).clone()
+        });
+    }
+}
+
+/// Convert an already checked `value` to `ty` as if by assignment (C11 6.5.16.1)
+fn assigned(value: ASTNode, ty: &Type) -> Result<ASTNode, ASTError> {
+    let from = value.ty.clone().unwrap_or(Type::Void);
+    if from == *ty {
+        return Ok(value);
+    }
+
+    let allowed = match (from.pointee(), ty.pointee()) {
+        (None, None) => from.is_arithmetic() && ty.is_arithmetic(),
+        // `void *` converts to and from any other pointer
+        (Some(from_pointee), Some(to_pointee)) => *from_pointee == Type::Void || *to_pointee == Type::Void,
+        (None, Some(_)) => is_null_pointer_constant(Some(&value)),
+        (Some(_), None) => false,
+    };
+    if allowed {
+        Ok(ASTNode::cast(value, ty.clone()))
+    } else {
+        Err(ASTError::InvalidConversion(from, ty.clone()))
+    }
+}
+
+/// An integer literal `0`, which converts to a null pointer of any type
+fn is_null_pointer_constant(node: Option<&ASTNode>) -> bool {
+    node.is_some_and(|node| node.operation.integer_value() == Some(0))
+}
+
+fn typed_leaf(token: Token, ty: Type) -> ASTNode {
+    let mut leaf = ASTNode::make_leaf(token).expect("Not a literal");
+    leaf.ty = Some(ty);
+    leaf
+}
+
+/// Turn an integer added to a pointer into the number of bytes, `p + i` advances by `i` elements
+fn scale_operand(operand: &mut Option<Box<ASTNode>>, size: usize) {
+    convert_operand(operand, &Type::Long);
+    if size > 1 {
+        if let Some(index) = operand.take() {
+            let size = typed_leaf(Token::LONG(size as i64), Type::Long);
+            let mut scaled = ASTNode::new(Ok(Token::ASTERISK), index, Box::new(size)).expect("Valid operation");
+            scaled.ty = Some(Type::Long);
+            *operand = Some(Box::new(scaled));
+        }
+    }
+}
+
+/// Wrap an operand that has already been checked in a cast, unless it has the type already
+fn convert_operand(operand: &mut Option<Box<ASTNode>>, ty: &Type) {
+    if let Some(node) = operand.as_mut() {
+        if node.ty.as_ref() != Some(ty) {
+            node.wrap(Token::Cast, ty.clone());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::Lexer;
+
+    fn check(source: &str) -> Result<TranslationUnit, ASTError> {
+        let mut unit = TranslationUnit::parse(&mut Lexer::new(source.as_bytes())).map_err(ASTError::without_position)?;
+        check_translation_unit(&mut unit).map_err(ASTError::without_position)?;
+        Ok(unit)
+    }
+
+    /// The line and column a type error in `source` was found at, and the error
+    fn error_position(source: &str) -> (usize, usize, ASTError) {
+        let mut unit = TranslationUnit::parse(&mut Lexer::new(source.as_bytes())).unwrap();
+        match check_translation_unit(&mut unit).unwrap_err() {
+            ASTError::At(token, error) => (token.line, token.column, *error),
+            error => panic!("{:?} has no position", error),
+        }
+    }
+
+    fn checked_expression(source: &str) -> ASTNode {
+        let mut node = ASTNode::parse_stream(&mut Lexer::new(source.as_bytes())).unwrap();
+        check_expression(&mut node).unwrap();
+        node
+    }
+
+    fn returned_value(unit: &TranslationUnit) -> &ASTNode {
+        match unit.functions()[0].body.last() {
+            Some(Statement::Return(Some(value), _)) => value,
+            other => panic!("expected a return, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_integer_promotions_insert_casts() {
+        let unit = check("int main() { char a; unsigned short b; return a + b; }").unwrap();
+        let sum = returned_value(&unit);
+        assert_eq!(sum.ty, Some(Type::Int));
+
+        let left = sum.left.as_ref().unwrap();
+        assert_eq!((&left.operation, &left.ty), (&Token::Cast, &Some(Type::Int)));
+        assert_eq!(left.left.as_ref().unwrap().ty, Some(Type::Char));
+        assert_eq!(left.left.as_ref().unwrap().symbol.as_ref().unwrap().slot, 0);
+
+        let right = sum.right.as_ref().unwrap();
+        assert_eq!((&right.operation, &right.ty), (&Token::Cast, &Some(Type::Int)));
+    }
+
+    #[test]
+    fn test_usual_arithmetic_conversions() {
+        let node = checked_expression("1 - 2u");
+        assert_eq!(node.ty, Some(Type::UnsignedInt));
+        assert_eq!(node.left.as_ref().unwrap().operation, Token::Cast);
+        // Already the right type, no cast needed
+        assert_eq!(node.right.as_ref().unwrap().operation, Token::UINT(2));
+
+        assert_eq!(checked_expression("2l * 3").ty, Some(Type::Long));
+        assert_eq!(checked_expression("(unsigned char) 300 / 'a'").ty, Some(Type::Int));
+        assert_eq!(checked_expression("(long long) 1 + 1ul").ty, Some(Type::UnsignedLongLong));
+    }
+
+    #[test]
+    fn test_floating_types() {
+        let node = checked_expression("1 + 2.5f");
+        assert_eq!(node.ty, Some(Type::Float));
+        assert_eq!(node.left.as_ref().unwrap().operation, Token::Cast);
+        assert_eq!(checked_expression("1.5f * 2.0").ty, Some(Type::Double));
+        assert_eq!(checked_expression("0.5 < 1").ty, Some(Type::Int));
+
+        let unit = check("double f(float x) { long l = x; return l; }").unwrap();
+        let Statement::Declaration(_, Some(initializer)) = &unit.functions()[0].body[0] else {
+            panic!("expected a declaration");
+        };
+        assert_eq!((&initializer.operation, &initializer.ty), (&Token::Cast, &Some(Type::Long)));
+        assert_eq!(returned_value(&unit).ty, Some(Type::Double));
+
+        // Pointers only convert to and from integers
+        assert_eq!(
+            check("int main() { char *p; double d = p; }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Char.pointer_to(), Type::Double)
+        );
+        assert_eq!(
+            check("int main() { float f; return *(int *) f; }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Float, Type::Int.pointer_to())
+        );
+        assert!(matches!(check("int main() { int a[2]; return a[1.0]; }").unwrap_err(), ASTError::InvalidOperands(..)));
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let node = checked_expression("(char) 1 | 2u");
+        assert_eq!(node.ty, Some(Type::UnsignedInt));
+        assert_eq!(checked_expression("5l % 3").ty, Some(Type::Long));
+
+        // A shift has the type of its left operand, whatever the right one is
+        let node = checked_expression("(unsigned char) 1 << 3l");
+        assert_eq!(node.ty, Some(Type::Int));
+        assert_eq!(node.right.as_ref().unwrap().ty, Some(Type::Int));
+        assert_eq!(checked_expression("1u >> (char) 2").ty, Some(Type::UnsignedInt));
+
+        assert_eq!(
+            check("int main() { double d; return d % 2; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::PERCENT, Type::Double, Type::Int)
+        );
+        assert_eq!(
+            check("int main() { char *p; return p & 1; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::AMPER, Type::Char.pointer_to(), Type::Int)
+        );
+
+        let constant = |source: &str| evaluate_constant(&checked_expression(source));
+        assert_eq!(constant("0 - 8 >> 1"), Some(Constant::Integer(-4)));
+        assert_eq!(constant("0u - 8 >> 1"), Some(Constant::Integer(0x7ffffffc)));
+        assert_eq!(constant("0 - 7 % 3"), Some(Constant::Integer(-1)));
+        assert_eq!(constant("(0 - 7) % 3"), Some(Constant::Integer(-1)));
+        assert_eq!(constant("1 << 31"), Some(Constant::Integer(i32::MIN as i64)));
+        assert_eq!(constant("1 << 32"), None);
+        assert_eq!(constant("1l << 32 | 5 ^ 1 & 3"), Some(Constant::Integer((1 << 32) | 4)));
+    }
+
+    #[test]
+    fn test_unary_operators() {
+        // `-` and `~` promote their operand, `+` is only the conversion
+        let node = checked_expression("-(char) 1");
+        assert_eq!((&node.operation, &node.ty), (&Token::Negate, &Some(Type::Int)));
+        assert_eq!(node.left.as_ref().unwrap().operation, Token::Cast);
+        assert_eq!(checked_expression("~1ul").ty, Some(Type::UnsignedLong));
+        assert_eq!(checked_expression("-2.5f").ty, Some(Type::Float));
+        let node = checked_expression("+(unsigned short) 1");
+        assert_eq!((&node.operation, &node.ty), (&Token::Cast, &Some(Type::Int)));
+        assert_eq!(check("int main() { int i; +i = 1; }").unwrap_err(), ASTError::ExpectedLvalue);
+
+        // `!` takes pointers too, and makes an int
+        let unit = check("int main() { char *p; return !p; }").unwrap();
+        assert_eq!(returned_value(&unit).ty, Some(Type::Int));
+        assert_eq!(returned_value(&unit).left.as_ref().unwrap().ty, Some(Type::Char.pointer_to()));
+        assert_eq!(checked_expression("!0.5").ty, Some(Type::Int));
+
+        assert_eq!(
+            check("int main() { double d; return ~d; }").unwrap_err(),
+            ASTError::InvalidOperand(Token::INVERT, Type::Double)
+        );
+        assert_eq!(
+            check("int main() { char *p; return -p; }").unwrap_err(),
+            ASTError::InvalidOperand(Token::Negate, Type::Char.pointer_to())
+        );
+        assert!(matches!(
+            check("struct s { int a; }; int main() { struct s v; return !v; }").unwrap_err(),
+            ASTError::InvalidOperand(Token::LOGNOT, Type::Record(_))
+        ));
+
+        let constant = |source: &str| evaluate_constant(&checked_expression(source));
+        assert_eq!(constant("-1"), Some(Constant::Integer(-1)));
+        assert_eq!(constant("-1u"), Some(Constant::Integer(0xffff_ffff)));
+        assert_eq!(constant("~0u >> 28"), Some(Constant::Integer(15)));
+        assert_eq!(constant("~(unsigned char) 0"), Some(Constant::Integer(-1)));
+        assert_eq!(constant("!2.5 + !0 + +3"), Some(Constant::Integer(4)));
+        assert_eq!(constant("-1.5"), Some(Constant::Float(-1.5)));
+    }
+
+    #[test]
+    fn test_logical_and_conditional_operators() {
+        // The operands of && and || keep their own types
+        let node = checked_expression("2.5 && \"s\" || 0l");
+        assert_eq!(node.ty, Some(Type::Int));
+        assert_eq!(node.right.as_ref().unwrap().ty, Some(Type::Long));
+        assert_eq!(node.left.as_ref().unwrap().left.as_ref().unwrap().ty, Some(Type::Double));
+
+        // Both results of ?: are converted to one type
+        let node = checked_expression("1 ? 2 : 3.0f");
+        assert_eq!(node.ty, Some(Type::Float));
+        let results = node.right.as_ref().unwrap();
+        assert_eq!((&results.ty, &results.left.as_ref().unwrap().operation), (&Some(Type::Float), &Token::Cast));
+        assert_eq!(results.right.as_ref().unwrap().operation, Token::FLOAT(3.0f32.to_bits()));
+
+        let conditional_type = |source: &str| {
+            let unit = check(source).unwrap();
+            returned_value(&unit).left.as_ref().unwrap().ty.clone()
+        };
+        assert_eq!(conditional_type("long f(char *p, int *q) { return (long) (1 ? p : 0); }"), Some(Type::Char.pointer_to()));
+        assert_eq!(conditional_type("long f(void *p, int *q) { return (long) (1 ? q : p); }"), Some(Type::Void.pointer_to()));
+        assert_eq!(
+            conditional_type("struct s { int a; }; int f(struct s x) { return (1 ? x : x).a; }"),
+            Some(Type::Int.pointer_to())
+        );
+
+        assert_eq!(
+            check("int f(char *p, int *q) { return 1 ? p : q; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::COLON, Type::Char.pointer_to(), Type::Int.pointer_to())
+        );
+        assert_eq!(
+            check("int f(char *p) { return 1 ? p : 1; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::COLON, Type::Char.pointer_to(), Type::Int)
+        );
+        assert!(matches!(
+            check("struct s { int a; }; int f(struct s x) { return x && 1; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::LOGAND, Type::Record(_), Type::Int)
+        ));
+        assert!(matches!(
+            check("struct s { int a; }; int f(struct s x) { return x ? 1 : 2; }").unwrap_err(),
+            ASTError::InvalidOperand(Token::QUESTION, Type::Record(_))
+        ));
+        assert_eq!(check("int f(int a, int b) { return (a ? a : b) = 1; }").unwrap_err(), ASTError::ExpectedLvalue);
+    }
+
+    #[test]
+    fn test_initializers_and_returns_are_converted() {
+        let unit = check("long f() { unsigned char c = 1000; return c; }").unwrap();
+        let Statement::Declaration(symbol, Some(initializer)) = &unit.functions()[0].body[0] else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(symbol.ty, Type::UnsignedChar);
+        assert_eq!((&initializer.operation, &initializer.ty), (&Token::Cast, &Some(Type::UnsignedChar)));
+
+        let value = returned_value(&unit);
+        assert_eq!((&value.operation, &value.ty), (&Token::Cast, &Some(Type::Long)));
+    }
+
+    #[test]
+    fn test_scopes() {
+        assert!(check("int main() { int x = 1; { long x = x; return x; } }").is_ok());
+        assert_eq!(
+            check("int main() { { int x; } return x; }").unwrap_err(),
+            ASTError::UndeclaredIdentifier("x".to_string())
+        );
+        assert_eq!(check("int main() { int x; char x; }").unwrap_err(), ASTError::Redeclaration("x".to_string()));
+        // Every function starts without variables
+        assert_eq!(
+            check("int f() { int y; return y; } int g() { return y; }").unwrap_err(),
+            ASTError::UndeclaredIdentifier("y".to_string())
+        );
+    }
+
+    #[test]
+    fn test_type_errors() {
+        assert_eq!(check("int main() { void v; }").unwrap_err(), ASTError::VoidVariable("v".to_string()));
+        assert_eq!(
+            check("int main() { return \"a\" * 2; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::ASTERISK, Type::Char.pointer_to(), Type::Int)
+        );
+        assert_eq!(
+            check("int main() { int x = \"a\"; }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Char.pointer_to(), Type::Int)
+        );
+        assert_eq!(check("void f() { return 1; }").unwrap_err(), ASTError::InvalidReturn("f".to_string()));
+        assert_eq!(check("int main() { return; }").unwrap_err(), ASTError::InvalidReturn("main".to_string()));
+        assert_eq!(error_position("int main() {\n  return;\n}"), (2, 3, ASTError::InvalidReturn("main".to_string())));
+        assert!(check("void f() { (void) 1; return; }").is_ok());
+    }
+
+    #[test]
+    fn test_pointer_types() {
+        let unit = check("long main() { int x; int *p = &x; int **q = &p; return **q + *p; }").unwrap();
+        let sum = returned_value(&unit);
+        let deref = sum.left.as_ref().unwrap().left.as_ref().unwrap();
+        assert_eq!((&deref.operation, &deref.ty), (&Token::Dereference, &Some(Type::Int)));
+        assert_eq!(deref.left.as_ref().unwrap().ty, Some(Type::Int.pointer_to()));
+
+        assert!(check("int main() { void *v = 0; char *c = v; v = c; return c == v; }").is_ok());
+        assert_eq!(
+            check("int main() { int x; char *c = &x; }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Int.pointer_to(), Type::Char.pointer_to())
+        );
+        assert_eq!(
+            check("int main() { int *p = 1; }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Int, Type::Int.pointer_to())
+        );
+        assert_eq!(
+            check("int main() { int x; return *x; }").unwrap_err(),
+            ASTError::InvalidOperand(Token::Dereference, Type::Int)
+        );
+        assert_eq!(
+            check("int main() { void *v; return *v; }").unwrap_err(),
+            ASTError::InvalidOperand(Token::Dereference, Type::Void.pointer_to())
+        );
+    }
+
+    #[test]
+    fn test_lvalues() {
+        assert!(check("int main() { int x; int *p; p = &x; *p = 1; x = *p = 2; }").is_ok());
+        assert_eq!(check("int main() { return &1; }").unwrap_err(), ASTError::ExpectedLvalue);
+        assert_eq!(check("int main() { int x; x + 1 = 2; }").unwrap_err(), ASTError::ExpectedLvalue);
+        assert_eq!(check("int main() { int x; (long) x = 2; }").unwrap_err(), ASTError::ExpectedLvalue);
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        let unit = check("char f(char c, int *p, float x) { c *= 2.5; p += 2; x++; return c <<= 1l; }").unwrap();
+        let body = &unit.functions()[0].body;
+        let Statement::Expression(Some(multiply)) = &body[0] else {
+            panic!("expected a compound assignment");
+        };
+        // The object keeps its type, the value has the type the operation is done in
+        assert_eq!(multiply.operation, Token::ASSTAR);
+        assert_eq!(multiply.ty, Some(Type::Char));
+        assert_eq!(multiply.right.as_ref().unwrap().ty, Some(Type::Double));
+        let Statement::Expression(Some(add)) = &body[1] else {
+            panic!("expected a compound assignment");
+        };
+        // A constant is scaled right away
+        let amount = add.right.as_ref().unwrap();
+        assert_eq!((&amount.operation, amount.ty.as_ref()), (&Token::LONG(8), Some(&Type::Long)));
+        let Statement::Expression(Some(increment)) = &body[2] else {
+            panic!("expected an increment");
+        };
+        assert_eq!(increment.operation, Token::PostIncrement);
+        assert_eq!(increment.right.as_ref().unwrap().ty, Some(Type::Float));
+        let Statement::Return(Some(shift), _) = &body[3] else {
+            panic!("expected a return");
+        };
+        // A shift is done in the promoted type of the left side
+        assert_eq!(shift.right.as_ref().unwrap().ty, Some(Type::Int));
+
+        assert!(check("int main() { int a[2]; int *p; p = a; *p++ = 1; --*p; a[1] %= 2; return a[0] ^= a[1]; }").is_ok());
+        assert_eq!(check("int main() { 1 += 2; }").unwrap_err(), ASTError::ExpectedLvalue);
+        assert_eq!(check("int main() { int x; (x + 1)++; }").unwrap_err(), ASTError::ExpectedLvalue);
+        assert_eq!(check("int main() { int a[2]; a += 1; }").unwrap_err(), ASTError::ExpectedLvalue);
+        assert_eq!(check("int main() { int a[2]; ++a; }").unwrap_err(), ASTError::ExpectedLvalue);
+        assert_eq!(
+            check("int main() { double d; d %= 2; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::ASMOD, Type::Double, Type::Int)
+        );
+        assert_eq!(
+            check("int main() { int *p; int *q; p -= q; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::ASMINUS, Type::Int.pointer_to(), Type::Int.pointer_to())
+        );
+        assert_eq!(
+            check("int main() { int *p; long i; i += p; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::ASPLUS, Type::Long, Type::Int.pointer_to())
+        );
+        assert_eq!(
+            check("int main() { void *p; p++; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::PostIncrement, Type::Void.pointer_to(), Type::Int)
+        );
+    }
+
+    #[test]
+    fn test_switch() {
+        let unit = check("int f(char c, unsigned u) { switch (c) { case 'a' + 1: switch (u) case 0 - 1: case 1l: break; default: ; } }").unwrap();
+        let Statement::Switch { value, body, cases, default } = &unit.functions()[0].body[0] else {
+            panic!("expected a switch");
+        };
+        // The value is promoted, the cases are converted to its type
+        assert_eq!(value.ty, Some(Type::Int));
+        assert_eq!((cases.as_slice(), *default), ([98].as_slice(), true));
+        let Statement::Block(statements) = body.as_ref() else {
+            panic!("expected a block");
+        };
+        let Statement::Case(_, inner, _) = &statements[0] else {
+            panic!("expected a case");
+        };
+        let Statement::Switch { cases, default, .. } = inner.as_ref() else {
+            panic!("expected a switch");
+        };
+        assert_eq!((cases.as_slice(), *default), ([0xffff_ffff, 1].as_slice(), false));
+
+        assert_eq!(check("int f(int x) { switch (x) { case 1: case 2 - 1: ; } }").unwrap_err(), ASTError::DuplicateCase(1));
+        assert_eq!(check("int f(unsigned char x) { switch (x) { case 256 + 1: case 257: ; } }").unwrap_err(), ASTError::DuplicateCase(257));
+        assert_eq!(check("int f(unsigned x) { switch (x) { case 0 - 1: case 4294967295: ; } }").unwrap_err(), ASTError::DuplicateCase(0xffff_ffff));
+        assert_eq!(check("int f(int x) { switch (x) { case x: ; } }").unwrap_err(), ASTError::NonConstantCase);
+        assert_eq!(check("int f(int x) { switch (x) { case 1.0: ; } }").unwrap_err(), ASTError::NonConstantCase);
+        assert_eq!(check("int f(int x) { switch (x) { default: default: ; } }").unwrap_err(), ASTError::DuplicateDefault);
+        assert_eq!(check("int f(double x) { switch (x) ; }").unwrap_err(), ASTError::InvalidSwitch(Type::Double));
+        assert_eq!(check("int f(int x) { case 1: ; }").unwrap_err(), ASTError::MisplacedStatement(Keyword::Case));
+        assert_eq!(check("int f(int x) { default: ; }").unwrap_err(), ASTError::MisplacedStatement(Keyword::Default));
+        assert_eq!(check("int f(int x) { break; }").unwrap_err(), ASTError::MisplacedStatement(Keyword::Break));
+        // Each switch has cases of its own
+        assert!(check("int f(int x) { switch (x) { case 1: switch (x) { case 1: default: ; } default: ; } }").is_ok());
+    }
+
+    #[test]
+    fn test_switch_errors_have_positions() {
+        // Errors point at the `case`, `default` or `break` they are about, a bad switch at its value
+        let source = "int f(int x) {\n  switch (x) {\n    case 1: case 1: ;\n  }\n}";
+        assert_eq!(error_position(source), (3, 13, ASTError::DuplicateCase(1)));
+        assert_eq!(error_position("int f(int x) { switch (x) { case x: ; } }"), (1, 29, ASTError::NonConstantCase));
+        assert_eq!(error_position("int f(int x) { switch (x) { default: default: ; } }"), (1, 38, ASTError::DuplicateDefault));
+        assert_eq!(error_position("int f(int x) { case 1: ; }"), (1, 16, ASTError::MisplacedStatement(Keyword::Case)));
+        assert_eq!(error_position("int f(int x) { default: ; }"), (1, 16, ASTError::MisplacedStatement(Keyword::Default)));
+        assert_eq!(error_position("int f(int x) {\n  break;\n}"), (2, 3, ASTError::MisplacedStatement(Keyword::Break)));
+        assert_eq!(error_position("int f(double x) { switch (x) ; }"), (1, 27, ASTError::InvalidSwitch(Type::Double)));
+    }
+
+    #[test]
+    fn test_labels() {
+        // A goto can jump ahead, and into or out of any block
+        assert!(check("int f(int x) { goto b; a: x = 1; { b: switch (x) { case 1: c: goto a; } } goto c; }").is_ok());
+        // Every function has labels of its own, and they do not clash with variables
+        assert!(check("int f() { a: ; } int g() { int a; a: goto a; }").is_ok());
+        assert_eq!(check("int f() { goto b; }").unwrap_err(), ASTError::UndefinedLabel("b".to_string()));
+        assert_eq!(check("int f() { a: ; } int g() { goto a; }").unwrap_err(), ASTError::UndefinedLabel("a".to_string()));
+        assert_eq!(check("int f() { a: ; { a: ; } }").unwrap_err(), ASTError::DuplicateLabel("a".to_string()));
+
+        // An undefined label is reported at the goto, a duplicate one at its second definition
+        assert_eq!(error_position("int f() {\n  goto b;\n}"), (2, 8, ASTError::UndefinedLabel("b".to_string())));
+        assert_eq!(error_position("int f() { a: ;\n  { a: ; }\n}"), (2, 5, ASTError::DuplicateLabel("a".to_string())));
+    }
+
+    #[test]
+    fn test_pointer_arithmetic_is_scaled() {
+        let unit = check("long main() { long *p; p = 1 + p - 2u; return p - p; }").unwrap();
+        let Statement::Expression(Some(assignment)) = &unit.functions()[0].body[1] else {
+            panic!("expected an assignment");
+        };
+        let difference = assignment.right.as_ref().unwrap();
+        assert_eq!((&difference.operation, &difference.ty), (&Token::MINUS, &Some(Type::Long.pointer_to())));
+
+        // 2u becomes (long) 2u * 8
+        let scaled = difference.right.as_ref().unwrap();
+        assert_eq!((&scaled.operation, &scaled.ty), (&Token::ASTERISK, &Some(Type::Long)));
+        assert_eq!(scaled.left.as_ref().unwrap().operation, Token::Cast);
+        assert_eq!(scaled.right.as_ref().unwrap().operation, Token::LONG(8));
+        // The integer can come first too
+        let sum = difference.left.as_ref().unwrap();
+        assert_eq!(sum.left.as_ref().unwrap().operation, Token::ASTERISK);
+
+        // Subtracting pointers counts elements
+        let elements = returned_value(&unit);
+        assert_eq!((&elements.operation, &elements.ty), (&Token::SLASH, &Some(Type::Long)));
+        assert_eq!(elements.left.as_ref().unwrap().operation, Token::MINUS);
+
+        // Bytes need no scaling
+        let unit = check("int main() { char *c; c = c + 1; }").unwrap();
+        let Statement::Expression(Some(assignment)) = &unit.functions()[0].body[1] else {
+            panic!("expected an assignment");
+        };
+        assert_eq!(assignment.right.as_ref().unwrap().right.as_ref().unwrap().operation, Token::Cast);
+    }
+
+    #[test]
+    fn test_pointer_operand_errors() {
+        let int_pointer = Type::Int.pointer_to();
+        assert_eq!(
+            check("int main() { int *p; return p * 2; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::ASTERISK, int_pointer.clone(), Type::Int)
+        );
+        assert_eq!(
+            check("int main() { int *p; return p + p; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::PLUS, int_pointer.clone(), int_pointer.clone())
+        );
+        assert_eq!(
+            check("int main() { int *p; return 1 - p; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::MINUS, Type::Int, int_pointer.clone())
+        );
+        assert_eq!(
+            check("int main() { int *p; char *c; return p < c; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::LT, int_pointer.clone(), Type::Char.pointer_to())
+        );
+        assert_eq!(
+            check("int main() { int *p; return p == 1; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::EQ, int_pointer, Type::Int)
+        );
+        assert_eq!(
+            check("int main() { void *v; v = v + 1; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::PLUS, Type::Void.pointer_to(), Type::Int)
+        );
+        assert!(check("int main() { int *p; return (p <= p) + (0 != p); }").is_ok());
+    }
+
+    #[test]
+    fn test_arrays_decay() {
+        let unit = check("int main() { int a[4][2]; return a[3][1]; }").unwrap();
+        let element = returned_value(&unit);
+        assert_eq!((&element.operation, &element.ty), (&Token::Dereference, &Some(Type::Int)));
+
+        // a[3] is an int [2], which decays again before the second subscript
+        let outer = element.left.as_ref().unwrap();
+        let row = outer.left.as_ref().unwrap();
+        assert_eq!((&row.operation, &row.ty), (&Token::AddressOf, &Some(Type::Int.pointer_to())));
+        let row = row.left.as_ref().unwrap();
+        assert_eq!((&row.operation, &row.ty), (&Token::Dereference, &Some(Type::Int.array_of(2))));
+
+        // a itself becomes a pointer to its first row, scaled by the size of a row
+        let sum = row.left.as_ref().unwrap();
+        let decayed = sum.left.as_ref().unwrap();
+        assert_eq!((&decayed.operation, &decayed.ty), (&Token::AddressOf, &Some(Type::Int.array_of(2).pointer_to())));
+        assert_eq!(sum.right.as_ref().unwrap().right.as_ref().unwrap().operation, Token::LONG(8));
+
+        assert!(check("int main() { char s[3]; char *p = s; p = \"abc\"; return &s == &s; }").is_ok());
+        assert_eq!(check("int main() { int a[2]; int b[2]; a = b; }").unwrap_err(), ASTError::ExpectedLvalue);
+        assert_eq!(
+            check("int main() { int a[2]; long *p = a; }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Int.pointer_to(), Type::Long.pointer_to())
+        );
+    }
+
+    #[test]
+    fn test_sizeof() {
+        let unit = check("long main() { short a[3][5]; char *p; return sizeof a + sizeof a[1] + sizeof p + sizeof \"hi\" + sizeof(int); }").unwrap();
+        // The unsigned long sum is converted to the return type
+        let value = returned_value(&unit);
+        assert_eq!((&value.operation, &value.ty), (&Token::Cast, &Some(Type::Long)));
+
+        let mut sizes = Vec::new();
+        let mut node = value.left.as_ref().unwrap().as_ref();
+        while node.operation == Token::PLUS {
+            sizes.push(node.right.as_ref().unwrap().operation.clone());
+            node = node.left.as_ref().unwrap();
+        }
+        sizes.push(node.operation.clone());
+        assert_eq!(sizes, vec![Token::ULONG(4), Token::ULONG(3), Token::ULONG(8), Token::ULONG(10), Token::ULONG(30)]);
+
+        assert_eq!(checked_expression("sizeof 1").ty, Some(Type::UnsignedLong));
+        assert_eq!(
+            check("int main() { void *v; return sizeof *v; }").unwrap_err(),
+            ASTError::InvalidOperand(Token::Dereference, Type::Void.pointer_to())
+        );
+        assert_eq!(
+            check("int main() { return sizeof x; }").unwrap_err(),
+            ASTError::UndeclaredIdentifier("x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_member_access_is_pointer_arithmetic() {
+        let unit = check("struct s { char c; short a[2]; }; int main() { struct s v; struct s *p = &v; return p->a[1] + v.c; }").unwrap();
+        let sum = returned_value(&unit);
+
+        // v.c is at offset 0, *(char *)(char *)&v
+        let c = sum.right.as_ref().unwrap().left.as_ref().unwrap();
+        assert_eq!((&c.operation, &c.ty), (&Token::Dereference, &Some(Type::Char)));
+        let cast = c.left.as_ref().unwrap();
+        assert_eq!((&cast.operation, &cast.ty), (&Token::Cast, &Some(Type::Char.pointer_to())));
+        let bytes = cast.left.as_ref().unwrap();
+        assert_eq!(bytes.left.as_ref().unwrap().operation, Token::AddressOf);
+
+        // p->a is *(short (*)[2])((char *)&*p + 2), which decays before the subscript
+        let element = sum.left.as_ref().unwrap().left.as_ref().unwrap();
+        let array = element.left.as_ref().unwrap().left.as_ref().unwrap();
+        assert_eq!((&array.operation, &array.ty), (&Token::AddressOf, &Some(Type::Short.pointer_to())));
+        let member = array.left.as_ref().unwrap();
+        assert_eq!((&member.operation, &member.ty), (&Token::Dereference, &Some(Type::Short.array_of(2))));
+        let offset = member.left.as_ref().unwrap().left.as_ref().unwrap();
+        assert_eq!(offset.operation, Token::PLUS);
+        assert_eq!(offset.right.as_ref().unwrap().operation, Token::LONG(2));
+
+        assert!(check("struct s { int a; }; int main() { struct s x; struct s y; x = y; return (x = y).a; }").is_ok());
+    }
+
+    #[test]
+    fn test_struct_errors() {
+        assert_eq!(
+            check("struct s { int a; }; int main() { struct s x; return x.b; }").unwrap_err(),
+            ASTError::UnknownMember("b".to_string())
+        );
+        assert_eq!(
+            check("int main() { int x; return x.a; }").unwrap_err(),
+            ASTError::InvalidOperand(Token::DOT, Type::Int)
+        );
+        assert!(matches!(
+            check("int main() { struct s *p; return p->a; }").unwrap_err(),
+            ASTError::IncompleteType(Type::Record(_))
+        ));
+        assert!(matches!(
+            check("int main() { struct s x; }").unwrap_err(),
+            ASTError::IncompleteType(Type::Record(_))
+        ));
+        assert_eq!(
+            check("int main() { int a[]; }").unwrap_err(),
+            ASTError::IncompleteType(Type::Int.array_of(0))
+        );
+        // Two declarations make two different types, even with the same members
+        assert!(matches!(
+            check("struct a { int x; }; struct b { int x; }; int main() { struct a x; struct b y; x = y; }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Record(_), Type::Record(_))
+        ));
+        assert!(matches!(
+            check("struct a { int x; }; int main() { struct a x; return x == x; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::EQ, _, _)
+        ));
+        assert!(matches!(
+            check("struct a { int x; }; int main() { struct a x; return x; }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Record(_), Type::Int)
+        ));
+    }
+
+    #[test]
+    fn test_calls() {
+        let unit = check(
+            "struct pair { long a, b; };\n\
+             struct pair swap(struct pair p, char c) { struct pair q; q.a = p.b; q.b = p.a; return q; }\n\
+             long main() { struct pair p; return swap(swap(p, 1), 'x').a; }",
+        )
+        .unwrap();
+        let main = unit.functions()[1];
+
+        // Every call returning a struct gets a temporary for the result
+        let temporaries: Vec<&str> = main.locals.iter().skip(1).map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(temporaries, vec!["<temporary 1>", "<temporary 2>"]);
+        assert!(main.locals[1..].iter().all(|symbol| symbol.ty == main.locals[0].ty));
+
+        let Some(Statement::Return(Some(value), _)) = main.body.last() else {
+            panic!("expected a return");
+        };
+        let outer = value.left.as_ref().unwrap().left.as_ref().unwrap().left.as_ref().unwrap().left.as_ref().unwrap();
+        assert_eq!(outer.operation, Token::Call);
+        // The arguments are checked first, so the inner call has the first temporary
+        assert_eq!(outer.symbol.as_ref().unwrap().slot, 2);
+        let arguments = outer.arguments();
+        assert_eq!((&arguments[0].operation, &arguments[0].symbol.as_ref().unwrap().slot), (&Token::Call, &1));
+        // The arguments are converted to the parameter types
+        assert_eq!((&arguments[1].operation, &arguments[1].ty), (&Token::Cast, &Some(Type::Char)));
+
+        // Functions can call themselves and the ones before them, the ones after are not declared yet
+        assert!(check("int f(int n) { return f(n - 1); } int main() { return f(3); }").is_ok());
+        assert!(check("int main() { return f(); } int f() { return 1; }").is_ok());
+        assert_eq!(
+            check("int main() { return f; } int f() { return 1; }").unwrap_err(),
+            ASTError::UndeclaredIdentifier("f".to_string())
+        );
+    }
+
+    #[test]
+    fn test_call_errors() {
+        assert_eq!(
+            check("int f(int a, char *b) { return a; } int main() { return f(1); }").unwrap_err(),
+            ASTError::ArgumentCount { function: "f".to_string(), expected: 2, found: 1 }
+        );
+        assert_eq!(
+            check("int f(int a, char *b) { return a; } int main() { return f(1, 2); }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Int, Type::Char.pointer_to())
+        );
+        assert_eq!(
+            check("int main() { int x; return x(); }").unwrap_err(),
+            ASTError::NotAFunction(Type::Int)
+        );
+        assert_eq!(
+            check("void f() {} int main() { return f(); }").unwrap_err(),
+            ASTError::InvalidConversion(Type::Void, Type::Int)
+        );
+        assert_eq!(check("int f() {} int f() {}").unwrap_err(), ASTError::Redeclaration("f".to_string()));
+        assert_eq!(check("int f(int a) { long a; }").unwrap_err(), ASTError::Redeclaration("a".to_string()));
+        assert_eq!(check("int f(void v) {}").unwrap_err(), ASTError::VoidVariable("v".to_string()));
+        assert!(matches!(check("struct s f() {}").unwrap_err(), ASTError::IncompleteType(Type::Record(_))));
+    }
+
+    #[test]
+    fn test_external_calls() {
+        let unit = check(
+            "int printf(char *format, ...); int printf(char *, ...);\n\
+             int main() { char c; float f; return printf(\"%c %f\", c, f) + puts(\"done\", f); }",
+        )
+        .unwrap();
+        let Some(Statement::Return(Some(sum), _)) = unit.functions()[0].body.last() else {
+            panic!("expected a return");
+        };
+        // The variadic arguments get the default argument promotions
+        let types: Vec<Option<Type>> = sum.left.as_ref().unwrap().arguments().iter().map(|argument| argument.ty.clone()).collect();
+        assert_eq!(types, vec![Some(Type::Char.pointer_to()), Some(Type::Int), Some(Type::Double)]);
+        // A function that is not declared returns int and takes the promoted arguments
+        let puts = sum.right.as_ref().unwrap();
+        assert_eq!(puts.left.as_ref().unwrap().ty.as_ref().unwrap().to_string(), "int (char *, double)");
+        assert_eq!(puts.ty, Some(Type::Int));
+
+        assert_eq!(
+            check("int printf(char *format, ...); int main() { return printf(); }").unwrap_err(),
+            ASTError::ArgumentCount { function: "printf".to_string(), expected: 1, found: 0 }
+        );
+        assert_eq!(
+            check("void f(void); int main() { return g(f()); }").unwrap_err(),
+            ASTError::IncompleteType(Type::Void)
+        );
+        // A declaration has to agree with the ones before it and the definition
+        assert!(check("int f(int a); int f(int b) { return b; } int f(int);").is_ok());
+        assert_eq!(check("int f(int a); long f(int a);").unwrap_err(), ASTError::Redeclaration("f".to_string()));
+        assert_eq!(check("int f(int a, ...); int f(int a) {}").unwrap_err(), ASTError::Redeclaration("f".to_string()));
+    }
+
+    #[test]
+    fn test_function_pointers() {
+        let unit = check(
+            "int add(int a, int b) { return a + b; }\n\
+             int main() { int (*fp)(int, int) = add; fp = &add; return fp(1, 2) + (*fp)(3, 4); }",
+        )
+        .unwrap();
+        let body = &unit.functions()[1].body;
+        // A function used as a value is its address, like `&add`
+        let Statement::Declaration(_, Some(initializer)) = &body[0] else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(initializer.operation, Token::AddressOf);
+        assert_eq!(initializer.ty.as_ref().unwrap().to_string(), "int (int, int) *");
+
+        let Some(Statement::Return(Some(sum), _)) = body.last() else {
+            panic!("expected a return");
+        };
+        let direct = sum.left.as_ref().unwrap();
+        assert_eq!(direct.ty, Some(Type::Int));
+        // Calling `*fp` calls the function it points to, through the pointer again
+        let through = sum.right.as_ref().unwrap().left.as_ref().unwrap();
+        assert_eq!(through.operation, Token::AddressOf);
+        assert_eq!(through.left.as_ref().unwrap().operation, Token::Dereference);
+
+        assert_eq!(
+            check("int f(int a); int main() { int (*fp)(int) = f; return fp(); }").unwrap_err(),
+            ASTError::ArgumentCount { function: "fp".to_string(), expected: 1, found: 0 }
+        );
+        let Err(ASTError::InvalidConversion(from, to)) = check("int f(int a); int main() { long (*fp)(int) = f; }") else {
+            panic!("expected an invalid conversion");
+        };
+        assert_eq!((from.to_string(), to.to_string()), ("int (int) *".to_string(), "long (int) *".to_string()));
+        assert_eq!(check("int f(int a); int g(int a); int main() { f = g; }").unwrap_err(), ASTError::ExpectedLvalue);
+        // Functions have no size, so there is no arithmetic on pointers to them
+        assert!(matches!(
+            check("int f(int a); int main() { return sizeof f; }").unwrap_err(),
+            ASTError::InvalidOperand(Token::Sizeof, Type::Function { .. })
+        ));
+        assert!(matches!(
+            check("int f(int a); int main() { int (*fp)(int) = f + 1; }").unwrap_err(),
+            ASTError::InvalidOperands(Token::PLUS, _, _)
+        ));
+    }
+
+    #[test]
+    fn test_variadic_builtins() {
+        let unit = check(
+            "int sum(int n, ...) { __builtin_va_list ap; __builtin_va_start(ap, n);\n\
+             return __builtin_va_arg(ap, char); }",
+        )
+        .unwrap();
+        let body = &unit.functions()[0].body;
+        // va_start assigns the address of the variadic arguments and is thrown away
+        let Statement::Expression(Some(start)) = &body[1] else {
+            panic!("expected an expression");
+        };
+        assert_eq!((&start.operation, &start.ty), (&Token::Cast, &Some(Type::Void)));
+        let assignment = start.left.as_ref().unwrap();
+        assert_eq!(assignment.operation, Token::ASSIGN);
+        assert_eq!(assignment.right.as_ref().unwrap().operation, Token::VariadicArguments);
+        // va_arg loads from where the list pointed before moving it on by a whole word
+        let Statement::Return(Some(value), _) = &body[2] else {
+            panic!("expected a return");
+        };
+        let load = value.left.as_ref().unwrap();
+        assert_eq!((&load.operation, &load.ty), (&Token::Dereference, &Some(Type::Char)));
+        let next = load.left.as_ref().unwrap().left.as_ref().unwrap();
+        assert_eq!(next.operation, Token::PostIncrement);
+        assert_eq!(next.right.as_ref().unwrap().operation, Token::LONG(8));
+
+        // A big struct is passed by address
+        let unit = check(
+            "struct big { long a[3]; }; long f(int n, ...) { char *ap; return __builtin_va_arg(ap, struct big).a[2]; }",
+        )
+        .unwrap();
+        let Statement::Return(Some(value), _) = &unit.functions()[0].body[1] else {
+            panic!("expected a return");
+        };
+        let mut node = value;
+        while node.operation != Token::PostIncrement {
+            node = node.left.as_ref().unwrap();
+        }
+        assert_eq!(node.right.as_ref().unwrap().operation, Token::LONG(8));
+
+        assert_eq!(
+            check("int f(int n) { __builtin_va_list ap; __builtin_va_start(ap, n); }").unwrap_err(),
+            ASTError::NotVariadic
+        );
+        assert_eq!(
+            check("int f(int n, ...) { long ap; return __builtin_va_arg(ap, int); }").unwrap_err(),
+            ASTError::InvalidOperand(Token::VaArg, Type::Long)
+        );
+        assert_eq!(
+            check("int f(int n, ...) { int *ap; __builtin_va_end(ap); }").unwrap_err(),
+            ASTError::InvalidOperand(Token::VaEnd, Type::Int.pointer_to())
+        );
+        assert_eq!(
+            check("int f(int n, ...) { __builtin_va_list ap; __builtin_va_arg(ap, void); }").unwrap_err(),
+            ASTError::IncompleteType(Type::Void)
+        );
+        assert_eq!(
+            check("int f(int n, ...) { __builtin_va_list ap; __builtin_va_copy(1, ap); }").unwrap_err(),
+            ASTError::ExpectedLvalue
+        );
+    }
+
+    #[test]
+    fn test_linux_variadic_builtins() {
+        let check = |source: &str| {
+            let architecture = SupportedArchitectures::ARM64Linux;
+            let mut unit = TranslationUnit::parse_for_target(&mut Lexer::new(source.as_bytes()), architecture)?;
+            check_translation_unit(&mut unit).map_err(ASTError::without_position)?;
+            Ok::<_, ASTError>(unit)
+        };
+        let unit = check(
+            "struct pair { float x, y; }; float f(int n, ...) { __builtin_va_list ap, copy;\n\
+             __builtin_va_start(ap, n); __builtin_va_copy(copy, ap); return __builtin_va_arg(copy, struct pair).y; }",
+        )
+        .unwrap();
+        let body = &unit.functions()[0].body;
+        // va_start and va_copy copy the whole struct
+        let Statement::Expression(Some(start)) = &body[2] else {
+            panic!("expected an expression");
+        };
+        let assignment = start.left.as_ref().unwrap();
+        assert_eq!(assignment.ty, Some(SupportedArchitectures::ARM64Linux.va_list()));
+        assert_eq!(assignment.right.as_ref().unwrap().operation, Token::VariadicArguments);
+        // va_arg is left to the code generator, with a temporary for a struct
+        let Statement::Return(Some(value), _) = &body[4] else {
+            panic!("expected a return");
+        };
+        let mut node = value;
+        while node.operation != Token::VaArg {
+            node = node.left.as_ref().unwrap();
+        }
+        assert!(node.symbol.is_some());
+        assert_eq!(node.left.as_ref().unwrap().ty, Some(SupportedArchitectures::ARM64Linux.va_list()));
+
+        assert_eq!(
+            check("int f(int n, ...) { char *ap; return __builtin_va_arg(ap, int); }").unwrap_err(),
+            ASTError::InvalidOperand(Token::VaArg, Type::Char.pointer_to())
+        );
+    }
+
+    #[test]
+    fn test_linkage() {
+        // Only the first of the tentative definitions defines `x`, and only without an initialised one
+        let declared = |source: &str| -> Vec<(String, bool)> {
+            check(source)
+                .unwrap()
+                .declarations
+                .iter()
+                .filter_map(|declaration| match declaration {
+                    ExternalDeclaration::Variable(symbol, _) => Some((symbol.name.clone(), true)),
+                    ExternalDeclaration::VariableDeclaration(symbol) => Some((symbol.name.clone(), false)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let defines = |name: &str, defined| (name.to_string(), defined);
+        assert_eq!(declared("int x; int x; int y; int x = 3;"), vec![
+            defines("x", false),
+            defines("x", false),
+            defines("y", true),
+            defines("x", true)
+        ]);
+        assert_eq!(declared("extern int x; int x; extern int x;"), vec![
+            defines("x", false),
+            defines("x", true),
+            defines("x", false)
+        ]);
+        assert_eq!(check("int x = 1; int x = 2;").unwrap_err(), ASTError::Redeclaration("x".to_string()));
+
+        // The first declaration decides whether other files see the name
+        let unit = check("static int x; extern int x; static int f(void); int f(void) { return x; }").unwrap();
+        let ExternalDeclaration::VariableDeclaration(symbol) = &unit.declarations[1] else {
+            panic!("expected a declaration");
+        };
+        assert!(!is_external(symbol));
+        assert!(!unit.functions()[0].external);
+        assert_eq!(check("extern int x; static int x;").unwrap_err(), ASTError::Redeclaration("x".to_string()));
+        assert_eq!(check("int f(void); static int f(void) {}").unwrap_err(), ASTError::Redeclaration("f".to_string()));
+
+        // A global declared inside a function has to agree with the one outside
+        assert!(check("int main() { extern int y; return y; } int y = 1;").is_ok());
+        assert_eq!(check("int g; int main() { extern long g; }").unwrap_err(), ASTError::Redeclaration("g".to_string()));
+    }
+
+    #[test]
+    fn test_static_initializers() {
+        let unit = check(
+            "int a[4]; struct s { char c; int i; } s;\n\
+             int *p = &a[3] - 1, *q = &s.i, n = sizeof(a) / 2;\n\
+             char *text = \"hi\" + 1; short big = 65537;\n\
+             int main() { static int *r = a; return *p + *q + n; }",
+        )
+        .unwrap();
+        let values: Vec<Constant> = unit
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                ExternalDeclaration::Variable(_, Some(initializer)) => evaluate_constant(initializer),
+                _ => None,
+            })
+            .collect();
+        let [Constant::SymbolAddress(a, 8), Constant::SymbolAddress(s, 4), Constant::Integer(8), text, Constant::Integer(1)] =
+            values.as_slice()
+        else {
+            panic!("unexpected values {:?}", values);
+        };
+        assert_eq!((a.name.as_str(), s.name.as_str()), ("a", "s"));
+        assert_eq!(*text, Constant::StringAddress(b"hi".to_vec(), 1));
+
+        let Statement::Declaration(r, Some(initializer)) = &unit.functions()[0].body[0] else {
+            panic!("expected a declaration");
+        };
+        assert!(r.is_static() && unit.functions()[0].locals.is_empty());
+        assert!(matches!(evaluate_constant(initializer), Some(Constant::SymbolAddress(a, 0)) if a.name == "a"));
+
+        assert_eq!(check("int x; int y = x;").unwrap_err(), ASTError::NotConstant("y".to_string()));
+        assert_eq!(check("int main() { int x; static int *p = &x; }").unwrap_err(), ASTError::NotConstant("p".to_string()));
+        assert_eq!(check("int f() { return 1; } int x = f();").unwrap_err(), ASTError::NotConstant("x".to_string()));
+        assert_eq!(check("int x = 1 / 0;").unwrap_err(), ASTError::NotConstant("x".to_string()));
+        assert_eq!(check("int x; char c = (char) &x;").unwrap_err(), ASTError::NotConstant("c".to_string()));
+        // Only the operands that are evaluated have to be constant
+        assert_eq!(check("int x; int y = 0 && x; int z = 1 ? 2 : x;").unwrap().declarations.len(), 3);
+        assert_eq!(check("int x; int y = 1 && x;").unwrap_err(), ASTError::NotConstant("y".to_string()));
+
+        let unit = check("double d = 1 / 4.0f; float f = 0.1; int i = 2.9 * 2; unsigned u = 0 - 1.0;").unwrap_err();
+        assert_eq!(unit, ASTError::NotConstant("u".to_string()));
+        let unit = check("double d = 1 / 4.0f; float f = 0.1; int i = 2.9 * 2; char c = 1.5 < 2;").unwrap();
+        let values: Vec<Option<Constant>> = unit
+            .declarations
+            .iter()
+            .map(|declaration| match declaration {
+                ExternalDeclaration::Variable(_, Some(initializer)) => evaluate_constant(initializer),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                Some(Constant::Float(0.25)),
+                Some(Constant::Float(0.1f32 as f64)),
+                Some(Constant::Integer(5)),
+                Some(Constant::Integer(1))
+            ]
+        );
+        // Globals are only visible after their declaration
+        assert_eq!(check("int main() { return x; } int x;").unwrap_err(), ASTError::UndeclaredIdentifier("x".to_string()));
+        assert_eq!(check("int x; long x;").unwrap_err(), ASTError::Redeclaration("x".to_string()));
+    }
+
+    #[test]
+    fn test_initializer_lists() {
+        let unit = check(
+            "struct p { int x, y; } g = {1, 2}; int table[4] = {1, 2, 3, 4};\n\
+             int m[][2] = {1, 2, {3}}; char s[] = \"abc\", t[3] = \"abc\", u[8] = {\"ab\"};\n\
+             struct q { struct p p; char name[4]; } qs[] = {1, 2, \"ab\", {{3}, {'c'}}}; long one = {1};\n\
+             int main() { static double d[] = {1, 2.5}; return d[0]; }",
+        )
+        .unwrap();
+        let types: Vec<String> = unit
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                ExternalDeclaration::Variable(symbol, _) => Some(symbol.ty.to_string()),
+                _ => None,
+            })
+            .collect();
+        // The lengths left out come from the initialisers
+        assert_eq!(types, vec!["struct p", "int [4]", "int [2][2]", "char [4]", "char [3]", "char [8]", "struct q [2]", "long"]);
+
+        // Every aggregate gets a list of its own, with the values converted
+        let ExternalDeclaration::Variable(_, Some(m)) = &unit.declarations[2] else {
+            panic!("expected m");
+        };
+        let rows = m.arguments();
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| row.operation == Token::InitializerList && row.ty == Some(Type::Int.array_of(2))));
+        assert_eq!(rows[1].arguments().len(), 1);
+        let ExternalDeclaration::Variable(_, Some(qs)) = &unit.declarations[6] else {
+            panic!("expected qs");
+        };
+        let first = qs.arguments()[0].arguments();
+        assert_eq!(first[0].arguments().len(), 2);
+        assert_eq!((&first[1].operation, &first[1].ty), (&Token::STRING(b"ab".to_vec()), &Some(Type::Char.array_of(4))));
+        let Statement::Declaration(d, Some(values)) = &unit.functions()[0].body[0] else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(d.ty, Type::Double.array_of(2));
+        assert_eq!(evaluate_constant(values.arguments()[0]), Some(Constant::Float(1.0)));
+
+        assert_eq!(check("int a[2] = {1, 2, 3};").unwrap_err(), ASTError::TooManyInitializers);
+        assert_eq!(check("char s[2] = \"abc\";").unwrap_err(), ASTError::TooManyInitializers);
+        assert_eq!(check("struct p { int x; } g = {1, 2};").unwrap_err(), ASTError::TooManyInitializers);
+        assert_eq!(check("int x; int a[2] = {1, x};").unwrap_err(), ASTError::NotConstant("a".to_string()));
+        assert_eq!(check("int a[] = {};").unwrap_err(), ASTError::IncompleteType(Type::Int.array_of(0)));
+        assert!(matches!(check("int a[2] = \"ab\";").unwrap_err(), ASTError::InvalidConversion(..)));
+        assert!(matches!(check("struct p { int x; } g = {\"ab\"};").unwrap_err(), ASTError::InvalidConversion(..)));
+        assert_eq!(
+            check("int main() { int a[2] = {1, 2}; return a[0]; }").unwrap_err(),
+            ASTError::UnsupportedInitializer("a".to_string())
+        );
+        assert_eq!(check("int main() { int x = {1}; return x; }").unwrap().functions().len(), 1);
+    }
+}
@@ -0,0 +1,652 @@
+use crate::scan::{Keyword, Token};
+use std::cell::RefCell;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// A C type. Sizes follow the LP64 data model used on ARM64.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Type {
+    Void,
+    /// Plain `char` is signed on ARM64 Darwin, but it stays a type of its own as in C
+    Char,
+    SignedChar,
+    UnsignedChar,
+    Short,
+    UnsignedShort,
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+    /// IEEE 754 single precision
+    Float,
+    /// IEEE 754 double precision, `long double` is the same type on ARM64 Darwin
+    Double,
+    Pointer(Box<Type>),
+    /// A fixed number of elements, laid out one after another. A length of 0 is a flexible array member.
+    Array(Box<Type>, usize),
+    /// A struct or union
+    Record(Rc<Record>),
+    Function {
+        return_type: Box<Type>,
+        parameters: Vec<Type>,
+        /// Whether the parameters end with `...`, then any number of arguments can follow them
+        variadic: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RecordKind {
+    Struct,
+    Union,
+}
+
+/// A struct or union type. Every declaration makes a type of its own, so two records
+/// are only the same type when they come from the same declaration.
+pub struct Record {
+    pub kind: RecordKind,
+    pub tag: Option<String>,
+    /// `None` while the type is incomplete, as in `struct node;` or inside its own member list
+    layout: RefCell<Option<Layout>>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Member {
+    pub name: String,
+    pub ty: Type,
+    /// Bytes from the start of the record
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Layout {
+    members: Vec<Member>,
+    size: usize,
+    alignment: usize,
+}
+
+impl Record {
+    pub fn new(kind: RecordKind, tag: Option<String>) -> Rc<Record> {
+        Rc::new(Record {
+            kind,
+            tag,
+            layout: RefCell::new(None),
+        })
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.layout.borrow().is_some()
+    }
+
+    /// Complete the type with its members, laid out as C requires: struct members in order,
+    /// each aligned to its type with padding before it, union members all at offset 0.
+    /// Unnamed members that are records themselves have their members lifted into this one.
+    /// Returns the name of a member declared twice as the error.
+    ///
+    /// # Arguments
+    ///
+    /// * `members`: the members in declaration order, only the last one can be a flexible array
+    ///
+    /// returns: Result<(), String>
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::types::*;
+    /// let record = Record::new(RecordKind::Struct, Some("s".to_string()));
+    /// record.define(vec![
+    ///     (Some("c".to_string()), Type::Char),
+    ///     (Some("l".to_string()), Type::Long),
+    ///     (Some("tail".to_string()), Type::Short.array_of(0)),
+    /// ]).unwrap();
+    /// let ty = Type::Record(record.clone());
+    /// assert_eq!((ty.size(), ty.alignment()), (16, 8));
+    /// assert_eq!(record.member("l").unwrap().offset, 8);
+    /// assert_eq!(record.member("tail").unwrap().offset, 16);
+    /// ```
+    pub fn define(&self, members: Vec<(Option<String>, Type)>) -> Result<(), String> {
+        let mut laid_out: Vec<Member> = Vec::new();
+        let (mut size, mut alignment) = (0usize, 1usize);
+        for (name, ty) in members {
+            let offset = match self.kind {
+                RecordKind::Struct => size.next_multiple_of(ty.alignment()),
+                RecordKind::Union => 0,
+            };
+            size = size.max(offset + ty.size());
+            alignment = alignment.max(ty.alignment());
+
+            let lifted = match (name, &ty) {
+                (Some(name), _) => vec![Member { name, ty, offset }],
+                (None, Type::Record(inner)) => inner
+                    .members()
+                    .into_iter()
+                    .map(|member| Member { offset: offset + member.offset, ..member })
+                    .collect(),
+                (None, _) => Vec::new(),
+            };
+            for member in lifted {
+                if laid_out.iter().any(|existing| existing.name == member.name) {
+                    return Err(member.name);
+                }
+                laid_out.push(member);
+            }
+        }
+
+        *self.layout.borrow_mut() = Some(Layout {
+            members: laid_out,
+            size: size.next_multiple_of(alignment),
+            alignment,
+        });
+        Ok(())
+    }
+
+    /// Every member with its offset, empty while the type is incomplete
+    pub fn members(&self) -> Vec<Member> {
+        self.layout.borrow().as_ref().map(|layout| layout.members.clone()).unwrap_or_default()
+    }
+
+    /// The members a `{ ... }` initialiser gives values to, in order: the first member of a union,
+    /// every member of a struct but a flexible array. Of the members lifted from an unnamed union
+    /// only the first one is initialised, like the union itself.
+    pub fn initialized_members(&self) -> Vec<Member> {
+        let mut members = self.members();
+        if self.kind == RecordKind::Union {
+            members.truncate(1);
+            return members;
+        }
+        let mut end = 0;
+        members.retain(|member| {
+            let kept = member.offset >= end && member.ty.size() > 0;
+            if kept {
+                end = member.offset + member.ty.size();
+            }
+            kept
+        });
+        members
+    }
+
+    pub fn member(&self, name: &str) -> Option<Member> {
+        self.members().into_iter().find(|member| member.name == name)
+    }
+
+    fn size(&self) -> usize {
+        self.layout.borrow().as_ref().map_or(0, |layout| layout.size)
+    }
+
+    fn alignment(&self) -> usize {
+        self.layout.borrow().as_ref().map_or(1, |layout| layout.alignment)
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Record {}
+
+impl Hash for Record {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self, state);
+    }
+}
+
+// Members can point back at the record, so only the name is printed
+impl fmt::Debug for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            RecordKind::Struct => "struct",
+            RecordKind::Union => "union",
+        };
+        write!(f, "{} {}", kind, self.tag.as_deref().unwrap_or("<anonymous>"))
+    }
+}
+
+impl Type {
+    /// The type of a literal token, `None` for tokens that are not literals
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::scan::Token;
+    /// # use compiler::types::Type;
+    /// assert_eq!(Type::of_literal(&Token::ULONG(1)), Some(Type::UnsignedLong));
+    /// // Character constants are ints in C
+    /// assert_eq!(Type::of_literal(&Token::CHAR(b'a')), Some(Type::Int));
+    /// assert_eq!(Type::of_literal(&Token::STRING(b"hi".to_vec())), Some(Type::Char.array_of(3)));
+    /// assert_eq!(Type::of_literal(&Token::PLUS), None);
+    /// ```
+    pub fn of_literal(token: &Token) -> Option<Type> {
+        match token {
+            Token::INT(_) | Token::CHAR(_) => Some(Type::Int),
+            Token::UINT(_) => Some(Type::UnsignedInt),
+            Token::LONG(_) => Some(Type::Long),
+            Token::ULONG(_) => Some(Type::UnsignedLong),
+            Token::LONGLONG(_) => Some(Type::LongLong),
+            Token::ULONGLONG(_) => Some(Type::UnsignedLongLong),
+            Token::FLOAT(_) => Some(Type::Float),
+            Token::DOUBLE(_) => Some(Type::Double),
+            // The terminating null byte is part of the array
+            Token::STRING(bytes) => Some(Type::Array(Box::new(Type::Char), bytes.len() + 1)),
+            _ => None,
+        }
+    }
+
+    /// The type named by a list of type specifiers, which C allows in any order.
+    /// `None` if they do not name a type, like `short long` or `unsigned void`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::scan::Keyword;
+    /// # use compiler::types::Type;
+    /// let specifiers = [Keyword::Long, Keyword::Unsigned, Keyword::Int, Keyword::Long];
+    /// assert_eq!(Type::from_specifiers(&specifiers), Some(Type::UnsignedLongLong));
+    /// assert_eq!(Type::from_specifiers(&[Keyword::Signed]), Some(Type::Int));
+    /// assert_eq!(Type::from_specifiers(&[Keyword::Short, Keyword::Char]), None);
+    /// assert_eq!(Type::from_specifiers(&[Keyword::Long, Keyword::Double]), Some(Type::Double));
+    /// ```
+    pub fn from_specifiers(specifiers: &[Keyword]) -> Option<Type> {
+        let count = |keyword: Keyword| specifiers.iter().filter(|specifier| **specifier == keyword).count();
+        match (count(Keyword::Float), count(Keyword::Double)) {
+            (0, 0) => {}
+            (1, 0) if specifiers.len() == 1 => return Some(Type::Float),
+            (0, 1) if specifiers.len() == 1 || (specifiers.len() == 2 && count(Keyword::Long) == 1) => {
+                return Some(Type::Double)
+            }
+            _ => return None,
+        }
+        let (signed, unsigned, int, long) = (
+            count(Keyword::Signed),
+            count(Keyword::Unsigned),
+            count(Keyword::Int),
+            count(Keyword::Long),
+        );
+        if signed + unsigned > 1 || int > 1 || long > 2 {
+            return None;
+        }
+
+        let pick = |signed_type: Type, unsigned_type: Type| if unsigned == 1 { unsigned_type } else { signed_type };
+        match (count(Keyword::Void), count(Keyword::Char), count(Keyword::Short), long) {
+            (1, 0, 0, 0) if signed + unsigned + int == 0 => Some(Type::Void),
+            (0, 1, 0, 0) if int == 0 => Some(match (signed, unsigned) {
+                (1, _) => Type::SignedChar,
+                (_, 1) => Type::UnsignedChar,
+                _ => Type::Char,
+            }),
+            (0, 0, 1, 0) => Some(pick(Type::Short, Type::UnsignedShort)),
+            (0, 0, 0, 1) => Some(pick(Type::Long, Type::UnsignedLong)),
+            (0, 0, 0, 2) => Some(pick(Type::LongLong, Type::UnsignedLongLong)),
+            (0, 0, 0, 0) if signed + unsigned + int > 0 => Some(pick(Type::Int, Type::UnsignedInt)),
+            _ => None,
+        }
+    }
+
+    pub fn pointer_to(self) -> Type {
+        Type::Pointer(Box::new(self))
+    }
+
+    pub fn array_of(self, length: usize) -> Type {
+        Type::Array(Box::new(self), length)
+    }
+
+    /// The type a pointer points to, `None` for types that are not pointers
+    pub fn pointee(&self) -> Option<&Type> {
+        match self {
+            Type::Pointer(pointee) => Some(pointee),
+            _ => None,
+        }
+    }
+
+    /// Size in bytes, as `sizeof` gives it
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Void => 0,
+            Type::Char | Type::SignedChar | Type::UnsignedChar => 1,
+            Type::Short | Type::UnsignedShort => 2,
+            Type::Int | Type::UnsignedInt | Type::Float => 4,
+            Type::Long | Type::UnsignedLong | Type::LongLong | Type::UnsignedLongLong | Type::Pointer(_) => 8,
+            Type::Double => 8,
+            Type::Array(element, length) => element.size() * length,
+            Type::Record(record) => record.size(),
+            Type::Function { .. } => 0,
+        }
+    }
+
+    /// Alignment in bytes, scalars are aligned to their size and arrays like their elements
+    pub fn alignment(&self) -> usize {
+        match self {
+            Type::Array(element, _) => element.alignment(),
+            Type::Record(record) => record.alignment(),
+            _ => self.size().max(1),
+        }
+    }
+
+    /// Whether the size of the type is known. `void`, functions, arrays without a length
+    /// and records without their members are not.
+    pub fn is_complete(&self) -> bool {
+        match self {
+            Type::Void | Type::Function { .. } => false,
+            Type::Record(record) => record.is_complete(),
+            Type::Array(element, length) => *length > 0 && element.is_complete(),
+            _ => true,
+        }
+    }
+
+    pub fn is_record(&self) -> bool {
+        matches!(self, Type::Record(_))
+    }
+
+    /// The type an expression of this type has when used as a value: arrays decay to a pointer
+    /// to their first element and functions to a pointer to the function (C11 6.3.2.1)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::types::Type;
+    /// let matrix = Type::Int.array_of(3).array_of(2);
+    /// assert_eq!(matrix.decayed(), Type::Int.array_of(3).pointer_to());
+    /// assert_eq!(Type::Long.decayed(), Type::Long);
+    /// let function = Type::Function { return_type: Box::new(Type::Int), parameters: vec![Type::Int], variadic: false };
+    /// assert_eq!(function.decayed(), function.clone().pointer_to());
+    /// ```
+    pub fn decayed(&self) -> Type {
+        match self {
+            Type::Array(element, _) => (**element).clone().pointer_to(),
+            Type::Function { .. } => self.clone().pointer_to(),
+            other => other.clone(),
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.integer_rank().is_some()
+    }
+
+    /// Whether this is one of the `char` types, arrays of them can be initialised with a string literal
+    pub fn is_character(&self) -> bool {
+        matches!(self, Type::Char | Type::SignedChar | Type::UnsignedChar)
+    }
+
+    pub fn is_floating(&self) -> bool {
+        matches!(self, Type::Float | Type::Double)
+    }
+
+    pub fn is_arithmetic(&self) -> bool {
+        self.is_integer() || self.is_floating()
+    }
+
+    pub fn is_scalar(&self) -> bool {
+        self.is_arithmetic() || matches!(self, Type::Pointer(_))
+    }
+
+    /// Whether values of this type are sign extended, pointers count as unsigned and
+    /// floating types as neither
+    pub fn is_signed(&self) -> bool {
+        matches!(
+            self,
+            Type::Char | Type::SignedChar | Type::Short | Type::Int | Type::Long | Type::LongLong
+        )
+    }
+
+    /// Integer conversion rank (C11 6.3.1.1), `None` for types that are not integers
+    fn integer_rank(&self) -> Option<u8> {
+        match self {
+            Type::Char | Type::SignedChar | Type::UnsignedChar => Some(1),
+            Type::Short | Type::UnsignedShort => Some(2),
+            Type::Int | Type::UnsignedInt => Some(3),
+            Type::Long | Type::UnsignedLong => Some(4),
+            Type::LongLong | Type::UnsignedLongLong => Some(5),
+            _ => None,
+        }
+    }
+
+    /// The unsigned type of the same rank, other types are returned as they are
+    pub fn to_unsigned(&self) -> Type {
+        match self {
+            Type::Char | Type::SignedChar => Type::UnsignedChar,
+            Type::Short => Type::UnsignedShort,
+            Type::Int => Type::UnsignedInt,
+            Type::Long => Type::UnsignedLong,
+            Type::LongLong => Type::UnsignedLongLong,
+            other => other.clone(),
+        }
+    }
+
+    /// The integer promotions (C11 6.3.1.1): everything smaller than `int` becomes `int`,
+    /// since `int` can hold every value of those types
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::types::Type;
+    /// assert_eq!(Type::UnsignedShort.promoted(), Type::Int);
+    /// assert_eq!(Type::UnsignedInt.promoted(), Type::UnsignedInt);
+    /// ```
+    pub fn promoted(&self) -> Type {
+        match self.integer_rank() {
+            Some(rank) if rank < 3 => Type::Int,
+            _ => self.clone(),
+        }
+    }
+
+    /// The default argument promotions (C11 6.5.2.2), which apply to the arguments that
+    /// match no parameter: the integer promotions, and `float` becomes `double`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::types::Type;
+    /// assert_eq!(Type::Char.argument_promoted(), Type::Int);
+    /// assert_eq!(Type::Float.argument_promoted(), Type::Double);
+    /// ```
+    pub fn argument_promoted(&self) -> Type {
+        match self {
+            Type::Float => Type::Double,
+            _ => self.promoted(),
+        }
+    }
+
+    /// The usual arithmetic conversions (C11 6.3.1.8), the type both operands of a binary
+    /// operator are converted to
+    ///
+    /// # Arguments
+    ///
+    /// * `left`, `right`: the operand types, both have to be arithmetic types
+    ///
+    /// returns: Type
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use compiler::types::Type;
+    /// assert_eq!(Type::common(&Type::Char, &Type::Short), Type::Int);
+    /// assert_eq!(Type::common(&Type::Int, &Type::UnsignedInt), Type::UnsignedInt);
+    /// // long can hold every unsigned int
+    /// assert_eq!(Type::common(&Type::UnsignedInt, &Type::Long), Type::Long);
+    /// // but not every unsigned long
+    /// assert_eq!(Type::common(&Type::LongLong, &Type::UnsignedLong), Type::UnsignedLongLong);
+    /// // any floating operand wins over integers
+    /// assert_eq!(Type::common(&Type::UnsignedLongLong, &Type::Float), Type::Float);
+    /// ```
+    pub fn common(left: &Type, right: &Type) -> Type {
+        if left.is_floating() || right.is_floating() {
+            return if *left == Type::Double || *right == Type::Double { Type::Double } else { Type::Float };
+        }
+        let (left, right) = (left.promoted(), right.promoted());
+        if left == right {
+            return left;
+        }
+
+        let (left_rank, right_rank) = (left.integer_rank(), right.integer_rank());
+        if left.is_signed() == right.is_signed() {
+            return if left_rank >= right_rank { left } else { right };
+        }
+
+        let (signed, unsigned) = if left.is_signed() { (left, right) } else { (right, left) };
+        if unsigned.integer_rank() >= signed.integer_rank() {
+            unsigned
+        } else if signed.size() > unsigned.size() {
+            signed
+        } else {
+            signed.to_unsigned()
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::Char => write!(f, "char"),
+            Type::SignedChar => write!(f, "signed char"),
+            Type::UnsignedChar => write!(f, "unsigned char"),
+            Type::Short => write!(f, "short"),
+            Type::UnsignedShort => write!(f, "unsigned short"),
+            Type::Int => write!(f, "int"),
+            Type::UnsignedInt => write!(f, "unsigned int"),
+            Type::Long => write!(f, "long"),
+            Type::UnsignedLong => write!(f, "unsigned long"),
+            Type::LongLong => write!(f, "long long"),
+            Type::UnsignedLongLong => write!(f, "unsigned long long"),
+            Type::Float => write!(f, "float"),
+            Type::Double => write!(f, "double"),
+            Type::Pointer(pointee) => write!(f, "{} *", pointee),
+            Type::Array(..) => {
+                // Dimensions are written outermost first, `int [2][3]` is two arrays of three ints
+                let mut element = self;
+                let mut dimensions = String::new();
+                while let Type::Array(inner, length) = element {
+                    match length {
+                        0 => dimensions.push_str("[]"),
+                        _ => dimensions.push_str(&format!("[{}]", length)),
+                    }
+                    element = inner;
+                }
+                write!(f, "{} {}", element, dimensions)
+            }
+            Type::Record(record) => write!(f, "{}", record),
+            Type::Function { return_type, parameters, variadic } => {
+                let mut parameters: Vec<String> = parameters.iter().map(Type::to_string).collect();
+                if *variadic {
+                    parameters.push("...".to_string());
+                }
+                write!(f, "{} ({})", return_type, parameters.join(", "))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sizes() {
+        assert_eq!(Type::Char.size(), 1);
+        assert_eq!(Type::UnsignedShort.size(), 2);
+        assert_eq!(Type::Int.size(), 4);
+        assert_eq!(Type::Long.size(), 8);
+        assert_eq!(Type::UnsignedLongLong.size(), 8);
+        assert_eq!(Type::Char.pointer_to().size(), 8);
+        assert_eq!(Type::Short.array_of(3).array_of(5).size(), 30);
+        assert_eq!(Type::Short.array_of(3).alignment(), 2);
+        assert_eq!(Type::Long.array_of(0).size(), 0);
+    }
+
+    #[test]
+    fn test_signedness() {
+        assert!(Type::Char.is_signed());
+        assert!(!Type::UnsignedChar.is_signed());
+        assert!(!Type::Int.pointer_to().is_signed());
+        assert_eq!(Type::SignedChar.to_unsigned(), Type::UnsignedChar);
+        assert_eq!(Type::LongLong.to_unsigned(), Type::UnsignedLongLong);
+    }
+
+    #[test]
+    fn test_integer_promotions() {
+        for small in [Type::Char, Type::SignedChar, Type::UnsignedChar, Type::Short, Type::UnsignedShort] {
+            assert_eq!(small.promoted(), Type::Int);
+        }
+        for large in [Type::Int, Type::UnsignedInt, Type::Long, Type::UnsignedLongLong] {
+            assert_eq!(large.promoted(), large);
+        }
+    }
+
+    #[test]
+    fn test_usual_arithmetic_conversions() {
+        assert_eq!(Type::common(&Type::UnsignedChar, &Type::UnsignedChar), Type::Int);
+        assert_eq!(Type::common(&Type::Int, &Type::Long), Type::Long);
+        assert_eq!(Type::common(&Type::UnsignedInt, &Type::Int), Type::UnsignedInt);
+        assert_eq!(Type::common(&Type::UnsignedShort, &Type::UnsignedInt), Type::UnsignedInt);
+        assert_eq!(Type::common(&Type::Long, &Type::UnsignedLong), Type::UnsignedLong);
+        assert_eq!(Type::common(&Type::UnsignedInt, &Type::LongLong), Type::LongLong);
+        assert_eq!(Type::common(&Type::Long, &Type::LongLong), Type::LongLong);
+        assert_eq!(Type::common(&Type::Long, &Type::UnsignedLongLong), Type::UnsignedLongLong);
+        assert_eq!(Type::common(&Type::Char, &Type::Float), Type::Float);
+        assert_eq!(Type::common(&Type::Float, &Type::Double), Type::Double);
+        assert_eq!(Type::common(&Type::Double, &Type::LongLong), Type::Double);
+        assert_eq!(Type::Float.promoted(), Type::Float);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Type::UnsignedLongLong.to_string(), "unsigned long long");
+        assert_eq!(Type::Char.pointer_to().to_string(), "char *");
+        assert_eq!(Type::Int.array_of(3).array_of(2).to_string(), "int [2][3]");
+        assert_eq!(Type::Record(Record::new(RecordKind::Union, None)).to_string(), "union <anonymous>");
+        let function = Type::Function {
+            return_type: Box::new(Type::Void),
+            parameters: vec![Type::Int, Type::Char.pointer_to()],
+            variadic: false,
+        };
+        assert_eq!(function.to_string(), "void (int, char *)");
+        let printf = Type::Function {
+            return_type: Box::new(Type::Int),
+            parameters: vec![Type::Char.pointer_to()],
+            variadic: true,
+        };
+        assert_eq!(printf.to_string(), "int (char *, ...)");
+    }
+
+    #[test]
+    fn test_record_layout() {
+        let union = Record::new(RecordKind::Union, None);
+        union.define(vec![(Some("c".to_string()), Type::Char.array_of(5)), (Some("i".to_string()), Type::Int)]).unwrap();
+        assert_eq!((Type::Record(union.clone()).size(), Type::Record(union.clone()).alignment()), (8, 4));
+
+        let record = Record::new(RecordKind::Struct, Some("outer".to_string()));
+        assert!(!Type::Record(record.clone()).is_complete());
+        record.define(vec![
+            (Some("s".to_string()), Type::Short),
+            // The members of an unnamed union become members of the struct
+            (None, Type::Record(union.clone())),
+            (Some("p".to_string()), Type::Record(record.clone()).pointer_to()),
+        ]).unwrap();
+        let ty = Type::Record(record.clone());
+        assert!(ty.is_complete());
+        assert_eq!((ty.size(), ty.alignment()), (24, 8));
+        let offsets: Vec<(String, usize)> = record.members().into_iter().map(|member| (member.name, member.offset)).collect();
+        assert_eq!(offsets, vec![("s".to_string(), 0), ("c".to_string(), 4), ("i".to_string(), 4), ("p".to_string(), 16)]);
+        // An initialiser sets `c` for the unnamed union, not `i` as well
+        let initialized: Vec<String> = record.initialized_members().into_iter().map(|member| member.name).collect();
+        assert_eq!(initialized, vec!["s", "c", "p"]);
+        assert_eq!(union.initialized_members().len(), 1);
+
+        // Records are only equal to themselves
+        assert_ne!(Type::Record(Record::new(RecordKind::Struct, None)), Type::Record(Record::new(RecordKind::Struct, None)));
+        assert_eq!(ty.clone(), ty);
+
+        let duplicate = Record::new(RecordKind::Struct, None);
+        assert_eq!(duplicate.define(vec![(Some("a".to_string()), Type::Int), (Some("a".to_string()), Type::Long)]), Err("a".to_string()));
+    }
+}